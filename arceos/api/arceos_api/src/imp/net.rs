@@ -1,7 +1,8 @@
 use crate::io::AxPollState;
 use axerrno::AxResult;
-use axnet::{UdpSocket, TcpSocket};
+use axnet::{IcmpSocket, UdpSocket, TcpSocket};
 use core::net::{IpAddr, SocketAddr};
+use core::time::Duration;
 
 /// A handle to a TCP socket.
 pub struct AxTcpSocketHandle(TcpSocket);
@@ -9,6 +10,9 @@ pub struct AxTcpSocketHandle(TcpSocket);
 /// A handle to a UDP socket.
 pub struct AxUdpSocketHandle(UdpSocket);
 
+/// A handle to an ICMP socket.
+pub struct AxIcmpSocketHandle(IcmpSocket);
+
 ////////////////////////////////////////////////////////////////////////////////
 // TCP socket
 ////////////////////////////////////////////////////////////////////////////////
@@ -117,6 +121,22 @@ pub fn ax_udp_poll(socket: &AxUdpSocketHandle) -> AxResult<AxPollState> {
     socket.0.poll()
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// ICMP socket
+////////////////////////////////////////////////////////////////////////////////
+
+pub fn ax_icmp_socket() -> AxIcmpSocketHandle {
+    AxIcmpSocketHandle(IcmpSocket::new())
+}
+
+pub fn ax_icmp_bind(socket: &AxIcmpSocketHandle, ident: u16) -> AxResult {
+    socket.0.bind(ident)
+}
+
+pub fn ax_icmp_ping(socket: &AxIcmpSocketHandle, addr: IpAddr, seq_no: u16, timeout: Duration) -> AxResult<Option<Duration>> {
+    socket.0.ping(addr, seq_no, timeout)
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Miscellaneous
 ////////////////////////////////////////////////////////////////////////////////
@@ -129,3 +149,22 @@ pub fn ax_poll_interfaces() -> AxResult {
     axnet::poll_interfaces();
     Ok(())
 }
+
+////////////////////////////////////////////////////////////////////////////////
+// Packet capture
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(feature = "net-pcap")]
+pub fn ax_net_capture_start(sink: alloc::boxed::Box<dyn axio::Write + Send>) -> AxResult {
+    axnet::pcap::start_capture(sink)
+}
+
+#[cfg(feature = "net-pcap")]
+pub fn ax_net_capture_stop() -> AxResult {
+    axnet::pcap::stop_capture()
+}
+
+#[cfg(feature = "net-pcap")]
+pub fn ax_net_capture_enabled() -> bool {
+    axnet::pcap::is_capturing()
+}
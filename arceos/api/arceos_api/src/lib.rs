@@ -261,11 +261,13 @@ pub mod fs {
 pub mod net {
     use crate::{io::AxPollState, AxResult};
     use core::net::{IpAddr, SocketAddr};
+    use core::time::Duration;
 
     define_api_type! {
         @cfg "net";
         pub type AxTcpSocketHandle;
         pub type AxUdpSocketHandle;
+        pub type AxIcmpSocketHandle;
     }
 
     define_api! {
@@ -340,6 +342,18 @@ pub mod net {
         /// Returns whether the UDP socket is readable or writable.
         pub fn ax_udp_poll(socket: &AxUdpSocketHandle) -> AxResult<AxPollState>;
 
+        // ICMP socket
+
+        /// Creates a new ICMP socket.
+        pub fn ax_icmp_socket() -> AxIcmpSocketHandle;
+        /// Binds the ICMP socket to the given identifier, or to an automatically
+        /// generated one if `ident` is 0.
+        pub fn ax_icmp_bind(socket: &AxIcmpSocketHandle, ident: u16) -> AxResult;
+        /// Sends an ICMP echo request to the given address and waits for the
+        /// reply. On success, returns the round-trip time, or `None` if no
+        /// reply arrived within `timeout`.
+        pub fn ax_icmp_ping(socket: &AxIcmpSocketHandle, addr: IpAddr, seq_no: u16, timeout: Duration) -> AxResult<Option<Duration>>;
+
         // Miscellaneous
 
         /// Resolves the host name to a list of IP addresses.
@@ -350,6 +364,18 @@ pub mod net {
         /// packets to the NIC.
         pub fn ax_poll_interfaces() -> AxResult;
     }
+
+    define_api! {
+        @cfg "net-pcap";
+
+        /// Starts capturing the frames sent and received by the NIC, and writes
+        /// them to `sink` in the pcap format.
+        pub fn ax_net_capture_start(sink: alloc::boxed::Box<dyn axio::Write + Send>) -> AxResult;
+        /// Stops the packet capture, and flushes the sink.
+        pub fn ax_net_capture_stop() -> AxResult;
+        /// Returns whether a packet capture is in progress.
+        pub fn ax_net_capture_enabled() -> bool;
+    }
 }
 
 /// Graphics manipulation operations.
@@ -53,6 +53,8 @@ typedef struct {{
             "clockid_t",
             "rlimit",
             "aibuf",
+            "msghdr",
+            "cmsghdr",
         ];
         let allow_vars = [
             "CLOCK_.*",
@@ -68,6 +70,9 @@ typedef struct {{
             "RLIMIT_.*",
             "EAI_.*",
             "MAXADDRS",
+            "SOL_.*",
+            "SCM_.*",
+            "MSG_.*",
         ];
 
         #[derive(Debug)]
@@ -1,5 +1,7 @@
 use alloc::sync::Arc;
 use core::ffi::c_int;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
 
 use axerrno::{LinuxError, LinuxResult};
 use axio::PollState;
@@ -11,6 +13,19 @@ use crate::ctypes;
 
 pub const AX_FILE_LIMIT: usize = 1024;
 
+/// Hang-up state of a file, reported as `EPOLLRDHUP`, `EPOLLHUP` and
+/// `EPOLLERR`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HangUpState {
+    /// The peer has shut down writing, reading will return EOF.
+    pub read_hup: bool,
+    /// The peer is closed in both directions.
+    pub hup: bool,
+    /// The peer is closed and writing will fail, e.g., the read end of a pipe
+    /// for its write end.
+    pub err: bool,
+}
+
 #[allow(dead_code)]
 pub trait FileLike: Send + Sync {
     fn read(&self, buf: &mut [u8]) -> LinuxResult<usize>;
@@ -19,6 +34,66 @@ pub trait FileLike: Send + Sync {
     fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync>;
     fn poll(&self) -> LinuxResult<PollState>;
     fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult;
+
+    fn poll_hup(&self) -> LinuxResult<HangUpState> {
+        Ok(HangUpState::default())
+    }
+
+    /// Returns a counter of the events on the file, such as data arriving or
+    /// being consumed. Edge-triggered `epoll` reports a file that is still
+    /// ready again only after it changes.
+    fn poll_generation(&self) -> usize {
+        0
+    }
+}
+
+/// Number of readiness changes of all files so far.
+static POLL_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "multitask")]
+static POLL_WAIT_QUEUE: axtask::WaitQueue = axtask::WaitQueue::new();
+
+/// Notifies the tasks blocked in [`poll_wait`] that the readiness of some file
+/// may have changed.
+pub fn poll_notify() {
+    POLL_EVENTS.fetch_add(1, Ordering::AcqRel);
+    #[cfg(feature = "multitask")]
+    POLL_WAIT_QUEUE.notify_all(false);
+}
+
+/// Returns the number of readiness changes so far.
+///
+/// It must be read before checking the readiness, then passed to [`poll_wait`]
+/// so that the changes in between are not missed.
+pub fn poll_events() -> usize {
+    POLL_EVENTS.load(Ordering::Acquire)
+}
+
+/// Blocks the current task until [`poll_notify`] is called after `events` is
+/// returned by [`poll_events`], or the `timeout` has elapsed.
+///
+/// It may return earlier, so the caller must check the readiness again. If
+/// the timeout cannot be implemented (without `multitask` or `irq`), it just
+/// yields the CPU.
+pub fn poll_wait(events: usize, timeout: Option<Duration>) {
+    #[cfg(feature = "multitask")]
+    {
+        let changed = || POLL_EVENTS.load(Ordering::Acquire) != events;
+        match timeout {
+            #[cfg(feature = "irq")]
+            Some(dur) => {
+                POLL_WAIT_QUEUE.wait_timeout_until(dur, changed);
+            }
+            #[cfg(not(feature = "irq"))]
+            Some(_) => axtask::yield_now(),
+            None => POLL_WAIT_QUEUE.wait_until(changed),
+        }
+    }
+    #[cfg(not(feature = "multitask"))]
+    {
+        let _ = (events, timeout);
+        crate::sys_sched_yield();
+    }
 }
 
 lazy_static::lazy_static! {
@@ -49,6 +124,7 @@ pub fn close_file_like(fd: c_int) -> LinuxResult {
         .remove(fd as usize)
         .ok_or(LinuxError::EBADF)?;
     drop(f);
+    poll_notify();
     Ok(())
 }
 
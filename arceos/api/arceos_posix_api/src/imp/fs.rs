@@ -24,12 +24,16 @@ impl File {
         super::fd_ops::add_file_like(Arc::new(self))
     }
 
-    fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>> {
+    pub(crate) fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>> {
         let f = super::fd_ops::get_file_like(fd)?;
         f.into_any()
             .downcast::<Self>()
             .map_err(|_| LinuxError::EINVAL)
     }
+
+    pub(crate) fn inner(&self) -> &Mutex<axfs::fops::File> {
+        &self.inner
+    }
 }
 
 impl FileLike for File {
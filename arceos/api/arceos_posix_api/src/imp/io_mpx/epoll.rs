@@ -1,6 +1,10 @@
 //! `epoll` implementation.
 //!
-//! TODO: do not support `EPOLLET` flag
+//! Both level-triggered and edge-triggered (`EPOLLET`) modes are supported.
+//! The edges are detected with the event counter of each file
+//! ([`FileLike::poll_generation`]), so a ready file is reported again in the
+//! edge-triggered mode once new events have happened on it since the last
+//! report.
 
 use alloc::collections::btree_map::Entry;
 use alloc::collections::BTreeMap;
@@ -11,11 +15,55 @@ use axerrno::{LinuxError, LinuxResult};
 use axhal::time::wall_time;
 use axsync::Mutex;
 
+use super::{poll_interfaces, wait_events};
 use crate::ctypes;
-use crate::imp::fd_ops::{add_file_like, get_file_like, FileLike};
+use crate::imp::fd_ops::{add_file_like, get_file_like, poll_events, FileLike};
+
+/// Events that are always reported, regardless of the requested ones.
+const EPOLL_ALWAYS: u32 = ctypes::EPOLLERR | ctypes::EPOLLHUP;
+
+struct EpollEntry {
+    event: ctypes::epoll_event,
+    /// Events already reported in the edge-triggered mode.
+    reported: u32,
+    /// Value of [`FileLike::poll_generation`] at the time of the last report.
+    events_gen: usize,
+    /// Disabled by `EPOLLONESHOT` until rearmed by `EPOLL_CTL_MOD`.
+    disabled: bool,
+}
+
+impl EpollEntry {
+    fn new(event: ctypes::epoll_event) -> Self {
+        Self {
+            event,
+            reported: 0,
+            events_gen: 0,
+            disabled: false,
+        }
+    }
+
+    /// Returns the events to report among the ready ones.
+    fn report(&mut self, ready: u32, events_gen: usize) -> u32 {
+        let mut ready = ready & (self.event.events | EPOLL_ALWAYS);
+        if self.event.events & ctypes::EPOLLET != 0 {
+            if self.events_gen != events_gen {
+                self.reported = 0;
+            }
+            // events that become not ready can be reported again later
+            self.reported &= ready;
+            ready &= !self.reported;
+            self.reported |= ready;
+            self.events_gen = events_gen;
+        }
+        if ready != 0 && self.event.events & ctypes::EPOLLONESHOT != 0 {
+            self.disabled = true;
+        }
+        ready
+    }
+}
 
 pub struct EpollInstance {
-    events: Mutex<BTreeMap<usize, ctypes::epoll_event>>,
+    events: Mutex<BTreeMap<usize, EpollEntry>>,
 }
 
 unsafe impl Send for ctypes::epoll_event {}
@@ -36,24 +84,28 @@ impl EpollInstance {
             .map_err(|_| LinuxError::EINVAL)
     }
 
-    fn control(&self, op: usize, fd: usize, event: &ctypes::epoll_event) -> LinuxResult<usize> {
-        match get_file_like(fd as c_int) {
-            Ok(_) => {}
-            Err(e) => return Err(e),
-        }
+    fn control(
+        &self,
+        op: usize,
+        fd: usize,
+        event: Option<&ctypes::epoll_event>,
+    ) -> LinuxResult<usize> {
+        get_file_like(fd as c_int)?;
 
         match op as u32 {
             ctypes::EPOLL_CTL_ADD => {
+                let event = event.ok_or(LinuxError::EFAULT)?;
                 if let Entry::Vacant(e) = self.events.lock().entry(fd) {
-                    e.insert(*event);
+                    e.insert(EpollEntry::new(*event));
                 } else {
                     return Err(LinuxError::EEXIST);
                 }
             }
             ctypes::EPOLL_CTL_MOD => {
+                let event = event.ok_or(LinuxError::EFAULT)?;
                 let mut events = self.events.lock();
                 if let Entry::Occupied(mut ocp) = events.entry(fd) {
-                    ocp.insert(*event);
+                    ocp.insert(EpollEntry::new(*event));
                 } else {
                     return Err(LinuxError::ENOENT);
                 }
@@ -73,35 +125,56 @@ impl EpollInstance {
         Ok(0)
     }
 
-    fn poll_all(&self, events: &mut [ctypes::epoll_event]) -> LinuxResult<usize> {
-        let ready_list = self.events.lock();
+    fn poll_all(&self, events: &mut [ctypes::epoll_event]) -> usize {
+        let mut ready_list = self.events.lock();
         let mut events_num = 0;
 
-        for (infd, ev) in ready_list.iter() {
-            match get_file_like(*infd as c_int)?.poll() {
-                Err(_) => {
-                    if (ev.events & ctypes::EPOLLERR) != 0 {
-                        events[events_num].events = ctypes::EPOLLERR;
-                        events[events_num].data = ev.data;
-                        events_num += 1;
-                    }
-                }
+        for (infd, entry) in ready_list.iter_mut() {
+            if events_num >= events.len() {
+                break;
+            }
+            if entry.disabled {
+                continue;
+            }
+            // the file may have been closed without `EPOLL_CTL_DEL`
+            let Ok(file) = get_file_like(*infd as c_int) else {
+                continue;
+            };
+
+            // read before the readiness, so events in between are not missed
+            let events_gen = file.poll_generation();
+            let mut ready = 0;
+            match file.poll() {
                 Ok(state) => {
-                    if state.readable && (ev.events & ctypes::EPOLLIN != 0) {
-                        events[events_num].events = ctypes::EPOLLIN;
-                        events[events_num].data = ev.data;
-                        events_num += 1;
+                    if state.readable {
+                        ready |= ctypes::EPOLLIN;
                     }
-
-                    if state.writable && (ev.events & ctypes::EPOLLOUT != 0) {
-                        events[events_num].events = ctypes::EPOLLOUT;
-                        events[events_num].data = ev.data;
-                        events_num += 1;
+                    if state.writable {
+                        ready |= ctypes::EPOLLOUT;
                     }
                 }
+                Err(_) => ready |= ctypes::EPOLLERR,
+            }
+            if let Ok(hup) = file.poll_hup() {
+                if hup.read_hup {
+                    ready |= ctypes::EPOLLRDHUP;
+                }
+                if hup.hup {
+                    ready |= ctypes::EPOLLHUP;
+                }
+                if hup.err {
+                    ready |= ctypes::EPOLLERR;
+                }
+            }
+
+            let ready = entry.report(ready, events_gen);
+            if ready != 0 {
+                events[events_num].events = ready;
+                events[events_num].data = entry.event.data;
+                events_num += 1;
             }
         }
-        Ok(events_num)
+        events_num
     }
 }
 
@@ -160,9 +233,8 @@ pub unsafe fn sys_epoll_ctl(
 ) -> c_int {
     debug!("sys_epoll_ctl <= epfd: {} op: {} fd: {}", epfd, op, fd);
     syscall_body!(sys_epoll_ctl, {
-        let ret = unsafe {
-            EpollInstance::from_fd(epfd)?.control(op as usize, fd as usize, &(*event))? as c_int
-        };
+        let event = unsafe { event.as_ref() };
+        let ret = EpollInstance::from_fd(epfd)?.control(op as usize, fd as usize, event)? as c_int;
         Ok(ret)
     })
 }
@@ -188,9 +260,9 @@ pub unsafe fn sys_epoll_wait(
             (!timeout.is_negative()).then(|| wall_time() + Duration::from_millis(timeout as u64));
         let epoll_instance = EpollInstance::from_fd(epfd)?;
         loop {
-            #[cfg(feature = "net")]
-            axnet::poll_interfaces();
-            let events_num = epoll_instance.poll_all(events)?;
+            let events_gen = poll_events();
+            poll_interfaces();
+            let events_num = epoll_instance.poll_all(events);
             if events_num > 0 {
                 return Ok(events_num as c_int);
             }
@@ -199,7 +271,7 @@ pub unsafe fn sys_epoll_wait(
                 debug!("    timeout!");
                 return Ok(0);
             }
-            crate::sys_sched_yield();
+            wait_events(events_gen, deadline);
         }
     })
 }
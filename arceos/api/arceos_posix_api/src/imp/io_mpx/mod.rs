@@ -10,7 +10,77 @@ mod epoll;
 #[cfg(feature = "select")]
 mod select;
 
+#[cfg(all(feature = "net", feature = "multitask"))]
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use axhal::time::wall_time;
+
+use super::fd_ops::poll_wait;
+
 #[cfg(feature = "epoll")]
 pub use self::epoll::{sys_epoll_create, sys_epoll_ctl, sys_epoll_wait};
 #[cfg(feature = "select")]
 pub use self::select::sys_select;
+
+/// Polls the network interfaces, and notifies the waiters if the readiness
+/// of some sockets may have changed.
+fn poll_interfaces() {
+    #[cfg(feature = "net")]
+    if axnet::poll_interfaces() {
+        super::fd_ops::poll_notify();
+    }
+}
+
+/// Blocks until the readiness of some file may have changed since `events`
+/// was returned by [`poll_events`](super::fd_ops::poll_events), or the
+/// `deadline` is reached.
+fn wait_events(events: usize, deadline: Option<Duration>) {
+    #[cfg(all(feature = "net", feature = "multitask"))]
+    {
+        NET_WAITERS.fetch_add(1, Ordering::SeqCst);
+        start_net_poller();
+    }
+    poll_wait(events, deadline.map(|ddl| ddl.saturating_sub(wall_time())));
+    #[cfg(all(feature = "net", feature = "multitask"))]
+    NET_WAITERS.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// Number of tasks blocked in [`wait_events`].
+#[cfg(all(feature = "net", feature = "multitask"))]
+static NET_WAITERS: AtomicUsize = AtomicUsize::new(0);
+/// Whether the task started by [`start_net_poller`] is running.
+#[cfg(all(feature = "net", feature = "multitask"))]
+static NET_POLLER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Starts a task that polls the network interfaces once per tick, as the
+/// network stack is not interrupt driven, to wake up the tasks waiting for
+/// sockets to become ready.
+///
+/// The task exits once no task is waiting, and is started again by the next
+/// waiter.
+#[cfg(all(feature = "net", feature = "multitask"))]
+fn start_net_poller() {
+    if NET_POLLER_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    axtask::spawn(|| loop {
+        if NET_WAITERS.load(Ordering::SeqCst) == 0 {
+            NET_POLLER_RUNNING.store(false, Ordering::SeqCst);
+            // A waiter that came before the flag was cleared did not start
+            // another poller, keep running for it.
+            if NET_WAITERS.load(Ordering::SeqCst) == 0
+                || NET_POLLER_RUNNING.swap(true, Ordering::SeqCst)
+            {
+                break;
+            }
+        }
+        poll_interfaces();
+        #[cfg(feature = "irq")]
+        axtask::sleep(Duration::from_nanos(
+            axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64,
+        ));
+        #[cfg(not(feature = "irq"))]
+        axtask::yield_now();
+    });
+}
@@ -3,7 +3,11 @@ use core::ffi::c_int;
 use axerrno::{LinuxError, LinuxResult};
 use axhal::time::wall_time;
 
-use crate::{ctypes, imp::fd_ops::get_file_like};
+use super::{poll_interfaces, wait_events};
+use crate::{
+    ctypes,
+    imp::fd_ops::{get_file_like, poll_events},
+};
 
 const FD_SETSIZE: usize = 1024;
 const BITS_PER_USIZE: usize = usize::BITS as usize;
@@ -134,8 +138,8 @@ pub unsafe fn sys_select(
         }
 
         loop {
-            #[cfg(feature = "net")]
-            axnet::poll_interfaces();
+            let events = poll_events();
+            poll_interfaces();
             let res = fd_sets.poll_all(readfds, writefds, exceptfds)?;
             if res > 0 {
                 return Ok(res);
@@ -145,7 +149,7 @@ pub unsafe fn sys_select(
                 debug!("    timeout!");
                 return Ok(0);
             }
-            crate::sys_sched_yield();
+            wait_events(events, deadline);
         }
     })
 }
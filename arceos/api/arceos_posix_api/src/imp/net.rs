@@ -2,37 +2,54 @@ use alloc::{sync::Arc, vec, vec::Vec};
 use core::ffi::{c_char, c_int, c_void};
 use core::mem::size_of;
 use core::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 
 use axerrno::{LinuxError, LinuxResult};
 use axio::PollState;
-use axnet::{TcpSocket, UdpSocket};
+use axnet::{IcmpSocket, RawSocket, TcpSocket, UdpSocket};
 use axsync::Mutex;
 
-use super::fd_ops::FileLike;
+use super::fd_ops::{
+    add_file_like, close_file_like, get_file_like, poll_notify, FileLike, HangUpState,
+};
+use super::unix::{UnixAddr, UnixSocket, UnixSocketType};
 use crate::ctypes;
 use crate::utils::char_ptr_to_str;
 
 pub enum Socket {
     Udp(Mutex<UdpSocket>),
     Tcp(Mutex<TcpSocket>),
+    Icmp(Mutex<IcmpSocket>),
+    Raw(Mutex<RawSocket>),
+    Unix(UnixSocket),
 }
 
 impl Socket {
     fn add_to_fd_table(self) -> LinuxResult<c_int> {
-        super::fd_ops::add_file_like(Arc::new(self))
+        add_file_like(Arc::new(SocketFile::new(self)))
     }
 
-    fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>> {
-        let f = super::fd_ops::get_file_like(fd)?;
+    fn from_fd(fd: c_int) -> LinuxResult<Arc<SocketFile>> {
+        let f = get_file_like(fd)?;
         f.into_any()
-            .downcast::<Self>()
+            .downcast::<SocketFile>()
             .map_err(|_| LinuxError::EINVAL)
     }
 
+    fn as_unix(&self) -> Option<&UnixSocket> {
+        match self {
+            Socket::Unix(unixsocket) => Some(unixsocket),
+            _ => None,
+        }
+    }
+
     fn send(&self, buf: &[u8]) -> LinuxResult<usize> {
         match self {
             Socket::Udp(udpsocket) => Ok(udpsocket.lock().send(buf)?),
             Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().send(buf)?),
+            Socket::Icmp(_) | Socket::Raw(_) => Err(LinuxError::EDESTADDRREQ),
+            Socket::Unix(unixsocket) => unixsocket.send(buf),
         }
     }
 
@@ -40,6 +57,9 @@ impl Socket {
         match self {
             Socket::Udp(udpsocket) => Ok(udpsocket.lock().recv_from(buf).map(|e| e.0)?),
             Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().recv(buf)?),
+            Socket::Icmp(icmpsocket) => Ok(icmpsocket.lock().recv_from(buf).map(|e| e.0)?),
+            Socket::Raw(rawsocket) => Ok(rawsocket.lock().recv_from(buf).map(|e| e.0)?),
+            Socket::Unix(unixsocket) => unixsocket.recv(buf),
         }
     }
 
@@ -47,6 +67,9 @@ impl Socket {
         match self {
             Socket::Udp(udpsocket) => Ok(udpsocket.lock().poll()?),
             Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().poll()?),
+            Socket::Icmp(icmpsocket) => Ok(icmpsocket.lock().poll()?),
+            Socket::Raw(rawsocket) => Ok(rawsocket.lock().poll()?),
+            Socket::Unix(unixsocket) => unixsocket.poll(),
         }
     }
 
@@ -54,6 +77,13 @@ impl Socket {
         match self {
             Socket::Udp(udpsocket) => Ok(udpsocket.lock().local_addr()?),
             Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().local_addr()?),
+            // the ICMP identifier is reported as the port, like Linux ping sockets
+            Socket::Icmp(icmpsocket) => Ok(SocketAddr::new(
+                Ipv4Addr::UNSPECIFIED.into(),
+                icmpsocket.lock().ident()?,
+            )),
+            Socket::Raw(_) => Ok(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)),
+            Socket::Unix(_) => Err(LinuxError::EAFNOSUPPORT),
         }
     }
 
@@ -61,6 +91,8 @@ impl Socket {
         match self {
             Socket::Udp(udpsocket) => Ok(udpsocket.lock().peer_addr()?),
             Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().peer_addr()?),
+            Socket::Icmp(_) | Socket::Raw(_) => Err(LinuxError::ENOTCONN),
+            Socket::Unix(_) => Err(LinuxError::EAFNOSUPPORT),
         }
     }
 
@@ -68,6 +100,11 @@ impl Socket {
         match self {
             Socket::Udp(udpsocket) => Ok(udpsocket.lock().bind(addr)?),
             Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().bind(addr)?),
+            // the port is used as the ICMP identifier, like Linux ping sockets
+            Socket::Icmp(icmpsocket) => Ok(icmpsocket.lock().bind(addr.port())?),
+            // diff: the local address is not used to filter received packets
+            Socket::Raw(_) => Ok(()),
+            Socket::Unix(_) => Err(LinuxError::EAFNOSUPPORT),
         }
     }
 
@@ -75,6 +112,8 @@ impl Socket {
         match self {
             Socket::Udp(udpsocket) => Ok(udpsocket.lock().connect(addr)?),
             Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().connect(addr)?),
+            Socket::Icmp(_) | Socket::Raw(_) => Err(LinuxError::EOPNOTSUPP),
+            Socket::Unix(_) => Err(LinuxError::EAFNOSUPPORT),
         }
     }
 
@@ -83,6 +122,9 @@ impl Socket {
             // diff: must bind before sendto
             Socket::Udp(udpsocket) => Ok(udpsocket.lock().send_to(buf, addr)?),
             Socket::Tcp(_) => Err(LinuxError::EISCONN),
+            Socket::Icmp(icmpsocket) => Ok(icmpsocket.lock().send_to(buf, addr.ip())?),
+            Socket::Raw(rawsocket) => Ok(rawsocket.lock().send_to(buf, addr.ip())?),
+            Socket::Unix(_) => Err(LinuxError::EAFNOSUPPORT),
         }
     }
 
@@ -94,20 +136,31 @@ impl Socket {
                 .recv_from(buf)
                 .map(|res| (res.0, Some(res.1)))?),
             Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().recv(buf).map(|res| (res, None))?),
+            Socket::Icmp(icmpsocket) => Ok(icmpsocket
+                .lock()
+                .recv_from(buf)
+                .map(|res| (res.0, Some(SocketAddr::new(res.1, 0))))?),
+            Socket::Raw(rawsocket) => Ok(rawsocket
+                .lock()
+                .recv_from(buf)
+                .map(|res| (res.0, Some(SocketAddr::new(res.1, 0))))?),
+            Socket::Unix(unixsocket) => Ok((unixsocket.recv(buf)?, None)),
         }
     }
 
-    fn listen(&self) -> LinuxResult {
+    fn listen(&self, backlog: usize) -> LinuxResult {
         match self {
-            Socket::Udp(_) => Err(LinuxError::EOPNOTSUPP),
+            Socket::Udp(_) | Socket::Icmp(_) | Socket::Raw(_) => Err(LinuxError::EOPNOTSUPP),
             Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().listen()?),
+            Socket::Unix(unixsocket) => unixsocket.listen(backlog),
         }
     }
 
-    fn accept(&self) -> LinuxResult<TcpSocket> {
+    fn accept(&self) -> LinuxResult<Socket> {
         match self {
-            Socket::Udp(_) => Err(LinuxError::EOPNOTSUPP),
-            Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().accept()?),
+            Socket::Udp(_) | Socket::Icmp(_) | Socket::Raw(_) => Err(LinuxError::EOPNOTSUPP),
+            Socket::Tcp(tcpsocket) => Ok(Socket::Tcp(Mutex::new(tcpsocket.lock().accept()?))),
+            Socket::Unix(unixsocket) => Ok(Socket::Unix(unixsocket.accept()?)),
         }
     }
 
@@ -126,11 +179,90 @@ impl Socket {
                 tcpsocket.shutdown()?;
                 Ok(())
             }
+
+            Socket::Icmp(_) | Socket::Raw(_) => Err(LinuxError::ENOTCONN),
+            Socket::Unix(unixsocket) => unixsocket.shutdown(),
         }
     }
 }
 
-impl FileLike for Socket {
+/// The socket was readable when last polled.
+const SEEN_READABLE: u8 = 1 << 0;
+/// The socket was writable when last polled.
+const SEEN_WRITABLE: u8 = 1 << 1;
+
+/// A socket in the file descriptor table.
+///
+/// The network stack does not report the events on Internet sockets, so the
+/// readiness is tracked here instead to detect edges for `EPOLLET`: a new
+/// event is counted when the socket is seen ready in a direction it was not
+/// ready in at the last check, or that data has been transferred in since.
+/// A readable socket also counts an event when frames have been received
+/// from the NIC since the last check, as they may carry more data for it.
+pub struct SocketFile {
+    socket: Socket,
+    /// `SEEN_READABLE` and `SEEN_WRITABLE` bits.
+    seen: AtomicU8,
+    /// Value of [`axnet::rx_frames`] at the last check.
+    rx_frames: AtomicUsize,
+    /// Number of times the socket was seen becoming ready.
+    events: AtomicUsize,
+}
+
+impl SocketFile {
+    fn new(socket: Socket) -> Self {
+        Self {
+            socket,
+            seen: AtomicU8::new(0),
+            rx_frames: AtomicUsize::new(0),
+            events: AtomicUsize::new(0),
+        }
+    }
+
+    /// Transfers data in the direction of `seen` by `f`.
+    ///
+    /// It forgets that the socket was seen ready in that direction, so it is
+    /// counted as a new event if it is still ready on the next poll. Waiters
+    /// are notified on success, as the readiness of the peer may change.
+    fn transfer<T>(&self, seen: u8, f: impl FnOnce(&Socket) -> LinuxResult<T>) -> LinuxResult<T> {
+        self.seen.fetch_and(!seen, Ordering::AcqRel);
+        let res = f(&self.socket);
+        if res.is_ok() {
+            poll_notify();
+        }
+        res
+    }
+
+    fn send(&self, buf: &[u8]) -> LinuxResult<usize> {
+        self.transfer(SEEN_WRITABLE, |socket| socket.send(buf))
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        self.transfer(SEEN_READABLE, |socket| socket.recv(buf))
+    }
+
+    fn sendto(&self, buf: &[u8], addr: SocketAddr) -> LinuxResult<usize> {
+        self.transfer(SEEN_WRITABLE, |socket| socket.sendto(buf, addr))
+    }
+
+    fn recvfrom(&self, buf: &mut [u8]) -> LinuxResult<(usize, Option<SocketAddr>)> {
+        self.transfer(SEEN_READABLE, |socket| socket.recvfrom(buf))
+    }
+
+    fn accept(&self) -> LinuxResult<Socket> {
+        self.transfer(SEEN_READABLE, |socket| socket.accept())
+    }
+}
+
+impl Deref for SocketFile {
+    type Target = Socket;
+
+    fn deref(&self) -> &Socket {
+        &self.socket
+    }
+}
+
+impl FileLike for SocketFile {
     fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
         self.recv(buf)
     }
@@ -139,6 +271,21 @@ impl FileLike for Socket {
         self.send(buf)
     }
 
+    fn poll_hup(&self) -> LinuxResult<HangUpState> {
+        match &self.socket {
+            Socket::Tcp(tcpsocket) => {
+                let (read_hup, hup) = tcpsocket.lock().poll_hup()?;
+                Ok(HangUpState {
+                    read_hup,
+                    hup,
+                    err: false,
+                })
+            }
+            Socket::Unix(unixsocket) => Ok(unixsocket.poll_hup()),
+            _ => Ok(HangUpState::default()),
+        }
+    }
+
     fn stat(&self) -> LinuxResult<ctypes::stat> {
         // not really implemented
         let st_mode = 0o140000 | 0o777u32; // S_IFSOCK | rwxrwxrwx
@@ -158,13 +305,39 @@ impl FileLike for Socket {
     }
 
     fn poll(&self) -> LinuxResult<PollState> {
-        self.poll()
+        self.socket.poll()
+    }
+
+    fn poll_generation(&self) -> usize {
+        if let Socket::Unix(unixsocket) = &self.socket {
+            return unixsocket.poll_generation();
+        }
+        let rx_frames = axnet::rx_frames();
+        let received = self.rx_frames.swap(rx_frames, Ordering::AcqRel) != rx_frames;
+        if let Ok(state) = self.socket.poll() {
+            let mut seen = 0;
+            if state.readable {
+                seen |= SEEN_READABLE;
+            }
+            if state.writable {
+                seen |= SEEN_WRITABLE;
+            }
+            let became_ready = seen & !self.seen.swap(seen, Ordering::AcqRel) != 0;
+            if became_ready || (state.readable && received) {
+                self.events.fetch_add(1, Ordering::AcqRel);
+                poll_notify();
+            }
+        }
+        self.events.load(Ordering::Acquire)
     }
 
     fn set_nonblocking(&self, nonblock: bool) -> LinuxResult {
-        match self {
+        match &self.socket {
             Socket::Udp(udpsocket) => udpsocket.lock().set_nonblocking(nonblock),
             Socket::Tcp(tcpsocket) => tcpsocket.lock().set_nonblocking(nonblock),
+            Socket::Icmp(icmpsocket) => icmpsocket.lock().set_nonblocking(nonblock),
+            Socket::Raw(rawsocket) => rawsocket.lock().set_nonblocking(nonblock),
+            Socket::Unix(unixsocket) => unixsocket.set_nonblocking(nonblock),
         }
         Ok(())
     }
@@ -226,6 +399,148 @@ fn from_sockaddr(
     Ok(res)
 }
 
+/// Size of the buffer through which `sendfile` copies the file data.
+#[cfg(feature = "fs")]
+const SENDFILE_BUF_SIZE: usize = 64 * 1024;
+
+/// Maximum number of buffers in an I/O vector, like `UIO_MAXIOV` in Linux.
+const UIO_MAXIOV: usize = 1024;
+
+/// Size of the buffer through which `recvmsg` receives the data, longer
+/// datagrams are truncated.
+const RECVMSG_BUF_SIZE: usize = 64 * 1024;
+
+/// Returns the total length of the buffers of an I/O vector, which must fit in
+/// an `ssize_t`.
+fn iovecs_len(iovs: &[ctypes::iovec]) -> LinuxResult<usize> {
+    iovs.iter()
+        .try_fold(0usize, |sum, iov| sum.checked_add(iov.iov_len))
+        .filter(|&len| len <= isize::MAX as usize)
+        .ok_or(LinuxError::EINVAL)
+}
+
+/// Aligns the length of a control message to the size of `size_t`, like the
+/// `CMSG_ALIGN` macro in C.
+const fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+/// Gathers the data of an I/O vector into a contiguous buffer.
+unsafe fn gather_iovecs(iov: *const ctypes::iovec, iovcnt: usize) -> LinuxResult<Vec<u8>> {
+    if iovcnt > UIO_MAXIOV {
+        return Err(LinuxError::EINVAL);
+    }
+    if iovcnt == 0 {
+        return Ok(Vec::new());
+    }
+    if iov.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    let iovs = core::slice::from_raw_parts(iov, iovcnt);
+    let mut buf = Vec::with_capacity(iovecs_len(iovs)?);
+    for iov in iovs.iter().filter(|iov| iov.iov_len > 0) {
+        buf.extend_from_slice(core::slice::from_raw_parts(
+            iov.iov_base as *const u8,
+            iov.iov_len,
+        ));
+    }
+    Ok(buf)
+}
+
+/// Scatters `data` into the buffers of an I/O vector.
+unsafe fn scatter_iovecs(iov: *const ctypes::iovec, iovcnt: usize, mut data: &[u8]) {
+    if iov.is_null() {
+        return;
+    }
+    for iov in core::slice::from_raw_parts(iov, iovcnt) {
+        if data.is_empty() {
+            break;
+        }
+        let len = iov.iov_len.min(data.len());
+        core::slice::from_raw_parts_mut(iov.iov_base as *mut u8, len).copy_from_slice(&data[..len]);
+        data = &data[len..];
+    }
+}
+
+/// Collects the file descriptors passed in the `SCM_RIGHTS` control messages.
+unsafe fn parse_rights(msg: &ctypes::msghdr) -> LinuxResult<Vec<Arc<dyn FileLike>>> {
+    let mut rights = Vec::new();
+    if msg.msg_control.is_null() {
+        return Ok(rights);
+    }
+    let control =
+        core::slice::from_raw_parts(msg.msg_control as *const u8, msg.msg_controllen as usize);
+    let hdr_len = cmsg_align(size_of::<ctypes::cmsghdr>());
+    let mut offset = 0;
+    while offset + size_of::<ctypes::cmsghdr>() <= control.len() {
+        let cmsg = (control.as_ptr().add(offset) as *const ctypes::cmsghdr).read_unaligned();
+        let cmsg_len = cmsg.cmsg_len as usize;
+        if cmsg_len < hdr_len || offset + cmsg_len > control.len() {
+            return Err(LinuxError::EINVAL);
+        }
+        if cmsg.cmsg_level == ctypes::SOL_SOCKET as c_int
+            && cmsg.cmsg_type == ctypes::SCM_RIGHTS as c_int
+        {
+            let data = &control[offset + hdr_len..offset + cmsg_len];
+            for fd in data.chunks_exact(size_of::<c_int>()) {
+                let fd = c_int::from_ne_bytes(fd.try_into().unwrap());
+                rights.push(get_file_like(fd)?);
+            }
+        }
+        offset += cmsg_align(cmsg_len);
+    }
+    Ok(rights)
+}
+
+/// Installs the received files into the fd table, and reports their file
+/// descriptors in a `SCM_RIGHTS` control message.
+///
+/// Files that do not fit in the control buffer are dropped and `MSG_CTRUNC`
+/// is set, as Linux does.
+unsafe fn put_rights(msg: &mut ctypes::msghdr, rights: Vec<Arc<dyn FileLike>>) -> LinuxResult {
+    let hdr_len = cmsg_align(size_of::<ctypes::cmsghdr>());
+    let max_fds = if msg.msg_control.is_null() {
+        0
+    } else {
+        (msg.msg_controllen as usize).saturating_sub(hdr_len) / size_of::<c_int>()
+    };
+    let nfds = rights.len().min(max_fds);
+    if nfds < rights.len() {
+        msg.msg_flags |= ctypes::MSG_CTRUNC as c_int;
+    }
+    if nfds == 0 {
+        msg.msg_controllen = 0;
+        return Ok(());
+    }
+
+    let mut fds = Vec::with_capacity(nfds);
+    for f in rights.into_iter().take(nfds) {
+        match add_file_like(f) {
+            Ok(fd) => fds.push(fd),
+            Err(e) => {
+                for fd in fds {
+                    close_file_like(fd).ok();
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    let cmsg_len = hdr_len + nfds * size_of::<c_int>();
+    (msg.msg_control as *mut ctypes::cmsghdr).write_unaligned(ctypes::cmsghdr {
+        cmsg_len: cmsg_len as _,
+        cmsg_level: ctypes::SOL_SOCKET as _,
+        cmsg_type: ctypes::SCM_RIGHTS as _,
+        ..Default::default()
+    });
+    let data = (msg.msg_control as *mut u8).add(hdr_len) as *mut c_int;
+    for (i, fd) in fds.into_iter().enumerate() {
+        data.add(i).write_unaligned(fd);
+    }
+    msg.msg_controllen = cmsg_len as _;
+    Ok(())
+}
+
 /// Create an socket for communication.
 ///
 /// Return the socket file descriptor.
@@ -242,6 +557,18 @@ pub fn sys_socket(domain: c_int, socktype: c_int, protocol: c_int) -> c_int {
             | (ctypes::AF_INET, ctypes::SOCK_DGRAM, 0) => {
                 Socket::Udp(Mutex::new(UdpSocket::new())).add_to_fd_table()
             }
+            (ctypes::AF_INET, ctypes::SOCK_DGRAM, ctypes::IPPROTO_ICMP) => {
+                Socket::Icmp(Mutex::new(IcmpSocket::new())).add_to_fd_table()
+            }
+            (ctypes::AF_INET, ctypes::SOCK_RAW, protocol) if (1..=255).contains(&protocol) => {
+                Socket::Raw(Mutex::new(RawSocket::new(protocol as u8))).add_to_fd_table()
+            }
+            (ctypes::AF_UNIX, ctypes::SOCK_STREAM, 0) => {
+                Socket::Unix(UnixSocket::new(UnixSocketType::Stream)).add_to_fd_table()
+            }
+            (ctypes::AF_UNIX, ctypes::SOCK_DGRAM, 0) => {
+                Socket::Unix(UnixSocket::new(UnixSocketType::Datagram)).add_to_fd_table()
+            }
             _ => Err(LinuxError::EINVAL),
         }
     })
@@ -260,8 +587,11 @@ pub fn sys_bind(
         socket_fd, socket_addr as usize, addrlen
     );
     syscall_body!(sys_bind, {
-        let addr = from_sockaddr(socket_addr, addrlen)?;
-        Socket::from_fd(socket_fd)?.bind(addr)?;
+        let socket = Socket::from_fd(socket_fd)?;
+        match socket.as_unix() {
+            Some(unixsocket) => unixsocket.bind(UnixAddr::from_raw(socket_addr, addrlen)?)?,
+            None => socket.bind(from_sockaddr(socket_addr, addrlen)?)?,
+        }
         Ok(0)
     })
 }
@@ -279,8 +609,11 @@ pub fn sys_connect(
         socket_fd, socket_addr as usize, addrlen
     );
     syscall_body!(sys_connect, {
-        let addr = from_sockaddr(socket_addr, addrlen)?;
-        Socket::from_fd(socket_fd)?.connect(addr)?;
+        let socket = Socket::from_fd(socket_fd)?;
+        match socket.as_unix() {
+            Some(unixsocket) => unixsocket.connect(UnixAddr::from_raw(socket_addr, addrlen)?)?,
+            None => socket.connect(from_sockaddr(socket_addr, addrlen)?)?,
+        }
         Ok(0)
     })
 }
@@ -304,9 +637,15 @@ pub fn sys_sendto(
         if buf_ptr.is_null() {
             return Err(LinuxError::EFAULT);
         }
-        let addr = from_sockaddr(socket_addr, addrlen)?;
         let buf = unsafe { core::slice::from_raw_parts(buf_ptr as *const u8, len) };
-        Socket::from_fd(socket_fd)?.sendto(buf, addr)
+        let socket = Socket::from_fd(socket_fd)?;
+        match socket.as_unix() {
+            Some(unixsocket) => {
+                let addr = UnixAddr::from_raw(socket_addr, addrlen)?;
+                unixsocket.sendmsg(buf, Some(addr), Vec::new())
+            }
+            None => socket.sendto(buf, from_sockaddr(socket_addr, addrlen)?),
+        }
     })
 }
 
@@ -354,6 +693,11 @@ pub unsafe fn sys_recvfrom(
         let socket = Socket::from_fd(socket_fd)?;
         let buf = unsafe { core::slice::from_raw_parts_mut(buf_ptr as *mut u8, len) };
 
+        if let Some(unixsocket) = socket.as_unix() {
+            let msg = unixsocket.recvmsg(buf)?;
+            unsafe { msg.from.write_to(socket_addr, addrlen)? };
+            return Ok(msg.len);
+        }
         let res = socket.recvfrom(buf)?;
         if let Some(addr) = res.1 {
             unsafe {
@@ -391,11 +735,11 @@ pub fn sys_recv(
 /// Return 0 if success.
 pub fn sys_listen(
     socket_fd: c_int,
-    backlog: c_int, // currently not used
+    backlog: c_int, // only used by Unix domain sockets
 ) -> c_int {
     debug!("sys_listen <= {} {}", socket_fd, backlog);
     syscall_body!(sys_listen, {
-        Socket::from_fd(socket_fd)?.listen()?;
+        Socket::from_fd(socket_fd)?.listen(backlog.max(0) as usize)?;
         Ok(0)
     })
 }
@@ -418,8 +762,14 @@ pub unsafe fn sys_accept(
         }
         let socket = Socket::from_fd(socket_fd)?;
         let new_socket = socket.accept()?;
+        if let Some(unixsocket) = new_socket.as_unix() {
+            let addr = unixsocket.peer_addr()?;
+            let new_fd = Socket::add_to_fd_table(new_socket)?;
+            unsafe { addr.write_to(socket_addr, socket_len)? };
+            return Ok(new_fd);
+        }
         let addr = new_socket.peer_addr()?;
-        let new_fd = Socket::add_to_fd_table(Socket::Tcp(Mutex::new(new_socket)))?;
+        let new_fd = Socket::add_to_fd_table(new_socket)?;
         unsafe {
             (*socket_addr, *socket_len) = into_sockaddr(addr);
         }
@@ -441,6 +791,227 @@ pub fn sys_shutdown(
     })
 }
 
+/// Transfer data from the file `in_fd` to the socket `out_fd`.
+///
+/// This is not zero-copy: the file data is copied through a kernel buffer of
+/// 64 KiB, as the network stack only sends from its own socket buffers. It
+/// still saves the copies through a user buffer.
+///
+/// If `offset` is not null, the file is read from `*offset`, which is then
+/// updated, and the file offset is left unchanged. Otherwise the file is read
+/// from the file offset. Either offset is left after the last byte sent.
+///
+/// Return the number of bytes sent if success.
+#[cfg(feature = "fs")]
+pub unsafe fn sys_sendfile(
+    out_fd: c_int,
+    in_fd: c_int,
+    offset: *mut ctypes::off_t,
+    count: ctypes::size_t,
+) -> ctypes::ssize_t {
+    debug!(
+        "sys_sendfile <= {} {} {:#x} {}",
+        out_fd, in_fd, offset as usize, count
+    );
+    syscall_body!(sys_sendfile, {
+        let file = super::fs::File::from_fd(in_fd)?;
+        let socket = Socket::from_fd(out_fd)?;
+        let mut pos = match unsafe { offset.as_ref() } {
+            Some(&off) if off < 0 => return Err(LinuxError::EINVAL),
+            Some(&off) => Some(off as u64),
+            None => None,
+        };
+
+        // The file is read outside of the socket (and the network stack)
+        // locks, so a slow read does not stall other sockets.
+        let mut buf = vec![0; count.min(SENDFILE_BUF_SIZE)];
+        let mut sent = 0;
+        let res = 'copy: loop {
+            if sent == count {
+                break Ok(());
+            }
+            let len = buf.len().min(count - sent);
+            let read = {
+                let mut file = file.inner().lock();
+                match pos.as_mut() {
+                    Some(off) => file
+                        .read_at(*off, &mut buf[..len])
+                        .inspect(|&n| *off += n as u64),
+                    None => file.read(&mut buf[..len]),
+                }
+            };
+            let n = match read {
+                Ok(0) => break Ok(()),
+                Ok(n) => n,
+                Err(e) => break Err(e.into()),
+            };
+            let mut written = 0;
+            while written < n {
+                match socket.send(&buf[written..n]) {
+                    Ok(m) => written += m,
+                    Err(e) => {
+                        // Leave the offset after the last byte sent.
+                        let unsent = (n - written) as u64;
+                        match pos.as_mut() {
+                            Some(off) => *off -= unsent,
+                            None => {
+                                // The data was just read, so this cannot fail.
+                                let _ = file
+                                    .inner()
+                                    .lock()
+                                    .seek(axio::SeekFrom::Current(-(unsent as i64)));
+                            }
+                        }
+                        sent += written;
+                        break 'copy Err(e);
+                    }
+                }
+            }
+            sent += n;
+        };
+
+        if let Some(pos) = pos {
+            unsafe { *offset = pos as _ };
+        }
+        match res {
+            Err(e) if sent == 0 => Err(e),
+            _ => Ok(sent),
+        }
+    })
+}
+
+/// Create a pair of connected sockets.
+///
+/// Only `AF_UNIX` sockets are supported.
+pub fn sys_socketpair(domain: c_int, socktype: c_int, protocol: c_int, sv: &mut [c_int]) -> c_int {
+    debug!(
+        "sys_socketpair <= {} {} {} {:#x}",
+        domain,
+        socktype,
+        protocol,
+        sv.as_ptr() as usize
+    );
+    let (domain, socktype, protocol) = (domain as u32, socktype as u32, protocol as u32);
+    syscall_body!(sys_socketpair, {
+        if sv.len() != 2 {
+            return Err(LinuxError::EFAULT);
+        }
+        let ty = match (domain, socktype, protocol) {
+            (ctypes::AF_UNIX, ctypes::SOCK_STREAM, 0) => UnixSocketType::Stream,
+            (ctypes::AF_UNIX, ctypes::SOCK_DGRAM, 0) => UnixSocketType::Datagram,
+            (ctypes::AF_UNIX, _, _) => return Err(LinuxError::EPROTONOSUPPORT),
+            _ => return Err(LinuxError::EOPNOTSUPP),
+        };
+
+        let (sock0, sock1) = UnixSocket::new_pair(ty);
+        let fd0 = Socket::Unix(sock0).add_to_fd_table()?;
+        let fd1 = Socket::Unix(sock1).add_to_fd_table().inspect_err(|_| {
+            close_file_like(fd0).ok();
+        })?;
+
+        sv[0] = fd0;
+        sv[1] = fd1;
+
+        Ok(0)
+    })
+}
+
+/// Send a message on a socket, gathered from the I/O vector of `msg`.
+///
+/// For Unix domain sockets, file descriptors can be passed along with
+/// `SCM_RIGHTS` control messages.
+///
+/// Return the number of bytes sent if success.
+pub unsafe fn sys_sendmsg(
+    socket_fd: c_int,
+    msg: *const ctypes::msghdr,
+    flags: c_int, // currently not used
+) -> ctypes::ssize_t {
+    debug!("sys_sendmsg <= {} {:#x} {}", socket_fd, msg as usize, flags);
+    syscall_body!(sys_sendmsg, {
+        if msg.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let msg = unsafe { &*msg };
+        let buf = unsafe { gather_iovecs(msg.msg_iov, msg.msg_iovlen as usize)? };
+        let socket = Socket::from_fd(socket_fd)?;
+        if let Some(unixsocket) = socket.as_unix() {
+            let addr = if msg.msg_name.is_null() {
+                None
+            } else {
+                Some(UnixAddr::from_raw(msg.msg_name as _, msg.msg_namelen)?)
+            };
+            let rights = unsafe { parse_rights(msg)? };
+            return unixsocket.sendmsg(&buf, addr, rights);
+        }
+        if msg.msg_name.is_null() {
+            socket.send(&buf)
+        } else {
+            socket.sendto(&buf, from_sockaddr(msg.msg_name as _, msg.msg_namelen)?)
+        }
+    })
+}
+
+/// Receive a message on a socket, scattered into the I/O vector of `msg`.
+///
+/// For Unix domain sockets, file descriptors passed by the sender are
+/// installed and returned in a `SCM_RIGHTS` control message.
+///
+/// Return the number of bytes received if success.
+pub unsafe fn sys_recvmsg(
+    socket_fd: c_int,
+    msg: *mut ctypes::msghdr,
+    flags: c_int, // currently not used
+) -> ctypes::ssize_t {
+    debug!("sys_recvmsg <= {} {:#x} {}", socket_fd, msg as usize, flags);
+    syscall_body!(sys_recvmsg, {
+        if msg.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let msg = unsafe { &mut *msg };
+        let iovcnt = msg.msg_iovlen as usize;
+        if iovcnt > UIO_MAXIOV {
+            return Err(LinuxError::EINVAL);
+        }
+        let total_len = match iovcnt {
+            0 => 0,
+            _ if msg.msg_iov.is_null() => return Err(LinuxError::EFAULT),
+            _ => iovecs_len(unsafe { core::slice::from_raw_parts(msg.msg_iov, iovcnt) })?,
+        };
+        let mut buf = vec![0; total_len.min(RECVMSG_BUF_SIZE)];
+        msg.msg_flags = 0;
+
+        let socket = Socket::from_fd(socket_fd)?;
+        let len = if let Some(unixsocket) = socket.as_unix() {
+            let res = unixsocket.recvmsg(&mut buf)?;
+            if msg.msg_name.is_null() {
+                msg.msg_namelen = 0;
+            } else {
+                unsafe { res.from.write_to(msg.msg_name as _, &mut msg.msg_namelen)? };
+            }
+            if res.truncated {
+                msg.msg_flags |= ctypes::MSG_TRUNC as c_int;
+            }
+            unsafe { put_rights(msg, res.rights)? };
+            res.len
+        } else {
+            let (len, addr) = socket.recvfrom(&mut buf)?;
+            let name_fits = msg.msg_namelen >= size_of::<ctypes::sockaddr>() as _;
+            match addr {
+                Some(addr) if !msg.msg_name.is_null() && name_fits => {
+                    let name = msg.msg_name as *mut ctypes::sockaddr;
+                    unsafe { (*name, msg.msg_namelen) = into_sockaddr(addr) };
+                }
+                _ => msg.msg_namelen = 0,
+            }
+            msg.msg_controllen = 0;
+            len
+        };
+        unsafe { scatter_iovecs(msg.msg_iov, iovcnt, &buf[..len]) };
+        Ok(len)
+    })
+}
+
 /// Query addresses for a domain name.
 ///
 /// Only IPv4. Ports are always 0. Ignore servname and hint.
@@ -546,11 +1117,16 @@ pub unsafe fn sys_getsockname(
         if addr.is_null() || addrlen.is_null() {
             return Err(LinuxError::EFAULT);
         }
+        let socket = Socket::from_fd(sock_fd)?;
+        if let Some(unixsocket) = socket.as_unix() {
+            unsafe { unixsocket.local_addr().write_to(addr, addrlen)? };
+            return Ok(0);
+        }
         if unsafe { *addrlen } < size_of::<ctypes::sockaddr>() as u32 {
             return Err(LinuxError::EINVAL);
         }
         unsafe {
-            (*addr, *addrlen) = into_sockaddr(Socket::from_fd(sock_fd)?.local_addr()?);
+            (*addr, *addrlen) = into_sockaddr(socket.local_addr()?);
         }
         Ok(0)
     })
@@ -570,11 +1146,16 @@ pub unsafe fn sys_getpeername(
         if addr.is_null() || addrlen.is_null() {
             return Err(LinuxError::EFAULT);
         }
+        let socket = Socket::from_fd(sock_fd)?;
+        if let Some(unixsocket) = socket.as_unix() {
+            unsafe { unixsocket.peer_addr()?.write_to(addr, addrlen)? };
+            return Ok(0);
+        }
         if unsafe { *addrlen } < size_of::<ctypes::sockaddr>() as u32 {
             return Err(LinuxError::EINVAL);
         }
         unsafe {
-            (*addr, *addrlen) = into_sockaddr(Socket::from_fd(sock_fd)?.peer_addr()?);
+            (*addr, *addrlen) = into_sockaddr(socket.peer_addr()?);
         }
         Ok(0)
     })
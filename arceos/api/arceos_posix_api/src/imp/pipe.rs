@@ -5,7 +5,8 @@ use axerrno::{LinuxError, LinuxResult};
 use axio::PollState;
 use axsync::Mutex;
 
-use super::fd_ops::{add_file_like, close_file_like, FileLike};
+use super::fd_ops::{add_file_like, close_file_like, poll_events, poll_notify, poll_wait};
+use super::fd_ops::{FileLike, HangUpState};
 use crate::ctypes;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -22,6 +23,9 @@ pub struct PipeRingBuffer {
     head: usize,
     tail: usize,
     status: RingBufferStatus,
+    end_closed: bool,
+    /// Number of reads, writes and closes so far.
+    events: usize,
 }
 
 impl PipeRingBuffer {
@@ -31,6 +35,8 @@ impl PipeRingBuffer {
             head: 0,
             tail: 0,
             status: RingBufferStatus::Empty,
+            end_closed: false,
+            events: 0,
         }
     }
 
@@ -102,7 +108,7 @@ impl Pipe {
     }
 
     pub fn write_end_close(&self) -> bool {
-        Arc::strong_count(&self.buffer) == 1
+        Arc::strong_count(&self.buffer) == 1 || self.buffer.lock().end_closed
     }
 }
 
@@ -114,24 +120,32 @@ impl FileLike for Pipe {
         let mut read_size = 0usize;
         let max_len = buf.len();
         loop {
+            let events = poll_events();
             let mut ring_buffer = self.buffer.lock();
             let loop_read = ring_buffer.available_read();
             if loop_read == 0 {
-                if self.write_end_close() {
+                let closed = ring_buffer.end_closed;
+                drop(ring_buffer);
+                if closed || self.write_end_close() {
                     return Ok(read_size);
                 }
-                drop(ring_buffer);
                 // Data not ready, wait for write end
-                crate::sys_sched_yield(); // TODO: use synconize primitive
+                poll_wait(events, None);
                 continue;
             }
             for _ in 0..loop_read {
                 if read_size == max_len {
-                    return Ok(read_size);
+                    break;
                 }
                 buf[read_size] = ring_buffer.read_byte();
                 read_size += 1;
             }
+            ring_buffer.events += 1;
+            drop(ring_buffer);
+            poll_notify();
+            if read_size == max_len {
+                return Ok(read_size);
+            }
         }
     }
 
@@ -142,21 +156,32 @@ impl FileLike for Pipe {
         let mut write_size = 0usize;
         let max_len = buf.len();
         loop {
+            let events = poll_events();
             let mut ring_buffer = self.buffer.lock();
+            if ring_buffer.end_closed || Arc::strong_count(&self.buffer) == 1 {
+                // The read end is closed
+                return Err(LinuxError::EPIPE);
+            }
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
                 drop(ring_buffer);
                 // Buffer is full, wait for read end to consume
-                crate::sys_sched_yield(); // TODO: use synconize primitive
+                poll_wait(events, None);
                 continue;
             }
             for _ in 0..loop_write {
                 if write_size == max_len {
-                    return Ok(write_size);
+                    break;
                 }
                 ring_buffer.write_byte(buf[write_size]);
                 write_size += 1;
             }
+            ring_buffer.events += 1;
+            drop(ring_buffer);
+            poll_notify();
+            if write_size == max_len {
+                return Ok(write_size);
+            }
         }
     }
 
@@ -185,11 +210,36 @@ impl FileLike for Pipe {
         })
     }
 
+    fn poll_hup(&self) -> LinuxResult<HangUpState> {
+        let closed = Arc::strong_count(&self.buffer) == 1 || self.buffer.lock().end_closed;
+        // Like Linux, the read end reports a hang-up, and the write end an
+        // error.
+        Ok(HangUpState {
+            read_hup: self.readable() && closed,
+            hup: self.readable() && closed,
+            err: self.writable() && closed,
+        })
+    }
+
+    fn poll_generation(&self) -> usize {
+        self.buffer.lock().events
+    }
+
     fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
         Ok(())
     }
 }
 
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut ring_buffer = self.buffer.lock();
+        ring_buffer.end_closed = true;
+        ring_buffer.events += 1;
+        drop(ring_buffer);
+        poll_notify();
+    }
+}
+
 /// Create a pipe
 ///
 /// Return 0 if succeed
@@ -0,0 +1,794 @@
+//! Unix domain sockets (`AF_UNIX`).
+//!
+//! Both stream and datagram sockets are supported. A socket can be bound to a
+//! path, which is also created as a file when the `fs` feature is enabled, or
+//! to a name in the abstract namespace (a `sun_path` starting with `'\0'`).
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsync::Mutex;
+
+use super::fd_ops::{poll_events, poll_notify, poll_wait, FileLike, HangUpState};
+use crate::ctypes;
+
+const UNIX_STREAM_BUF_SIZE: usize = 64 * 1024;
+const UNIX_DGRAM_QUEUE_LEN: usize = 64;
+const UNIX_MAX_BACKLOG: usize = 128;
+
+/// Offset of `sun_path` in `struct sockaddr_un`.
+const SUN_PATH_OFFSET: usize = size_of::<ctypes::sa_family_t>();
+
+/// Bound addresses, mapped to the sockets bound on them.
+static UNIX_ADDRS: Mutex<BTreeMap<UnixAddr, Weak<UnixSocketInner>>> = Mutex::new(BTreeMap::new());
+
+/// Type of a Unix domain socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnixSocketType {
+    /// `SOCK_STREAM`
+    Stream,
+    /// `SOCK_DGRAM`
+    Datagram,
+}
+
+/// Address of a Unix domain socket.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnixAddr {
+    /// The socket is not bound.
+    Unnamed,
+    /// A path in the file system namespace.
+    Path(String),
+    /// A name in the abstract namespace, without the leading `'\0'`.
+    Abstract(Vec<u8>),
+}
+
+impl UnixAddr {
+    /// Loads a `struct sockaddr_un` of `addrlen` bytes.
+    pub fn from_raw(
+        addr: *const ctypes::sockaddr,
+        addrlen: ctypes::socklen_t,
+    ) -> LinuxResult<Self> {
+        if addr.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let addrlen = addrlen as usize;
+        if !(SUN_PATH_OFFSET..=size_of::<ctypes::sockaddr_un>()).contains(&addrlen) {
+            return Err(LinuxError::EINVAL);
+        }
+
+        let addr = unsafe { &*(addr as *const ctypes::sockaddr_un) };
+        if addr.sun_family != ctypes::AF_UNIX as ctypes::sa_family_t {
+            return Err(LinuxError::EINVAL);
+        }
+        let path = unsafe {
+            core::slice::from_raw_parts(
+                addr.sun_path.as_ptr() as *const u8,
+                addrlen - SUN_PATH_OFFSET,
+            )
+        };
+        let res = match path.first() {
+            None => UnixAddr::Unnamed,
+            Some(0) => UnixAddr::Abstract(path[1..].to_vec()),
+            Some(_) => {
+                let len = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+                let path = core::str::from_utf8(&path[..len]).map_err(|_| LinuxError::EINVAL)?;
+                UnixAddr::Path(path.into())
+            }
+        };
+        debug!("    load sockaddr_un: {:?}", res);
+        Ok(res)
+    }
+
+    /// Stores the address as a `struct sockaddr_un` into `addr`.
+    ///
+    /// `*addrlen` holds the size of the buffer on input, and the full length of
+    /// the address on output. The address is truncated if the buffer is too
+    /// small.
+    pub unsafe fn write_to(
+        &self,
+        addr: *mut ctypes::sockaddr,
+        addrlen: *mut ctypes::socklen_t,
+    ) -> LinuxResult {
+        if addr.is_null() || addrlen.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let mut sun = ctypes::sockaddr_un {
+            sun_family: ctypes::AF_UNIX as _,
+            ..Default::default()
+        };
+        let sun_path = core::slice::from_raw_parts_mut(
+            sun.sun_path.as_mut_ptr() as *mut u8,
+            sun.sun_path.len(),
+        );
+        let path_len = match self {
+            UnixAddr::Unnamed => 0,
+            UnixAddr::Path(path) => {
+                // keep the terminating '\0'
+                let len = path.len().min(sun_path.len() - 1);
+                sun_path[..len].copy_from_slice(&path.as_bytes()[..len]);
+                len + 1
+            }
+            UnixAddr::Abstract(name) => {
+                let len = name.len().min(sun_path.len() - 1);
+                sun_path[1..len + 1].copy_from_slice(&name[..len]);
+                len + 1
+            }
+        };
+
+        let full_len = SUN_PATH_OFFSET + path_len;
+        let copy_len = full_len.min(*addrlen as usize);
+        core::ptr::copy_nonoverlapping(&sun as *const _ as *const u8, addr as *mut u8, copy_len);
+        *addrlen = full_len as _;
+        Ok(())
+    }
+}
+
+/// One direction of a stream connection.
+struct StreamBuffer {
+    data: VecDeque<u8>,
+    /// File descriptors sent with `SCM_RIGHTS`, along with the stream offset
+    /// of the first byte they were sent with.
+    rights: VecDeque<(usize, Vec<Arc<dyn FileLike>>)>,
+    /// Total number of bytes ever written.
+    written: usize,
+    /// Total number of bytes ever read.
+    read: usize,
+    /// The writer has shut down or closed, no more data will come.
+    write_closed: bool,
+    /// The reader has shut down or closed, no more data will be consumed.
+    read_closed: bool,
+}
+
+impl StreamBuffer {
+    const fn new() -> Self {
+        Self {
+            data: VecDeque::new(),
+            rights: VecDeque::new(),
+            written: 0,
+            read: 0,
+            write_closed: false,
+            read_closed: false,
+        }
+    }
+
+    fn available_write(&self) -> usize {
+        UNIX_STREAM_BUF_SIZE - self.data.len()
+    }
+
+    fn write(&mut self, buf: &[u8], rights: Vec<Arc<dyn FileLike>>) -> usize {
+        let len = buf.len().min(self.available_write());
+        if len > 0 && !rights.is_empty() {
+            self.rights.push_back((self.written, rights));
+        }
+        self.data.extend(&buf[..len]);
+        self.written += len;
+        len
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> (usize, Vec<Arc<dyn FileLike>>) {
+        let mut rights = Vec::new();
+        if matches!(self.rights.front(), Some((offset, _)) if *offset == self.read) {
+            rights = self.rights.pop_front().unwrap().1;
+        }
+        // do not merge data sent with different `SCM_RIGHTS` messages
+        let mut len = buf.len().min(self.data.len());
+        if let Some((offset, _)) = self.rights.front() {
+            len = len.min(offset - self.read);
+        }
+        for (dst, src) in buf.iter_mut().zip(self.data.drain(..len)) {
+            *dst = src;
+        }
+        self.read += len;
+        (len, rights)
+    }
+}
+
+/// A message queued on a datagram socket.
+struct Datagram {
+    data: Vec<u8>,
+    from: UnixAddr,
+    rights: Vec<Arc<dyn FileLike>>,
+}
+
+enum UnixSocketState {
+    Unconnected,
+    /// Waiting for room in the backlog of the listening socket.
+    Connecting,
+    Listening {
+        backlog: usize,
+        pending: VecDeque<UnixSocket>,
+    },
+    Stream {
+        peer_addr: UnixAddr,
+        rx: Arc<Mutex<StreamBuffer>>,
+        tx: Arc<Mutex<StreamBuffer>>,
+    },
+    Datagram {
+        peer_addr: UnixAddr,
+        peer: Weak<UnixSocketInner>,
+    },
+}
+
+struct UnixSocketInner {
+    ty: UnixSocketType,
+    local_addr: Mutex<UnixAddr>,
+    state: Mutex<UnixSocketState>,
+    /// Received messages of a datagram socket.
+    datagrams: Mutex<VecDeque<Datagram>>,
+    /// Number of datagrams and connections queued on the socket so far.
+    queued: AtomicUsize,
+}
+
+/// A Unix domain socket.
+pub struct UnixSocket {
+    inner: Arc<UnixSocketInner>,
+    nonblock: AtomicBool,
+}
+
+impl UnixSocket {
+    /// Creates a new unbound Unix domain socket.
+    pub fn new(ty: UnixSocketType) -> Self {
+        Self::new_with_state(ty, UnixAddr::Unnamed, UnixSocketState::Unconnected)
+    }
+
+    /// Creates a pair of connected Unix domain sockets.
+    pub fn new_pair(ty: UnixSocketType) -> (Self, Self) {
+        match ty {
+            UnixSocketType::Stream => {
+                let (a2b, b2a) = stream_buffers();
+                let a = Self::new_with_state(
+                    ty,
+                    UnixAddr::Unnamed,
+                    UnixSocketState::Stream {
+                        peer_addr: UnixAddr::Unnamed,
+                        rx: b2a.clone(),
+                        tx: a2b.clone(),
+                    },
+                );
+                let b = Self::new_with_state(
+                    ty,
+                    UnixAddr::Unnamed,
+                    UnixSocketState::Stream {
+                        peer_addr: UnixAddr::Unnamed,
+                        rx: a2b,
+                        tx: b2a,
+                    },
+                );
+                (a, b)
+            }
+            UnixSocketType::Datagram => {
+                let a = Self::new(ty);
+                let b = Self::new(ty);
+                *a.inner.state.lock() = UnixSocketState::Datagram {
+                    peer_addr: UnixAddr::Unnamed,
+                    peer: Arc::downgrade(&b.inner),
+                };
+                *b.inner.state.lock() = UnixSocketState::Datagram {
+                    peer_addr: UnixAddr::Unnamed,
+                    peer: Arc::downgrade(&a.inner),
+                };
+                (a, b)
+            }
+        }
+    }
+
+    fn new_with_state(ty: UnixSocketType, local_addr: UnixAddr, state: UnixSocketState) -> Self {
+        Self {
+            inner: Arc::new(UnixSocketInner {
+                ty,
+                local_addr: Mutex::new(local_addr),
+                state: Mutex::new(state),
+                datagrams: Mutex::new(VecDeque::new()),
+                queued: AtomicUsize::new(0),
+            }),
+            nonblock: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the type of the socket.
+    pub fn socket_type(&self) -> UnixSocketType {
+        self.inner.ty
+    }
+
+    /// Returns the address the socket is bound to.
+    pub fn local_addr(&self) -> UnixAddr {
+        self.inner.local_addr.lock().clone()
+    }
+
+    /// Returns the address of the connected peer.
+    pub fn peer_addr(&self) -> LinuxResult<UnixAddr> {
+        match &*self.inner.state.lock() {
+            UnixSocketState::Stream { peer_addr, .. }
+            | UnixSocketState::Datagram { peer_addr, .. } => Ok(peer_addr.clone()),
+            _ => Err(LinuxError::ENOTCONN),
+        }
+    }
+
+    /// Returns whether this socket is in nonblocking mode.
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    /// Moves this socket into or out of nonblocking mode.
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    /// Binds the socket to the given address.
+    ///
+    /// If the address is [`UnixAddr::Unnamed`], a unique name in the abstract
+    /// namespace is generated.
+    pub fn bind(&self, addr: UnixAddr) -> LinuxResult {
+        let mut local_addr = self.inner.local_addr.lock();
+        if *local_addr != UnixAddr::Unnamed {
+            return Err(LinuxError::EINVAL);
+        }
+
+        let addr = match addr {
+            UnixAddr::Unnamed => autobind_addr(),
+            UnixAddr::Path(path) => UnixAddr::Path(create_socket_file(&path)?),
+            addr => addr,
+        };
+        let mut addrs = UNIX_ADDRS.lock();
+        if addrs.get(&addr).is_some_and(|s| s.strong_count() > 0) {
+            return Err(LinuxError::EADDRINUSE);
+        }
+        addrs.insert(addr.clone(), Arc::downgrade(&self.inner));
+        debug!("Unix socket bound on {:?}", addr);
+        *local_addr = addr;
+        Ok(())
+    }
+
+    /// Starts listening for connections.
+    pub fn listen(&self, backlog: usize) -> LinuxResult {
+        if self.inner.ty != UnixSocketType::Stream {
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+        if self.local_addr() == UnixAddr::Unnamed {
+            self.bind(UnixAddr::Unnamed)?;
+        }
+
+        let mut state = self.inner.state.lock();
+        match &mut *state {
+            UnixSocketState::Unconnected => {
+                *state = UnixSocketState::Listening {
+                    backlog: backlog.clamp(1, UNIX_MAX_BACKLOG),
+                    pending: VecDeque::new(),
+                };
+                Ok(())
+            }
+            UnixSocketState::Listening { backlog: old, .. } => {
+                *old = backlog.clamp(1, UNIX_MAX_BACKLOG);
+                Ok(())
+            }
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+
+    /// Accepts a new connection on a listening socket.
+    pub fn accept(&self) -> LinuxResult<UnixSocket> {
+        self.block_on(|| match &mut *self.inner.state.lock() {
+            UnixSocketState::Listening { pending, .. } => {
+                pending.pop_front().ok_or(LinuxError::EAGAIN)
+            }
+            _ => Err(LinuxError::EINVAL),
+        })
+    }
+
+    /// Connects the socket to the given address.
+    ///
+    /// For stream sockets, it queues a new connection on the listening socket
+    /// bound to `addr`. For datagram sockets, it sets the default destination
+    /// of [`send`](Self::send), datagrams from any source are still received.
+    pub fn connect(&self, addr: UnixAddr) -> LinuxResult {
+        let target = lookup_addr(&addr)?;
+        if target.ty != self.inner.ty {
+            return Err(LinuxError::EPROTOTYPE);
+        }
+        let addr = target.local_addr.lock().clone();
+        match self.inner.ty {
+            UnixSocketType::Stream => self.connect_stream(addr, target),
+            UnixSocketType::Datagram => {
+                *self.inner.state.lock() = UnixSocketState::Datagram {
+                    peer_addr: addr,
+                    peer: Arc::downgrade(&target),
+                };
+                Ok(())
+            }
+        }
+    }
+
+    fn connect_stream(&self, addr: UnixAddr, target: Arc<UnixSocketInner>) -> LinuxResult {
+        if Arc::ptr_eq(&target, &self.inner) {
+            return Err(LinuxError::ECONNREFUSED);
+        }
+        {
+            let mut state = self.inner.state.lock();
+            match &*state {
+                UnixSocketState::Unconnected => {}
+                UnixSocketState::Connecting => return Err(LinuxError::EALREADY),
+                UnixSocketState::Stream { .. } => return Err(LinuxError::EISCONN),
+                _ => return Err(LinuxError::EINVAL),
+            }
+            // The lock is released while waiting, so that the socket can
+            // still be polled or closed.
+            *state = UnixSocketState::Connecting;
+        }
+
+        let (c2s, s2c) = stream_buffers();
+        let mut server = Some(Self::new_with_state(
+            UnixSocketType::Stream,
+            addr.clone(),
+            UnixSocketState::Stream {
+                peer_addr: self.local_addr(),
+                rx: c2s.clone(),
+                tx: s2c.clone(),
+            },
+        ));
+        let res = self.block_on(|| match &mut *target.state.lock() {
+            UnixSocketState::Listening { backlog, pending } => {
+                if pending.len() >= *backlog {
+                    Err(LinuxError::EAGAIN)
+                } else {
+                    pending.push_back(server.take().unwrap());
+                    target.queued.fetch_add(1, Ordering::Release);
+                    Ok(())
+                }
+            }
+            _ => Err(LinuxError::ECONNREFUSED),
+        });
+
+        let mut state = self.inner.state.lock();
+        if let Err(e) = res {
+            *state = UnixSocketState::Unconnected;
+            return Err(e);
+        }
+        debug!("Unix socket connected to {:?}", addr);
+        *state = UnixSocketState::Stream {
+            peer_addr: addr,
+            rx: s2c,
+            tx: c2s,
+        };
+        Ok(())
+    }
+
+    /// Sends data on the socket, to `addr` for unconnected datagram sockets,
+    /// along with the given file descriptors (`SCM_RIGHTS`).
+    ///
+    /// Returns the number of bytes sent.
+    pub fn sendmsg(
+        &self,
+        buf: &[u8],
+        addr: Option<UnixAddr>,
+        rights: Vec<Arc<dyn FileLike>>,
+    ) -> LinuxResult<usize> {
+        match self.inner.ty {
+            UnixSocketType::Stream => self.send_stream(buf, rights),
+            UnixSocketType::Datagram => self.send_datagram(buf, addr, rights),
+        }
+    }
+
+    fn send_stream(&self, buf: &[u8], rights: Vec<Arc<dyn FileLike>>) -> LinuxResult<usize> {
+        let tx = match &*self.inner.state.lock() {
+            UnixSocketState::Stream { tx, .. } => tx.clone(),
+            _ => return Err(LinuxError::ENOTCONN),
+        };
+
+        let mut rights = Some(rights);
+        let mut written = 0;
+        loop {
+            let events = poll_events();
+            let mut tx = tx.lock();
+            if tx.read_closed || tx.write_closed {
+                return Err(LinuxError::EPIPE);
+            }
+            if tx.available_write() > 0 {
+                written += tx.write(&buf[written..], rights.take().unwrap_or_default());
+                drop(tx);
+                poll_notify();
+                if written == buf.len() || self.is_nonblocking() {
+                    return Ok(written);
+                }
+                continue;
+            } else if self.is_nonblocking() {
+                return if written > 0 {
+                    Ok(written)
+                } else {
+                    Err(LinuxError::EAGAIN)
+                };
+            }
+            drop(tx);
+            // Buffer is full, wait for the peer to consume
+            poll_wait(events, None);
+        }
+    }
+
+    fn send_datagram(
+        &self,
+        buf: &[u8],
+        addr: Option<UnixAddr>,
+        rights: Vec<Arc<dyn FileLike>>,
+    ) -> LinuxResult<usize> {
+        let target = match addr {
+            Some(addr) => {
+                let target = lookup_addr(&addr)?;
+                if target.ty != UnixSocketType::Datagram {
+                    return Err(LinuxError::EPROTOTYPE);
+                }
+                target
+            }
+            None => match &*self.inner.state.lock() {
+                UnixSocketState::Datagram { peer, .. } => {
+                    peer.upgrade().ok_or(LinuxError::ECONNREFUSED)?
+                }
+                _ => return Err(LinuxError::ENOTCONN),
+            },
+        };
+
+        let mut msg = Some(Datagram {
+            data: buf.to_vec(),
+            from: self.local_addr(),
+            rights,
+        });
+        self.block_on(|| {
+            let mut queue = target.datagrams.lock();
+            if queue.len() >= UNIX_DGRAM_QUEUE_LEN {
+                return Err(LinuxError::EAGAIN);
+            }
+            queue.push_back(msg.take().unwrap());
+            target.queued.fetch_add(1, Ordering::Release);
+            Ok(buf.len())
+        })
+    }
+
+    /// Receives data on the socket, along with the file descriptors sent with
+    /// it (`SCM_RIGHTS`).
+    ///
+    /// Returns the number of bytes received, the source address, the passed
+    /// file descriptors, and whether the datagram was truncated.
+    pub fn recvmsg(&self, buf: &mut [u8]) -> LinuxResult<UnixMessage> {
+        match self.inner.ty {
+            UnixSocketType::Stream => self.recv_stream(buf),
+            UnixSocketType::Datagram => self.recv_datagram(buf),
+        }
+    }
+
+    fn recv_stream(&self, buf: &mut [u8]) -> LinuxResult<UnixMessage> {
+        let (rx, peer_addr) = match &*self.inner.state.lock() {
+            UnixSocketState::Stream { rx, peer_addr, .. } => (rx.clone(), peer_addr.clone()),
+            _ => return Err(LinuxError::ENOTCONN),
+        };
+        self.block_on(|| {
+            let mut rx = rx.lock();
+            if rx.data.is_empty() && !rx.write_closed && !rx.read_closed {
+                // Data not ready, wait for the peer to write
+                return Err(LinuxError::EAGAIN);
+            }
+            let (len, rights) = rx.read(buf);
+            Ok(UnixMessage {
+                len,
+                from: peer_addr.clone(),
+                rights,
+                truncated: false,
+            })
+        })
+    }
+
+    fn recv_datagram(&self, buf: &mut [u8]) -> LinuxResult<UnixMessage> {
+        self.block_on(|| {
+            let msg = self
+                .inner
+                .datagrams
+                .lock()
+                .pop_front()
+                .ok_or(LinuxError::EAGAIN)?;
+            let len = buf.len().min(msg.data.len());
+            buf[..len].copy_from_slice(&msg.data[..len]);
+            Ok(UnixMessage {
+                len,
+                from: msg.from,
+                rights: msg.rights,
+                truncated: len < msg.data.len(),
+            })
+        })
+    }
+
+    /// Sends data on the connected socket.
+    pub fn send(&self, buf: &[u8]) -> LinuxResult<usize> {
+        self.sendmsg(buf, None, Vec::new())
+    }
+
+    /// Receives data on the socket.
+    pub fn recv(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        self.recvmsg(buf).map(|msg| msg.len)
+    }
+
+    /// Shuts down both directions of a stream connection.
+    pub fn shutdown(&self) -> LinuxResult {
+        match &*self.inner.state.lock() {
+            UnixSocketState::Stream { rx, tx, .. } => {
+                rx.lock().read_closed = true;
+                tx.lock().write_closed = true;
+                poll_notify();
+                Ok(())
+            }
+            UnixSocketState::Datagram { .. } => Ok(()),
+            _ => Err(LinuxError::ENOTCONN),
+        }
+    }
+
+    /// Whether the socket is readable or writable.
+    pub fn poll(&self) -> LinuxResult<PollState> {
+        if self.inner.ty == UnixSocketType::Datagram {
+            return Ok(PollState {
+                readable: !self.inner.datagrams.lock().is_empty(),
+                writable: true,
+            });
+        }
+        match &*self.inner.state.lock() {
+            UnixSocketState::Listening { pending, .. } => Ok(PollState {
+                readable: !pending.is_empty(),
+                writable: false,
+            }),
+            UnixSocketState::Stream { rx, tx, .. } => {
+                let readable = {
+                    let rx = rx.lock();
+                    !rx.data.is_empty() || rx.write_closed
+                };
+                let tx = tx.lock();
+                Ok(PollState {
+                    readable,
+                    writable: tx.available_write() > 0 || tx.read_closed,
+                })
+            }
+            _ => Ok(PollState {
+                readable: false,
+                writable: false,
+            }),
+        }
+    }
+
+    /// Returns a counter that changes when data or connections arrive, data
+    /// is consumed by the peer, or the connection is shut down.
+    pub fn poll_generation(&self) -> usize {
+        let queued = self.inner.queued.load(Ordering::Acquire);
+        match &*self.inner.state.lock() {
+            UnixSocketState::Stream { rx, tx, .. } => {
+                // The buffers are locked one at a time, the peer locks them
+                // in the reverse order.
+                let rx_events = {
+                    let rx = rx.lock();
+                    rx.written + rx.write_closed as usize
+                };
+                let tx_events = {
+                    let tx = tx.lock();
+                    tx.read + tx.read_closed as usize
+                };
+                queued + rx_events + tx_events
+            }
+            _ => queued,
+        }
+    }
+
+    /// Whether the peer has shut down the connection.
+    pub fn poll_hup(&self) -> HangUpState {
+        match &*self.inner.state.lock() {
+            UnixSocketState::Stream { rx, tx, .. } => {
+                let read_hup = rx.lock().write_closed;
+                HangUpState {
+                    read_hup,
+                    hup: read_hup && tx.lock().read_closed,
+                    err: false,
+                }
+            }
+            _ => HangUpState::default(),
+        }
+    }
+
+    /// Calls `f` until it does not return `EAGAIN`, waiting for the buffers
+    /// to change in between. Other waiters are notified on success.
+    fn block_on<F, T>(&self, mut f: F) -> LinuxResult<T>
+    where
+        F: FnMut() -> LinuxResult<T>,
+    {
+        loop {
+            let events = poll_events();
+            match f() {
+                Ok(t) => {
+                    poll_notify();
+                    return Ok(t);
+                }
+                Err(LinuxError::EAGAIN) if !self.is_nonblocking() => poll_wait(events, None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        self.shutdown().ok();
+        let local_addr = self.local_addr();
+        if local_addr != UnixAddr::Unnamed {
+            let mut addrs = UNIX_ADDRS.lock();
+            if addrs
+                .get(&local_addr)
+                .is_some_and(|s| s.as_ptr() == Arc::as_ptr(&self.inner))
+            {
+                addrs.remove(&local_addr);
+            }
+        }
+    }
+}
+
+/// A message received by [`UnixSocket::recvmsg`].
+pub struct UnixMessage {
+    /// Number of bytes received.
+    pub len: usize,
+    /// Address of the sender.
+    pub from: UnixAddr,
+    /// File descriptors passed with the message.
+    pub rights: Vec<Arc<dyn FileLike>>,
+    /// The datagram was longer than the buffer.
+    pub truncated: bool,
+}
+
+fn stream_buffers() -> (Arc<Mutex<StreamBuffer>>, Arc<Mutex<StreamBuffer>>) {
+    (
+        Arc::new(Mutex::new(StreamBuffer::new())),
+        Arc::new(Mutex::new(StreamBuffer::new())),
+    )
+}
+
+fn lookup_addr(addr: &UnixAddr) -> LinuxResult<Arc<UnixSocketInner>> {
+    let addr = match addr {
+        UnixAddr::Unnamed => return Err(LinuxError::EINVAL),
+        UnixAddr::Path(path) => UnixAddr::Path(canonicalize(path)?),
+        addr => addr.clone(),
+    };
+    match UNIX_ADDRS.lock().get(&addr) {
+        Some(socket) => socket.upgrade().ok_or(LinuxError::ECONNREFUSED),
+        None if matches!(addr, UnixAddr::Path(_)) => Err(LinuxError::ENOENT),
+        None => Err(LinuxError::ECONNREFUSED),
+    }
+}
+
+fn autobind_addr() -> UnixAddr {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    UnixAddr::Abstract(format!("{:05x}", id).into_bytes())
+}
+
+#[cfg(feature = "fs")]
+fn canonicalize(path: &str) -> LinuxResult<String> {
+    Ok(axfs::api::canonicalize(path)?)
+}
+
+#[cfg(not(feature = "fs"))]
+fn canonicalize(path: &str) -> LinuxResult<String> {
+    Ok(path.into())
+}
+
+/// Creates the file that represents a socket bound to `path`, and returns the
+/// absolute path.
+#[cfg(feature = "fs")]
+fn create_socket_file(path: &str) -> LinuxResult<String> {
+    let path = canonicalize(path)?;
+    if axfs::api::metadata(&path).is_ok() {
+        return Err(LinuxError::EADDRINUSE);
+    }
+    axfs::api::write(&path, [])?;
+    Ok(path)
+}
+
+#[cfg(not(feature = "fs"))]
+fn create_socket_file(path: &str) -> LinuxResult<String> {
+    canonicalize(path)
+}
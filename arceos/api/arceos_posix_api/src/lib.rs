@@ -47,9 +47,11 @@ pub use imp::io_mpx::{sys_epoll_create, sys_epoll_ctl, sys_epoll_wait};
 #[cfg(feature = "net")]
 pub use imp::net::{
     sys_accept, sys_bind, sys_connect, sys_freeaddrinfo, sys_getaddrinfo, sys_getpeername,
-    sys_getsockname, sys_listen, sys_recv, sys_recvfrom, sys_send, sys_sendto, sys_shutdown,
-    sys_socket,
+    sys_getsockname, sys_listen, sys_recv, sys_recvfrom, sys_recvmsg, sys_send, sys_sendmsg,
+    sys_sendto, sys_shutdown, sys_socket, sys_socketpair,
 };
+#[cfg(all(feature = "net", feature = "fs"))]
+pub use imp::net::sys_sendfile;
 #[cfg(feature = "pipe")]
 pub use imp::pipe::sys_pipe;
 #[cfg(feature = "multitask")]
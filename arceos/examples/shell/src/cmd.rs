@@ -23,6 +23,7 @@ const CMD_TABLE: &[(&str, CmdHandler)] = &[
     ("exit", do_exit),
     ("help", do_help),
     ("ls", do_ls),
+    ("meminfo", do_meminfo),
     ("mkdir", do_mkdir),
     ("pwd", do_pwd),
     ("rm", do_rm),
@@ -260,6 +261,13 @@ fn do_uname(_args: &str) {
     );
 }
 
+fn do_meminfo(_args: &str) {
+    #[cfg(feature = "alloc-tracking")]
+    print!("{}", std::os::arceos::modules::axalloc::tracking::report());
+    #[cfg(not(feature = "alloc-tracking"))]
+    print_err!("meminfo", "allocation tracking is not enabled");
+}
+
 fn do_help(_args: &str) {
     println!("Available commands:");
     for (name, _) in CMD_TABLE {
@@ -0,0 +1,155 @@
+//! Per-CPU caches of small memory blocks in front of the byte allocator.
+//!
+//! Each CPU keeps a stack of free blocks for each size class, so that most
+//! small allocations and deallocations do not take the lock of the byte
+//! allocator. An empty cache is refilled, and a full cache is drained by half,
+//! with a batch of blocks under a single lock acquisition.
+//!
+//! Blocks are not owned by any CPU: a block freed on another CPU than the one
+//! that allocated it goes to the cache of the freeing CPU, and is given back to
+//! the byte allocator once that cache overflows.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use allocator::AllocResult;
+use kernel_guard::NoPreemptIrqSave;
+
+/// Number of size classes, the `i`-th of which holds blocks of
+/// `1 << (i + MIN_CLASS_SHIFT)` bytes, aligned to their size.
+const NUM_CLASSES: usize = 9;
+
+/// The smallest size class holds blocks of `1 << MIN_CLASS_SHIFT` bytes.
+const MIN_CLASS_SHIFT: usize = 3;
+
+/// Number of blocks moved between a cache and the byte allocator at a time.
+const BATCH: usize = 16;
+
+/// Maximum number of free blocks in a cache.
+const CAPACITY: usize = 2 * BATCH;
+
+/// Free blocks of a size class, the top ones are used first.
+struct Magazine {
+    len: usize,
+    blocks: [usize; CAPACITY],
+}
+
+struct CpuCache {
+    mags: [Magazine; NUM_CLASSES],
+}
+
+#[percpu::def_percpu]
+static CPU_CACHE: CpuCache = CpuCache {
+    mags: [const {
+        Magazine {
+            len: 0,
+            blocks: [0; CAPACITY],
+        }
+    }; NUM_CLASSES],
+};
+
+/// Returns the size class of the blocks that can hold `layout`, or `None` if
+/// it is too large to be cached.
+pub(crate) fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).next_power_of_two();
+    let class = (size.trailing_zeros() as usize).saturating_sub(MIN_CLASS_SHIFT);
+    (class < NUM_CLASSES).then_some(class)
+}
+
+/// Returns the layout of the blocks of the size class, which is used to
+/// allocate them from the byte allocator.
+fn class_layout(class: usize) -> Layout {
+    let size = 1 << (class + MIN_CLASS_SHIFT);
+    Layout::from_size_align(size, size).unwrap()
+}
+
+/// Takes a block of the size class from the cache of the current CPU.
+///
+/// If the cache is empty, `refill` is called to allocate up to the length of
+/// the given slice of blocks with the given layout, and returns the number of
+/// blocks allocated, which must be at least one if it succeeds.
+pub(crate) fn alloc(
+    class: usize,
+    refill: impl FnOnce(Layout, &mut [usize]) -> AllocResult<usize>,
+) -> AllocResult<NonNull<u8>> {
+    let _guard = NoPreemptIrqSave::new();
+    // Safety: preemption and IRQs are disabled.
+    let mag = unsafe { &mut CPU_CACHE.current_ref_mut_raw().mags[class] };
+    if mag.len == 0 {
+        mag.len = refill(class_layout(class), &mut mag.blocks[..BATCH])?;
+        debug_assert!(mag.len > 0);
+    }
+    mag.len -= 1;
+    Ok(unsafe { NonNull::new_unchecked(mag.blocks[mag.len] as *mut u8) })
+}
+
+/// Puts a block of the size class to the cache of the current CPU.
+///
+/// If the cache is full, `drain` is called to give back the given blocks with
+/// the given layout to the byte allocator.
+pub(crate) fn dealloc(pos: NonNull<u8>, class: usize, drain: impl FnOnce(Layout, &[usize])) {
+    let _guard = NoPreemptIrqSave::new();
+    // Safety: preemption and IRQs are disabled.
+    let mag = unsafe { &mut CPU_CACHE.current_ref_mut_raw().mags[class] };
+    if mag.len == CAPACITY {
+        drain(class_layout(class), &mag.blocks[BATCH..]);
+        mag.len = BATCH;
+    }
+    mag.blocks[mag.len] = pos.as_ptr() as usize;
+    mag.len += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class_of(size: usize, align: usize) -> Option<usize> {
+        size_class(Layout::from_size_align(size, align).unwrap())
+    }
+
+    #[test]
+    fn test_size_class() {
+        assert_eq!(class_of(0, 1), Some(0));
+        assert_eq!(class_of(1, 1), Some(0));
+        assert_eq!(class_of(8, 8), Some(0));
+        assert_eq!(class_of(9, 1), Some(1));
+        assert_eq!(class_of(24, 8), Some(2));
+        assert_eq!(class_of(2048, 8), Some(NUM_CLASSES - 1));
+        assert_eq!(class_of(2049, 8), None);
+        assert_eq!(class_of(0x10_0000, 8), None);
+        // The alignment takes a larger class.
+        assert_eq!(class_of(1, 64), Some(3));
+        assert_eq!(class_of(16, 2048), Some(NUM_CLASSES - 1));
+        assert_eq!(class_of(8, 4096), None);
+    }
+
+    #[test]
+    fn test_class_layout() {
+        for class in 0..NUM_CLASSES {
+            let layout = class_layout(class);
+            assert_eq!(layout.size(), 8 << class);
+            assert_eq!(layout.align(), layout.size());
+            assert_eq!(size_class(layout), Some(class));
+        }
+    }
+
+    #[test]
+    fn test_blocks_fit() {
+        // A block of the class of a layout holds it, and is the smallest one.
+        for align in (0..12).map(|shift| 1 << shift) {
+            for size in 0..=4096 {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let Some(class) = size_class(layout) else {
+                    assert!(size.max(align) > 1 << (NUM_CLASSES - 1 + MIN_CLASS_SHIFT));
+                    continue;
+                };
+                let block = class_layout(class);
+                assert!(block.size() >= size && block.align() >= align);
+                if class > 0 {
+                    let smaller = class_layout(class - 1);
+                    assert!(smaller.size() < size || smaller.align() < align);
+                }
+            }
+        }
+    }
+}
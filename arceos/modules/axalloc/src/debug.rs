@@ -0,0 +1,228 @@
+//! Detection of heap buffer overflows, double frees and use after free.
+//!
+//! Each allocation is surrounded by red zones filled with a known pattern, and
+//! prefixed with a header that records its size, state and allocation site.
+//! Fresh allocations are filled with [`POISON_INUSE`] to expose reads of
+//! uninitialized memory.
+//!
+//! Freed blocks are filled with [`POISON_FREE`] and kept in a quarantine for a
+//! while before they are given back, so that the memory is not reused soon.
+//! When a block leaves the quarantine, it is checked that the poison is still
+//! intact, i.e., nothing has written to it after it was freed.
+//!
+//! Corruption is reported by a panic with the address of the block and the
+//! sites that allocated and freed it.
+
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr::NonNull;
+
+use allocator::AllocResult;
+use kspin::SpinNoIrq;
+use memory_addr::align_up;
+
+use crate::site::{self, Site};
+
+/// Minimum size of the red zone before and after each allocation.
+const REDZONE_SIZE: usize = 16;
+
+/// Pattern of the red zones.
+const RED_ACTIVE: u8 = 0xcc;
+/// Pattern of fresh allocations.
+const POISON_INUSE: u8 = 0x5a;
+/// Pattern of freed blocks.
+const POISON_FREE: u8 = 0x6b;
+
+const MAGIC_ALLOCATED: usize = 0xa110_c8ed;
+const MAGIC_FREED: usize = 0xf4ee_d0ff;
+
+/// Maximum number of blocks in the quarantine.
+const QUARANTINE_BLOCKS: usize = 256;
+/// Maximum number of bytes in the quarantine.
+const QUARANTINE_BYTES: usize = 0x10_0000; // 1 M
+
+/// The header at the start of each underlying allocation.
+#[repr(C)]
+struct Header {
+    magic: usize,
+    size: usize,
+    alloc_site: usize,
+    free_site: usize,
+}
+
+/// A freed block, with the layout requested by the user.
+#[derive(Clone, Copy)]
+struct Quarantined {
+    ptr: usize,
+    layout: Layout,
+}
+
+/// Freed blocks in a ring, the oldest at `head`.
+struct Quarantine {
+    blocks: [Option<Quarantined>; QUARANTINE_BLOCKS],
+    head: usize,
+    len: usize,
+    bytes: usize,
+}
+
+static QUARANTINE: SpinNoIrq<Quarantine> = SpinNoIrq::new(Quarantine {
+    blocks: [None; QUARANTINE_BLOCKS],
+    head: 0,
+    len: 0,
+    bytes: 0,
+});
+
+impl Quarantine {
+    fn push(&mut self, block: Quarantined) {
+        self.blocks[(self.head + self.len) % QUARANTINE_BLOCKS] = Some(block);
+        self.len += 1;
+        self.bytes += block.layout.size();
+    }
+
+    fn pop_over_limit(&mut self) -> Option<Quarantined> {
+        if self.len < QUARANTINE_BLOCKS && self.bytes <= QUARANTINE_BYTES {
+            return None;
+        }
+        let block = self.blocks[self.head].take()?;
+        self.head = (self.head + 1) % QUARANTINE_BLOCKS;
+        self.len -= 1;
+        self.bytes -= block.layout.size();
+        Some(block)
+    }
+}
+
+/// Returns the offset of the user data from the start of the underlying
+/// allocation, and the layout of the underlying allocation.
+fn outer_layout(layout: Layout) -> (usize, Layout) {
+    let offset = align_up(
+        core::mem::size_of::<Header>() + REDZONE_SIZE,
+        layout.align(),
+    );
+    let align = layout.align().max(core::mem::align_of::<Header>());
+    let size = offset + layout.size() + REDZONE_SIZE;
+    (offset, Layout::from_size_align(size, align).unwrap())
+}
+
+/// Returns the offset of the first byte in `[start, end)` that is not `pat`.
+unsafe fn find_mismatch(start: usize, end: usize, pat: u8) -> Option<usize> {
+    let bytes = core::slice::from_raw_parts(start as *const u8, end - start);
+    bytes.iter().position(|&b| b != pat)
+}
+
+/// Reports the corruption of the block at `ptr` and panics.
+#[cold]
+fn corrupted(what: fmt::Arguments, ptr: usize, header: &Header) -> ! {
+    if header.magic == MAGIC_FREED {
+        panic!(
+            "heap corruption: {} of block {:#x} ({} bytes) allocated at {}, freed at {}",
+            what,
+            ptr,
+            header.size,
+            Site(header.alloc_site),
+            Site(header.free_site)
+        )
+    } else {
+        panic!(
+            "heap corruption: {} of block {:#x} ({} bytes) allocated at {}",
+            what,
+            ptr,
+            header.size,
+            Site(header.alloc_site)
+        )
+    }
+}
+
+/// Checks the red zones of the block at `ptr`.
+unsafe fn check_redzones(ptr: usize, offset: usize, header: &Header) {
+    let start = ptr - offset + core::mem::size_of::<Header>();
+    if let Some(pos) = find_mismatch(start, ptr, RED_ACTIVE) {
+        let off = ptr - start - pos;
+        corrupted(format_args!("underflow at offset -{}", off), ptr, header);
+    }
+    let end = ptr + header.size;
+    if let Some(pos) = find_mismatch(end, end + REDZONE_SIZE, RED_ACTIVE) {
+        let off = header.size + pos;
+        corrupted(format_args!("overflow at offset {}", off), ptr, header);
+    }
+}
+
+/// Allocates with the header and red zones by `alloc_raw`.
+pub(crate) fn alloc(
+    layout: Layout,
+    alloc_raw: impl FnOnce(Layout) -> AllocResult<NonNull<u8>>,
+) -> AllocResult<NonNull<u8>> {
+    let (offset, outer) = outer_layout(layout);
+    let raw = alloc_raw(outer)?;
+    unsafe {
+        raw.cast::<Header>().write(Header {
+            magic: MAGIC_ALLOCATED,
+            size: layout.size(),
+            alloc_site: site::current(),
+            free_site: 0,
+        });
+        let start = raw.add(core::mem::size_of::<Header>());
+        let ptr = raw.add(offset);
+        start.write_bytes(RED_ACTIVE, offset - core::mem::size_of::<Header>());
+        ptr.write_bytes(POISON_INUSE, layout.size());
+        ptr.add(layout.size()).write_bytes(RED_ACTIVE, REDZONE_SIZE);
+        Ok(ptr)
+    }
+}
+
+/// Checks the block, poisons it and puts it into the quarantine. The oldest
+/// blocks in the quarantine are checked and deallocated by `dealloc_raw`.
+pub(crate) fn dealloc(pos: NonNull<u8>, layout: Layout, dealloc_raw: impl Fn(NonNull<u8>, Layout)) {
+    let (offset, _) = outer_layout(layout);
+    let ptr = pos.as_ptr() as usize;
+    let header = unsafe { &mut *((ptr - offset) as *mut Header) };
+    match header.magic {
+        MAGIC_ALLOCATED => {}
+        MAGIC_FREED => corrupted(format_args!("double free"), ptr, header),
+        _ => panic!(
+            "heap corruption: invalid free or corrupted header of block {:#x} ({} bytes)",
+            ptr,
+            layout.size()
+        ),
+    }
+    if header.size != layout.size() {
+        corrupted(
+            format_args!("freed with wrong size {}", layout.size()),
+            ptr,
+            header,
+        );
+    }
+    unsafe {
+        check_redzones(ptr, offset, header);
+        pos.write_bytes(POISON_FREE, layout.size());
+    }
+    header.magic = MAGIC_FREED;
+    header.free_site = site::current();
+
+    let mut quarantine = QUARANTINE.lock();
+    quarantine.push(Quarantined { ptr, layout });
+    while let Some(block) = quarantine.pop_over_limit() {
+        let (offset, outer) = outer_layout(block.layout);
+        let header = unsafe { &*((block.ptr - offset) as *const Header) };
+        if header.magic != MAGIC_FREED {
+            corrupted(
+                format_args!("header overwritten after free"),
+                block.ptr,
+                header,
+            );
+        }
+        unsafe {
+            if let Some(off) = find_mismatch(block.ptr, block.ptr + header.size, POISON_FREE) {
+                corrupted(
+                    format_args!("use after free at offset {}", off),
+                    block.ptr,
+                    header,
+                );
+            }
+            check_redzones(block.ptr, offset, header);
+            dealloc_raw(
+                NonNull::new_unchecked((block.ptr - offset) as *mut u8),
+                outer,
+            );
+        }
+    }
+}
@@ -5,24 +5,47 @@
 //! [`GlobalAllocator`] is defined with the `#[global_allocator]` attribute, to
 //! be registered as the standard library’s default allocator.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
 extern crate alloc;
 
 mod page;
+mod region;
 
-use allocator::{AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
+#[cfg(feature = "smp")]
+mod cache;
+
+#[cfg(any(feature = "tracking", feature = "debug"))]
+mod site;
+
+#[cfg(feature = "debug")]
+mod debug;
+#[cfg(feature = "tracking")]
+pub mod tracking;
+
+use allocator::{BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
 use kspin::SpinNoIrq;
 
+use self::region::RegionPageAllocator;
+
 const PAGE_SIZE: usize = 0x1000;
 const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
+const MAX_HEAP_EXPAND_SIZE: usize = 0x20_0000; // 2 M
 
+/// Allocations not smaller than this are served by the page allocator
+/// directly, so that their pages are returned once freed.
+const LARGE_ALLOC_SIZE: usize = 4 * PAGE_SIZE; // 16 K
+
+pub use allocator::{AllocError, AllocResult};
 pub use page::GlobalPage;
 
+#[cfg(any(feature = "tracking", feature = "debug"))]
+pub use site::TrackIf;
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "slab")] {
         /// The default byte allocator.
@@ -43,13 +66,26 @@ cfg_if::cfg_if! {
 /// there is no memory, asks the page allocator for more memory and adds it to
 /// the byte allocator.
 ///
+/// Allocations of 16 KB or more bypass the byte allocator and
+/// take pages directly, which are given back to the page allocator on
+/// deallocation. Memory added to the byte allocator is never given back, even
+/// if all of it becomes free, as the byte allocators do not support removing
+/// memory. So the pages taken to expand the heap stay in use forever.
+///
+/// With the `smp` feature, small allocations are served by per-CPU caches of
+/// free blocks, which are refilled from and drained to the byte allocator in
+/// batches, so the lock of the byte allocator is rarely taken.
+///
 /// Currently, [`TlsfByteAllocator`] is used as the byte allocator, while
-/// [`BitmapPageAllocator`] is used as the page allocator.
+/// [`BitmapPageAllocator`] is used as the page allocator for the initial
+/// region. Regions added later are managed separately, and can be removed
+/// again once all their pages are free.
 ///
 /// [`TlsfByteAllocator`]: allocator::TlsfByteAllocator
 pub struct GlobalAllocator {
     balloc: SpinNoIrq<DefaultByteAllocator>,
     palloc: SpinNoIrq<BitmapPageAllocator<PAGE_SIZE>>,
+    regions: SpinNoIrq<RegionPageAllocator>,
 }
 
 impl GlobalAllocator {
@@ -58,6 +94,7 @@ impl GlobalAllocator {
         Self {
             balloc: SpinNoIrq::new(DefaultByteAllocator::new()),
             palloc: SpinNoIrq::new(BitmapPageAllocator::new()),
+            regions: SpinNoIrq::new(RegionPageAllocator::new()),
         }
     }
 
@@ -91,9 +128,24 @@ impl GlobalAllocator {
 
     /// Add the given region to the allocator.
     ///
-    /// It will add the whole region to the byte allocator.
+    /// It will add the whole region to the page allocator, from which the byte
+    /// allocator takes memory on demand. The first pages of the region are
+    /// used to track the allocated pages in it.
     pub fn add_memory(&self, start_vaddr: usize, size: usize) -> AllocResult {
-        self.balloc.lock().add_memory(start_vaddr, size)
+        unsafe { self.regions.lock().add_region(start_vaddr, size) }
+    }
+
+    /// Removes the region added by [`add_memory`] with the same bounds from
+    /// the allocator.
+    ///
+    /// It fails with [`AllocError::MemoryOverlap`] if some pages in the region
+    /// are still in use, or [`AllocError::InvalidParam`] if there is no such
+    /// region. A region from which the heap has been expanded can never be
+    /// removed, as the pages given to the byte allocator are never freed.
+    ///
+    /// [`add_memory`]: GlobalAllocator::add_memory
+    pub fn remove_memory(&self, start_vaddr: usize, size: usize) -> AllocResult {
+        self.regions.lock().remove_region(start_vaddr, size)
     }
 
     /// Allocate arbitrary number of bytes. Returns the left bound of the
@@ -101,26 +153,105 @@ impl GlobalAllocator {
     ///
     /// It firstly tries to allocate from the byte allocator. If there is no
     /// memory, it asks the page allocator for more memory and adds it to the
-    /// byte allocator.
+    /// byte allocator. Large allocations are served by the page allocator
+    /// directly.
+    ///
+    /// With the `tracking` feature, the allocation is also recorded, see the
+    /// [`tracking`] module. With the `debug` feature, the allocation is
+    /// surrounded by red zones, and checked for corruption when freed.
     pub fn alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        #[cfg(feature = "tracking")]
+        {
+            tracking::alloc(layout, |layout| self.alloc_raw(layout))
+        }
+        #[cfg(not(feature = "tracking"))]
+        {
+            self.alloc_raw(layout)
+        }
+    }
+
+    fn alloc_raw(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        #[cfg(feature = "debug")]
+        {
+            debug::alloc(layout, |layout| self.alloc_block(layout))
+        }
+        #[cfg(not(feature = "debug"))]
+        {
+            self.alloc_block(layout)
+        }
+    }
+
+    fn alloc_block(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        if layout.size() >= LARGE_ALLOC_SIZE {
+            let num_pages = layout.size().div_ceil(PAGE_SIZE);
+            let ptr = self.alloc_pages(num_pages, layout.align().max(PAGE_SIZE))?;
+            return Ok(unsafe { NonNull::new_unchecked(ptr as *mut u8) });
+        }
+
+        #[cfg(feature = "smp")]
+        if let Some(class) = cache::size_class(layout) {
+            return cache::alloc(class, |layout, blocks| self.refill_cache(layout, blocks));
+        }
+
+        self.alloc_from_heap(&mut self.balloc.lock(), layout)
+    }
+
+    fn alloc_from_heap(
+        &self,
+        balloc: &mut DefaultByteAllocator,
+        layout: Layout,
+    ) -> AllocResult<NonNull<u8>> {
         // simple two-level allocator: if no heap memory, allocate from the page allocator.
-        let mut balloc = self.balloc.lock();
         loop {
             if let Ok(ptr) = balloc.alloc(layout) {
                 return Ok(ptr);
             } else {
-                let old_size = balloc.total_bytes();
-                let expand_size = old_size
-                    .max(layout.size())
-                    .next_power_of_two()
-                    .max(PAGE_SIZE);
-                let heap_ptr = self.alloc_pages(expand_size / PAGE_SIZE, PAGE_SIZE)?;
-                debug!(
-                    "expand heap memory: [{:#x}, {:#x})",
-                    heap_ptr,
-                    heap_ptr + expand_size
-                );
-                balloc.add_memory(heap_ptr, expand_size)?;
+                self.expand_heap(balloc, layout)?;
+            }
+        }
+    }
+
+    /// Allocates blocks for a per-CPU cache, returns the number of blocks
+    /// allocated.
+    ///
+    /// The heap is expanded only for the first block, fewer blocks are
+    /// allocated if the heap runs out of memory after that.
+    #[cfg(feature = "smp")]
+    fn refill_cache(&self, layout: Layout, blocks: &mut [usize]) -> AllocResult<usize> {
+        let mut balloc = self.balloc.lock();
+        blocks[0] = self.alloc_from_heap(&mut balloc, layout)?.as_ptr() as usize;
+        for (i, block) in blocks.iter_mut().enumerate().skip(1) {
+            match balloc.alloc(layout) {
+                Ok(ptr) => *block = ptr.as_ptr() as usize,
+                Err(_) => return Ok(i),
+            }
+        }
+        Ok(blocks.len())
+    }
+
+    /// Adds more memory from the page allocator to the byte allocator.
+    ///
+    /// The heap grows by its current size up to [`MAX_HEAP_EXPAND_SIZE`]
+    /// each time, and by less if there are no such contiguous pages.
+    fn expand_heap(&self, balloc: &mut DefaultByteAllocator, layout: Layout) -> AllocResult {
+        let min_size = layout.size().next_power_of_two().max(PAGE_SIZE);
+        let mut expand_size = balloc
+            .total_bytes()
+            .next_power_of_two()
+            .min(MAX_HEAP_EXPAND_SIZE)
+            .max(min_size);
+        loop {
+            match self.alloc_pages(expand_size / PAGE_SIZE, PAGE_SIZE) {
+                Ok(heap_ptr) => {
+                    debug!(
+                        "expand heap memory: [{:#x}, {:#x})",
+                        heap_ptr,
+                        heap_ptr + expand_size
+                    );
+                    return balloc.add_memory(heap_ptr, expand_size);
+                }
+                Err(e) if expand_size <= min_size => return Err(e),
+                Err(_) => expand_size /= 2,
             }
         }
     }
@@ -133,17 +264,59 @@ impl GlobalAllocator {
     ///
     /// [`alloc`]: GlobalAllocator::alloc
     pub fn dealloc(&self, pos: NonNull<u8>, layout: Layout) {
-        self.balloc.lock().dealloc(pos, layout)
+        #[cfg(feature = "tracking")]
+        {
+            tracking::dealloc(pos, layout, |pos, layout| self.dealloc_raw(pos, layout))
+        }
+        #[cfg(not(feature = "tracking"))]
+        {
+            self.dealloc_raw(pos, layout)
+        }
+    }
+
+    fn dealloc_raw(&self, pos: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "debug")]
+        {
+            debug::dealloc(pos, layout, |pos, layout| self.dealloc_block(pos, layout))
+        }
+        #[cfg(not(feature = "debug"))]
+        {
+            self.dealloc_block(pos, layout)
+        }
+    }
+
+    fn dealloc_block(&self, pos: NonNull<u8>, layout: Layout) {
+        if layout.size() >= LARGE_ALLOC_SIZE {
+            let num_pages = layout.size().div_ceil(PAGE_SIZE);
+            self.dealloc_pages(pos.as_ptr() as usize, num_pages)
+        } else {
+            #[cfg(feature = "smp")]
+            if let Some(class) = cache::size_class(layout) {
+                cache::dealloc(pos, class, |layout, blocks| {
+                    let mut balloc = self.balloc.lock();
+                    for &block in blocks {
+                        balloc.dealloc(unsafe { NonNull::new_unchecked(block as *mut u8) }, layout);
+                    }
+                });
+                return;
+            }
+
+            self.balloc.lock().dealloc(pos, layout)
+        }
     }
 
     /// Allocates contiguous pages.
     ///
-    /// It allocates `num_pages` pages from the page allocator.
+    /// It allocates `num_pages` pages from the page allocator, firstly from
+    /// the initial region, then from the regions added later.
     ///
     /// `align_pow2` must be a power of 2, and the returned region bound will be
     /// aligned to it.
     pub fn alloc_pages(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
-        self.palloc.lock().alloc_pages(num_pages, align_pow2)
+        match self.palloc.lock().alloc_pages(num_pages, align_pow2) {
+            Err(AllocError::NoMemory) => self.regions.lock().alloc_pages(num_pages, align_pow2),
+            res => res,
+        }
     }
 
     /// Gives back the allocated pages starts from `pos` to the page allocator.
@@ -154,10 +327,14 @@ impl GlobalAllocator {
     ///
     /// [`alloc_pages`]: GlobalAllocator::alloc_pages
     pub fn dealloc_pages(&self, pos: usize, num_pages: usize) {
-        self.palloc.lock().dealloc_pages(pos, num_pages)
+        if !self.regions.lock().dealloc_pages(pos, num_pages) {
+            self.palloc.lock().dealloc_pages(pos, num_pages)
+        }
     }
 
     /// Returns the number of allocated bytes in the byte allocator.
+    ///
+    /// The free blocks held by the per-CPU caches are counted as allocated.
     pub fn used_bytes(&self) -> usize {
         self.balloc.lock().used_bytes()
     }
@@ -169,12 +346,12 @@ impl GlobalAllocator {
 
     /// Returns the number of allocated pages in the page allocator.
     pub fn used_pages(&self) -> usize {
-        self.palloc.lock().used_pages()
+        self.palloc.lock().used_pages() + self.regions.lock().used_pages()
     }
 
     /// Returns the number of available pages in the page allocator.
     pub fn available_pages(&self) -> usize {
-        self.palloc.lock().available_pages()
+        self.palloc.lock().available_pages() + self.regions.lock().available_pages()
     }
 }
 
@@ -220,9 +397,11 @@ pub fn global_init(start_vaddr: usize, size: usize) {
 /// Add the given memory region to the global allocator.
 ///
 /// Users should ensure that the region is valid and not being used by others,
-/// so that the allocated memory is also valid.
+/// so that the allocated memory is also valid. Unlike [`global_init`], the
+/// region is accessed immediately to store the page bookkeeping.
 ///
-/// It's similar to [`global_init`], but can be called multiple times.
+/// It's similar to [`global_init`], but can be called multiple times, also
+/// for regions found at runtime (e.g., from a DTB or a memory hotplug device).
 pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
     debug!(
         "add a memory region to global allocator: [{:#x}, {:#x})",
@@ -231,3 +410,19 @@ pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
     );
     GLOBAL_ALLOCATOR.add_memory(start_vaddr, size)
 }
+
+/// Removes the memory region added by [`global_add_memory`] with the same
+/// bounds from the global allocator.
+///
+/// It fails if some pages in the region are still in use, which is always the
+/// case once the heap has been expanded from the region, see
+/// [`GlobalAllocator::remove_memory`]. Once it succeeds, the region is no
+/// longer accessed by the allocator.
+pub fn global_remove_memory(start_vaddr: usize, size: usize) -> AllocResult {
+    debug!(
+        "remove a memory region from global allocator: [{:#x}, {:#x})",
+        start_vaddr,
+        start_vaddr + size
+    );
+    GLOBAL_ALLOCATOR.remove_memory(start_vaddr, size)
+}
@@ -0,0 +1,334 @@
+//! Page allocation from memory regions added at runtime.
+
+use allocator::{AllocError, AllocResult};
+use memory_addr::{align_down_4k, align_up, align_up_4k};
+
+use crate::PAGE_SIZE;
+
+/// Maximum number of memory regions that can be added.
+const MAX_REGIONS: usize = 32;
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// A memory region, whose pages are tracked by a bitmap stored in its first
+/// pages.
+struct Region {
+    /// Start address of the whole region, including the bitmap.
+    start: usize,
+    /// Size of the whole region, including the bitmap.
+    size: usize,
+    /// Address of the first allocatable page.
+    base: usize,
+    total_pages: usize,
+    used_pages: usize,
+    /// One bit for each page, set if the page is allocated.
+    bitmap: &'static mut [u64],
+}
+
+impl Region {
+    /// Creates a region and clears its bitmap.
+    ///
+    /// # Safety
+    ///
+    /// The memory region must be valid, and not used by others.
+    unsafe fn new(start: usize, size: usize) -> Option<Self> {
+        let num_pages = size / PAGE_SIZE;
+        let num_words = num_pages.div_ceil(BITS_PER_WORD);
+        let bitmap_pages = (num_words * 8).div_ceil(PAGE_SIZE);
+        if num_pages <= bitmap_pages {
+            return None;
+        }
+        let bitmap = core::slice::from_raw_parts_mut(start as *mut u64, num_words);
+        bitmap.fill(0);
+        Some(Self {
+            start,
+            size,
+            base: start + bitmap_pages * PAGE_SIZE,
+            total_pages: num_pages - bitmap_pages,
+            used_pages: 0,
+            bitmap,
+        })
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.size
+    }
+
+    fn contains(&self, pos: usize) -> bool {
+        self.base <= pos && pos < self.base + self.total_pages * PAGE_SIZE
+    }
+
+    /// Finds the first page in `[from, to)` that is allocated (if `used` is
+    /// `true`) or free (if `used` is `false`).
+    fn find(&self, from: usize, to: usize, used: bool) -> Option<usize> {
+        let mut idx = from;
+        while idx < to {
+            let word = self.bitmap[idx / BITS_PER_WORD];
+            let bits = (if used { word } else { !word }) >> (idx % BITS_PER_WORD);
+            if bits != 0 {
+                let found = idx + bits.trailing_zeros() as usize;
+                return (found < to).then_some(found);
+            }
+            idx = align_up(idx + 1, BITS_PER_WORD);
+        }
+        None
+    }
+
+    fn set_range(&mut self, from: usize, num_pages: usize, used: bool) {
+        for idx in from..from + num_pages {
+            let mask = 1 << (idx % BITS_PER_WORD);
+            let word = &mut self.bitmap[idx / BITS_PER_WORD];
+            debug_assert_eq!(*word & mask != 0, !used);
+            if used {
+                *word |= mask;
+            } else {
+                *word &= !mask;
+            }
+        }
+    }
+
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> Option<usize> {
+        if num_pages > self.total_pages - self.used_pages {
+            return None;
+        }
+        // Pages whose index is `first` modulo `align_pages` are aligned.
+        let align_pages = align_pow2 / PAGE_SIZE;
+        let first = (align_up(self.base, align_pow2) - self.base) / PAGE_SIZE;
+        let next_aligned = |idx: usize| first + align_up(idx.max(first) - first, align_pages);
+
+        let mut idx = first;
+        while idx + num_pages <= self.total_pages {
+            match self.find(idx, idx + num_pages, true) {
+                None => {
+                    self.set_range(idx, num_pages, true);
+                    self.used_pages += num_pages;
+                    return Some(self.base + idx * PAGE_SIZE);
+                }
+                Some(used) => match self.find(used + 1, self.total_pages, false) {
+                    Some(free) => idx = next_aligned(free),
+                    None => break,
+                },
+            }
+        }
+        None
+    }
+
+    fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        let idx = (pos - self.base) / PAGE_SIZE;
+        assert!(idx + num_pages <= self.total_pages);
+        self.set_range(idx, num_pages, false);
+        self.used_pages -= num_pages;
+    }
+}
+
+/// A page allocator over memory regions that can be added and removed at
+/// runtime, e.g., found after boot or plugged in.
+pub(crate) struct RegionPageAllocator {
+    regions: [Option<Region>; MAX_REGIONS],
+}
+
+impl RegionPageAllocator {
+    pub const fn new() -> Self {
+        Self {
+            regions: [const { None }; MAX_REGIONS],
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter().flatten()
+    }
+
+    /// Adds a memory region, whose bounds are aligned inwards to pages.
+    ///
+    /// # Safety
+    ///
+    /// The memory region must be valid, and not used by others.
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) -> AllocResult {
+        let (start, end) = (align_up_4k(start), align_down_4k(start + size));
+        if start >= end {
+            return Err(AllocError::InvalidParam);
+        }
+        if self.iter().any(|r| r.start < end && start < r.end()) {
+            return Err(AllocError::MemoryOverlap);
+        }
+        let slot = self
+            .regions
+            .iter_mut()
+            .find(|r| r.is_none())
+            .ok_or(AllocError::NoMemory)?;
+        *slot = Some(Region::new(start, end - start).ok_or(AllocError::InvalidParam)?);
+        Ok(())
+    }
+
+    /// Removes the memory region previously added with the same bounds.
+    ///
+    /// It fails with [`AllocError::MemoryOverlap`] if some pages in the region
+    /// are still allocated.
+    pub fn remove_region(&mut self, start: usize, size: usize) -> AllocResult {
+        let (start, end) = (align_up_4k(start), align_down_4k(start + size));
+        let slot = self
+            .regions
+            .iter_mut()
+            .find(|r| matches!(r, Some(r) if r.start == start && r.end() == end))
+            .ok_or(AllocError::InvalidParam)?;
+        if slot.as_ref().is_some_and(|r| r.used_pages > 0) {
+            return Err(AllocError::MemoryOverlap);
+        }
+        *slot = None;
+        Ok(())
+    }
+
+    pub fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if align_pow2 % PAGE_SIZE != 0 || !align_pow2.is_power_of_two() {
+            return Err(AllocError::InvalidParam);
+        }
+        self.regions
+            .iter_mut()
+            .flatten()
+            .find_map(|r| r.alloc_pages(num_pages, align_pow2))
+            .ok_or(AllocError::NoMemory)
+    }
+
+    /// Gives back the pages to the region containing `pos`, returns `false`
+    /// if there is no such region.
+    pub fn dealloc_pages(&mut self, pos: usize, num_pages: usize) -> bool {
+        match self.regions.iter_mut().flatten().find(|r| r.contains(pos)) {
+            Some(r) => {
+                r.dealloc_pages(pos, num_pages);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn total_pages(&self) -> usize {
+        self.iter().map(|r| r.total_pages).sum()
+    }
+
+    pub fn used_pages(&self) -> usize {
+        self.iter().map(|r| r.used_pages).sum()
+    }
+
+    pub fn available_pages(&self) -> usize {
+        self.total_pages() - self.used_pages()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a leaked memory region of `num_pages` pages aligned to 64 KB.
+    fn memory(num_pages: usize) -> usize {
+        let layout = std::alloc::Layout::from_size_align(num_pages * PAGE_SIZE, 0x10000).unwrap();
+        let ptr = unsafe { std::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+        ptr as usize
+    }
+
+    #[test]
+    fn test_bitmap() {
+        let start = memory(200);
+        let mut palloc = RegionPageAllocator::new();
+        unsafe { palloc.add_region(start, 200 * PAGE_SIZE).unwrap() };
+        // The bitmap of 200 pages takes the first page.
+        let base = start + PAGE_SIZE;
+        assert_eq!(palloc.total_pages(), 199);
+
+        assert_eq!(palloc.alloc_pages(1, PAGE_SIZE), Ok(base));
+        assert_eq!(palloc.alloc_pages(3, PAGE_SIZE), Ok(base + PAGE_SIZE));
+        assert_eq!(palloc.used_pages(), 4);
+        assert!(palloc.dealloc_pages(base, 1));
+        assert_eq!(palloc.alloc_pages(1, PAGE_SIZE), Ok(base));
+        // A hole of one page is skipped for two pages.
+        assert!(palloc.dealloc_pages(base + PAGE_SIZE, 1));
+        assert_eq!(palloc.alloc_pages(2, PAGE_SIZE), Ok(base + 4 * PAGE_SIZE));
+        assert_eq!(palloc.alloc_pages(1, PAGE_SIZE), Ok(base + PAGE_SIZE));
+
+        // Runs across the words of the bitmap.
+        assert_eq!(palloc.alloc_pages(100, PAGE_SIZE), Ok(base + 6 * PAGE_SIZE));
+        assert!(palloc.dealloc_pages(base + 60 * PAGE_SIZE, 10));
+        assert_eq!(palloc.alloc_pages(10, PAGE_SIZE), Ok(base + 60 * PAGE_SIZE));
+        assert_eq!(
+            palloc.alloc_pages(93, PAGE_SIZE),
+            Ok(base + 106 * PAGE_SIZE)
+        );
+        assert_eq!(palloc.available_pages(), 0);
+        assert_eq!(palloc.alloc_pages(1, PAGE_SIZE), Err(AllocError::NoMemory));
+
+        assert!(palloc.dealloc_pages(base + 106 * PAGE_SIZE, 93));
+        assert_eq!(palloc.available_pages(), 93);
+    }
+
+    #[test]
+    fn test_aligned() {
+        let start = memory(200);
+        let mut palloc = RegionPageAllocator::new();
+        unsafe { palloc.add_region(start, 200 * PAGE_SIZE).unwrap() };
+        let base = start + PAGE_SIZE;
+
+        // The first page is not aligned to 16 KB, as the bitmap is before it.
+        assert_eq!(palloc.alloc_pages(1, 0x4000), Ok(start + 0x4000));
+        assert_eq!(palloc.alloc_pages(1, PAGE_SIZE), Ok(base));
+        assert_eq!(palloc.alloc_pages(2, 0x4000), Ok(start + 0x8000));
+        assert!(palloc.dealloc_pages(start + 0x8000, 2));
+        assert_eq!(palloc.alloc_pages(4, 0x8000), Ok(start + 0x8000));
+        // The free pages from `start + 0xc000` are skipped, as they are not
+        // aligned to 32 KB.
+        assert_eq!(palloc.alloc_pages(3, 0x8000), Ok(start + 0x10000));
+        // The alignment must be a power of two, and at least a page.
+        assert_eq!(palloc.alloc_pages(1, 0x3000), Err(AllocError::InvalidParam));
+        assert_eq!(palloc.alloc_pages(1, 0x800), Err(AllocError::InvalidParam));
+        // No aligned run in the region.
+        assert_eq!(palloc.alloc_pages(1, 0x100_0000), Err(AllocError::NoMemory));
+    }
+
+    #[test]
+    fn test_add_remove() {
+        let start = memory(64);
+        let mut palloc = RegionPageAllocator::new();
+        let (a, b) = (start, start + 32 * PAGE_SIZE);
+        unsafe {
+            // The bounds are aligned inwards.
+            palloc.add_region(a + 1, 32 * PAGE_SIZE - 1).unwrap();
+            assert_eq!(palloc.total_pages(), 30);
+            palloc.add_region(a + 1, 32 * PAGE_SIZE - 1).unwrap_err();
+            palloc.remove_region(a + 1, 32 * PAGE_SIZE - 1).unwrap();
+
+            palloc.add_region(a, 32 * PAGE_SIZE).unwrap();
+            assert_eq!(
+                palloc.add_region(a + 16 * PAGE_SIZE, 32 * PAGE_SIZE),
+                Err(AllocError::MemoryOverlap)
+            );
+            // Too small to hold the bitmap and a page.
+            assert_eq!(
+                palloc.add_region(b, PAGE_SIZE),
+                Err(AllocError::InvalidParam)
+            );
+            assert_eq!(palloc.add_region(b + 1, 100), Err(AllocError::InvalidParam));
+            palloc.add_region(b, 32 * PAGE_SIZE).unwrap();
+        }
+        assert_eq!(palloc.total_pages(), 62);
+
+        // Only the region with the same bounds is removed.
+        assert_eq!(
+            palloc.remove_region(a, 16 * PAGE_SIZE),
+            Err(AllocError::InvalidParam)
+        );
+        let pos = palloc.alloc_pages(1, PAGE_SIZE).unwrap();
+        assert_eq!(pos, a + PAGE_SIZE);
+        assert_eq!(
+            palloc.remove_region(a, 32 * PAGE_SIZE),
+            Err(AllocError::MemoryOverlap)
+        );
+        assert!(palloc.dealloc_pages(pos, 1));
+        assert_eq!(palloc.remove_region(a, 32 * PAGE_SIZE), Ok(()));
+        assert_eq!(palloc.total_pages(), 31);
+
+        // Pages are taken from the remaining region, and are not found in the
+        // removed one.
+        assert_eq!(palloc.alloc_pages(1, PAGE_SIZE), Ok(b + PAGE_SIZE));
+        assert!(!palloc.dealloc_pages(a + PAGE_SIZE, 1));
+        assert_eq!(palloc.alloc_pages(31, PAGE_SIZE), Err(AllocError::NoMemory));
+    }
+}
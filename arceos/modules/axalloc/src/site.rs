@@ -0,0 +1,35 @@
+//! Allocation sites, which are found and symbolized by the kernel.
+
+use core::fmt;
+
+/// Extern interfaces for finding allocation sites that must be implemented in
+/// other crates, used by the `tracking` and `debug` features.
+#[crate_interface::def_interface]
+pub trait TrackIf {
+    /// Returns the address of the code that requests the current allocation,
+    /// outside the allocator and the `alloc` crate.
+    ///
+    /// It must not allocate memory.
+    fn alloc_site() -> usize;
+
+    /// Returns the name of the function containing `addr`, and the offset of
+    /// `addr` in it.
+    fn symbolize(addr: usize) -> Option<(&'static str, usize)>;
+}
+
+/// Returns the site of the current allocation or deallocation.
+pub(crate) fn current() -> usize {
+    crate_interface::call_interface!(TrackIf::alloc_site)
+}
+
+/// An allocation site, printed with its symbol if available.
+pub(crate) struct Site(pub usize);
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match crate_interface::call_interface!(TrackIf::symbolize, self.0) {
+            Some((name, off)) => write!(f, "{:#x} ({}+{:#x})", self.0, name, off),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Allocation statistics and tracking of live allocations.
+//!
+//! Each allocation is prefixed with a header that records its size and the
+//! allocation site, and links it into a list of live allocations. The site is
+//! found by [`TrackIf::alloc_site`](crate::TrackIf::alloc_site), which is
+//! implemented by the kernel, as the allocator itself cannot unwind the stack.
+//!
+//! A [`Report`] summarizes the counters and the live allocations, which can be
+//! used as a leak report at shutdown or on demand.
+
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use allocator::AllocResult;
+use kspin::SpinNoIrq;
+use memory_addr::align_up;
+
+use crate::site::{self, Site};
+
+/// Number of size classes, each of which covers the sizes from the previous
+/// power of 2 (exclusive) to the next one (inclusive), the last one covers
+/// all the larger sizes.
+const NUM_SIZE_CLASSES: usize = 16;
+
+/// The smallest size class is for sizes up to `1 << MIN_SIZE_CLASS_SHIFT`.
+const MIN_SIZE_CLASS_SHIFT: usize = 3;
+
+/// Maximum number of distinct sites shown in a [`Report`].
+const MAX_REPORT_SITES: usize = 32;
+
+/// Maximum number of live allocations listed in a [`Report`].
+const MAX_REPORT_BLOCKS: usize = 32;
+
+/// The header placed right before each tracked allocation.
+#[repr(C)]
+struct Header {
+    prev: *mut Header,
+    next: *mut Header,
+    size: usize,
+    site: usize,
+}
+
+/// The list of live allocations, the most recent first.
+struct LiveList {
+    head: *mut Header,
+}
+
+unsafe impl Send for LiveList {}
+
+static LIVE: SpinNoIrq<LiveList> = SpinNoIrq::new(LiveList {
+    head: core::ptr::null_mut(),
+});
+
+struct Counters {
+    live_bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    failed_allocs: AtomicUsize,
+    class_allocs: [AtomicUsize; NUM_SIZE_CLASSES],
+    class_frees: [AtomicUsize; NUM_SIZE_CLASSES],
+}
+
+static COUNTERS: Counters = Counters {
+    live_bytes: AtomicUsize::new(0),
+    peak_bytes: AtomicUsize::new(0),
+    failed_allocs: AtomicUsize::new(0),
+    class_allocs: [const { AtomicUsize::new(0) }; NUM_SIZE_CLASSES],
+    class_frees: [const { AtomicUsize::new(0) }; NUM_SIZE_CLASSES],
+};
+
+fn size_class(size: usize) -> usize {
+    let shift = size.next_power_of_two().trailing_zeros() as usize;
+    shift
+        .saturating_sub(MIN_SIZE_CLASS_SHIFT)
+        .min(NUM_SIZE_CLASSES - 1)
+}
+
+/// Returns the offset of the user data from the start of the underlying
+/// allocation, and the layout of the underlying allocation.
+fn outer_layout(layout: Layout) -> (usize, Layout) {
+    let offset = align_up(core::mem::size_of::<Header>(), layout.align());
+    let align = layout.align().max(core::mem::align_of::<Header>());
+    let outer = Layout::from_size_align(offset + layout.size(), align).unwrap();
+    (offset, outer)
+}
+
+/// Allocates with the header by `alloc_raw`, and records the allocation.
+pub(crate) fn alloc(
+    layout: Layout,
+    alloc_raw: impl FnOnce(Layout) -> AllocResult<NonNull<u8>>,
+) -> AllocResult<NonNull<u8>> {
+    let (offset, outer) = outer_layout(layout);
+    let raw = alloc_raw(outer).inspect_err(|_| {
+        COUNTERS.failed_allocs.fetch_add(1, Ordering::Relaxed);
+    })?;
+    let site = site::current();
+
+    let ptr = unsafe { raw.add(offset) };
+    let header = unsafe { ptr.cast::<Header>().as_ptr().sub(1) };
+    {
+        let mut live = LIVE.lock();
+        unsafe {
+            header.write(Header {
+                prev: core::ptr::null_mut(),
+                next: live.head,
+                size: layout.size(),
+                site,
+            });
+            if let Some(next) = live.head.as_mut() {
+                next.prev = header;
+            }
+        }
+        live.head = header;
+    }
+
+    let live_bytes = COUNTERS
+        .live_bytes
+        .fetch_add(layout.size(), Ordering::Relaxed)
+        + layout.size();
+    COUNTERS.peak_bytes.fetch_max(live_bytes, Ordering::Relaxed);
+    COUNTERS.class_allocs[size_class(layout.size())].fetch_add(1, Ordering::Relaxed);
+    Ok(ptr)
+}
+
+/// Removes the record of the allocation, and deallocates it with the header
+/// by `dealloc_raw`.
+pub(crate) fn dealloc(
+    pos: NonNull<u8>,
+    layout: Layout,
+    dealloc_raw: impl FnOnce(NonNull<u8>, Layout),
+) {
+    let (offset, outer) = outer_layout(layout);
+    let header = unsafe { pos.cast::<Header>().as_ptr().sub(1) };
+    {
+        let mut live = LIVE.lock();
+        unsafe {
+            let Header { prev, next, .. } = header.read();
+            match prev.as_mut() {
+                Some(prev) => prev.next = next,
+                None => live.head = next,
+            }
+            if let Some(next) = next.as_mut() {
+                next.prev = prev;
+            }
+        }
+    }
+
+    COUNTERS
+        .live_bytes
+        .fetch_sub(layout.size(), Ordering::Relaxed);
+    COUNTERS.class_frees[size_class(layout.size())].fetch_add(1, Ordering::Relaxed);
+    dealloc_raw(unsafe { pos.sub(offset) }, outer);
+}
+
+/// Live allocations from the same site.
+#[derive(Clone, Copy, Default)]
+struct SiteStat {
+    site: usize,
+    blocks: usize,
+    bytes: usize,
+}
+
+/// A live allocation.
+#[derive(Clone, Copy, Default)]
+struct BlockInfo {
+    addr: usize,
+    size: usize,
+    site: usize,
+}
+
+/// A snapshot of the allocation statistics and the live allocations.
+///
+/// It is printed by the [`Display`](fmt::Display) implementation. Taking the
+/// snapshot does not allocate memory, and no lock is held while printing.
+pub struct Report {
+    live_bytes: usize,
+    live_blocks: usize,
+    peak_bytes: usize,
+    failed_allocs: usize,
+    class_allocs: [usize; NUM_SIZE_CLASSES],
+    class_frees: [usize; NUM_SIZE_CLASSES],
+    sites: [SiteStat; MAX_REPORT_SITES],
+    num_sites: usize,
+    /// Live allocations from the sites not in `sites`.
+    other_sites: SiteStat,
+    blocks: [BlockInfo; MAX_REPORT_BLOCKS],
+    num_blocks: usize,
+}
+
+/// Takes a snapshot of the allocation statistics and the live allocations.
+pub fn report() -> Report {
+    let mut report = Report {
+        live_bytes: 0,
+        live_blocks: 0,
+        peak_bytes: COUNTERS.peak_bytes.load(Ordering::Relaxed),
+        failed_allocs: COUNTERS.failed_allocs.load(Ordering::Relaxed),
+        class_allocs: core::array::from_fn(|i| COUNTERS.class_allocs[i].load(Ordering::Relaxed)),
+        class_frees: core::array::from_fn(|i| COUNTERS.class_frees[i].load(Ordering::Relaxed)),
+        sites: [SiteStat::default(); MAX_REPORT_SITES],
+        num_sites: 0,
+        other_sites: SiteStat::default(),
+        blocks: [BlockInfo::default(); MAX_REPORT_BLOCKS],
+        num_blocks: 0,
+    };
+
+    let live = LIVE.lock();
+    let mut header = live.head;
+    while let Some(h) = unsafe { header.as_ref() } {
+        report.live_bytes += h.size;
+        report.live_blocks += 1;
+        if report.num_blocks < MAX_REPORT_BLOCKS {
+            report.blocks[report.num_blocks] = BlockInfo {
+                addr: header as usize + core::mem::size_of::<Header>(),
+                size: h.size,
+                site: h.site,
+            };
+            report.num_blocks += 1;
+        }
+
+        let found = report.sites[..report.num_sites]
+            .iter()
+            .position(|s| s.site == h.site);
+        let stat = match found {
+            Some(i) => &mut report.sites[i],
+            None if report.num_sites < MAX_REPORT_SITES => {
+                report.num_sites += 1;
+                let stat = &mut report.sites[report.num_sites - 1];
+                stat.site = h.site;
+                stat
+            }
+            None => &mut report.other_sites,
+        };
+        stat.blocks += 1;
+        stat.bytes += h.size;
+        header = h.next;
+    }
+    drop(live);
+
+    report.sites[..report.num_sites].sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes));
+    report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Live: {} bytes in {} allocations, peak: {} bytes, failed: {}",
+            self.live_bytes, self.live_blocks, self.peak_bytes, self.failed_allocs
+        )?;
+
+        writeln!(
+            f,
+            "{:>12} {:>10} {:>10} {:>10}",
+            "size", "allocs", "frees", "live"
+        )?;
+        for i in 0..NUM_SIZE_CLASSES {
+            let (allocs, frees) = (self.class_allocs[i], self.class_frees[i]);
+            if allocs == 0 {
+                continue;
+            }
+            let limit = 1usize << (i + MIN_SIZE_CLASS_SHIFT);
+            let (op, size) = if i == NUM_SIZE_CLASSES - 1 {
+                (">", limit / 2)
+            } else {
+                ("<=", limit)
+            };
+            let live = allocs.saturating_sub(frees);
+            writeln!(
+                f,
+                "{:>2} {:>9} {:>10} {:>10} {:>10}",
+                op, size, allocs, frees, live
+            )?;
+        }
+
+        if self.live_blocks == 0 {
+            return Ok(());
+        }
+        writeln!(f, "Live allocations by site:")?;
+        for s in &self.sites[..self.num_sites] {
+            writeln!(
+                f,
+                "  {:>10} bytes in {:>6} allocations from {}",
+                s.bytes,
+                s.blocks,
+                Site(s.site)
+            )?;
+        }
+        if self.other_sites.blocks > 0 {
+            let s = &self.other_sites;
+            writeln!(
+                f,
+                "  {:>10} bytes in {:>6} allocations from other sites",
+                s.bytes, s.blocks
+            )?;
+        }
+        writeln!(f, "Most recent live allocations:")?;
+        for b in &self.blocks[..self.num_blocks] {
+            writeln!(f, "  {:#x}: {} bytes from {}", b.addr, b.size, Site(b.site))?;
+        }
+        Ok(())
+    }
+}
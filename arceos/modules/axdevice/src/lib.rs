@@ -0,0 +1,66 @@
+//! [ArceOS](https://github.com/arceos-org/arceos) emulated devices for guest
+//! VMs.
+//!
+//! Devices occupy guest physical MMIO regions and are driven by the
+//! [`MmioRead`](riscv_vcpu::AxVCpuExitReason::MmioRead) and
+//! [`MmioWrite`](riscv_vcpu::AxVCpuExitReason::MmioWrite) VM exits. Currently
+//! supported devices:
+//!
+//! - [`VirtioBalloon`]: virtio memory balloon, to reclaim guest RAM.
+//! - [`VirtioBlk`]: virtio block device backed by a file on the host.
+//! - [`VirtioConsole`]: virtio console backed by the host console.
+//! - [`VPlic`]: PLIC that injects the interrupts of the devices into the
+//!   guest.
+
+#![cfg_attr(not(test), no_std)]
+
+#[macro_use]
+extern crate log;
+extern crate alloc;
+
+mod plic;
+mod state;
+mod virtio;
+
+pub use self::plic::{VPlic, PLIC_MAX_SOURCES, PLIC_MMIO_SIZE};
+pub use self::state::{StateReader, StateWriter};
+pub use self::virtio::{
+    Descriptor, DescriptorChain, VirtioBackend, VirtioBalloon, VirtioBlk, VirtioConsole,
+    VirtioMmioDevice, Virtqueue,
+};
+
+use alloc::sync::Arc;
+
+use axerrno::AxResult;
+use axmm::AddrSpace;
+use axsync::Mutex;
+use riscv_vcpu::AccessWidth;
+
+/// The guest physical memory, through which the devices access the buffers
+/// provided by the guest.
+pub type GuestMemory = Arc<Mutex<AddrSpace>>;
+
+/// An emulated device that occupies a guest physical MMIO region.
+///
+/// `offset` is relative to the start of the region.
+pub trait MmioDevice: Send + Sync {
+    /// Handles a read from the device, returns the value read.
+    fn handle_read(&self, offset: usize, width: AccessWidth) -> AxResult<u64>;
+    /// Handles a write to the device.
+    fn handle_write(&self, offset: usize, width: AccessWidth, val: u64) -> AxResult;
+
+    /// Saves the device state for a VM snapshot. Stateless devices save
+    /// nothing.
+    fn save_state(&self, _w: &mut StateWriter) {}
+
+    /// Restores the device state saved by [`save_state`](Self::save_state).
+    fn restore_state(&self, _r: &mut StateReader) -> AxResult {
+        Ok(())
+    }
+}
+
+/// An interrupt line from a device to the guest interrupt controller.
+pub trait IrqLine: Send + Sync {
+    /// Sets the level of the interrupt line, `true` for asserted.
+    fn set_level(&self, level: bool);
+}
@@ -0,0 +1,301 @@
+//! Emulated PLIC (Platform-Level Interrupt Controller).
+//!
+//! The register layout follows the PLIC of the QEMU `virt` machine, where
+//! context `2 * hart` is the M-mode context of the hart and `2 * hart + 1` is
+//! the S-mode one. Only the S-mode contexts are delivered to the guest, as the
+//! virtual supervisor external interrupt (`VSEIP`) of the target vCPU.
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use axsync::Mutex;
+use riscv_vcpu::{AccessWidth, VmHarts};
+
+use crate::{IrqLine, MmioDevice, StateReader, StateWriter};
+
+const PRIORITY_BASE: usize = 0x0;
+const PENDING_BASE: usize = 0x1000;
+const ENABLE_BASE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CONTEXT_THRESHOLD: usize = 0x0;
+const CONTEXT_CLAIM: usize = 0x4;
+
+/// Size of the MMIO region of the PLIC.
+pub const PLIC_MMIO_SIZE: usize = 0x60_0000;
+
+/// Maximum number of interrupt sources, including the reserved source 0.
+pub const PLIC_MAX_SOURCES: usize = 1024;
+/// Number of contexts per hart.
+const CONTEXTS_PER_HART: usize = 2;
+
+struct PlicState {
+    priority: Vec<u32>,
+    /// Bitmaps of the pending sources, and of the claimed but not completed
+    /// sources.
+    pending: Vec<u32>,
+    claimed: Vec<u32>,
+    /// Levels of the interrupt lines.
+    level: Vec<u32>,
+    /// Bitmaps of the enabled sources of each context.
+    enable: Vec<Vec<u32>>,
+    threshold: Vec<u32>,
+}
+
+fn save_words(w: &mut StateWriter, words: &[u32]) {
+    w.put_u32(words.len() as u32);
+    words.iter().for_each(|&word| w.put_u32(word));
+}
+
+fn restore_words(r: &mut StateReader, words: &mut [u32]) -> AxResult {
+    if r.get_u32()? as usize != words.len() {
+        return ax_err!(InvalidData, "vplic: state size mismatch");
+    }
+    for word in words {
+        *word = r.get_u32()?;
+    }
+    Ok(())
+}
+
+fn test_bit(bitmap: &[u32], irq: usize) -> bool {
+    bitmap[irq / 32] & (1 << (irq % 32)) != 0
+}
+
+fn set_bit(bitmap: &mut [u32], irq: usize, val: bool) {
+    if val {
+        bitmap[irq / 32] |= 1 << (irq % 32);
+    } else {
+        bitmap[irq / 32] &= !(1 << (irq % 32));
+    }
+}
+
+impl PlicState {
+    /// Returns the pending and enabled source with the highest priority above
+    /// the threshold of the context. Ties are broken by the lowest ID.
+    fn best_irq(&self, ctx: usize) -> Option<usize> {
+        let mut best: Option<(usize, u32)> = None;
+        for irq in 1..self.priority.len() {
+            let prio = self.priority[irq];
+            if test_bit(&self.pending, irq)
+                && test_bit(&self.enable[ctx], irq)
+                && prio > self.threshold[ctx]
+                && best.map_or(true, |(_, p)| prio > p)
+            {
+                best = Some((irq, prio));
+            }
+        }
+        best.map(|(irq, _)| irq)
+    }
+}
+
+/// An emulated PLIC for the harts of a VM.
+pub struct VPlic {
+    num_sources: usize,
+    harts: Arc<VmHarts>,
+    state: Mutex<PlicState>,
+}
+
+impl VPlic {
+    /// Creates a PLIC with `num_sources` interrupt sources (source 0 is
+    /// reserved), for the harts in `harts`.
+    pub fn new(num_sources: usize, harts: Arc<VmHarts>) -> Self {
+        let num_sources = num_sources.clamp(1, PLIC_MAX_SOURCES);
+        let words = num_sources.div_ceil(32);
+        let num_contexts = harts.num_harts() * CONTEXTS_PER_HART;
+        Self {
+            num_sources,
+            harts,
+            state: Mutex::new(PlicState {
+                priority: vec![0; num_sources],
+                pending: vec![0; words],
+                claimed: vec![0; words],
+                level: vec![0; words],
+                enable: vec![vec![0; words]; num_contexts],
+                threshold: vec![0; num_contexts],
+            }),
+        }
+    }
+
+    /// Returns the interrupt line of the source `irq`, to be connected to a
+    /// device.
+    pub fn irq_line(self: &Arc<Self>, irq: usize) -> Arc<dyn IrqLine> {
+        assert!(irq > 0 && irq < self.num_sources, "invalid PLIC source");
+        Arc::new(VPlicIrqLine {
+            plic: self.clone(),
+            irq,
+        })
+    }
+
+    /// Sets the level of the source `irq`.
+    ///
+    /// The source becomes pending on the assertion, or on the completion while
+    /// the line is still asserted.
+    pub fn set_irq_level(&self, irq: usize, level: bool) {
+        if irq == 0 || irq >= self.num_sources {
+            return;
+        }
+        let mut state = self.state.lock();
+        set_bit(&mut state.level, irq, level);
+        if level && !test_bit(&state.claimed, irq) {
+            set_bit(&mut state.pending, irq, true);
+        }
+        self.update(&state);
+    }
+
+    /// Updates the external interrupts of the harts.
+    fn update(&self, state: &PlicState) {
+        for hart in 0..self.harts.num_harts() {
+            let ctx = hart * CONTEXTS_PER_HART + 1;
+            self.harts
+                .set_external_irq(hart, state.best_irq(ctx).is_some());
+        }
+    }
+
+    fn claim(&self, state: &mut PlicState, ctx: usize) -> u32 {
+        let Some(irq) = state.best_irq(ctx) else {
+            return 0;
+        };
+        set_bit(&mut state.pending, irq, false);
+        set_bit(&mut state.claimed, irq, true);
+        self.update(state);
+        irq as u32
+    }
+
+    fn complete(&self, state: &mut PlicState, ctx: usize, irq: usize) {
+        // The completion is ignored if the source is not enabled for the
+        // context.
+        if irq == 0
+            || irq >= self.num_sources
+            || !test_bit(&state.enable[ctx], irq)
+            || !test_bit(&state.claimed, irq)
+        {
+            return;
+        }
+        set_bit(&mut state.claimed, irq, false);
+        if test_bit(&state.level, irq) {
+            set_bit(&mut state.pending, irq, true);
+        }
+        self.update(state);
+    }
+}
+
+impl MmioDevice for VPlic {
+    fn handle_read(&self, offset: usize, width: AccessWidth) -> AxResult<u64> {
+        if width != AccessWidth::Dword {
+            warn!("vplic: unsupported read width at offset {:#x}", offset);
+            return Ok(0);
+        }
+        let mut state = self.state.lock();
+        let num_contexts = state.threshold.len();
+        let words = state.pending.len();
+        let val = if offset < PENDING_BASE {
+            let irq = (offset - PRIORITY_BASE) / 4;
+            state.priority.get(irq).copied().unwrap_or(0)
+        } else if offset < ENABLE_BASE {
+            let word = (offset - PENDING_BASE) / 4;
+            state.pending.get(word).copied().unwrap_or(0)
+        } else if offset < CONTEXT_BASE {
+            let ctx = (offset - ENABLE_BASE) / ENABLE_STRIDE;
+            let word = (offset - ENABLE_BASE) % ENABLE_STRIDE / 4;
+            if ctx < num_contexts && word < words {
+                state.enable[ctx][word]
+            } else {
+                0
+            }
+        } else {
+            let ctx = (offset - CONTEXT_BASE) / CONTEXT_STRIDE;
+            if ctx >= num_contexts {
+                0
+            } else {
+                match (offset - CONTEXT_BASE) % CONTEXT_STRIDE {
+                    CONTEXT_THRESHOLD => state.threshold[ctx],
+                    CONTEXT_CLAIM => self.claim(&mut state, ctx),
+                    _ => 0,
+                }
+            }
+        };
+        Ok(val as u64)
+    }
+
+    fn handle_write(&self, offset: usize, width: AccessWidth, val: u64) -> AxResult {
+        if width != AccessWidth::Dword {
+            warn!("vplic: unsupported write width at offset {:#x}", offset);
+            return Ok(());
+        }
+        let val = val as u32;
+        let mut state = self.state.lock();
+        let num_contexts = state.threshold.len();
+        let words = state.pending.len();
+        if offset < PENDING_BASE {
+            let irq = (offset - PRIORITY_BASE) / 4;
+            if irq > 0 && irq < self.num_sources {
+                state.priority[irq] = val;
+            }
+        } else if offset < ENABLE_BASE {
+            // The pending bits are read-only.
+        } else if offset < CONTEXT_BASE {
+            let ctx = (offset - ENABLE_BASE) / ENABLE_STRIDE;
+            let word = (offset - ENABLE_BASE) % ENABLE_STRIDE / 4;
+            if ctx < num_contexts && word < words {
+                // Source 0 does not exist.
+                state.enable[ctx][word] = if word == 0 { val & !1 } else { val };
+            }
+        } else {
+            let ctx = (offset - CONTEXT_BASE) / CONTEXT_STRIDE;
+            if ctx < num_contexts {
+                match (offset - CONTEXT_BASE) % CONTEXT_STRIDE {
+                    CONTEXT_THRESHOLD => state.threshold[ctx] = val,
+                    CONTEXT_CLAIM => {
+                        self.complete(&mut state, ctx, val as usize);
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.update(&state);
+        Ok(())
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        let state = self.state.lock();
+        save_words(w, &state.priority);
+        save_words(w, &state.pending);
+        save_words(w, &state.claimed);
+        save_words(w, &state.level);
+        save_words(w, &state.threshold);
+        for enable in &state.enable {
+            save_words(w, enable);
+        }
+    }
+
+    fn restore_state(&self, r: &mut StateReader) -> AxResult {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        restore_words(r, &mut state.priority)?;
+        restore_words(r, &mut state.pending)?;
+        restore_words(r, &mut state.claimed)?;
+        restore_words(r, &mut state.level)?;
+        restore_words(r, &mut state.threshold)?;
+        for enable in &mut state.enable {
+            restore_words(r, enable)?;
+        }
+        self.update(state);
+        Ok(())
+    }
+}
+
+/// An interrupt line connected to a source of [`VPlic`].
+struct VPlicIrqLine {
+    plic: Arc<VPlic>,
+    irq: usize,
+}
+
+impl IrqLine for VPlicIrqLine {
+    fn set_level(&self, level: bool) {
+        self.plic.set_irq_level(self.irq, level);
+    }
+}
@@ -0,0 +1,141 @@
+//! Encoding of the device state saved in VM snapshots.
+//!
+//! The state is a sequence of little-endian integers and length-prefixed byte
+//! strings, read back in the same order as written.
+
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+
+/// Writes device state.
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Creates an empty writer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put_u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn put_u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn put_u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    /// Writes a byte string with its length.
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Returns the encoded state.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads device state written by [`StateWriter`].
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Creates a reader of the encoded state.
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn take(&mut self, len: usize) -> AxResult<&'a [u8]> {
+        if self.buf.len() < len {
+            return ax_err!(InvalidData, "device state is truncated");
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub fn get_u8(&mut self) -> AxResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn get_u16(&mut self) -> AxResult<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn get_u32(&mut self) -> AxResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn get_u64(&mut self) -> AxResult<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a byte string written by [`StateWriter::put_bytes`].
+    pub fn get_bytes(&mut self) -> AxResult<&'a [u8]> {
+        let len = self.get_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Whether all state has been read.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut w = StateWriter::new();
+        w.put_u8(0xab);
+        w.put_u16(0x1234);
+        w.put_u32(0xdead_beef);
+        w.put_u64(0x0123_4567_89ab_cdef);
+        w.put_bytes(b"virtio");
+        w.put_bytes(b"");
+        let bytes = w.into_bytes();
+        assert_eq!(bytes.len(), 1 + 2 + 4 + 8 + (4 + 6) + 4);
+        assert_eq!(&bytes[..3], &[0xab, 0x34, 0x12]);
+
+        let mut r = StateReader::new(&bytes);
+        assert_eq!(r.get_u8().unwrap(), 0xab);
+        assert_eq!(r.get_u16().unwrap(), 0x1234);
+        assert_eq!(r.get_u32().unwrap(), 0xdead_beef);
+        assert_eq!(r.get_u64().unwrap(), 0x0123_4567_89ab_cdef);
+        assert_eq!(r.get_bytes().unwrap(), b"virtio");
+        assert!(!r.is_empty());
+        assert_eq!(r.get_bytes().unwrap(), b"");
+        assert!(r.is_empty());
+        assert!(r.get_u8().is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut w = StateWriter::new();
+        w.put_bytes(b"abcd");
+        let bytes = w.into_bytes();
+        assert!(StateReader::new(&bytes[..7]).get_bytes().is_err());
+        assert!(StateReader::new(&bytes[..3]).get_u32().is_err());
+        assert!(StateReader::new(&[1, 2, 3]).get_u64().is_err());
+
+        // A failed read consumes nothing.
+        let mut r = StateReader::new(&[1, 2, 3]);
+        assert!(r.get_u32().is_err());
+        assert_eq!(r.get_u16().unwrap(), 0x0201);
+    }
+}
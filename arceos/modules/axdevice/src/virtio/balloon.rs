@@ -0,0 +1,160 @@
+use alloc::sync::Arc;
+
+use axerrno::AxResult;
+use axmm::AddrSpace;
+use memory_addr::VirtAddr;
+use riscv_vcpu::{AccessWidth, VmHarts};
+
+use super::{read_le, VirtioBackend, Virtqueue};
+use crate::{StateReader, StateWriter};
+
+const INFLATEQ: usize = 0;
+const DEFLATEQ: usize = 1;
+
+/// Balloon pages are always 4 KiB, whatever the page size of the guest.
+const VIRTIO_BALLOON_PFN_SHIFT: usize = 12;
+
+/// Maximum number of PFNs read and freed at a time.
+const PFN_BATCH: usize = 256;
+
+/// A virtio memory balloon, through which the host asks the guest to give
+/// back some of its RAM.
+///
+/// The host sets the target size of the balloon with
+/// [`set_target`](Self::set_target). The guest driver then inflates the
+/// balloon with pages it no longer uses, whose frames are freed. Pages of a
+/// deflated balloon are allocated again on demand when the guest touches
+/// them, so the deflate queue is only acknowledged.
+pub struct VirtioBalloon {
+    /// The harts of the guest, whose nested TLBs are flushed before the
+    /// frames are freed.
+    harts: Arc<VmHarts>,
+    /// Number of pages the host wants in the balloon.
+    num_pages: u32,
+    /// Number of pages in the balloon, reported by the guest.
+    actual: u32,
+}
+
+impl VirtioBalloon {
+    /// Creates an empty balloon for the VM with `harts`.
+    pub fn new(harts: Arc<VmHarts>) -> Self {
+        Self {
+            harts,
+            num_pages: 0,
+            actual: 0,
+        }
+    }
+
+    /// Sets the number of 4 KiB pages the host wants the guest to give back,
+    /// returns whether it is changed.
+    ///
+    /// The guest is not aware of the change until
+    /// [`VirtioMmioDevice::notify_config_change`](super::VirtioMmioDevice::notify_config_change)
+    /// is called.
+    pub fn set_target(&mut self, num_pages: u32) -> bool {
+        let changed = self.num_pages != num_pages;
+        self.num_pages = num_pages;
+        changed
+    }
+
+    /// Returns the number of 4 KiB pages in the balloon, as reported by the
+    /// guest.
+    pub fn actual(&self) -> u32 {
+        self.actual
+    }
+
+    /// Frees the frames of the pages put into the balloon.
+    fn inflate(&mut self, queue: &mut Virtqueue, mem: &mut AddrSpace) -> AxResult<bool> {
+        let mut used = false;
+        let mut buf = [0u8; PFN_BATCH * 4];
+        while let Some(chain) = queue.pop(mem)? {
+            // PFNs are read and freed in batches, however many the driver
+            // describes.
+            for desc in chain.descs.iter().filter(|d| !d.writable) {
+                let mut offset = 0;
+                while desc.len - offset >= 4 {
+                    let len = (desc.len - offset).min(buf.len()) & !3;
+                    mem.read(desc.addr + offset, &mut buf[..len])?;
+                    offset += len;
+                    let pages = buf[..len].chunks_exact(4).map(|pfn| {
+                        let pfn = u32::from_le_bytes(pfn.try_into().unwrap()) as usize;
+                        VirtAddr::from(pfn << VIRTIO_BALLOON_PFN_SHIFT)
+                    });
+                    // Drop the stale translations on all vCPUs before the
+                    // frames are freed.
+                    let skipped = mem.reclaim_pages(pages, || self.harts.hfence_gvma_all());
+                    if skipped > 0 {
+                        debug!("virtio-balloon: cannot reclaim {} pages", skipped);
+                    }
+                }
+            }
+            queue.push_used(mem, chain.head, 0)?;
+            used = true;
+        }
+        Ok(used)
+    }
+
+    /// Returns the pages taken out of the balloon to the guest, they are
+    /// populated again on access.
+    fn deflate(&mut self, queue: &mut Virtqueue, mem: &mut AddrSpace) -> AxResult<bool> {
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem)? {
+            queue.push_used(mem, chain.head, 0)?;
+            used = true;
+        }
+        Ok(used)
+    }
+}
+
+impl VirtioBackend for VirtioBalloon {
+    const DEVICE_ID: u32 = 5;
+    const QUEUE_NUM: usize = 2;
+
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn read_config(&self, offset: usize, width: AccessWidth) -> u64 {
+        let mut config = [0u8; 8];
+        config[0..4].copy_from_slice(&self.num_pages.to_le_bytes());
+        config[4..8].copy_from_slice(&self.actual.to_le_bytes());
+        read_le(&config, offset, width)
+    }
+
+    fn write_config(&mut self, offset: usize, width: AccessWidth, val: u64) {
+        // Only `actual` is writable by the driver.
+        match (offset, width) {
+            (4, AccessWidth::Dword) => self.actual = val as u32,
+            _ => warn!("virtio-balloon: ignore write to config space {:#x}", offset),
+        }
+    }
+
+    fn notify(
+        &mut self,
+        index: usize,
+        queues: &mut [Virtqueue],
+        mem: &mut AddrSpace,
+    ) -> AxResult<bool> {
+        match index {
+            INFLATEQ => self.inflate(&mut queues[INFLATEQ], mem),
+            DEFLATEQ => self.deflate(&mut queues[DEFLATEQ], mem),
+            _ => Ok(false),
+        }
+    }
+
+    fn reset(&mut self) {
+        // The target is set by the host, it is kept for the next driver.
+        self.actual = 0;
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.put_u32(self.num_pages);
+        w.put_u32(self.actual);
+    }
+
+    fn restore_state(&mut self, r: &mut StateReader) -> AxResult {
+        self.num_pages = r.get_u32()?;
+        self.actual = r.get_u32()?;
+        Ok(())
+    }
+}
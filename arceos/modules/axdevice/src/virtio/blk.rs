@@ -0,0 +1,195 @@
+use axerrno::{ax_err, AxResult};
+use axfs::fops::{File, OpenOptions};
+use axmm::AddrSpace;
+use riscv_vcpu::AccessWidth;
+
+use super::{read_le, write_guest, DescriptorChain, VirtioBackend, Virtqueue, BOUNCE_BUF_SIZE};
+
+const SECTOR_SIZE: u64 = 512;
+
+/// Device is read-only.
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+/// Cache flush command support.
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+/// Size of the request header: type, reserved and sector.
+const REQ_HEADER_SIZE: usize = 16;
+/// Length of the device ID returned by `VIRTIO_BLK_T_GET_ID`.
+const DEVICE_ID_LEN: usize = 20;
+
+/// A virtio block device backed by a file on the host.
+pub struct VirtioBlk {
+    file: File,
+    /// Capacity in 512-byte sectors.
+    capacity: u64,
+    writable: bool,
+}
+
+impl VirtioBlk {
+    /// Creates a block device backed by the disk image at `path`.
+    ///
+    /// The image is opened for reading, and also for writing if `writable`.
+    pub fn new(path: &str, writable: bool) -> AxResult<Self> {
+        let mut opts = OpenOptions::new();
+        opts.read(true);
+        opts.write(writable);
+        let file = File::open(path, &opts)?;
+        let capacity = file.get_attr()?.size() / SECTOR_SIZE;
+        info!(
+            "virtio-blk: {}, {} sectors{}",
+            path,
+            capacity,
+            if writable { "" } else { ", read-only" }
+        );
+        Ok(Self {
+            file,
+            capacity,
+            writable,
+        })
+    }
+
+    /// Processes a request, returns the status and the number of bytes written
+    /// to the data buffers.
+    ///
+    /// The data is copied through a bounce buffer. The direction of the data
+    /// buffers and the range of sectors are checked before the access.
+    fn handle_request(
+        &self,
+        req_type: u32,
+        sector: u64,
+        chain: &DescriptorChain,
+        mem: &mut AddrSpace,
+    ) -> AxResult<(u8, usize)> {
+        // The header and the status are in the first and the last descriptors.
+        let data = &chain.descs[1..chain.descs.len() - 1];
+        let data_len = data.iter().map(|d| d.len as u64).sum::<u64>();
+        if matches!(req_type, VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT)
+            && sector
+                .checked_add(data_len.div_ceil(SECTOR_SIZE))
+                .map_or(true, |end| end > self.capacity)
+        {
+            return ax_err!(InvalidInput, "virtio-blk: access beyond the capacity");
+        }
+        // only used by reads and writes, whose range is checked above
+        let mut offset = sector.wrapping_mul(SECTOR_SIZE);
+        let mut buf = [0; BOUNCE_BUF_SIZE];
+        let mut written = 0;
+        match req_type {
+            VIRTIO_BLK_T_IN => {
+                if data.iter().any(|d| !d.writable) {
+                    return ax_err!(InvalidInput, "virtio-blk: read into a read-only buffer");
+                }
+                for desc in data {
+                    for start in (0..desc.len).step_by(BOUNCE_BUF_SIZE) {
+                        let buf = &mut buf[..BOUNCE_BUF_SIZE.min(desc.len - start)];
+                        let n = self.file.read_at(offset, buf)?;
+                        // Reads beyond the end of the image return zeros.
+                        buf[n..].fill(0);
+                        write_guest(mem, desc.addr + start, buf)?;
+                        offset += buf.len() as u64;
+                        written += buf.len();
+                    }
+                }
+            }
+            VIRTIO_BLK_T_OUT => {
+                if !self.writable {
+                    return ax_err!(PermissionDenied, "virtio-blk: the image is read-only");
+                }
+                if data.iter().any(|d| d.writable) {
+                    return ax_err!(InvalidInput, "virtio-blk: write from a write-only buffer");
+                }
+                for desc in data {
+                    for start in (0..desc.len).step_by(BOUNCE_BUF_SIZE) {
+                        let buf = &mut buf[..BOUNCE_BUF_SIZE.min(desc.len - start)];
+                        mem.read(desc.addr + start, buf)?;
+                        self.file.write_at(offset, buf)?;
+                        offset += buf.len() as u64;
+                    }
+                }
+            }
+            VIRTIO_BLK_T_FLUSH => self.file.flush()?,
+            VIRTIO_BLK_T_GET_ID => {
+                let mut id = [0u8; DEVICE_ID_LEN];
+                let name = b"arceos-virtio-blk";
+                id[..name.len()].copy_from_slice(name);
+                if let Some(desc) = data.first().filter(|d| d.writable) {
+                    let len = desc.len.min(DEVICE_ID_LEN);
+                    write_guest(mem, desc.addr, &id[..len])?;
+                    written += len;
+                }
+            }
+            _ => return Ok((VIRTIO_BLK_S_UNSUPP, 0)),
+        }
+        Ok((VIRTIO_BLK_S_OK, written))
+    }
+}
+
+impl VirtioBackend for VirtioBlk {
+    const DEVICE_ID: u32 = 2;
+    const QUEUE_NUM: usize = 1;
+
+    fn features(&self) -> u64 {
+        if self.writable {
+            VIRTIO_BLK_F_FLUSH
+        } else {
+            VIRTIO_BLK_F_FLUSH | VIRTIO_BLK_F_RO
+        }
+    }
+
+    fn read_config(&self, offset: usize, width: AccessWidth) -> u64 {
+        // Only `capacity` is provided in the configuration space.
+        read_le(&self.capacity.to_le_bytes(), offset, width)
+    }
+
+    fn notify(
+        &mut self,
+        index: usize,
+        queues: &mut [Virtqueue],
+        mem: &mut AddrSpace,
+    ) -> AxResult<bool> {
+        let queue = &mut queues[index];
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem)? {
+            let status_desc = match chain.descs.last() {
+                Some(desc) if chain.descs.len() >= 2 && desc.writable && desc.len >= 1 => *desc,
+                _ => {
+                    // The status cannot be reported, just return the buffers.
+                    warn!("virtio-blk: malformed request without a status");
+                    queue.push_used(mem, chain.head, 0)?;
+                    used = true;
+                    continue;
+                }
+            };
+            let header_desc = chain.descs[0];
+            let mut header = [0u8; REQ_HEADER_SIZE];
+            let (status, written) = if header_desc.writable || header_desc.len < REQ_HEADER_SIZE {
+                warn!("virtio-blk: malformed request header");
+                (VIRTIO_BLK_S_IOERR, 0)
+            } else if let Err(e) = mem.read(header_desc.addr, &mut header) {
+                warn!("virtio-blk: cannot read the request header: {:?}", e);
+                (VIRTIO_BLK_S_IOERR, 0)
+            } else {
+                let req_type = u32::from_le_bytes(header[0..4].try_into().unwrap());
+                let sector = u64::from_le_bytes(header[8..16].try_into().unwrap());
+                self.handle_request(req_type, sector, &chain, mem)
+                    .unwrap_or_else(|e| {
+                        warn!("virtio-blk: request {} failed: {:?}", req_type, e);
+                        (VIRTIO_BLK_S_IOERR, 0)
+                    })
+            };
+            write_guest(mem, status_desc.addr, &[status])?;
+            queue.push_used(mem, chain.head, (written as u32).saturating_add(1))?;
+            used = true;
+        }
+        Ok(used)
+    }
+}
@@ -0,0 +1,106 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use axerrno::AxResult;
+use axmm::AddrSpace;
+use riscv_vcpu::AccessWidth;
+
+use super::{VirtioBackend, Virtqueue, BOUNCE_BUF_SIZE};
+
+const RECEIVEQ: usize = 0;
+const TRANSMITQ: usize = 1;
+
+/// A virtio console backed by the host console.
+///
+/// The input is only read from the host console on [`poll`], so
+/// [`VirtioMmioDevice::poll`](super::VirtioMmioDevice::poll) must be called
+/// periodically.
+///
+/// [`poll`]: VirtioBackend::poll
+#[derive(Default)]
+pub struct VirtioConsole {
+    /// Input from the host that is not received by the guest yet.
+    input: VecDeque<u8>,
+}
+
+impl VirtioConsole {
+    /// Creates a new virtio console.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transmits the output of the guest to the host console.
+    fn transmit(&mut self, queue: &mut Virtqueue, mem: &mut AddrSpace) -> AxResult<bool> {
+        let mut used = false;
+        let mut buf = [0; BOUNCE_BUF_SIZE];
+        while let Some(chain) = queue.pop(mem)? {
+            chain.read_chunks(mem, &mut buf, |data| {
+                axhal::console::write_bytes(data);
+                Ok(())
+            })?;
+            queue.push_used(mem, chain.head, 0)?;
+            used = true;
+        }
+        Ok(used)
+    }
+
+    /// Fills the receive buffers of the guest with the pending input.
+    fn receive(&mut self, queue: &mut Virtqueue, mem: &mut AddrSpace) -> AxResult<bool> {
+        let mut used = false;
+        while !self.input.is_empty() {
+            let Some(chain) = queue.pop(mem)? else {
+                break;
+            };
+            let capacity = chain
+                .descs
+                .iter()
+                .filter(|d| d.writable)
+                .map(|d| d.len)
+                .sum::<usize>();
+            let len = self.input.len().min(capacity);
+            let buf: Vec<u8> = self.input.drain(..len).collect();
+            let written = chain.write_all(mem, &buf)?;
+            queue.push_used(mem, chain.head, written as u32)?;
+            used = true;
+        }
+        Ok(used)
+    }
+}
+
+impl VirtioBackend for VirtioConsole {
+    const DEVICE_ID: u32 = 3;
+    const QUEUE_NUM: usize = 2;
+
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn read_config(&self, _offset: usize, _width: AccessWidth) -> u64 {
+        // No size or multiport features are offered, so no config is valid.
+        0
+    }
+
+    fn notify(
+        &mut self,
+        index: usize,
+        queues: &mut [Virtqueue],
+        mem: &mut AddrSpace,
+    ) -> AxResult<bool> {
+        match index {
+            RECEIVEQ => self.receive(&mut queues[RECEIVEQ], mem),
+            TRANSMITQ => self.transmit(&mut queues[TRANSMITQ], mem),
+            _ => Ok(false),
+        }
+    }
+
+    fn poll(&mut self, queues: &mut [Virtqueue], mem: &mut AddrSpace) -> AxResult<bool> {
+        while let Some(c) = axhal::console::getchar() {
+            self.input.push_back(c);
+        }
+        self.receive(&mut queues[RECEIVEQ], mem)
+    }
+
+    fn reset(&mut self) {
+        self.input.clear();
+    }
+}
@@ -0,0 +1,394 @@
+//! Virtio devices over the MMIO transport (version 2).
+//!
+//! See the [virtio specification](https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html),
+//! section 4.2 for the register layout.
+
+mod balloon;
+mod blk;
+mod console;
+mod queue;
+
+pub use self::balloon::VirtioBalloon;
+pub use self::blk::VirtioBlk;
+pub use self::console::VirtioConsole;
+pub use self::queue::{Descriptor, DescriptorChain, Virtqueue};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::VirtAddr;
+use riscv_vcpu::AccessWidth;
+
+use self::queue::QUEUE_SIZE_MAX;
+use crate::{GuestMemory, IrqLine, MmioDevice, StateReader, StateWriter};
+
+const MAGIC_VALUE: u32 = 0x7472_6976; // "virt"
+const VERSION: u32 = 2;
+const VENDOR_ID: u32 = 0x554d_4551; // "QEMU"
+
+/// The device conforms to the virtio 1.0+ specification.
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// The device has added buffers to the used ring.
+const INTERRUPT_USED_BUFFER: u32 = 1 << 0;
+/// The configuration of the device has changed.
+const INTERRUPT_CONFIG_CHANGE: u32 = 1 << 1;
+
+/// The device has experienced an error from which it can't recover.
+const STATUS_DEVICE_NEEDS_RESET: u32 = 1 << 6;
+
+/// Size of the buffers through which the data of requests is copied.
+pub(crate) const BOUNCE_BUF_SIZE: usize = 4096;
+
+mod reg {
+    pub const MAGIC_VALUE: usize = 0x000;
+    pub const VERSION: usize = 0x004;
+    pub const DEVICE_ID: usize = 0x008;
+    pub const VENDOR_ID: usize = 0x00c;
+    pub const DEVICE_FEATURES: usize = 0x010;
+    pub const DEVICE_FEATURES_SEL: usize = 0x014;
+    pub const DRIVER_FEATURES: usize = 0x020;
+    pub const DRIVER_FEATURES_SEL: usize = 0x024;
+    pub const QUEUE_SEL: usize = 0x030;
+    pub const QUEUE_NUM_MAX: usize = 0x034;
+    pub const QUEUE_NUM: usize = 0x038;
+    pub const QUEUE_READY: usize = 0x044;
+    pub const QUEUE_NOTIFY: usize = 0x050;
+    pub const INTERRUPT_STATUS: usize = 0x060;
+    pub const INTERRUPT_ACK: usize = 0x064;
+    pub const STATUS: usize = 0x070;
+    pub const QUEUE_DESC_LOW: usize = 0x080;
+    pub const QUEUE_DESC_HIGH: usize = 0x084;
+    pub const QUEUE_DRIVER_LOW: usize = 0x090;
+    pub const QUEUE_DRIVER_HIGH: usize = 0x094;
+    pub const QUEUE_DEVICE_LOW: usize = 0x0a0;
+    pub const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+    pub const CONFIG_GENERATION: usize = 0x0fc;
+    pub const CONFIG: usize = 0x100;
+}
+
+/// The device type specific part of a virtio device.
+pub trait VirtioBackend: Send {
+    /// The virtio device ID, e.g., 2 for block devices.
+    const DEVICE_ID: u32;
+    /// The number of virtqueues.
+    const QUEUE_NUM: usize;
+
+    /// Returns the device type specific feature bits.
+    fn features(&self) -> u64;
+
+    /// Reads the device configuration space.
+    fn read_config(&self, offset: usize, width: AccessWidth) -> u64;
+
+    /// Writes the device configuration space. Writes are ignored by default.
+    fn write_config(&mut self, offset: usize, _width: AccessWidth, _val: u64) {
+        warn!("virtio-mmio: ignore write to config space {:#x}", offset);
+    }
+
+    /// Handles a notification from the driver that new buffers are available
+    /// in the `index`-th queue.
+    ///
+    /// Returns whether some buffers are used and the driver should be
+    /// interrupted.
+    fn notify(
+        &mut self,
+        index: usize,
+        queues: &mut [Virtqueue],
+        mem: &mut AddrSpace,
+    ) -> AxResult<bool>;
+
+    /// Polls the host side for new data, e.g., the input of a console.
+    ///
+    /// Returns whether some buffers are used and the driver should be
+    /// interrupted.
+    fn poll(&mut self, _queues: &mut [Virtqueue], _mem: &mut AddrSpace) -> AxResult<bool> {
+        Ok(false)
+    }
+
+    /// Resets the device type specific state.
+    fn reset(&mut self) {}
+
+    /// Saves the device type specific state for a VM snapshot.
+    fn save_state(&self, _w: &mut StateWriter) {}
+
+    /// Restores the state saved by [`save_state`](Self::save_state).
+    fn restore_state(&mut self, _r: &mut StateReader) -> AxResult {
+        Ok(())
+    }
+}
+
+struct MmioState<B: VirtioBackend> {
+    backend: B,
+    queues: Vec<Virtqueue>,
+    queue_sel: usize,
+    device_features_sel: u32,
+    driver_features_sel: u32,
+    driver_features: u64,
+    status: u32,
+    interrupt_status: u32,
+}
+
+/// A virtio device exposed to the guest with the MMIO transport.
+pub struct VirtioMmioDevice<B: VirtioBackend> {
+    state: Mutex<MmioState<B>>,
+    mem: GuestMemory,
+    irq: Option<Arc<dyn IrqLine>>,
+}
+
+/// Reads `width` bytes at `offset` of a little-endian byte array, missing bytes
+/// are read as zeros.
+pub(crate) fn read_le(bytes: &[u8], offset: usize, width: AccessWidth) -> u64 {
+    let mut buf = [0u8; 8];
+    let start = offset.min(bytes.len());
+    let end = (offset + width.size()).min(bytes.len());
+    buf[..end - start].copy_from_slice(&bytes[start..end]);
+    u64::from_le_bytes(buf)
+}
+
+/// Writes `buf` to the guest memory at `addr`, allocating the pages that are
+/// not populated yet.
+pub(crate) fn write_guest(mem: &mut AddrSpace, addr: VirtAddr, buf: &[u8]) -> AxResult {
+    mem.populate_area(addr, buf.len())?;
+    mem.write(addr, buf)
+}
+
+fn set_low(val: &mut u64, low: u32) {
+    *val = (*val & !0xffff_ffff) | low as u64;
+}
+
+fn set_high(val: &mut u64, high: u32) {
+    *val = (*val & 0xffff_ffff) | (high as u64) << 32;
+}
+
+fn write_queue_reg(q: &mut Virtqueue, offset: usize, val: u32) {
+    match offset {
+        reg::QUEUE_NUM => q.num = (val as u16).min(QUEUE_SIZE_MAX),
+        reg::QUEUE_READY => q.ready = val & 1 != 0,
+        reg::QUEUE_DESC_LOW => set_low(&mut q.desc, val),
+        reg::QUEUE_DESC_HIGH => set_high(&mut q.desc, val),
+        reg::QUEUE_DRIVER_LOW => set_low(&mut q.avail, val),
+        reg::QUEUE_DRIVER_HIGH => set_high(&mut q.avail, val),
+        reg::QUEUE_DEVICE_LOW => set_low(&mut q.used, val),
+        reg::QUEUE_DEVICE_HIGH => set_high(&mut q.used, val),
+        _ => warn!(
+            "virtio-mmio: ignore write {:#x} to queue register {:#x}",
+            val, offset
+        ),
+    }
+}
+
+impl<B: VirtioBackend> VirtioMmioDevice<B> {
+    /// Creates a new virtio-mmio device.
+    ///
+    /// `irq` is asserted when the device has used some buffers, and deasserted
+    /// when the driver acknowledges the interrupt.
+    pub fn new(backend: B, mem: GuestMemory, irq: Option<Arc<dyn IrqLine>>) -> Self {
+        let mut queues = Vec::with_capacity(B::QUEUE_NUM);
+        queues.resize_with(B::QUEUE_NUM, Virtqueue::default);
+        Self {
+            state: Mutex::new(MmioState {
+                backend,
+                queues,
+                queue_sel: 0,
+                device_features_sel: 0,
+                driver_features_sel: 0,
+                driver_features: 0,
+                status: 0,
+                interrupt_status: 0,
+            }),
+            mem,
+            irq,
+        }
+    }
+
+    /// Polls the backend for new data from the host, and interrupts the guest
+    /// if some buffers are used.
+    ///
+    /// It should be called periodically, e.g., on each VM exit.
+    pub fn poll(&self) -> AxResult {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        if state.status & STATUS_DEVICE_NEEDS_RESET != 0 {
+            return Ok(());
+        }
+        let mut mem = self.mem.lock();
+        let res = state.backend.poll(&mut state.queues, &mut mem);
+        drop(mem);
+        self.complete(state, res);
+        Ok(())
+    }
+
+    /// Locks the device and calls `f` with the backend, e.g., to change its
+    /// configuration.
+    pub fn with_backend<R>(&self, f: impl FnOnce(&mut B) -> R) -> R {
+        f(&mut self.state.lock().backend)
+    }
+
+    /// Notifies the driver that the device configuration has changed.
+    pub fn notify_config_change(&self) {
+        self.raise_interrupt(&mut self.state.lock(), INTERRUPT_CONFIG_CHANGE);
+    }
+
+    /// Interrupts the driver after the backend has processed the queues.
+    ///
+    /// Errors are caused by malformed queues set up by the driver, they are
+    /// reported to the driver by `DEVICE_NEEDS_RESET` instead of the caller,
+    /// so that the guest cannot crash the host.
+    fn complete(&self, state: &mut MmioState<B>, res: AxResult<bool>) {
+        match res {
+            Ok(true) => self.raise_interrupt(state, INTERRUPT_USED_BUFFER),
+            Ok(false) => {}
+            Err(e) => {
+                warn!("virtio-mmio: device {} needs reset: {:?}", B::DEVICE_ID, e);
+                state.status |= STATUS_DEVICE_NEEDS_RESET;
+                self.raise_interrupt(state, INTERRUPT_CONFIG_CHANGE);
+            }
+        }
+    }
+
+    fn raise_interrupt(&self, state: &mut MmioState<B>, reason: u32) {
+        state.interrupt_status |= reason;
+        if let Some(irq) = &self.irq {
+            irq.set_level(true);
+        }
+    }
+
+    fn reset(&self, state: &mut MmioState<B>) {
+        state.queues.iter_mut().for_each(Virtqueue::reset);
+        state.queue_sel = 0;
+        state.device_features_sel = 0;
+        state.driver_features_sel = 0;
+        state.driver_features = 0;
+        state.status = 0;
+        state.interrupt_status = 0;
+        state.backend.reset();
+        if let Some(irq) = &self.irq {
+            irq.set_level(false);
+        }
+    }
+}
+
+impl<B: VirtioBackend> MmioDevice for VirtioMmioDevice<B> {
+    fn handle_read(&self, offset: usize, width: AccessWidth) -> AxResult<u64> {
+        let state = self.state.lock();
+        if offset >= reg::CONFIG {
+            return Ok(state.backend.read_config(offset - reg::CONFIG, width));
+        }
+        let queue = state.queues.get(state.queue_sel);
+        let val = match offset {
+            reg::MAGIC_VALUE => MAGIC_VALUE,
+            reg::VERSION => VERSION,
+            reg::DEVICE_ID => B::DEVICE_ID,
+            reg::VENDOR_ID => VENDOR_ID,
+            reg::DEVICE_FEATURES => {
+                let features = state.backend.features() | VIRTIO_F_VERSION_1;
+                match state.device_features_sel {
+                    0 => features as u32,
+                    1 => (features >> 32) as u32,
+                    _ => 0,
+                }
+            }
+            reg::QUEUE_NUM_MAX => queue.map_or(0, |_| QUEUE_SIZE_MAX as u32),
+            reg::QUEUE_READY => queue.map_or(0, |q| q.ready as u32),
+            reg::INTERRUPT_STATUS => state.interrupt_status,
+            reg::STATUS => state.status,
+            reg::CONFIG_GENERATION => 0,
+            _ => {
+                warn!("virtio-mmio: read from unknown register {:#x}", offset);
+                0
+            }
+        };
+        Ok(val as u64)
+    }
+
+    fn handle_write(&self, offset: usize, width: AccessWidth, val: u64) -> AxResult {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        if offset >= reg::CONFIG {
+            state.backend.write_config(offset - reg::CONFIG, width, val);
+            return Ok(());
+        }
+        let val = val as u32;
+        match offset {
+            reg::DEVICE_FEATURES_SEL => state.device_features_sel = val,
+            reg::DRIVER_FEATURES_SEL => state.driver_features_sel = val,
+            reg::DRIVER_FEATURES => match state.driver_features_sel {
+                0 => set_low(&mut state.driver_features, val),
+                1 => set_high(&mut state.driver_features, val),
+                _ => {}
+            },
+            reg::QUEUE_SEL => state.queue_sel = val as usize,
+            reg::QUEUE_NUM..=reg::QUEUE_READY | reg::QUEUE_DESC_LOW..=reg::QUEUE_DEVICE_HIGH => {
+                if let Some(q) = state.queues.get_mut(state.queue_sel) {
+                    write_queue_reg(q, offset, val);
+                }
+            }
+            reg::QUEUE_NOTIFY => {
+                let index = val as usize;
+                if index < state.queues.len() && state.status & STATUS_DEVICE_NEEDS_RESET == 0 {
+                    let mut mem = self.mem.lock();
+                    let res = state.backend.notify(index, &mut state.queues, &mut mem);
+                    drop(mem);
+                    self.complete(state, res);
+                }
+            }
+            reg::INTERRUPT_ACK => {
+                state.interrupt_status &= !val;
+                if state.interrupt_status == 0 {
+                    if let Some(irq) = &self.irq {
+                        irq.set_level(false);
+                    }
+                }
+            }
+            reg::STATUS => {
+                if val == 0 {
+                    self.reset(state);
+                } else {
+                    state.status = val;
+                }
+            }
+            _ => warn!(
+                "virtio-mmio: ignore write {:#x} to register {:#x}",
+                val, offset
+            ),
+        }
+        Ok(())
+    }
+
+    /// Saves the transport, the queues and the backend. The console input not
+    /// received yet is dropped.
+    fn save_state(&self, w: &mut StateWriter) {
+        let state = self.state.lock();
+        w.put_u32(state.queue_sel as u32);
+        w.put_u32(state.device_features_sel);
+        w.put_u32(state.driver_features_sel);
+        w.put_u64(state.driver_features);
+        w.put_u32(state.status);
+        w.put_u32(state.interrupt_status);
+        w.put_u32(state.queues.len() as u32);
+        for q in &state.queues {
+            q.save_state(w);
+        }
+        state.backend.save_state(w);
+    }
+
+    fn restore_state(&self, r: &mut StateReader) -> AxResult {
+        let mut state = self.state.lock();
+        state.queue_sel = r.get_u32()? as usize;
+        state.device_features_sel = r.get_u32()?;
+        state.driver_features_sel = r.get_u32()?;
+        state.driver_features = r.get_u64()?;
+        state.status = r.get_u32()?;
+        state.interrupt_status = r.get_u32()?;
+        if r.get_u32()? as usize != state.queues.len() {
+            return ax_err!(InvalidData, "virtio-mmio: queue number mismatch");
+        }
+        for q in &mut state.queues {
+            q.restore_state(r)?;
+        }
+        state.backend.restore_state(r)
+    }
+}
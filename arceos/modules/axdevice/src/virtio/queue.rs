@@ -0,0 +1,229 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+
+use axerrno::{ax_err, AxResult};
+use axmm::AddrSpace;
+use memory_addr::VirtAddr;
+
+use super::write_guest;
+use crate::{StateReader, StateWriter};
+
+/// This marks a buffer as continuing via the next field.
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+/// This marks a buffer as device write-only (otherwise device read-only).
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// Size of a descriptor in the descriptor table.
+const DESC_SIZE: usize = 16;
+/// Size of an element in the used ring.
+const USED_ELEM_SIZE: usize = 8;
+
+/// The maximum number of descriptors in a queue.
+pub const QUEUE_SIZE_MAX: u16 = 256;
+
+/// A buffer described by a descriptor.
+#[derive(Debug, Clone, Copy)]
+pub struct Descriptor {
+    /// Guest physical address of the buffer.
+    pub addr: VirtAddr,
+    /// Length of the buffer.
+    pub len: usize,
+    /// Whether the buffer is device write-only.
+    pub writable: bool,
+}
+
+/// A chain of descriptors, i.e., a request from the driver.
+#[derive(Debug)]
+pub struct DescriptorChain {
+    /// Index of the head descriptor, which is returned in the used ring.
+    pub head: u16,
+    /// The buffers in the chain.
+    pub descs: Vec<Descriptor>,
+}
+
+/// A split virtqueue in the guest memory.
+#[derive(Debug, Default)]
+pub struct Virtqueue {
+    /// Queue size set by the driver.
+    pub(crate) num: u16,
+    pub(crate) ready: bool,
+    /// Guest physical addresses of the descriptor table, the available ring
+    /// and the used ring.
+    pub(crate) desc: u64,
+    pub(crate) avail: u64,
+    pub(crate) used: u64,
+    last_avail_idx: u16,
+    used_idx: u16,
+}
+
+fn read_u16(mem: &AddrSpace, addr: u64) -> AxResult<u16> {
+    let mut buf = [0; 2];
+    mem.read(VirtAddr::from(addr as usize), &mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+impl Virtqueue {
+    /// Whether the queue is set up and enabled by the driver.
+    pub fn is_ready(&self) -> bool {
+        self.ready && self.num != 0
+    }
+
+    /// Resets the queue to the initial state.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.put_u16(self.num);
+        w.put_u8(self.ready as u8);
+        w.put_u64(self.desc);
+        w.put_u64(self.avail);
+        w.put_u64(self.used);
+        w.put_u16(self.last_avail_idx);
+        w.put_u16(self.used_idx);
+    }
+
+    pub(crate) fn restore_state(&mut self, r: &mut StateReader) -> AxResult {
+        self.num = r.get_u16()?.min(QUEUE_SIZE_MAX);
+        self.ready = r.get_u8()? != 0;
+        self.desc = r.get_u64()?;
+        self.avail = r.get_u64()?;
+        self.used = r.get_u64()?;
+        self.last_avail_idx = r.get_u16()?;
+        self.used_idx = r.get_u16()?;
+        Ok(())
+    }
+
+    /// Takes the next available descriptor chain, if any.
+    pub fn pop(&mut self, mem: &AddrSpace) -> AxResult<Option<DescriptorChain>> {
+        if !self.is_ready() {
+            return Ok(None);
+        }
+        let avail_idx = read_u16(mem, self.avail + 2)?;
+        if avail_idx == self.last_avail_idx {
+            return Ok(None);
+        }
+        // Read the ring entries after the index.
+        fence(Ordering::Acquire);
+
+        let slot = (self.last_avail_idx % self.num) as u64;
+        let head = read_u16(mem, self.avail + 4 + slot * 2)?;
+        self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+
+        let mut descs = Vec::new();
+        let mut idx = head;
+        loop {
+            if idx >= self.num || descs.len() >= self.num as usize {
+                return ax_err!(InvalidData, "virtqueue: malformed descriptor chain");
+            }
+            let mut raw = [0u8; DESC_SIZE];
+            let addr = self.desc as usize + idx as usize * DESC_SIZE;
+            mem.read(VirtAddr::from(addr), &mut raw)?;
+            let flags = u16::from_le_bytes([raw[12], raw[13]]);
+            let addr = u64::from_le_bytes(raw[0..8].try_into().unwrap()) as usize;
+            let len = u32::from_le_bytes(raw[8..12].try_into().unwrap()) as usize;
+            if addr.checked_add(len).is_none() {
+                return ax_err!(InvalidData, "virtqueue: malformed descriptor");
+            }
+            descs.push(Descriptor {
+                addr: VirtAddr::from(addr),
+                len,
+                writable: flags & VIRTQ_DESC_F_WRITE != 0,
+            });
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                break;
+            }
+            idx = u16::from_le_bytes([raw[14], raw[15]]);
+        }
+        Ok(Some(DescriptorChain { head, descs }))
+    }
+
+    /// Returns a descriptor chain to the driver, with `len` bytes written
+    /// into its writable buffers.
+    pub fn push_used(&mut self, mem: &mut AddrSpace, head: u16, len: u32) -> AxResult {
+        let slot = (self.used_idx % self.num) as usize;
+        let mut elem = [0u8; USED_ELEM_SIZE];
+        elem[0..4].copy_from_slice(&(head as u32).to_le_bytes());
+        elem[4..8].copy_from_slice(&len.to_le_bytes());
+        write_guest(
+            mem,
+            VirtAddr::from(self.used as usize + 4 + slot * USED_ELEM_SIZE),
+            &elem,
+        )?;
+        // The element must be visible before the index.
+        fence(Ordering::Release);
+        self.used_idx = self.used_idx.wrapping_add(1);
+        write_guest(
+            mem,
+            VirtAddr::from(self.used as usize + 2),
+            &self.used_idx.to_le_bytes(),
+        )
+    }
+}
+
+impl DescriptorChain {
+    /// Reads the device-readable buffers into `buf`, returns the number of
+    /// bytes read.
+    pub fn read_all(&self, mem: &AddrSpace, buf: &mut [u8]) -> AxResult<usize> {
+        let mut read = 0;
+        for desc in self.descs.iter().filter(|d| !d.writable) {
+            let len = desc.len.min(buf.len() - read);
+            mem.read(desc.addr, &mut buf[read..read + len])?;
+            read += len;
+            if read == buf.len() {
+                break;
+            }
+        }
+        Ok(read)
+    }
+
+    /// Reads the device-readable buffers through `buf`, calling `f` each time
+    /// it is filled up, and once more with the rest.
+    ///
+    /// The buffers are copied piece by piece, so that the host does not
+    /// allocate as much memory as the driver describes. Returns the number of
+    /// bytes read.
+    pub fn read_chunks(
+        &self,
+        mem: &AddrSpace,
+        buf: &mut [u8],
+        mut f: impl FnMut(&[u8]) -> AxResult,
+    ) -> AxResult<usize> {
+        let mut read = 0;
+        let mut filled = 0;
+        for desc in self.descs.iter().filter(|d| !d.writable) {
+            let mut offset = 0;
+            while offset < desc.len {
+                let len = (desc.len - offset).min(buf.len() - filled);
+                mem.read(desc.addr + offset, &mut buf[filled..filled + len])?;
+                offset += len;
+                filled += len;
+                if filled == buf.len() {
+                    f(buf)?;
+                    read += filled;
+                    filled = 0;
+                }
+            }
+        }
+        if filled > 0 {
+            f(&buf[..filled])?;
+            read += filled;
+        }
+        Ok(read)
+    }
+
+    /// Writes `buf` into the device-writable buffers, returns the number of
+    /// bytes written.
+    pub fn write_all(&self, mem: &mut AddrSpace, buf: &[u8]) -> AxResult<usize> {
+        let mut written = 0;
+        for desc in self.descs.iter().filter(|d| d.writable) {
+            let len = desc.len.min(buf.len() - written);
+            write_guest(mem, desc.addr, &buf[written..written + len])?;
+            written += len;
+            if written == buf.len() {
+                break;
+            }
+        }
+        Ok(written)
+    }
+}
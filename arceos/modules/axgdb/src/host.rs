@@ -0,0 +1,155 @@
+//! Debugging of the kernel itself.
+//!
+//! After [`init`], the kernel stops and waits for GDB when it hits a
+//! breakpoint (`ebreak`): a breakpoint inserted by GDB, or one compiled into
+//! the kernel such as [`breakpoint`]. The stub runs in the breakpoint trap
+//! handler of the hart that hit it, the other harts keep running.
+//!
+//! Breakpoints cannot be put in the stub itself or in the code it calls (the
+//! connection, the trap entry), and the connection must not rely on
+//! interrupts or tasks.
+
+use alloc::boxed::Box;
+
+use axerrno::{ax_err, AxError, AxResult};
+use axhal::arch::TrapFrame;
+use axhal::mem::{memory_regions, phys_to_virt};
+use axhal::paging::MappingFlags;
+use axhal::trap::{register_trap_handler, BREAKPOINT};
+use kspin::SpinNoIrq;
+use memory_addr::{align_down_4k, align_up_4k, VirtAddr};
+
+use crate::riscv::{insn_len, C_EBREAK, EBREAK};
+use crate::{Action, Connection, GdbStub, Registers, Target, PC_REG, SIGTRAP};
+
+type HostStub = GdbStub<Box<dyn Connection + Send>>;
+
+static HOST_STUB: SpinNoIrq<Option<HostStub>> = SpinNoIrq::new(None);
+
+/// Enables kernel debugging with GDB connected by `conn`.
+///
+/// Call [`breakpoint`] to stop and wait for GDB.
+pub fn init(conn: Box<dyn Connection + Send>) {
+    *HOST_STUB.lock() = Some(GdbStub::new(conn));
+    info!("gdb: kernel debugging enabled");
+}
+
+/// Stops the kernel, to be inspected by GDB.
+#[inline(always)]
+pub fn breakpoint() {
+    unsafe { core::arch::asm!("ebreak") }
+}
+
+/// The kernel as a GDB target, stopped in a trap.
+struct HostTarget<'a> {
+    tf: &'a mut TrapFrame,
+}
+
+impl HostTarget<'_> {
+    /// `x1`-`x31` in the trap frame.
+    fn gprs(&mut self) -> &mut [usize; 31] {
+        // `GeneralRegisters` is `repr(C)`, with `x1`-`x31` in order.
+        unsafe { &mut *(&mut self.tf.regs as *mut _ as *mut [usize; 31]) }
+    }
+}
+
+/// Returns the flags of the kernel memory region that contains
+/// `[addr, addr + len)`.
+fn region_flags(addr: usize, len: usize) -> AxResult<MappingFlags> {
+    let end = addr.checked_add(len).ok_or(AxError::BadAddress)?;
+    memory_regions()
+        .find(|r| {
+            let start = phys_to_virt(r.paddr).as_usize();
+            addr >= start && end <= start + r.size
+        })
+        .map(|r| r.flags.into())
+        .ok_or(AxError::BadAddress)
+}
+
+impl Target for HostTarget<'_> {
+    fn read_registers(&mut self, _tid: usize, regs: &mut Registers) -> AxResult {
+        regs[0] = 0;
+        regs[1..PC_REG].copy_from_slice(self.gprs());
+        regs[PC_REG] = self.tf.sepc;
+        Ok(())
+    }
+
+    fn write_registers(&mut self, _tid: usize, regs: &Registers) -> AxResult {
+        self.gprs().copy_from_slice(&regs[1..PC_REG]);
+        self.tf.sepc = regs[PC_REG];
+        Ok(())
+    }
+
+    fn read_memory(&mut self, _tid: usize, addr: usize, buf: &mut [u8]) -> AxResult {
+        if region_flags(addr, buf.len())?.contains(MappingFlags::DEVICE) {
+            return ax_err!(PermissionDenied, "gdb: access to device memory");
+        }
+        unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), buf.len()) };
+        Ok(())
+    }
+
+    fn write_memory(&mut self, _tid: usize, addr: usize, buf: &[u8]) -> AxResult {
+        let flags = region_flags(addr, buf.len())?;
+        if flags.contains(MappingFlags::DEVICE) {
+            return ax_err!(PermissionDenied, "gdb: access to device memory");
+        }
+        let write =
+            || unsafe { core::ptr::copy_nonoverlapping(buf.as_ptr(), addr as *mut u8, buf.len()) };
+        if flags.contains(MappingFlags::WRITE) {
+            write();
+        } else {
+            // Kernel code is read-only, make it writable while writing.
+            let Some(mut aspace) = axmm::kernel_aspace().try_lock() else {
+                return ax_err!(ResourceBusy, "gdb: kernel address space is locked");
+            };
+            let start = VirtAddr::from(align_down_4k(addr));
+            let size = align_up_4k(addr + buf.len()) - start.as_usize();
+            aspace.protect(start, size, flags | MappingFlags::WRITE)?;
+            write();
+            aspace.protect(start, size, flags)?;
+        }
+        unsafe { core::arch::asm!("fence.i") };
+        Ok(())
+    }
+}
+
+#[register_trap_handler(BREAKPOINT)]
+fn handle_breakpoint(tf: &mut TrapFrame) -> bool {
+    // Other harts wait while the stub is running. It is not reentrant,
+    // breakpoints must not be hit by the stub itself.
+    let mut guard = HOST_STUB.lock();
+    let Some(stub) = guard.as_mut() else {
+        return false;
+    };
+    if !stub.is_breakpoint(tf.sepc) {
+        let insn = unsafe { (tf.sepc as *const u16).read() };
+        let len = insn_len(insn);
+        let is_ebreak = if len == 2 {
+            insn == C_EBREAK
+        } else {
+            unsafe { (tf.sepc as *const u32).read_unaligned() == EBREAK }
+        };
+        if is_ebreak {
+            // Compiled into the kernel, skip it when resuming.
+            tf.sepc += len;
+        } else {
+            // Removed while waiting for the stub, run the instruction again.
+            return true;
+        }
+    }
+    let mut target = HostTarget { tf };
+    match stub.handle_stop(&mut target, 0, SIGTRAP) {
+        Ok(Action::Resume) => {}
+        Ok(Action::Detach) => {
+            info!("gdb: detached on CPU {}", axhal::cpu::this_cpu_id());
+            *guard = None;
+        }
+        Ok(Action::Kill) => axhal::misc::terminate(),
+        Err(e) => {
+            warn!("gdb: connection error: {:?}, kernel debugging disabled", e);
+            let _ = stub.clear_breakpoints(&mut target);
+            *guard = None;
+        }
+    }
+    true
+}
@@ -0,0 +1,105 @@
+//! [ArceOS](https://github.com/arceos-org/arceos) GDB remote stub.
+//!
+//! A server of the [GDB Remote Serial Protocol][rsp] for RISC-V 64 targets,
+//! to debug guest VMs (the target is implemented by the hypervisor, see
+//! `axvm`) or the kernel itself (see [`host`]).
+//!
+//! - [`Connection`]: the byte stream to GDB, a [`Uart16550`] or a TCP socket
+//!   ([`TcpConnection`], with the `net` feature).
+//! - [`Target`]: the registers and the memory of the debugged threads.
+//! - [`GdbStub`]: handles the GDB commands while the target is stopped.
+//!
+//! Software breakpoints and single-stepping are implemented by the stub, by
+//! writing `ebreak` instructions into the target memory. The target stops
+//! when a hart executes one of them.
+//!
+//! [rsp]: https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+
+#![cfg_attr(not(test), no_std)]
+
+#[macro_use]
+extern crate log;
+extern crate alloc;
+
+mod packet;
+mod riscv;
+mod stub;
+#[cfg(feature = "net")]
+mod tcp;
+mod uart;
+
+#[cfg(target_arch = "riscv64")]
+pub mod host;
+
+use alloc::boxed::Box;
+
+use axerrno::AxResult;
+
+pub use self::stub::{Action, GdbStub};
+#[cfg(feature = "net")]
+pub use self::tcp::TcpConnection;
+pub use self::uart::Uart16550;
+
+/// Number of registers reported to GDB: `x0`-`x31` and `pc`.
+pub const NUM_REGS: usize = 33;
+/// Index of `pc` in [`Registers`].
+pub const PC_REG: usize = 32;
+
+/// The registers of a thread, in the GDB order.
+pub type Registers = [usize; NUM_REGS];
+
+/// Signal reported when the target stops on a breakpoint or a step.
+pub const SIGTRAP: u8 = 5;
+/// Signal reported when the target is interrupted by GDB (`Ctrl-C`).
+pub const SIGINT: u8 = 2;
+
+/// A byte stream to GDB.
+pub trait Connection {
+    /// Reads a byte, waits until one is available.
+    fn read_byte(&mut self) -> AxResult<u8>;
+
+    /// Reads a byte if one is available.
+    fn try_read_byte(&mut self) -> AxResult<Option<u8>>;
+
+    /// Writes all bytes of `buf`.
+    fn write_all(&mut self, buf: &[u8]) -> AxResult;
+}
+
+impl<C: Connection + ?Sized> Connection for Box<C> {
+    fn read_byte(&mut self) -> AxResult<u8> {
+        (**self).read_byte()
+    }
+
+    fn try_read_byte(&mut self) -> AxResult<Option<u8>> {
+        (**self).try_read_byte()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> AxResult {
+        (**self).write_all(buf)
+    }
+}
+
+/// A debugged target: a VM whose threads are the vCPUs, or the kernel.
+///
+/// Threads are numbered from 0, they are reported to GDB numbered from 1.
+pub trait Target {
+    /// Returns the number of threads.
+    fn num_threads(&self) -> usize {
+        1
+    }
+
+    /// Reads the registers of the thread `tid`.
+    fn read_registers(&mut self, tid: usize, regs: &mut Registers) -> AxResult;
+
+    /// Writes the registers of the thread `tid`, the write to `x0` is ignored.
+    fn write_registers(&mut self, tid: usize, regs: &Registers) -> AxResult;
+
+    /// Reads memory at the virtual address `addr` of the thread `tid`.
+    fn read_memory(&mut self, tid: usize, addr: usize, buf: &mut [u8]) -> AxResult;
+
+    /// Writes memory at the virtual address `addr` of the thread `tid`.
+    ///
+    /// The instruction caches must be synchronized before the target resumes,
+    /// as breakpoints are inserted by this method.
+    fn write_memory(&mut self, tid: usize, addr: usize, buf: &[u8]) -> AxResult;
+}
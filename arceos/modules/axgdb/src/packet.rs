@@ -0,0 +1,237 @@
+//! Packet framing of the remote protocol: `$<data>#<checksum>`, acknowledged
+//! by `+` (or `-` to request a retransmission).
+
+use alloc::vec::Vec;
+
+use axerrno::AxResult;
+
+use crate::Connection;
+
+/// The byte sent by GDB to interrupt the running target.
+pub const INTERRUPT: u8 = 0x03;
+
+/// Maximum size of a packet, reported to GDB.
+pub const PACKET_SIZE: usize = 0x1000;
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &c| sum.wrapping_add(c))
+}
+
+/// Receives a packet into `buf`, and acknowledges it.
+///
+/// Acknowledgements and interrupts received outside of packets are ignored.
+/// Packets longer than [`PACKET_SIZE`] are dropped and not acknowledged.
+pub fn recv_packet<C: Connection + ?Sized>(conn: &mut C, buf: &mut Vec<u8>) -> AxResult {
+    loop {
+        while conn.read_byte()? != b'$' {}
+        buf.clear();
+        let mut sum = 0u8;
+        let mut too_long = false;
+        loop {
+            let c = conn.read_byte()?;
+            if c == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(c);
+            let c = if c == b'}' {
+                let escaped = conn.read_byte()?;
+                sum = sum.wrapping_add(escaped);
+                escaped ^ 0x20
+            } else {
+                c
+            };
+            if buf.len() < PACKET_SIZE {
+                buf.push(c);
+            } else {
+                too_long = true;
+            }
+        }
+        let hi = conn.read_byte()?;
+        let lo = conn.read_byte()?;
+        match (hex_digit(hi), hex_digit(lo)) {
+            _ if too_long => {
+                warn!("gdb: packet longer than {PACKET_SIZE} bytes");
+                conn.write_all(b"-")?;
+            }
+            (Some(hi), Some(lo)) if (hi << 4 | lo) == sum => {
+                conn.write_all(b"+")?;
+                return Ok(());
+            }
+            _ => {
+                warn!("gdb: bad checksum of packet");
+                conn.write_all(b"-")?;
+            }
+        }
+    }
+}
+
+/// Sends a packet, and resends it until it is acknowledged.
+///
+/// `data` is sent as is, it must not contain the characters `$`, `#` and `}`.
+pub fn send_packet<C: Connection + ?Sized>(conn: &mut C, data: &[u8]) -> AxResult {
+    let sum = checksum(data);
+    loop {
+        conn.write_all(b"$")?;
+        conn.write_all(data)?;
+        conn.write_all(&[
+            b'#',
+            HEX_CHARS[(sum >> 4) as usize],
+            HEX_CHARS[(sum & 0xf) as usize],
+        ])?;
+        match conn.read_byte()? {
+            b'-' => continue,
+            _ => return Ok(()),
+        }
+    }
+}
+
+const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+
+pub fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parses a big-endian hex number, such as an address.
+pub fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0usize, |val, &c| Some(val << 4 | hex_digit(c)? as usize))
+}
+
+/// Decodes hex-encoded bytes.
+pub fn decode_hex(s: &[u8]) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    s.chunks(2)
+        .map(|pair| Some(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?))
+        .collect()
+}
+
+/// Appends the hex encoding of `bytes`.
+pub fn encode_hex(out: &mut Vec<u8>, bytes: &[u8]) {
+    for &b in bytes {
+        out.push(HEX_CHARS[(b >> 4) as usize]);
+        out.push(HEX_CHARS[(b & 0xf) as usize]);
+    }
+}
+
+/// Appends a big-endian hex number, such as a thread ID.
+pub fn encode_num(out: &mut Vec<u8>, val: usize) {
+    let digits = (usize::BITS - val.leading_zeros()).div_ceil(4).max(1);
+    for i in (0..digits).rev() {
+        out.push(HEX_CHARS[(val >> (i * 4)) & 0xf]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::VecDeque;
+    use alloc::vec;
+
+    use axerrno::AxError;
+
+    use super::*;
+
+    /// A connection replaying `input`, and recording the bytes written.
+    struct Mock {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Mock {
+        fn new(input: &[u8]) -> Self {
+            Self {
+                input: input.iter().copied().collect(),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Connection for Mock {
+        fn read_byte(&mut self) -> AxResult<u8> {
+            self.input.pop_front().ok_or(AxError::WouldBlock)
+        }
+
+        fn try_read_byte(&mut self) -> AxResult<Option<u8>> {
+            Ok(self.input.pop_front())
+        }
+
+        fn write_all(&mut self, buf: &[u8]) -> AxResult {
+            self.output.extend_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn checksum_wraps() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(&[0xff, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_hex(b"0"), Some(0));
+        assert_eq!(parse_hex(b"80200000"), Some(0x8020_0000));
+        assert_eq!(parse_hex(b"ffffFFFFffffFFFF"), Some(usize::MAX));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"12345678123456789"), None);
+        assert_eq!(parse_hex(b"12g4"), None);
+
+        assert_eq!(decode_hex(b""), Some(Vec::new()));
+        assert_eq!(decode_hex(b"00a1FF"), Some(vec![0x00, 0xa1, 0xff]));
+        assert_eq!(decode_hex(b"abc"), None);
+        assert_eq!(decode_hex(b"zz"), None);
+
+        let mut out = Vec::new();
+        encode_hex(&mut out, &[0x00, 0xa1, 0xff]);
+        encode_num(&mut out, 0);
+        encode_num(&mut out, 0x1000);
+        assert_eq!(out, b"00a1ff01000");
+    }
+
+    #[test]
+    fn recv() {
+        let mut buf = Vec::new();
+        // Acknowledgements before the packet are skipped.
+        let mut conn = Mock::new(b"+$OK#9a");
+        recv_packet(&mut conn, &mut buf).unwrap();
+        assert_eq!(buf, b"OK");
+        assert_eq!(conn.output, b"+");
+
+        // The checksum covers the escape characters.
+        let mut conn = Mock::new(b"$X}\x03#d8");
+        recv_packet(&mut conn, &mut buf).unwrap();
+        assert_eq!(buf, b"X#");
+
+        // A corrupted packet is retransmitted.
+        let mut conn = Mock::new(b"$OK#00$OK#9a");
+        recv_packet(&mut conn, &mut buf).unwrap();
+        assert_eq!(buf, b"OK");
+        assert_eq!(conn.output, b"-+");
+    }
+
+    #[test]
+    fn recv_too_long() {
+        let mut input = vec![b'$'];
+        input.resize(PACKET_SIZE + 2, b'0');
+        let sum = checksum(&input[1..]);
+        input.push(b'#');
+        encode_hex(&mut input, &[sum]);
+        input.extend_from_slice(b"$#00");
+
+        let mut conn = Mock::new(&input);
+        let mut buf = Vec::new();
+        recv_packet(&mut conn, &mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(conn.output, b"-+");
+    }
+}
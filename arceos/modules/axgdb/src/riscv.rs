@@ -0,0 +1,190 @@
+//! RISC-V instruction decoding for software single-stepping.
+
+use crate::{Registers, PC_REG};
+
+/// Encoding of `ebreak`.
+pub const EBREAK: u32 = 0x0010_0073;
+/// Encoding of `c.ebreak`.
+pub const C_EBREAK: u16 = 0x9002;
+
+/// Returns the length of the instruction whose lowest 16 bits are `insn`.
+pub fn insn_len(insn: u16) -> usize {
+    if insn & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+fn bits(insn: u32, hi: u32, lo: u32) -> usize {
+    ((insn >> lo) & ((1 << (hi - lo + 1)) - 1)) as usize
+}
+
+/// Sign-extends the lowest `width` bits of `val`.
+fn sext(val: usize, width: u32) -> usize {
+    let shift = usize::BITS - width;
+    ((val << shift) as isize >> shift) as usize
+}
+
+/// Returns the address of the instruction executed after `insn` at the `pc`
+/// in `regs`.
+///
+/// Jumps and branches are followed. Traps (`ecall`, `ebreak`, faults) and
+/// returns from traps are not, the step ends at the next instruction in this
+/// case.
+pub fn next_pc(insn: u32, regs: &Registers) -> usize {
+    let pc = regs[PC_REG];
+    let reg = |i: usize| if i == 0 { 0 } else { regs[i] };
+    if insn_len(insn as u16) == 2 {
+        return next_pc_compressed(insn as u16, pc, reg);
+    }
+    let rs1 = bits(insn, 19, 15);
+    let rs2 = bits(insn, 24, 20);
+    match insn & 0x7f {
+        // jal
+        0x6f => {
+            let imm = bits(insn, 31, 31) << 20
+                | bits(insn, 19, 12) << 12
+                | bits(insn, 20, 20) << 11
+                | bits(insn, 30, 21) << 1;
+            pc.wrapping_add(sext(imm, 21))
+        }
+        // jalr
+        0x67 => reg(rs1).wrapping_add(sext(bits(insn, 31, 20), 12)) & !1,
+        // branches
+        0x63 => {
+            let imm = bits(insn, 31, 31) << 12
+                | bits(insn, 7, 7) << 11
+                | bits(insn, 30, 25) << 5
+                | bits(insn, 11, 8) << 1;
+            let (a, b) = (reg(rs1), reg(rs2));
+            let taken = match bits(insn, 14, 12) {
+                0b000 => a == b,
+                0b001 => a != b,
+                0b100 => (a as isize) < (b as isize),
+                0b101 => (a as isize) >= (b as isize),
+                0b110 => a < b,
+                0b111 => a >= b,
+                _ => false,
+            };
+            if taken {
+                pc.wrapping_add(sext(imm, 13))
+            } else {
+                pc + 4
+            }
+        }
+        _ => pc + 4,
+    }
+}
+
+fn next_pc_compressed(insn: u16, pc: usize, reg: impl Fn(usize) -> usize) -> usize {
+    let insn = insn as u32;
+    let funct3 = bits(insn, 15, 13);
+    match (insn & 0b11, funct3) {
+        // c.j
+        (0b01, 0b101) => {
+            let imm = bits(insn, 12, 12) << 11
+                | bits(insn, 8, 8) << 10
+                | bits(insn, 10, 9) << 8
+                | bits(insn, 6, 6) << 7
+                | bits(insn, 7, 7) << 6
+                | bits(insn, 2, 2) << 5
+                | bits(insn, 11, 11) << 4
+                | bits(insn, 5, 3) << 1;
+            pc.wrapping_add(sext(imm, 12))
+        }
+        // c.beqz, c.bnez
+        (0b01, 0b110 | 0b111) => {
+            let imm = bits(insn, 12, 12) << 8
+                | bits(insn, 6, 5) << 6
+                | bits(insn, 2, 2) << 5
+                | bits(insn, 11, 10) << 3
+                | bits(insn, 4, 3) << 1;
+            let zero = reg(bits(insn, 9, 7) + 8) == 0;
+            if zero == (funct3 == 0b110) {
+                pc.wrapping_add(sext(imm, 9))
+            } else {
+                pc + 2
+            }
+        }
+        // c.jr, c.jalr
+        (0b10, 0b100) if bits(insn, 6, 2) == 0 && bits(insn, 11, 7) != 0 => {
+            reg(bits(insn, 11, 7)) & !1
+        }
+        _ => pc + 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NUM_REGS;
+
+    const PC: usize = 0x8020_1000;
+
+    fn regs(set: &[(usize, usize)]) -> Registers {
+        let mut regs = [0; NUM_REGS];
+        regs[PC_REG] = PC;
+        for &(i, val) in set {
+            regs[i] = val;
+        }
+        regs
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(insn_len(EBREAK as u16), 4);
+        assert_eq!(insn_len(C_EBREAK), 2);
+    }
+
+    #[test]
+    fn jumps() {
+        let r = regs(&[(1, 0x8030_0001)]);
+        // jal x0, 8
+        assert_eq!(next_pc(0x0080_006f, &r), PC + 8);
+        // jal x0, -4
+        assert_eq!(next_pc(0xffdf_f06f, &r), PC - 4);
+        // jal ra, 0x800
+        assert_eq!(next_pc(0x0010_00ef, &r), PC + 0x800);
+        // ret, the lowest bit is cleared
+        assert_eq!(next_pc(0x0000_8067, &r), 0x8030_0000);
+        // jalr x0, 4(ra)
+        assert_eq!(next_pc(0x0040_8067, &r), 0x8030_0004);
+        // Other instructions: addi x0, x0, 0 and ebreak
+        assert_eq!(next_pc(0x0000_0013, &r), PC + 4);
+        assert_eq!(next_pc(EBREAK, &r), PC + 4);
+    }
+
+    #[test]
+    fn branches() {
+        // beq a0, a1, 16
+        let beq = 0x00b5_0863;
+        assert_eq!(next_pc(beq, &regs(&[(10, 1), (11, 1)])), PC + 16);
+        assert_eq!(next_pc(beq, &regs(&[(10, 1), (11, 2)])), PC + 4);
+        // blt a0, a1, -8 and bltu a0, a1, -8
+        let (blt, bltu) = (0xfeb5_4ce3, 0xfeb5_6ce3);
+        let r = regs(&[(10, usize::MAX), (11, 0)]);
+        assert_eq!(next_pc(blt, &r), PC - 8);
+        assert_eq!(next_pc(bltu, &r), PC + 4);
+    }
+
+    #[test]
+    fn compressed() {
+        let r = regs(&[(1, 0x8030_0000)]);
+        // c.j 0, c.j -2 and c.j 0x7fe
+        assert_eq!(next_pc(0xa001, &r), PC);
+        assert_eq!(next_pc(0xbffd, &r), PC - 2);
+        assert_eq!(next_pc(0xaffd, &r), PC + 0x7fe);
+        // c.beqz a0, -4 and c.bnez a0, 8
+        assert_eq!(next_pc(0xdd75, &regs(&[])), PC - 4);
+        assert_eq!(next_pc(0xdd75, &regs(&[(10, 1)])), PC + 2);
+        assert_eq!(next_pc(0xe501, &regs(&[(10, 1)])), PC + 8);
+        assert_eq!(next_pc(0xe501, &regs(&[])), PC + 2);
+        // c.jr ra and c.jalr ra
+        assert_eq!(next_pc(0x8082, &r), 0x8030_0000);
+        assert_eq!(next_pc(0x9082, &r), 0x8030_0000);
+        // c.nop and c.ebreak
+        assert_eq!(next_pc(0x0001, &r), PC + 2);
+        assert_eq!(next_pc(C_EBREAK as u32, &r), PC + 2);
+    }
+}
@@ -0,0 +1,428 @@
+//! Handling of the GDB commands while the target is stopped.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxError, AxResult};
+
+use crate::packet::{self, decode_hex, encode_hex, encode_num, parse_hex, PACKET_SIZE};
+use crate::riscv::{self, C_EBREAK, EBREAK};
+use crate::{Connection, Registers, Target, NUM_REGS, PC_REG};
+
+/// ABI names of `x0`-`x31` expected by GDB.
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// What the target should do after [`GdbStub::handle_stop`] returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Resume running, until a breakpoint is hit or GDB interrupts.
+    Resume,
+    /// GDB has detached, all breakpoints are removed.
+    Detach,
+    /// GDB has killed the target.
+    Kill,
+}
+
+/// A software breakpoint: the original instruction bytes.
+struct Breakpoint {
+    orig: [u8; 4],
+    len: usize,
+}
+
+/// A GDB remote stub connected to GDB by `C`.
+pub struct GdbStub<C: Connection> {
+    conn: C,
+    /// The thread selected for register and memory accesses.
+    current: usize,
+    /// Breakpoints inserted by GDB.
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    /// Temporary breakpoints inserted to single-step.
+    step_breakpoints: BTreeMap<usize, Breakpoint>,
+    /// Whether GDB waits for a stop reply.
+    resumed: bool,
+    buf: Vec<u8>,
+}
+
+impl<C: Connection> GdbStub<C> {
+    /// Creates a stub talking to GDB through `conn`.
+    pub fn new(conn: C) -> Self {
+        Self {
+            conn,
+            current: 0,
+            breakpoints: BTreeMap::new(),
+            step_breakpoints: BTreeMap::new(),
+            resumed: false,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Whether a breakpoint of the stub is at `addr`.
+    ///
+    /// Other `ebreak` instructions are compiled into the target, they should
+    /// be skipped when the target resumes.
+    pub fn is_breakpoint(&self, addr: usize) -> bool {
+        self.breakpoints.contains_key(&addr) || self.step_breakpoints.contains_key(&addr)
+    }
+
+    /// Checks whether GDB has requested to interrupt the running target.
+    pub fn poll_interrupt(&mut self) -> AxResult<bool> {
+        while let Some(c) = self.conn.try_read_byte()? {
+            if c == packet::INTERRUPT {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Handles the GDB commands while the target is stopped, as thread `tid`
+    /// has received `signal`. Returns when GDB resumes, detaches or kills the
+    /// target.
+    pub fn handle_stop(
+        &mut self,
+        target: &mut dyn Target,
+        tid: usize,
+        signal: u8,
+    ) -> AxResult<Action> {
+        self.remove_step_breakpoints(target)?;
+        self.current = tid;
+        if self.resumed {
+            self.resumed = false;
+            let reply = self.stop_reply(tid, signal);
+            packet::send_packet(&mut self.conn, &reply)?;
+        }
+        let mut buf = core::mem::take(&mut self.buf);
+        let ret = loop {
+            packet::recv_packet(&mut self.conn, &mut buf)?;
+            trace!("gdb: <- {}", core::str::from_utf8(&buf).unwrap_or("?"));
+            let reply = match buf.first() {
+                Some(b'?') => self.stop_reply(tid, signal),
+                Some(b'c') => match self.resume(target, &buf[1..], false) {
+                    Ok(()) => break Ok(Action::Resume),
+                    Err(_) => b"E01".to_vec(),
+                },
+                Some(b's') => match self.resume(target, &buf[1..], true) {
+                    Ok(()) => break Ok(Action::Resume),
+                    Err(_) => b"E01".to_vec(),
+                },
+                Some(b'D') => {
+                    self.clear_breakpoints(target)?;
+                    packet::send_packet(&mut self.conn, b"OK")?;
+                    break Ok(Action::Detach);
+                }
+                Some(b'k') => break Ok(Action::Kill),
+                _ => self
+                    .handle_command(target, &buf)
+                    .unwrap_or_else(|_| b"E01".to_vec()),
+            };
+            packet::send_packet(&mut self.conn, &reply)?;
+        };
+        self.buf = buf;
+        ret
+    }
+
+    fn stop_reply(&self, tid: usize, signal: u8) -> Vec<u8> {
+        let mut reply = vec![b'T'];
+        encode_hex(&mut reply, &[signal]);
+        reply.extend_from_slice(b"thread:");
+        encode_num(&mut reply, tid + 1);
+        reply.push(b';');
+        reply
+    }
+
+    /// Handles a command that does not resume the target, returns the reply.
+    fn handle_command(&mut self, target: &mut dyn Target, cmd: &[u8]) -> AxResult<Vec<u8>> {
+        let mut reply = Vec::new();
+        let Some(&kind) = cmd.first() else {
+            // An empty packet, unsupported as well.
+            return Ok(reply);
+        };
+        match kind {
+            b'g' => {
+                let mut regs: Registers = [0; NUM_REGS];
+                target.read_registers(self.current, &mut regs)?;
+                for reg in regs {
+                    encode_hex(&mut reply, &reg.to_le_bytes());
+                }
+            }
+            b'G' => {
+                let bytes = decode_hex(&cmd[1..]).ok_or(AxError::InvalidInput)?;
+                let mut regs: Registers = [0; NUM_REGS];
+                target.read_registers(self.current, &mut regs)?;
+                for (reg, bytes) in regs.iter_mut().zip(bytes.chunks_exact(8)) {
+                    *reg = usize::from_le_bytes(bytes.try_into().unwrap());
+                }
+                target.write_registers(self.current, &regs)?;
+                reply.extend_from_slice(b"OK");
+            }
+            b'p' => {
+                let n = parse_hex(&cmd[1..]).ok_or(AxError::InvalidInput)?;
+                if n >= NUM_REGS {
+                    // Not a register known by the stub.
+                    return Ok(b"E01".to_vec());
+                }
+                let mut regs: Registers = [0; NUM_REGS];
+                target.read_registers(self.current, &mut regs)?;
+                encode_hex(&mut reply, &regs[n].to_le_bytes());
+            }
+            b'P' => {
+                let (n, val) = split_at_byte(&cmd[1..], b'=')?;
+                let n = parse_hex(n).ok_or(AxError::InvalidInput)?;
+                let val = decode_hex(val).ok_or(AxError::InvalidInput)?;
+                if n >= NUM_REGS || val.len() != 8 {
+                    return Ok(b"E01".to_vec());
+                }
+                let mut regs: Registers = [0; NUM_REGS];
+                target.read_registers(self.current, &mut regs)?;
+                regs[n] = usize::from_le_bytes(val.try_into().unwrap());
+                target.write_registers(self.current, &regs)?;
+                reply.extend_from_slice(b"OK");
+            }
+            b'm' => {
+                let (addr, len) = parse_addr_len(&cmd[1..])?;
+                let mut data = vec![0; len.min(PACKET_SIZE / 2)];
+                self.read_memory(target, addr, &mut data)?;
+                encode_hex(&mut reply, &data);
+            }
+            b'M' => {
+                let (addr_len, data) = split_at_byte(&cmd[1..], b':')?;
+                let (addr, len) = parse_addr_len(addr_len)?;
+                let data = decode_hex(data).ok_or(AxError::InvalidInput)?;
+                if data.len() != len {
+                    return ax_err!(InvalidInput);
+                }
+                target.write_memory(self.current, addr, &data)?;
+                reply.extend_from_slice(b"OK");
+            }
+            b'Z' | b'z' if cmd.get(1) == Some(&b'0') => {
+                let args = cmd.get(3..).ok_or(AxError::InvalidInput)?;
+                let (addr, kind) = parse_addr_len(args)?;
+                if cmd[0] == b'Z' {
+                    self.insert_breakpoint(target, addr, kind)?;
+                } else {
+                    self.remove_breakpoint(target, addr)?;
+                }
+                reply.extend_from_slice(b"OK");
+            }
+            b'H' => {
+                // `Hg<tid>` selects the thread of the register and memory
+                // accesses, `Hc` is ignored as all threads are resumed.
+                if cmd.get(1) == Some(&b'g') {
+                    if let Some(tid) = self.parse_tid(&cmd[2..], target) {
+                        self.current = tid;
+                    }
+                }
+                reply.extend_from_slice(b"OK");
+            }
+            b'T' => match self.parse_tid(&cmd[1..], target) {
+                Some(_) => reply.extend_from_slice(b"OK"),
+                None => reply.extend_from_slice(b"E01"),
+            },
+            b'q' => self.handle_query(target, &cmd[1..], &mut reply),
+            _ => {
+                // Unsupported, replied with an empty packet.
+            }
+        }
+        Ok(reply)
+    }
+
+    fn handle_query(&mut self, target: &mut dyn Target, query: &[u8], reply: &mut Vec<u8>) {
+        if query.starts_with(b"Supported") {
+            reply.extend_from_slice(b"PacketSize=");
+            encode_num(reply, PACKET_SIZE);
+            reply.extend_from_slice(b";qXfer:features:read+");
+        } else if query == b"Attached" {
+            reply.push(b'1');
+        } else if query == b"C" {
+            reply.extend_from_slice(b"QC");
+            encode_num(reply, self.current + 1);
+        } else if query == b"fThreadInfo" {
+            reply.push(b'm');
+            for tid in 0..target.num_threads() {
+                if tid > 0 {
+                    reply.push(b',');
+                }
+                encode_num(reply, tid + 1);
+            }
+        } else if query == b"sThreadInfo" {
+            reply.push(b'l');
+        } else if let Some(args) = query.strip_prefix(b"Xfer:features:read:target.xml:") {
+            match parse_addr_len(args) {
+                Ok((offset, len)) => {
+                    let xml = target_xml();
+                    let data = xml.get(offset..).unwrap_or_default();
+                    let len = len.min(PACKET_SIZE - 1);
+                    if data.len() > len {
+                        reply.push(b'm');
+                        reply.extend_from_slice(&data[..len]);
+                    } else {
+                        reply.push(b'l');
+                        reply.extend_from_slice(data);
+                    }
+                }
+                Err(_) => reply.extend_from_slice(b"E01"),
+            }
+        }
+    }
+
+    /// Parses a thread ID, `0` and `-1` are any thread.
+    fn parse_tid(&self, s: &[u8], target: &dyn Target) -> Option<usize> {
+        if s == b"0" || s == b"-1" {
+            return Some(self.current);
+        }
+        match parse_hex(s)? {
+            tid if tid >= 1 && tid <= target.num_threads() => Some(tid - 1),
+            _ => None,
+        }
+    }
+
+    /// Reads memory as seen by GDB, without the breakpoint instructions.
+    fn read_memory(&self, target: &mut dyn Target, addr: usize, buf: &mut [u8]) -> AxResult {
+        target.read_memory(self.current, addr, buf)?;
+        let end = addr.saturating_add(buf.len());
+        for (&bp_addr, bp) in self.breakpoints.iter().chain(&self.step_breakpoints) {
+            for i in 0..bp.len {
+                let a = bp_addr + i;
+                if a >= addr && a < end {
+                    buf[a - addr] = bp.orig[i];
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares to resume: sets the pc if GDB gives an address, and inserts
+    /// the breakpoints to stop after one instruction for a step.
+    fn resume(&mut self, target: &mut dyn Target, addr: &[u8], step: bool) -> AxResult {
+        let mut regs: Registers = [0; NUM_REGS];
+        target.read_registers(self.current, &mut regs)?;
+        if !addr.is_empty() {
+            regs[PC_REG] = parse_hex(addr).ok_or(AxError::InvalidInput)?;
+            target.write_registers(self.current, &regs)?;
+        }
+        if step {
+            let mut insn = [0; 4];
+            self.read_memory(target, regs[PC_REG], &mut insn[..2])?;
+            if riscv::insn_len(u16::from_le_bytes([insn[0], insn[1]])) == 4 {
+                self.read_memory(target, regs[PC_REG] + 2, &mut insn[2..])?;
+            }
+            let next = riscv::next_pc(u32::from_le_bytes(insn), &regs);
+            if !self.is_breakpoint(next) {
+                let bp = self.write_breakpoint(target, next)?;
+                self.step_breakpoints.insert(next, bp);
+            }
+        }
+        self.resumed = true;
+        Ok(())
+    }
+
+    /// Writes a breakpoint instruction at `addr`, as long as the instruction
+    /// there.
+    fn write_breakpoint(&self, target: &mut dyn Target, addr: usize) -> AxResult<Breakpoint> {
+        let mut orig = [0; 4];
+        target.read_memory(self.current, addr, &mut orig[..2])?;
+        let len = riscv::insn_len(u16::from_le_bytes([orig[0], orig[1]]));
+        if len == 4 {
+            target.read_memory(self.current, addr + 2, &mut orig[2..])?;
+            target.write_memory(self.current, addr, &EBREAK.to_le_bytes())?;
+        } else {
+            target.write_memory(self.current, addr, &C_EBREAK.to_le_bytes())?;
+        }
+        Ok(Breakpoint { orig, len })
+    }
+
+    fn restore_breakpoint(
+        &self,
+        target: &mut dyn Target,
+        addr: usize,
+        bp: &Breakpoint,
+    ) -> AxResult {
+        target.write_memory(self.current, addr, &bp.orig[..bp.len])
+    }
+
+    fn insert_breakpoint(
+        &mut self,
+        target: &mut dyn Target,
+        addr: usize,
+        _kind: usize,
+    ) -> AxResult {
+        if self.breakpoints.contains_key(&addr) {
+            return Ok(());
+        }
+        let bp = match self.step_breakpoints.remove(&addr) {
+            Some(bp) => bp,
+            None => self.write_breakpoint(target, addr)?,
+        };
+        self.breakpoints.insert(addr, bp);
+        Ok(())
+    }
+
+    fn remove_breakpoint(&mut self, target: &mut dyn Target, addr: usize) -> AxResult {
+        if let Some(bp) = self.breakpoints.remove(&addr) {
+            self.restore_breakpoint(target, addr, &bp)?;
+        }
+        Ok(())
+    }
+
+    fn remove_step_breakpoints(&mut self, target: &mut dyn Target) -> AxResult {
+        for (addr, bp) in core::mem::take(&mut self.step_breakpoints) {
+            self.restore_breakpoint(target, addr, &bp)?;
+        }
+        Ok(())
+    }
+
+    /// Removes all breakpoints from the target memory, for example when the
+    /// connection to GDB is lost.
+    pub fn clear_breakpoints(&mut self, target: &mut dyn Target) -> AxResult {
+        self.remove_step_breakpoints(target)?;
+        for (addr, bp) in core::mem::take(&mut self.breakpoints) {
+            self.restore_breakpoint(target, addr, &bp)?;
+        }
+        Ok(())
+    }
+}
+
+fn split_at_byte(s: &[u8], sep: u8) -> AxResult<(&[u8], &[u8])> {
+    match s.iter().position(|&c| c == sep) {
+        Some(pos) => Ok((&s[..pos], &s[pos + 1..])),
+        None => ax_err!(InvalidInput),
+    }
+}
+
+/// Parses `<addr>,<len>`.
+fn parse_addr_len(s: &[u8]) -> AxResult<(usize, usize)> {
+    let (addr, len) = split_at_byte(s, b',')?;
+    match (parse_hex(addr), parse_hex(len)) {
+        (Some(addr), Some(len)) => Ok((addr, len)),
+        _ => ax_err!(InvalidInput),
+    }
+}
+
+/// Returns the target description with the registers of [`Registers`].
+fn target_xml() -> Vec<u8> {
+    use core::fmt::Write;
+    let mut xml = alloc::string::String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><architecture>riscv:rv64</architecture>\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">",
+    );
+    for (i, name) in REG_NAMES.iter().enumerate() {
+        let ty = match i {
+            1 => "code_ptr",
+            2 | 8 => "data_ptr",
+            _ => "int",
+        };
+        let _ = write!(
+            xml,
+            "<reg name=\"{}\" bitsize=\"64\" type=\"{}\" regnum=\"{}\"/>",
+            name, ty, i
+        );
+    }
+    xml.push_str("<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\" regnum=\"32\"/>");
+    xml.push_str("</feature></target>");
+    xml.into_bytes()
+}
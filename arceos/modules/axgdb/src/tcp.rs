@@ -0,0 +1,52 @@
+//! TCP transport, GDB connects with `target remote <host>:<port>`.
+
+use core::net::{Ipv4Addr, SocketAddr};
+
+use axerrno::{ax_err, AxResult};
+use axnet::TcpSocket;
+
+use crate::Connection;
+
+/// A connection to GDB over TCP.
+pub struct TcpConnection {
+    socket: TcpSocket,
+}
+
+impl TcpConnection {
+    /// Listens on `port`, and waits for GDB to connect.
+    pub fn accept(port: u16) -> AxResult<Self> {
+        let listener = TcpSocket::new();
+        listener.bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))?;
+        listener.listen()?;
+        info!("gdb: waiting for a connection on port {}", port);
+        let socket = listener.accept()?;
+        info!("gdb: connected from {}", socket.peer_addr()?);
+        Ok(Self { socket })
+    }
+}
+
+impl Connection for TcpConnection {
+    fn read_byte(&mut self) -> AxResult<u8> {
+        let mut buf = [0];
+        match self.socket.recv(&mut buf)? {
+            0 => ax_err!(ConnectionReset, "gdb: connection closed"),
+            _ => Ok(buf[0]),
+        }
+    }
+
+    fn try_read_byte(&mut self) -> AxResult<Option<u8>> {
+        if self.socket.poll()?.readable {
+            self.read_byte().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn write_all(&mut self, mut buf: &[u8]) -> AxResult {
+        while !buf.is_empty() {
+            let n = self.socket.send(buf)?;
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+}
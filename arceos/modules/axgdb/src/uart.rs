@@ -0,0 +1,69 @@
+//! Polled 16550-compatible UART transport, for a UART not used as the console.
+
+use axerrno::AxResult;
+
+use crate::Connection;
+
+const RBR: usize = 0;
+const THR: usize = 0;
+const LSR: usize = 5;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// A connection to GDB through a 16550-compatible UART.
+///
+/// The UART must be mapped and initialized (baud rate, 8N1) before use, as
+/// done by the firmware or the platform.
+pub struct Uart16550 {
+    base: usize,
+    reg_shift: usize,
+}
+
+impl Uart16550 {
+    /// Creates a connection through the UART whose registers are mapped at
+    /// the virtual address `base`, `1 << reg_shift` bytes apart.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the mapped MMIO region of a UART used by nothing else.
+    pub unsafe fn new(base: usize, reg_shift: usize) -> Self {
+        Self { base, reg_shift }
+    }
+
+    fn read_reg(&self, reg: usize) -> u8 {
+        unsafe { ((self.base + (reg << self.reg_shift)) as *const u8).read_volatile() }
+    }
+
+    fn write_reg(&self, reg: usize, val: u8) {
+        unsafe { ((self.base + (reg << self.reg_shift)) as *mut u8).write_volatile(val) }
+    }
+}
+
+impl Connection for Uart16550 {
+    fn read_byte(&mut self) -> AxResult<u8> {
+        loop {
+            if let Some(c) = self.try_read_byte()? {
+                return Ok(c);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn try_read_byte(&mut self) -> AxResult<Option<u8>> {
+        if self.read_reg(LSR) & LSR_DATA_READY != 0 {
+            Ok(Some(self.read_reg(RBR)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> AxResult {
+        for &c in buf {
+            while self.read_reg(LSR) & LSR_THR_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+            self.write_reg(THR, c);
+        }
+        Ok(())
+    }
+}
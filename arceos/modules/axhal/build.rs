@@ -5,6 +5,7 @@ const BUILTIN_PLATFORMS: &[&str] = &[
     "aarch64-bsta1000b",
     "aarch64-qemu-virt",
     "aarch64-raspi4",
+    "loongarch64-qemu-virt",
     "riscv64-qemu-virt",
     "x86_64-pc-oslab",
     "x86_64-qemu-q35",
@@ -14,6 +15,7 @@ const BUILTIN_PLATFORM_FAMILIES: &[&str] = &[
     "aarch64-bsta1000b",
     "aarch64-qemu-virt",
     "aarch64-raspi",
+    "loongarch64-qemu-virt",
     "riscv64-qemu-virt",
     "x86-pc",
 ];
@@ -32,6 +34,7 @@ fn main() {
     if platform != "dummy" {
         gen_linker_script(&arch, platform).unwrap();
     }
+    gen_ksyms().unwrap();
 
     println!("cargo:rustc-cfg=platform=\"{}\"", platform);
     println!("cargo:rustc-cfg=platform_family=\"{}\"", axconfig::FAMILY);
@@ -51,6 +54,8 @@ fn gen_linker_script(arch: &str, platform: &str) -> Result<()> {
         "i386:x86-64"
     } else if arch.contains("riscv") {
         "riscv" // OUTPUT_ARCH of both riscv32/riscv64 is "riscv"
+    } else if arch == "loongarch64" {
+        "loongarch" // OUTPUT_ARCH of loongarch64 is "loongarch"
     } else {
         arch
     };
@@ -67,5 +72,22 @@ fn gen_linker_script(arch: &str, platform: &str) -> Result<()> {
     // target/<target_triple>/<mode>/linker_xxxx.lds
     let out_path = Path::new(&out_dir).join("../../..").join(fname);
     std::fs::write(out_path, ld_content)?;
+    println!("cargo:rerun-if-changed=linker.lds.S");
     Ok(())
 }
+
+/// Copies the symbol table at `AX_KSYMS`, generated by `tools/ksym` from the
+/// previous build of the kernel, to be embedded into the kernel image.
+fn gen_ksyms() -> Result<()> {
+    println!("cargo:rerun-if-env-changed=AX_KSYMS");
+    let ksyms = match std::env::var("AX_KSYMS") {
+        Ok(path) if !path.is_empty() => {
+            println!("cargo:rerun-if-changed={}", path);
+            // Not generated yet at the first build.
+            std::fs::read(&path).unwrap_or_default()
+        }
+        _ => Vec::new(),
+    };
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(Path::new(&out_dir).join("ksyms.bin"), ksyms)
+}
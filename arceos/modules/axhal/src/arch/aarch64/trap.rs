@@ -5,6 +5,7 @@ use page_table_entry::MappingFlags;
 use tock_registers::interfaces::Readable;
 
 use super::TrapFrame;
+use crate::backtrace::Backtrace;
 
 global_asm!(include_str!("trap.S"), cache_current_task_ptr = sym crate::cpu::cache_current_task_ptr);
 
@@ -30,9 +31,10 @@ enum TrapSource {
 
 #[no_mangle]
 fn invalid_exception(tf: &TrapFrame, kind: TrapKind, source: TrapSource) {
+    Backtrace::set_fatal_trap(tf);
     panic!(
         "Invalid exception {:?} from {:?}:\n{:#x?}",
-        kind, source, tf
+        kind, source, tf,
     );
 }
 
@@ -41,7 +43,7 @@ fn handle_irq_exception(_tf: &TrapFrame) {
     handle_trap!(IRQ, 0);
 }
 
-fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
+fn handle_instruction_abort(tf: &mut TrapFrame, iss: u64, is_user: bool) {
     let mut access_flags = MappingFlags::EXECUTE;
     if is_user {
         access_flags |= MappingFlags::USER;
@@ -52,6 +54,13 @@ fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
     if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
         || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
     {
+        if !is_user {
+            if let Some(fixup) = crate::extable::fixup_exception(tf.elr as usize) {
+                tf.elr = fixup as u64;
+                return;
+            }
+        }
+        Backtrace::set_fatal_trap(tf);
         panic!(
             "Unhandled {} Instruction Abort @ {:#x}, fault_vaddr={:#x}, ISS={:#x} ({:?}):\n{:#x?}",
             if is_user { "EL0" } else { "EL1" },
@@ -64,7 +73,7 @@ fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
     }
 }
 
-fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
+fn handle_data_abort(tf: &mut TrapFrame, iss: u64, is_user: bool) {
     debug!("ISS={:#x} tf={:#x?} is_user={}", iss, tf, is_user);
     let wnr = (iss & (1 << 6)) != 0; // WnR: Write not Read
     let cm = (iss & (1 << 8)) != 0; // CM: Cache maintenance
@@ -82,6 +91,13 @@ fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
     if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
         || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
     {
+        if !is_user {
+            if let Some(fixup) = crate::extable::fixup_exception(tf.elr as usize) {
+                tf.elr = fixup as u64;
+                return;
+            }
+        }
+        Backtrace::set_fatal_trap(tf);
         panic!(
             "Unhandled {} Data Abort @ {:#x}, fault_vaddr={:#x}, ISS=0b{:08b} ({:?}):\n{:#x?}",
             if is_user { "EL0" } else { "EL1" },
@@ -120,6 +136,7 @@ fn handle_sync_exception(tf: &mut TrapFrame) {
             tf.elr += 4;
         }
         _ => {
+            Backtrace::set_fatal_trap(tf);
             panic!(
                 "Unhandled synchronous exception @ {:#x}: ESR={:#x} (EC {:#08b}, ISS {:#x})",
                 tf.elr,
@@ -7,7 +7,6 @@ mod trap;
 use core::arch::asm;
 use loongArch64::register::{crmd, ecfg, eentry, pgdh, pgdl, stlbps, tlbidx, tlbrehi, tlbrentry};
 use memory_addr::{PhysAddr, VirtAddr};
-use page_table_multiarch::loongarch64::LA64MetaData;
 
 pub use self::context::{TaskContext, TrapFrame};
 
@@ -161,15 +160,24 @@ pub fn set_pwc(pwcl: u32, pwch: u32) {
 
 /// Init the TLB configuration and set tlb refill handler.
 ///
+/// The page walker is configured for 4-level page tables with 4K pages, i.e.,
+/// 9 bits per level from bit 12 up to bit 47.
+///
 /// TLBRENTY: <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#tlb-refill-exception-entry-base-address>
 pub fn init_tlb() {
     // Page Size 4KB
     const PS_4K: usize = 0x0c;
+    // PTbase = 12, PTwidth = 9, Dir1_base = 21, Dir1_width = 9,
+    // Dir2_base = 30, Dir2_width = 9
+    const PWCL_VALUE: u32 = 12 | (9 << 5) | (21 << 10) | (9 << 15) | (30 << 20) | (9 << 25);
+    // Dir3_base = 39, Dir3_width = 9
+    const PWCH_VALUE: u32 = 39 | (9 << 6);
+
     tlbidx::set_ps(PS_4K);
     stlbps::set_ps(PS_4K);
     tlbrehi::set_ps(PS_4K);
 
-    set_pwc(LA64MetaData::PWCL_VALUE, LA64MetaData::PWCH_VALUE);
+    set_pwc(PWCL_VALUE, PWCH_VALUE);
 
     unsafe extern "C" {
         fn handle_tlb_refill();
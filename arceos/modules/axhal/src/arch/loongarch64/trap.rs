@@ -1,4 +1,5 @@
 use super::context::TrapFrame;
+use crate::backtrace::Backtrace;
 use loongArch64::register::{
     badv,
     estat::{self, Exception, Trap},
@@ -16,12 +17,19 @@ fn handle_breakpoint(era: &mut usize) {
     *era += 4;
 }
 
-fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bool) {
+fn handle_page_fault(tf: &mut TrapFrame, mut access_flags: MappingFlags, is_user: bool) {
     if is_user {
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(badv::read().raw());
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
+        if !is_user {
+            if let Some(fixup) = crate::extable::fixup_exception(tf.era) {
+                tf.era = fixup;
+                return;
+            }
+        }
+        Backtrace::set_fatal_trap(tf);
         panic!(
             "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
             if is_user { "PLV3" } else { "PLV0" },
@@ -61,11 +69,12 @@ fn loongarch64_trap_handler(tf: &mut TrapFrame, from_user: bool) {
             handle_trap!(IRQ, irq_num);
         }
         _ => {
+            Backtrace::set_fatal_trap(tf);
             panic!(
                 "Unhandled trap {:?} @ {:#x}:\n{:#x?}",
                 estat.cause(),
                 tf.era,
-                tf
+                tf,
             );
         }
     }
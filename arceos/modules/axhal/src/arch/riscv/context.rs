@@ -3,8 +3,21 @@ use memory_addr::VirtAddr;
 #[cfg(feature = "uspace")]
 use memory_addr::PhysAddr;
 
+#[cfg(feature = "fp_simd")]
+use riscv::register::sstatus::{self, FS};
+
 include_asm_marcos!();
 
+/// The `FS` field of `sstatus`, and its values.
+#[cfg(feature = "fp_simd")]
+const SSTATUS_FS: usize = 0b11 << 13;
+#[cfg(all(feature = "fp_simd", feature = "uspace"))]
+const FS_INITIAL: usize = 0b01 << 13;
+#[cfg(all(feature = "fp_simd", feature = "uspace"))]
+const FS_CLEAN: usize = 0b10 << 13;
+#[cfg(all(feature = "fp_simd", feature = "uspace"))]
+const FS_DIRTY: usize = 0b11 << 13;
+
 /// General registers of RISC-V.
 #[allow(missing_docs)]
 #[repr(C)]
@@ -43,6 +56,54 @@ pub struct GeneralRegisters {
     pub t6: usize,
 }
 
+/// Floating-point registers of RISC-V.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FpState {
+    /// The F/D registers (f0-f31).
+    pub fp: [u64; 32],
+    /// Floating-point Control and Status Register.
+    pub fcsr: usize,
+}
+
+impl FpState {
+    /// Saves the FP registers of the CPU to this place.
+    ///
+    /// `sstatus.FS` must not be `Off`, and is not changed.
+    pub fn save(&mut self) {
+        let fcsr: usize;
+        unsafe {
+            asm!(
+                r"
+                .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+                    fsd     f\i, \i*8({fp})
+                .endr
+                frcsr   {fcsr}",
+                fp = in(reg) self.fp.as_mut_ptr(),
+                fcsr = out(reg) fcsr,
+            )
+        }
+        self.fcsr = fcsr;
+    }
+
+    /// Restores the FP registers of the CPU from this place.
+    ///
+    /// `sstatus.FS` must not be `Off`, and becomes `Dirty`.
+    pub fn restore(&self) {
+        unsafe {
+            asm!(
+                r"
+                .irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+                    fld     f\i, \i*8({fp})
+                .endr
+                fscsr   {fcsr}",
+                fp = in(reg) self.fp.as_ptr(),
+                fcsr = in(reg) self.fcsr,
+            )
+        }
+    }
+}
+
 /// Saved registers when a trap (interrupt or exception) occurs.
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
@@ -53,6 +114,11 @@ pub struct TrapFrame {
     pub sepc: usize,
     /// Supervisor Status Register.
     pub sstatus: usize,
+    /// FP registers of user space, only saved if they have been modified
+    /// (i.e., `sstatus.FS` is dirty) on a trap from user space, and only valid
+    /// if `sstatus.FS` is clean or dirty.
+    #[cfg(all(feature = "fp_simd", feature = "uspace"))]
+    pub fp_state: FpState,
 }
 
 impl TrapFrame {
@@ -85,6 +151,43 @@ impl TrapFrame {
     pub const fn arg5(&self) -> usize {
         self.regs.a5
     }
+
+    /// Saves the user FP registers on a trap from user space, if they have
+    /// been modified since they were restored.
+    #[cfg(all(feature = "fp_simd", feature = "uspace"))]
+    pub(crate) fn save_user_fp(&mut self) {
+        if self.sstatus & SSTATUS_FS == FS_DIRTY {
+            self.fp_state.save();
+            unsafe { sstatus::set_fs(FS::Clean) };
+        }
+    }
+
+    /// Loads the user FP registers: zeros if the user space has not used FP
+    /// (i.e., `sstatus.FS` is initial), or the saved ones if it has.
+    #[cfg(all(feature = "fp_simd", feature = "uspace"))]
+    fn load_user_fp(&self) {
+        match self.sstatus & SSTATUS_FS {
+            FS_INITIAL => FpState::default().restore(),
+            FS_CLEAN | FS_DIRTY => self.fp_state.restore(),
+            _ => {}
+        }
+    }
+
+    /// Updates the FP states before returning from the trap.
+    ///
+    /// The user FP registers are loaded again, as the kernel may have run
+    /// other tasks that changed them. For a trap from the kernel, `sstatus.FS`
+    /// of the CPU is kept, as it may be changed by the trap handler or a task
+    /// switch.
+    #[cfg(feature = "fp_simd")]
+    pub(crate) fn restore_fp(&mut self, from_user: bool) {
+        if from_user {
+            #[cfg(feature = "uspace")]
+            self.load_user_fp();
+        } else {
+            self.sstatus = (self.sstatus & !SSTATUS_FS) | (sstatus::read().bits() & SSTATUS_FS);
+        }
+    }
 }
 
 /// Saved hardware states of a task.
@@ -123,7 +226,12 @@ pub struct TaskContext {
     /// The `satp` register value, i.e., the page table root.
     #[cfg(feature = "uspace")]
     pub satp: PhysAddr,
-    // TODO: FP states
+    /// The FP registers, only valid if `fp_saved` is set.
+    #[cfg(feature = "fp_simd")]
+    pub fp_state: FpState,
+    /// Whether the task has used FP, and its FP registers are saved.
+    #[cfg(feature = "fp_simd")]
+    pub fp_saved: bool,
 }
 
 impl TaskContext {
@@ -177,9 +285,32 @@ impl TaskContext {
                 super::write_page_table_root(next_ctx.satp);
             }
         }
-        unsafe {
-            // TODO: switch FP states
-            context_switch(self, next_ctx)
+        #[cfg(feature = "fp_simd")]
+        self.switch_fp(next_ctx);
+        unsafe { context_switch(self, next_ctx) }
+    }
+
+    /// Switches the FP states lazily.
+    ///
+    /// The FP registers are saved only if they have been modified since the
+    /// last save or restore (i.e., `sstatus.FS` is dirty), and restored only
+    /// if the next task has used FP. A task that has not used FP runs with
+    /// zeroed registers, which are only zeroed again when it is switched to
+    /// from a task that has used FP. So tasks not using FP pay nothing among
+    /// themselves, and never see the registers of others.
+    #[cfg(feature = "fp_simd")]
+    fn switch_fp(&mut self, next_ctx: &Self) {
+        if sstatus::read().fs() == FS::Dirty {
+            self.fp_state.save();
+            self.fp_saved = true;
+            unsafe { sstatus::set_fs(FS::Clean) };
+        }
+        if next_ctx.fp_saved {
+            next_ctx.fp_state.restore();
+            unsafe { sstatus::set_fs(FS::Clean) };
+        } else if self.fp_saved {
+            FpState::default().restore();
+            unsafe { sstatus::set_fs(FS::Clean) };
         }
     }
 }
@@ -197,16 +328,24 @@ impl UspaceContext {
 
     /// Creates a new context with the given entry point, user stack pointer,
     /// and the argument.
+    ///
+    /// The FP registers are zeroed when entering user space.
     pub fn new(entry: usize, ustack_top: VirtAddr) -> Self {
         const SPIE: usize = 1 << 5;
         const SUM: usize = 1 << 18;
+        #[cfg(feature = "fp_simd")]
+        const FS: usize = FS_INITIAL;
+        #[cfg(not(feature = "fp_simd"))]
+        const FS: usize = 0;
         Self(TrapFrame {
             regs: GeneralRegisters {
                 sp: ustack_top.as_usize(),
                 ..Default::default()
             },
             sepc: entry,
-            sstatus: SPIE | SUM,
+            sstatus: SPIE | SUM | FS,
+            #[cfg(feature = "fp_simd")]
+            fp_state: FpState::default(),
         })
     }
 
@@ -256,6 +395,9 @@ impl UspaceContext {
         use riscv::register::{sepc, sscratch};
 
         super::disable_irqs();
+        // Do not leak the FP registers of the previous tasks.
+        #[cfg(feature = "fp_simd")]
+        self.0.load_user_fp();
         sscratch::write(kstack_top.as_usize());
         sepc::write(self.0.sepc);
         // Address of the top of the kernel stack after saving the trap frame.
@@ -10,7 +10,7 @@ use riscv::register::{satp, sstatus, stvec};
 
 #[cfg(feature = "uspace")]
 pub use self::context::UspaceContext;
-pub use self::context::{GeneralRegisters, TaskContext, TrapFrame};
+pub use self::context::{FpState, GeneralRegisters, TaskContext, TrapFrame};
 
 /// Allows the current CPU to respond to interrupts.
 #[inline]
@@ -3,6 +3,7 @@ use riscv::register::scause::{self, Exception as E, Trap};
 use riscv::register::stval;
 
 use super::TrapFrame;
+use crate::backtrace::Backtrace;
 
 include_asm_marcos!();
 
@@ -11,17 +12,26 @@ core::arch::global_asm!(
     trapframe_size = const core::mem::size_of::<TrapFrame>(),
 );
 
-fn handle_breakpoint(sepc: &mut usize) {
-    debug!("Exception(Breakpoint) @ {:#x} ", sepc);
-    *sepc += 2
+fn handle_breakpoint(tf: &mut TrapFrame) {
+    debug!("Exception(Breakpoint) @ {:#x} ", tf.sepc);
+    if crate::trap::BREAKPOINT.is_empty() || !handle_trap!(BREAKPOINT, tf) {
+        tf.sepc += 2
+    }
 }
 
-fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bool) {
+fn handle_page_fault(tf: &mut TrapFrame, mut access_flags: MappingFlags, is_user: bool) {
     if is_user {
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(stval::read());
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
+        if !is_user {
+            if let Some(fixup) = crate::extable::fixup_exception(tf.sepc) {
+                tf.sepc = fixup;
+                return;
+            }
+        }
+        Backtrace::set_fatal_trap(tf);
         panic!(
             "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
             if is_user { "User" } else { "Supervisor" },
@@ -35,6 +45,16 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bo
 
 #[no_mangle]
 fn riscv_trap_handler(tf: &mut TrapFrame, from_user: bool) {
+    #[cfg(all(feature = "fp_simd", feature = "uspace"))]
+    if from_user {
+        tf.save_user_fp();
+    }
+    dispatch_trap(tf, from_user);
+    #[cfg(feature = "fp_simd")]
+    tf.restore_fp(from_user);
+}
+
+fn dispatch_trap(tf: &mut TrapFrame, from_user: bool) {
     let scause = scause::read();
     match scause.cause() {
         #[cfg(feature = "uspace")]
@@ -47,16 +67,24 @@ fn riscv_trap_handler(tf: &mut TrapFrame, from_user: bool) {
         Trap::Exception(E::InstructionPageFault) => {
             handle_page_fault(tf, MappingFlags::EXECUTE, from_user)
         }
-        Trap::Exception(E::Breakpoint) => handle_breakpoint(&mut tf.sepc),
+        Trap::Exception(E::Breakpoint) => handle_breakpoint(tf),
         Trap::Interrupt(_) => {
             handle_trap!(IRQ, scause.bits());
         }
         _ => {
+            // e.g., a guest-page fault when a hypervisor reads guest memory
+            if !from_user {
+                if let Some(fixup) = crate::extable::fixup_exception(tf.sepc) {
+                    tf.sepc = fixup;
+                    return;
+                }
+            }
+            Backtrace::set_fatal_trap(tf);
             panic!(
                 "Unhandled trap {:?} @ {:#x}:\n{:#x?}",
                 scause.cause(),
                 tf.sepc,
-                tf
+                tf,
             );
         }
     }
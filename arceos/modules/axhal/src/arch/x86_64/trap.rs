@@ -3,17 +3,25 @@ use x86::{controlregs::cr2, irq::*};
 use x86_64::structures::idt::PageFaultErrorCode;
 
 use super::context::TrapFrame;
+use crate::backtrace::Backtrace;
 
 core::arch::global_asm!(include_str!("trap.S"));
 
 const IRQ_VECTOR_START: u8 = 0x20;
 const IRQ_VECTOR_END: u8 = 0xff;
 
-fn handle_page_fault(tf: &TrapFrame) {
+fn handle_page_fault(tf: &mut TrapFrame) {
     let access_flags = err_code_to_flags(tf.error_code)
         .unwrap_or_else(|e| panic!("Invalid #PF error code: {:#x}", e));
     let vaddr = va!(unsafe { cr2() });
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user()) {
+        if !tf.is_user() {
+            if let Some(fixup) = crate::extable::fixup_exception(tf.rip as usize) {
+                tf.rip = fixup as u64;
+                return;
+            }
+        }
+        Backtrace::set_fatal_trap(tf);
         panic!(
             "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
             if tf.is_user() { "user" } else { "kernel" },
@@ -27,27 +35,29 @@ fn handle_page_fault(tf: &TrapFrame) {
 }
 
 #[no_mangle]
-fn x86_trap_handler(tf: &TrapFrame) {
+fn x86_trap_handler(tf: &mut TrapFrame) {
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
         GENERAL_PROTECTION_FAULT_VECTOR => {
+            Backtrace::set_fatal_trap(tf);
             panic!(
                 "#GP @ {:#x}, error_code={:#x}:\n{:#x?}",
-                tf.rip, tf.error_code, tf
+                tf.rip, tf.error_code, tf,
             );
         }
         IRQ_VECTOR_START..=IRQ_VECTOR_END => {
             handle_trap!(IRQ, tf.vector as _);
         }
         _ => {
+            Backtrace::set_fatal_trap(tf);
             panic!(
                 "Unhandled exception {} ({}, error_code={:#x}) @ {:#x}:\n{:#x?}",
                 tf.vector,
                 vec_to_str(tf.vector),
                 tf.error_code,
                 tf.rip,
-                tf
+                tf,
             );
         }
     }
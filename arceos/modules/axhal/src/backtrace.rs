@@ -0,0 +1,353 @@
+//! Frame-pointer based stack unwinding and symbolization.
+//!
+//! The frames are found by following the chain of frame records (the saved
+//! frame pointer and return address), so the kernel should be built with
+//! `-C force-frame-pointers=yes` for the backtraces to be complete.
+//!
+//! Addresses are symbolized with the symbol table generated by `tools/ksym`
+//! from the kernel ELF, which is embedded into the `.ksyms` section by the
+//! build script (see the `BACKTRACE` option of the top-level Makefile).
+//! Without it, only the addresses are printed.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::TrapFrame;
+use crate::mem::{memory_regions, MemRegionFlags};
+
+/// Maximum number of frames to walk.
+const MAX_DEPTH: usize = 64;
+
+const KSYMS_MAGIC: &[u8; 4] = b"KSYM";
+const KSYMS_HEADER_SIZE: usize = 8;
+const KSYMS_ENTRY_SIZE: usize = 16;
+
+/// The program counter and frame pointer of the fatal trap being reported on
+/// each CPU, zero if none.
+static FATAL_TRAP: [[AtomicUsize; 2]; axconfig::SMP] =
+    [const { [AtomicUsize::new(0), AtomicUsize::new(0)] }; axconfig::SMP];
+
+/// The symbol table given by `AX_KSYMS` at build time, empty if not set.
+///
+/// It is located by the `__ksyms_start` and `__ksyms_end` symbols rather than
+/// its length, so the code does not change when the table is embedded.
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.bin")).len()] =
+    *include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.bin"));
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        /// Offsets of the saved frame pointer and return address in a frame
+        /// record, relative to the frame pointer.
+        const FRAME_RECORD: (isize, isize) = (0, 8);
+
+        #[inline(always)]
+        fn read_fp() -> usize {
+            let fp;
+            unsafe { core::arch::asm!("mov {}, rbp", out(reg) fp) };
+            fp
+        }
+
+        fn trap_pc_fp(tf: &TrapFrame) -> (usize, usize) {
+            (tf.rip as usize, tf.rbp as usize)
+        }
+    } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+        const XLEN: isize = core::mem::size_of::<usize>() as isize;
+        const FRAME_RECORD: (isize, isize) = (-2 * XLEN, -XLEN);
+
+        #[inline(always)]
+        fn read_fp() -> usize {
+            let fp;
+            unsafe { core::arch::asm!("mv {}, s0", out(reg) fp) };
+            fp
+        }
+
+        fn trap_pc_fp(tf: &TrapFrame) -> (usize, usize) {
+            (tf.sepc, tf.regs.s0)
+        }
+    } else if #[cfg(target_arch = "aarch64")] {
+        const FRAME_RECORD: (isize, isize) = (0, 8);
+
+        #[inline(always)]
+        fn read_fp() -> usize {
+            let fp;
+            unsafe { core::arch::asm!("mov {}, x29", out(reg) fp) };
+            fp
+        }
+
+        fn trap_pc_fp(tf: &TrapFrame) -> (usize, usize) {
+            (tf.elr as usize, tf.r[29] as usize)
+        }
+    } else if #[cfg(target_arch = "loongarch64")] {
+        const FRAME_RECORD: (isize, isize) = (-16, -8);
+
+        #[inline(always)]
+        fn read_fp() -> usize {
+            let fp;
+            unsafe { core::arch::asm!("move {}, $fp", out(reg) fp) };
+            fp
+        }
+
+        fn trap_pc_fp(tf: &TrapFrame) -> (usize, usize) {
+            (tf.era, tf.regs.fp)
+        }
+    }
+}
+
+/// Returns the embedded symbol table, or an empty slice if it is missing or
+/// malformed.
+fn ksyms() -> &'static [u8] {
+    let data = unsafe {
+        core::slice::from_raw_parts(
+            __ksyms_start as usize as *const u8,
+            __ksyms_end as usize - __ksyms_start as usize,
+        )
+    };
+    if data.len() < KSYMS_HEADER_SIZE || &data[..4] != KSYMS_MAGIC {
+        return &[];
+    }
+    if data.len() < KSYMS_HEADER_SIZE + ksyms_count(data) * KSYMS_ENTRY_SIZE {
+        return &[];
+    }
+    data
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn ksyms_count(data: &[u8]) -> usize {
+    read_u32(data, 4) as usize
+}
+
+/// Returns the name of the function containing `addr`, and the offset of
+/// `addr` in it.
+///
+/// Returns `None` if the symbol table is not embedded, or `addr` is not in any
+/// function.
+pub fn symbolize(addr: usize) -> Option<(&'static str, usize)> {
+    let data = ksyms();
+    if data.is_empty() {
+        return None;
+    }
+    let count = ksyms_count(data);
+    let entry = |i: usize| KSYMS_HEADER_SIZE + i * KSYMS_ENTRY_SIZE;
+
+    // Find the last symbol at or below `addr`.
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if read_u64(data, entry(mid)) as usize <= addr {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let i = lo.checked_sub(1)?;
+
+    let start = read_u64(data, entry(i)) as usize;
+    let size = read_u32(data, entry(i) + 8) as usize;
+    if size != 0 && addr - start >= size {
+        return None;
+    }
+    let names = &data[entry(count)..];
+    let name = names.get(read_u32(data, entry(i) + 12) as usize..)?;
+    let len = name.iter().position(|&c| c == 0)?;
+    let name = core::str::from_utf8(&name[..len]).ok()?;
+    Some((name, addr - start))
+}
+
+/// Whether the frame record of `fp` can be read safely, i.e., it is in the
+/// memory writable by the kernel, like stacks.
+fn is_valid_fp(fp: usize) -> bool {
+    if fp == 0 || fp % core::mem::size_of::<usize>() != 0 {
+        return false;
+    }
+    let (fp_off, ra_off) = FRAME_RECORD;
+    let (Some(start), Some(end)) = (
+        fp.checked_add_signed(fp_off.min(ra_off)),
+        fp.checked_add_signed(fp_off.max(ra_off) + core::mem::size_of::<usize>() as isize),
+    ) else {
+        return false;
+    };
+    let (Some(start), Some(end)) = (
+        start.checked_sub(axconfig::PHYS_VIRT_OFFSET),
+        end.checked_sub(axconfig::PHYS_VIRT_OFFSET),
+    ) else {
+        return false;
+    };
+    memory_regions().any(|r| {
+        r.flags.contains(MemRegionFlags::WRITE)
+            && !r.flags.contains(MemRegionFlags::DEVICE)
+            && start >= r.paddr.as_usize()
+            && end <= r.paddr.as_usize() + r.size
+    })
+}
+
+/// A frame of a [`Backtrace`].
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    /// The program counter: the trapped instruction for the first frame of a
+    /// trap backtrace, or the return address for the others.
+    pub pc: usize,
+    /// Whether `pc` is a return address, which is after the call instruction.
+    is_return: bool,
+}
+
+impl Frame {
+    /// Returns the name of the function of this frame, and the offset of `pc`
+    /// in it.
+    pub fn symbolize(&self) -> Option<(&'static str, usize)> {
+        // A return address may be past the end of the caller, if the call is
+        // its last instruction.
+        let addr = if self.is_return { self.pc - 1 } else { self.pc };
+        symbolize(addr).map(|(name, off)| (name, off + (self.pc - addr)))
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.symbolize() {
+            Some((name, off)) => write!(f, "{:#018x} - {}+{:#x}", self.pc, name, off),
+            None => write!(f, "{:#018x} - <unknown>", self.pc),
+        }
+    }
+}
+
+/// A call stack of the kernel.
+///
+/// It is printed with one [`Frame`] per line by the [`Display`](fmt::Display)
+/// implementation.
+#[derive(Debug, Clone, Copy)]
+pub struct Backtrace {
+    pc: usize,
+    fp: usize,
+}
+
+impl Backtrace {
+    /// Captures the call stack of the caller, whose first frame is the caller
+    /// of the current function.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        Self {
+            pc: 0,
+            fp: read_fp(),
+        }
+    }
+
+    /// Returns the call stack at the time of a trap from the kernel, whose
+    /// first frame is the trapped instruction.
+    pub fn from_trap(tf: &TrapFrame) -> Self {
+        let (pc, fp) = trap_pc_fp(tf);
+        Self { pc, fp }
+    }
+
+    /// Records a trap from the kernel that the current CPU is about to panic
+    /// on, so that the panic is reported with the call stack at the time of
+    /// the trap by [`Backtrace::for_panic`].
+    pub fn set_fatal_trap(tf: &TrapFrame) {
+        let (pc, fp) = trap_pc_fp(tf);
+        let slot = &FATAL_TRAP[crate::cpu::this_cpu_id()];
+        slot[0].store(pc, Ordering::Relaxed);
+        slot[1].store(fp, Ordering::Relaxed);
+    }
+
+    /// Returns the call stack to report a panic with: the one at the time of
+    /// the trap recorded by [`Backtrace::set_fatal_trap`] on the current CPU,
+    /// or the call stack of the caller otherwise.
+    #[inline(always)]
+    pub fn for_panic() -> Self {
+        let slot = &FATAL_TRAP[crate::cpu::this_cpu_id()];
+        match slot[0].swap(0, Ordering::Relaxed) {
+            0 => Self::capture(),
+            pc => Self {
+                pc,
+                fp: slot[1].load(Ordering::Relaxed),
+            },
+        }
+    }
+
+    /// Returns an iterator over the frames, from the innermost one.
+    pub fn frames(&self) -> Frames {
+        Frames {
+            next: (self.pc != 0).then_some(Frame {
+                pc: self.pc,
+                is_return: false,
+            }),
+            fp: self.fp,
+            depth: 0,
+        }
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+        let mut depth = 0;
+        for frame in self.frames() {
+            writeln!(f, "  #{:<2} {}", depth, frame)?;
+            depth += 1;
+        }
+        if depth == MAX_DEPTH {
+            writeln!(f, "  ... (truncated)")?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the frames of a [`Backtrace`].
+pub struct Frames {
+    next: Option<Frame>,
+    fp: usize,
+    depth: usize,
+}
+
+impl Frames {
+    /// Reads the frame record at `fp`, returns the caller's frame.
+    fn unwind(&mut self) -> Option<Frame> {
+        if !is_valid_fp(self.fp) {
+            return None;
+        }
+        let (fp_off, ra_off) = FRAME_RECORD;
+        let (next_fp, ra) = unsafe {
+            let fp = self.fp as *const u8;
+            (
+                *(fp.offset(fp_off) as *const usize),
+                *(fp.offset(ra_off) as *const usize),
+            )
+        };
+        // The stack grows downwards, so the caller's frame must be above.
+        if ra == 0 || next_fp <= self.fp {
+            self.fp = 0;
+        } else {
+            self.fp = next_fp;
+        }
+        (ra != 0).then_some(Frame {
+            pc: ra,
+            is_return: true,
+        })
+    }
+}
+
+impl Iterator for Frames {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.depth >= MAX_DEPTH {
+            return None;
+        }
+        let frame = self.next.take().or_else(|| self.unwind())?;
+        self.depth += 1;
+        Some(frame)
+    }
+}
+
+extern "C" {
+    fn __ksyms_start();
+    fn __ksyms_end();
+}
@@ -47,9 +47,13 @@ pub fn current_task_ptr<T>() -> *const T {
         // on x86, only one instruction is needed to read the per-CPU task pointer from `gs:[off]`.
         CURRENT_TASK_PTR.read_current_raw() as _
     }
-    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    #[cfg(any(
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    ))]
     unsafe {
-        // on RISC-V, reading `CURRENT_TASK_PTR` requires multiple instruction, so we disable local IRQs.
+        // on RISC-V and LoongArch, reading `CURRENT_TASK_PTR` requires multiple instruction, so we disable local IRQs.
         let _guard = kernel_guard::IrqSave::new();
         CURRENT_TASK_PTR.read_current_raw() as _
     }
@@ -76,7 +80,11 @@ pub unsafe fn set_current_task_ptr<T>(ptr: *const T) {
     {
         CURRENT_TASK_PTR.write_current_raw(ptr as usize)
     }
-    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    #[cfg(any(
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    ))]
     {
         let _guard = kernel_guard::IrqSave::new();
         CURRENT_TASK_PTR.write_current_raw(ptr as usize)
@@ -0,0 +1,39 @@
+//! The exception table.
+//!
+//! Instructions that may fault on purpose, such as accesses to user memory
+//! or to guest memory of a hypervisor, are recorded in the exception table
+//! (section `__ex_table`), with the address to resume at. When such an
+//! instruction faults in the kernel and the fault is not handled otherwise
+//! (e.g., by mapping a lazy page), the trap handler resumes at the fixup
+//! address instead of panicking.
+
+/// An entry of the exception table.
+#[repr(C)]
+struct ExceptionEntry {
+    /// Address of the instruction that may fault.
+    insn: usize,
+    /// Address to resume at if it faults.
+    fixup: usize,
+}
+
+extern "C" {
+    static __start_ex_table: ExceptionEntry;
+    static __stop_ex_table: ExceptionEntry;
+}
+
+fn exception_table() -> &'static [ExceptionEntry] {
+    unsafe {
+        let start = &__start_ex_table as *const ExceptionEntry;
+        let end = &__stop_ex_table as *const ExceptionEntry;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Returns the address to resume at if the instruction at `pc` faults, or
+/// `None` if `pc` is not in the exception table.
+pub(crate) fn fixup_exception(pc: usize) -> Option<usize> {
+    exception_table()
+        .iter()
+        .find(|e| e.insn == pc)
+        .map(|e| e.fixup)
+}
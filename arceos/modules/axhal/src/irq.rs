@@ -7,6 +7,9 @@ use crate::trap::{register_trap_handler, IRQ};
 
 pub use crate::platform::irq::{register_handler, set_enable};
 
+#[cfg(all(target_arch = "riscv64", platform_family = "riscv64-qemu-virt"))]
+pub use crate::platform::irq::kick_cpu;
+
 /// The type if an IRQ handler.
 pub type IrqHandler = handler_table::Handler;
 
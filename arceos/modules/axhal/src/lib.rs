@@ -10,6 +10,7 @@
 //! - `riscv64-qemu-virt`: QEMU virt machine with RISC-V ISA.
 //! - `aarch64-qemu-virt`: QEMU virt machine with AArch64 ISA.
 //! - `aarch64-raspi`: Raspberry Pi with AArch64 ISA.
+//! - `loongarch64-qemu-virt`: QEMU virt machine with LoongArch64 ISA.
 //! - `dummy`: If none of the above platform is selected, the dummy platform
 //!    will be used. In this platform, most of the operations are no-op or
 //!    `unimplemented!()`. This platform is mainly used for [cargo test].
@@ -20,11 +21,12 @@
 //! - `fp_simd`: Enable floating-point and SIMD support.
 //! - `paging`: Enable page table manipulation.
 //! - `irq`: Enable interrupt handling support.
+//! - `uspace`: Enable user space support, e.g., safe access to user memory.
 //!
 //! [ArceOS]: https://github.com/arceos-org/arceos
 //! [cargo test]: https://doc.rust-lang.org/cargo/guide/tests.html
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(asm_const)]
 #![feature(naked_functions)]
 #![feature(const_option)]
@@ -45,10 +47,13 @@ mod platform;
 pub mod trap;
 
 pub mod arch;
+pub mod backtrace;
 pub mod cpu;
 pub mod mem;
 pub mod time;
 
+mod extable;
+
 #[cfg(feature = "tls")]
 pub mod tls;
 
@@ -58,6 +63,9 @@ pub mod irq;
 #[cfg(feature = "paging")]
 pub mod paging;
 
+#[cfg(feature = "uspace")]
+pub mod uaccess;
+
 /// Console input and output.
 pub mod console {
     pub use super::platform::console::*;
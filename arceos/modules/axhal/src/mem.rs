@@ -126,10 +126,17 @@ pub(crate) fn default_mmio_regions() -> impl Iterator<Item = MemRegion> {
     })
 }
 
+/// Returns the physical address of the end of the kernel image (aligned up
+/// to 4K), where the free memory starts.
+#[allow(dead_code)]
+pub(crate) fn kernel_end_paddr() -> PhysAddr {
+    virt_to_phys((_ekernel as usize).into()).align_up_4k()
+}
+
 /// Returns the default free memory regions (kernel image end to physical memory end).
 #[allow(dead_code)]
 pub(crate) fn default_free_regions() -> impl Iterator<Item = MemRegion> {
-    let start = virt_to_phys((_ekernel as usize).into()).align_up_4k();
+    let start = kernel_end_paddr();
     let end = pa!(axconfig::PHYS_MEMORY_END).align_down_4k();
     core::iter::once(MemRegion {
         paddr: start,
@@ -63,6 +63,9 @@ cfg_if::cfg_if! {
     } else if #[cfg(target_arch = "aarch64")]{
         /// The architecture-specific page table.
         pub type PageTable = page_table_multiarch::aarch64::A64PageTable<PagingHandlerImpl>;
+    } else if #[cfg(target_arch = "loongarch64")] {
+        /// The architecture-specific page table.
+        pub type PageTable = page_table_multiarch::loongarch64::LA64PageTable<PagingHandlerImpl>;
     }
 }
 
@@ -0,0 +1,85 @@
+use axconfig::{PHYS_VIRT_OFFSET, TASK_STACK_SIZE};
+
+#[link_section = ".bss.stack"]
+static mut BOOT_STACK: [u8; TASK_STACK_SIZE] = [0; TASK_STACK_SIZE];
+
+/// Sets up the direct mapping windows, jumps to the kernel virtual address,
+/// and enables paging.
+///
+/// The kernel is accessed through DMW1 (`0x9000_xxxx_xxxx_xxxx`, cached), and
+/// DMW0 (`0x8000_xxxx_xxxx_xxxx`) is the uncached window. Before paging is
+/// enabled, the CPU is in the direct address translation mode, where the high
+/// bits of an address are ignored, so the jump lands on the physical address.
+macro_rules! enable_dmw {
+    () => {
+        "
+        ori         $t0, $zero, 0x1     // PLV0, strongly-ordered uncached
+        lu52i.d     $t0, $t0, -2048     // VSEG = 0x8
+        csrwr       $t0, 0x180          // LOONGARCH_CSR_DMW0
+        ori         $t0, $zero, 0x11    // PLV0, coherent cached
+        lu52i.d     $t0, $t0, -1792     // VSEG = 0x9
+        csrwr       $t0, 0x181          // LOONGARCH_CSR_DMW1
+
+        la.abs      $t0, 1f
+        jirl        $zero, $t0, 0       // jump to the virtual address
+    1:
+        li.w        $t0, 0xb0           // PLV=0, IE=0, PG=1, DATF=DATM=CC
+        csrwr       $t0, 0x0            // LOONGARCH_CSR_CRMD
+        "
+    };
+}
+
+/// The earliest entry point for the primary CPU.
+#[naked]
+#[no_mangle]
+#[link_section = ".text.boot"]
+unsafe extern "C" fn _start() -> ! {
+    // PC = 0x20_0000 (direct address translation mode)
+    // a0, a1, a2 = boot arguments from QEMU, unused
+    core::arch::asm!(
+        enable_dmw!(),
+        "
+        la.abs      $sp, {boot_stack}
+        li.d        $t0, {boot_stack_size}
+        add.d       $sp, $sp, $t0       // setup boot stack
+
+        csrrd       $a0, 0x20           // cpuid
+        move        $a1, $zero          // no DTB
+        la.abs      $t0, {entry}
+        jirl        $ra, $t0, 0         // call rust_entry(cpu_id, dtb)
+    2:
+        b           2b",
+        boot_stack_size = const TASK_STACK_SIZE,
+        boot_stack = sym BOOT_STACK,
+        entry = sym super::rust_entry,
+        options(noreturn),
+    )
+}
+
+/// The earliest entry point for secondary CPUs.
+///
+/// It is jumped to by the boot code of QEMU when woken up by an IPI, with the
+/// physical address of its stack top in the mailbox 1, see [`super::mp`].
+#[cfg(feature = "smp")]
+#[naked]
+#[no_mangle]
+#[link_section = ".text.boot"]
+unsafe extern "C" fn _start_secondary() -> ! {
+    core::arch::asm!(
+        enable_dmw!(),
+        "
+        li.d        $t0, 0x1028         // LOONGARCH_IOCSR_MBUF1
+        iocsrrd.d   $sp, $t0
+        li.d        $t0, {phys_virt_offset}
+        add.d       $sp, $sp, $t0       // set SP
+
+        csrrd       $a0, 0x20           // cpuid
+        la.abs      $t0, {entry}
+        jirl        $ra, $t0, 0         // call rust_entry_secondary(cpu_id)
+    2:
+        b           2b",
+        phys_virt_offset = const PHYS_VIRT_OFFSET,
+        entry = sym super::rust_entry_secondary,
+        options(noreturn),
+    )
+}
@@ -0,0 +1,110 @@
+//! NS16550 UART with byte-wide memory-mapped registers.
+
+use kspin::SpinNoIrq;
+use memory_addr::PhysAddr;
+
+use crate::mem::phys_to_virt;
+
+const UART_BASE: PhysAddr = pa!(axconfig::UART_PADDR);
+const UART_CLOCK_FACTOR: usize = 16;
+const OSC_FREQ: usize = 100_000_000;
+
+static UART: SpinNoIrq<Uart16550> =
+    SpinNoIrq::new(Uart16550::new(phys_to_virt(UART_BASE).as_usize()));
+
+bitflags::bitflags! {
+    /// Line status flags
+    struct LineStsFlags: u8 {
+        const INPUT_FULL = 1;
+        // 1 to 4 unknown
+        const OUTPUT_EMPTY = 1 << 5;
+        // 6 and 7 unknown
+    }
+}
+
+struct Uart16550 {
+    base: usize,
+}
+
+impl Uart16550 {
+    const DATA: usize = 0;
+    const INT_EN: usize = 1;
+    const FIFO_CTRL: usize = 2;
+    const LINE_CTRL: usize = 3;
+    const MODEM_CTRL: usize = 4;
+    const LINE_STS: usize = 5;
+
+    const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    fn read(&self, reg: usize) -> u8 {
+        unsafe { ((self.base + reg) as *const u8).read_volatile() }
+    }
+
+    fn write(&mut self, reg: usize, val: u8) {
+        unsafe { ((self.base + reg) as *mut u8).write_volatile(val) }
+    }
+
+    fn init(&mut self, baud_rate: usize) {
+        // Disable interrupts
+        self.write(Self::INT_EN, 0x00);
+
+        // Enable DLAB
+        self.write(Self::LINE_CTRL, 0x80);
+
+        // Set maximum speed according the input baud rate by configuring DLL and DLM
+        let divisor = OSC_FREQ / (baud_rate * UART_CLOCK_FACTOR);
+        self.write(Self::DATA, (divisor & 0xff) as u8);
+        self.write(Self::INT_EN, (divisor >> 8) as u8);
+
+        // Disable DLAB and set data word length to 8 bits
+        self.write(Self::LINE_CTRL, 0x03);
+
+        // Enable FIFO, clear TX/RX queues and
+        // set interrupt watermark at 14 bytes
+        self.write(Self::FIFO_CTRL, 0xC7);
+
+        // Mark data terminal ready, signal request to send
+        // and enable auxilliary output #2 (used as interrupt line for CPU)
+        self.write(Self::MODEM_CTRL, 0x0B);
+    }
+
+    fn line_sts(&self) -> LineStsFlags {
+        LineStsFlags::from_bits_truncate(self.read(Self::LINE_STS))
+    }
+
+    fn putchar(&mut self, c: u8) {
+        while !self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY) {}
+        self.write(Self::DATA, c);
+    }
+
+    fn getchar(&mut self) -> Option<u8> {
+        if self.line_sts().contains(LineStsFlags::INPUT_FULL) {
+            Some(self.read(Self::DATA))
+        } else {
+            None
+        }
+    }
+}
+
+/// Writes a byte to the console.
+pub fn putchar(c: u8) {
+    let mut uart = UART.lock();
+    match c {
+        b'\n' => {
+            uart.putchar(b'\r');
+            uart.putchar(b'\n');
+        }
+        c => uart.putchar(c),
+    }
+}
+
+/// Reads a byte from the console, or returns [`None`] if no input is available.
+pub fn getchar() -> Option<u8> {
+    UART.lock().getchar()
+}
+
+pub(super) fn init() {
+    UART.lock().init(115200);
+}
@@ -0,0 +1,30 @@
+//! Accesses to the IOCSR (I/O control and status registers), which are
+//! per-CPU, e.g., the IPI and EIOINTC registers.
+
+#![allow(dead_code)]
+
+use core::arch::asm;
+
+#[inline]
+pub fn read_w(reg: usize) -> u32 {
+    let val: u32;
+    unsafe { asm!("iocsrrd.w {}, {}", out(reg) val, in(reg) reg) };
+    val
+}
+
+#[inline]
+pub fn write_w(reg: usize, val: u32) {
+    unsafe { asm!("iocsrwr.w {}, {}", in(reg) val, in(reg) reg) };
+}
+
+#[inline]
+pub fn read_d(reg: usize) -> u64 {
+    let val: u64;
+    unsafe { asm!("iocsrrd.d {}, {}", out(reg) val, in(reg) reg) };
+    val
+}
+
+#[inline]
+pub fn write_d(reg: usize, val: u64) {
+    unsafe { asm!("iocsrwr.d {}, {}", in(reg) val, in(reg) reg) };
+}
@@ -0,0 +1,200 @@
+//! Interrupt handling with the extended I/O interrupt controller (EIOINTC) and
+//! the platform interrupt controller (PCH-PIC).
+//!
+//! Input `n` of PCH-PIC is sent to vector `n` of EIOINTC, and all the vectors
+//! are routed to the hardware interrupt line `HWI0` of CPU 0. IRQ numbers below
+//! 256 are EIOINTC vectors, and the following ones are the interrupt lines of
+//! the CPU (bits of `ESTAT.IS`), e.g., the timer.
+
+use kspin::SpinNoIrq;
+use loongArch64::register::ecfg::{self, LineBasedInterrupt};
+use loongArch64::register::ticlr;
+use memory_addr::PhysAddr;
+
+use super::iocsr;
+use crate::irq::IrqHandler;
+use crate::mem::phys_to_virt;
+
+/// Number of EIOINTC vectors.
+const EIOINTC_VEC_COUNT: usize = 256;
+
+/// Number of PCH-PIC inputs.
+const PCH_PIC_IRQ_COUNT: usize = 64;
+
+/// IRQ number of the first interrupt line of the CPU.
+const LOCAL_IRQ_BASE: usize = EIOINTC_VEC_COUNT;
+
+/// Hardware interrupt line 0 in `ESTAT.IS`, connected to EIOINTC.
+const HWI0: usize = 2;
+
+/// Timer interrupt line in `ESTAT.IS`.
+const TI: usize = 11;
+
+/// The maximum number of IRQs.
+pub const MAX_IRQ_COUNT: usize = LOCAL_IRQ_BASE + 13;
+
+/// The timer IRQ number.
+pub const TIMER_IRQ_NUM: usize = LOCAL_IRQ_BASE + TI;
+
+/// The UART IRQ number.
+pub const UART_IRQ_NUM: usize = axconfig::UART_IRQ;
+
+const IOCSR_MISC_FUNC: usize = 0x420;
+const IOCSR_MISC_FUNC_EXT_IOI_EN: u64 = 1 << 48;
+
+const EIOINTC_IPMAP: usize = 0x14c0;
+const EIOINTC_ENABLE: usize = 0x1600;
+const EIOINTC_BOUNCE: usize = 0x1680;
+const EIOINTC_ISR: usize = 0x1800;
+const EIOINTC_ROUTE: usize = 0x1c00;
+
+const PCH_PIC_BASE: PhysAddr = pa!(axconfig::PCH_PIC_PADDR);
+const PCH_PIC_INT_MASK: usize = 0x20;
+const PCH_PIC_HTMSI_EN: usize = 0x40;
+const PCH_PIC_EDGE: usize = 0x60;
+const PCH_PIC_ROUTE: usize = 0x100;
+const PCH_PIC_HTMSI_VEC: usize = 0x200;
+const PCH_PIC_POL: usize = 0x3e0;
+
+/// Serializes the read-modify-write of the enable and mask registers.
+static LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+fn pch_pic_reg<T>(offset: usize) -> *mut T {
+    (phys_to_virt(PCH_PIC_BASE).as_usize() + offset) as *mut T
+}
+
+/// Sets bit `bit` of the bitmap consisting of 32-bit registers at `offset`.
+fn pch_pic_set_bit(offset: usize, bit: usize, value: bool) {
+    let reg = pch_pic_reg::<u32>(offset + bit / 32 * 4);
+    unsafe {
+        let old = reg.read_volatile();
+        let mask = 1 << (bit % 32);
+        reg.write_volatile(if value { old | mask } else { old & !mask });
+    }
+}
+
+fn eiointc_set_enable(vector: usize, enabled: bool) {
+    let reg = EIOINTC_ENABLE + vector / 32 * 4;
+    let old = iocsr::read_w(reg);
+    let mask = 1 << (vector % 32);
+    iocsr::write_w(reg, if enabled { old | mask } else { old & !mask });
+}
+
+fn set_local_enable(line: usize, enabled: bool) {
+    let line = LineBasedInterrupt::from_bits_truncate(1 << line);
+    let old = ecfg::read().lie();
+    ecfg::set_lie(if enabled { old | line } else { old & !line });
+}
+
+/// Enables or disables the given IRQ.
+pub fn set_enable(irq_num: usize, enabled: bool) {
+    trace!("set enable: {} {}", irq_num, enabled);
+    if irq_num >= LOCAL_IRQ_BASE {
+        set_local_enable(irq_num - LOCAL_IRQ_BASE, enabled);
+        return;
+    }
+    let _guard = LOCK.lock();
+    eiointc_set_enable(irq_num, enabled);
+    if irq_num < PCH_PIC_IRQ_COUNT {
+        pch_pic_set_bit(PCH_PIC_INT_MASK, irq_num, !enabled);
+    }
+}
+
+/// Registers an IRQ handler for the given IRQ.
+///
+/// It also enables the IRQ if the registration succeeds. It returns `false` if
+/// the registration failed.
+pub fn register_handler(irq_num: usize, handler: IrqHandler) -> bool {
+    trace!("register handler irq {}", irq_num);
+    crate::irq::register_handler_common(irq_num, handler)
+}
+
+/// Dispatches the IRQ.
+///
+/// This function is called by the common interrupt handler, with the interrupt
+/// line of the CPU. It looks up in the IRQ handler table and calls the
+/// corresponding handler. If necessary, it also acknowledges the interrupt
+/// controller after handling.
+pub fn dispatch_irq(line: usize) {
+    match line {
+        HWI0 => dispatch_eiointc(),
+        TI => {
+            ticlr::clear_timer_interrupt();
+            crate::irq::dispatch_irq_common(TIMER_IRQ_NUM);
+        }
+        _ => crate::irq::dispatch_irq_common(LOCAL_IRQ_BASE + line),
+    }
+}
+
+fn dispatch_eiointc() {
+    for i in 0..EIOINTC_VEC_COUNT / 64 {
+        let reg = EIOINTC_ISR + i * 8;
+        let mut pending = iocsr::read_d(reg);
+        while pending != 0 {
+            let vector = i * 64 + pending.trailing_zeros() as usize;
+            pending &= pending - 1;
+            // PCH-PIC inputs are level-triggered, they are masked until
+            // handled, and sent again on unmasking if still asserted.
+            let from_pch_pic = vector < PCH_PIC_IRQ_COUNT;
+            if from_pch_pic {
+                let _guard = LOCK.lock();
+                pch_pic_set_bit(PCH_PIC_INT_MASK, vector, true);
+            }
+            iocsr::write_d(reg, 1 << (vector % 64));
+            crate::irq::dispatch_irq_common(vector);
+            if from_pch_pic {
+                let _guard = LOCK.lock();
+                pch_pic_set_bit(PCH_PIC_INT_MASK, vector, false);
+            }
+        }
+    }
+}
+
+fn init_eiointc() {
+    let misc = iocsr::read_d(IOCSR_MISC_FUNC);
+    iocsr::write_d(IOCSR_MISC_FUNC, misc | IOCSR_MISC_FUNC_EXT_IOI_EN);
+
+    // One byte per 32 vectors, route all to pin 0 (HWI0).
+    for i in 0..EIOINTC_VEC_COUNT / 32 / 4 {
+        iocsr::write_w(EIOINTC_IPMAP + i * 4, 0x0101_0101);
+    }
+    // One byte per vector, route all to CPU 0.
+    for i in 0..EIOINTC_VEC_COUNT / 4 {
+        iocsr::write_w(EIOINTC_ROUTE + i * 4, 0x0101_0101);
+    }
+    for i in 0..EIOINTC_VEC_COUNT / 32 {
+        iocsr::write_w(EIOINTC_ENABLE + i * 4, 0);
+        iocsr::write_w(EIOINTC_BOUNCE + i * 4, 0);
+    }
+}
+
+fn init_pch_pic() {
+    for i in 0..PCH_PIC_IRQ_COUNT / 32 {
+        unsafe {
+            // Mask all, level-triggered, active high, not sent by HT messages.
+            pch_pic_reg::<u32>(PCH_PIC_INT_MASK + i * 4).write_volatile(u32::MAX);
+            pch_pic_reg::<u32>(PCH_PIC_EDGE + i * 4).write_volatile(0);
+            pch_pic_reg::<u32>(PCH_PIC_POL + i * 4).write_volatile(0);
+            pch_pic_reg::<u32>(PCH_PIC_HTMSI_EN + i * 4).write_volatile(0);
+        }
+    }
+    for i in 0..PCH_PIC_IRQ_COUNT {
+        unsafe {
+            pch_pic_reg::<u8>(PCH_PIC_ROUTE + i).write_volatile(1);
+            pch_pic_reg::<u8>(PCH_PIC_HTMSI_VEC + i).write_volatile(i as u8);
+        }
+    }
+}
+
+/// Initializes EIOINTC and PCH-PIC on the primary CPU.
+pub(super) fn init_primary() {
+    info!("Initialize EIOINTC and PCH-PIC...");
+    init_eiointc();
+    init_pch_pic();
+}
+
+/// Enables the timer and external interrupt lines on the current CPU.
+pub(super) fn init_percpu() {
+    set_local_enable(TI, true);
+    set_local_enable(HWI0, true);
+}
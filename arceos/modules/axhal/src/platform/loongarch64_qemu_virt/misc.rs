@@ -0,0 +1,16 @@
+use crate::mem::phys_to_virt;
+
+/// Value of the sleep control register of the ACPI GED (generic event device)
+/// to enter S5 (soft off): `SLP_EN | (SLP_TYP_S5 << 2)`.
+const GED_SLEEP_CTL_S5: u8 = (1 << 5) | (5 << 2);
+
+/// Shutdown the whole system, including all CPUs.
+pub fn terminate() -> ! {
+    info!("Shutting down...");
+    let sleep_ctl = phys_to_virt(pa!(axconfig::GED_PADDR)).as_mut_ptr();
+    unsafe { sleep_ctl.write_volatile(GED_SLEEP_CTL_S5) };
+    warn!("It should shutdown!");
+    loop {
+        crate::arch::halt();
+    }
+}
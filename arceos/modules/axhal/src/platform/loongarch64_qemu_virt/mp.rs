@@ -0,0 +1,46 @@
+use super::iocsr;
+use crate::mem::{virt_to_phys, PhysAddr};
+
+const IOCSR_IPI_SEND: usize = 0x1040;
+const IOCSR_MBUF_SEND: usize = 0x1048;
+
+const IOCSR_SEND_BLOCKING: u64 = 1 << 31;
+const IOCSR_SEND_CPU_SHIFT: u64 = 16;
+const IOCSR_MBUF_SEND_BOX_SHIFT: u64 = 2;
+const IOCSR_MBUF_SEND_BUF_SHIFT: u64 = 32;
+
+/// The IPI vector to wake up a CPU waiting in the boot code of QEMU.
+const ACTION_BOOT_CPU: u64 = 0;
+
+/// Writes `data` to the mailbox `mailbox` of the CPU `cpu_id`, 32 bits at a
+/// time.
+fn mail_send(cpu_id: usize, mailbox: u64, data: u64) {
+    let send = |half: u64, data: u32| {
+        let val = IOCSR_SEND_BLOCKING
+            | ((mailbox * 2 + half) << IOCSR_MBUF_SEND_BOX_SHIFT)
+            | ((cpu_id as u64) << IOCSR_SEND_CPU_SHIFT)
+            | ((data as u64) << IOCSR_MBUF_SEND_BUF_SHIFT);
+        iocsr::write_d(IOCSR_MBUF_SEND, val);
+    };
+    send(1, (data >> 32) as u32);
+    send(0, data as u32);
+}
+
+fn send_ipi(cpu_id: usize, vector: u64) {
+    let val = IOCSR_SEND_BLOCKING | ((cpu_id as u64) << IOCSR_SEND_CPU_SHIFT) | vector;
+    iocsr::write_w(IOCSR_IPI_SEND, val as u32);
+}
+
+/// Starts the given secondary CPU with its boot stack.
+///
+/// The CPU waits in the boot code of QEMU for an IPI, then jumps to the
+/// address in its mailbox 0. The stack top is passed by its mailbox 1.
+pub fn start_secondary_cpu(cpu_id: usize, stack_top: PhysAddr) {
+    extern "C" {
+        fn _start_secondary();
+    }
+    let entry = virt_to_phys(va!(_start_secondary as usize));
+    mail_send(cpu_id, 1, stack_top.as_usize() as u64);
+    mail_send(cpu_id, 0, entry.as_usize() as u64);
+    send_ipi(cpu_id, ACTION_BOOT_CPU);
+}
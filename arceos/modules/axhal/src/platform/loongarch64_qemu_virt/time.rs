@@ -0,0 +1,48 @@
+//! The stable counter and the constant timer of the CPU.
+
+use loongArch64::time::Time;
+
+const NANOS_PER_TICK: u64 = crate::time::NANOS_PER_SEC / axconfig::TIMER_FREQUENCY as u64;
+
+/// Returns the current clock time in hardware ticks.
+#[inline]
+pub fn current_ticks() -> u64 {
+    Time::read() as u64
+}
+
+/// Converts hardware ticks to nanoseconds.
+#[inline]
+pub const fn ticks_to_nanos(ticks: u64) -> u64 {
+    ticks * NANOS_PER_TICK
+}
+
+/// Converts nanoseconds to hardware ticks.
+#[inline]
+pub const fn nanos_to_ticks(nanos: u64) -> u64 {
+    nanos / NANOS_PER_TICK
+}
+
+/// Return epoch offset in nanoseconds (wall time offset to monotonic clock start).
+pub fn epochoffset_nanos() -> u64 {
+    0
+}
+
+/// Set a one-shot timer.
+///
+/// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
+#[cfg(feature = "irq")]
+pub fn set_oneshot_timer(deadline_ns: u64) {
+    use loongArch64::register::tcfg;
+
+    // The timer counts down from the initial value, whose lowest 2 bits are
+    // ignored, so it must not be less than 4 for the timer to fire.
+    let ticks = nanos_to_ticks(deadline_ns).saturating_sub(current_ticks());
+    tcfg::set_init_val((ticks.max(4) as usize + 3) & !3);
+    tcfg::set_periodic(false);
+    tcfg::set_en(true);
+}
+
+pub(super) fn init_percpu() {
+    #[cfg(feature = "irq")]
+    set_oneshot_timer(0);
+}
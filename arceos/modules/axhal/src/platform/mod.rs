@@ -13,6 +13,9 @@ cfg_if::cfg_if! {
     } else if #[cfg(all(target_arch = "riscv64", platform_family = "riscv64-qemu-virt"))] {
         mod riscv64_qemu_virt;
         pub use self::riscv64_qemu_virt::*;
+    } else if #[cfg(all(target_arch = "loongarch64", platform_family = "loongarch64-qemu-virt"))] {
+        mod loongarch64_qemu_virt;
+        pub use self::loongarch64_qemu_virt::*;
     } else if #[cfg(all(target_arch = "aarch64", platform_family = "aarch64-qemu-virt"))] {
         mod aarch64_qemu_virt;
         pub use self::aarch64_qemu_virt::*;
@@ -27,3 +30,10 @@ cfg_if::cfg_if! {
         pub use self::dummy::*;
     }
 }
+
+// The Multiboot parser does not depend on the platform, so it is also tested
+// on the host.
+#[cfg(all(test, not(platform_family = "x86-pc")))]
+#[allow(dead_code)]
+#[path = "x86_pc/multiboot.rs"]
+mod multiboot;
@@ -1,4 +1,4 @@
-use riscv::register::satp;
+use riscv::register::{satp, sstatus};
 
 use axconfig::{PHYS_VIRT_OFFSET, TASK_STACK_SIZE};
 
@@ -15,6 +15,18 @@ unsafe fn init_boot_page_table() {
     BOOT_PT_SV39[0x102] = (0x80000 << 10) | 0xef;
 }
 
+/// Enables the FPU, its registers are saved and restored lazily on context
+/// switches.
+///
+/// The registers are zeroed, as tasks that have not used FP run with them.
+pub(super) unsafe fn enable_fp() {
+    if cfg!(feature = "fp_simd") {
+        sstatus::set_fs(sstatus::FS::Initial);
+        crate::arch::FpState::default().restore();
+        sstatus::set_fs(sstatus::FS::Clean);
+    }
+}
+
 unsafe fn init_mmu() {
     let page_table_root = BOOT_PT_SV39.as_ptr() as usize;
     satp::set(satp::Mode::Sv39, 0, page_table_root >> 12);
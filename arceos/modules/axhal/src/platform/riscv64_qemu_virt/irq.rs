@@ -1,14 +1,15 @@
 //! TODO: PLIC
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use crate::irq::IrqHandler;
 use lazyinit::LazyInit;
-use riscv::register::sie;
+use riscv::register::{sie, sip};
 
 /// `Interrupt` bit in `scause`
 pub(super) const INTC_IRQ_BASE: usize = 1 << (usize::BITS - 1);
 
 /// Supervisor software interrupt in `scause`
-#[allow(unused)]
 pub(super) const S_SOFT: usize = INTC_IRQ_BASE + 1;
 
 /// Supervisor timer interrupt in `scause`
@@ -19,6 +20,10 @@ pub(super) const S_EXT: usize = INTC_IRQ_BASE + 9;
 
 static TIMER_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
 
+/// Whether a kick sent by [`kick_cpu`] is pending on each CPU.
+static KICK_PENDING: [AtomicBool; axconfig::SMP] =
+    [const { AtomicBool::new(false) }; axconfig::SMP];
+
 /// The maximum number of IRQs.
 pub const MAX_IRQ_COUNT: usize = 1024;
 
@@ -65,6 +70,17 @@ pub fn register_handler(scause: usize, handler: IrqHandler) -> bool {
 /// up in the IRQ handler table and calls the corresponding handler. If
 /// necessary, it also acknowledges the interrupt controller after handling.
 pub fn dispatch_irq(scause: usize) {
+    if scause == S_SOFT {
+        // Clear it before taking the flag, so that a kick sent in between
+        // raises the interrupt again rather than being lost.
+        unsafe { sip::clear_ssoft() };
+        if KICK_PENDING[crate::cpu::this_cpu_id()].swap(false, Ordering::AcqRel) {
+            trace!("IRQ: kick");
+        } else {
+            warn!("IRQ: unexpected software interrupt");
+        }
+        return;
+    }
     with_cause!(
         scause,
         @TIMER => {
@@ -75,6 +91,13 @@ pub fn dispatch_irq(scause: usize) {
     );
 }
 
+/// Sends a software interrupt to the given CPU, which does nothing but kicking
+/// it out of a guest (or `wfi`).
+pub fn kick_cpu(cpu_id: usize) {
+    KICK_PENDING[cpu_id].store(true, Ordering::Release);
+    sbi_rt::send_ipi(1, cpu_id);
+}
+
 pub(super) fn init_percpu() {
     // enable soft interrupts, timer interrupts, and external interrupts
     unsafe {
@@ -22,6 +22,7 @@ unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
     crate::mem::clear_bss();
     crate::cpu::init_primary(cpu_id);
     crate::arch::set_trap_vector_base(trap_vector_base as usize);
+    self::boot::enable_fp();
     self::time::init_early();
     rust_main(cpu_id, dtb);
 }
@@ -29,6 +30,7 @@ unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
 #[cfg(feature = "smp")]
 unsafe extern "C" fn rust_entry_secondary(cpu_id: usize) {
     crate::arch::set_trap_vector_base(trap_vector_base as usize);
+    self::boot::enable_fp();
     crate::cpu::init_secondary(cpu_id);
     rust_main_secondary(cpu_id);
 }
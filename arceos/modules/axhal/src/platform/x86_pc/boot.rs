@@ -5,6 +5,8 @@ use x86_64::registers::model_specific::EferFlags;
 
 use axconfig::{PHYS_VIRT_OFFSET, TASK_STACK_SIZE};
 
+use super::multiboot::MULTIBOOT_BOOTLOADER_MAGIC;
+
 /// Flags set in the ’flags’ member of the multiboot header.
 ///
 /// (bits 1, 16: memory information, address fields in header)
@@ -13,8 +15,8 @@ const MULTIBOOT_HEADER_FLAGS: usize = 0x0001_0002;
 /// The magic field should contain this.
 const MULTIBOOT_HEADER_MAGIC: usize = 0x1BADB002;
 
-/// This should be in EAX.
-pub(super) const MULTIBOOT_BOOTLOADER_MAGIC: usize = 0x2BADB002;
+/// The magic field of the Multiboot2 header should contain this.
+const MULTIBOOT2_HEADER_MAGIC: usize = 0xE85250D6;
 
 const CR0: u64 = Cr0Flags::PROTECTED_MODE_ENABLE.bits()
     | Cr0Flags::MONITOR_COPROCESSOR.bits()
@@ -38,6 +40,7 @@ global_asm!(
     mb_magic = const MULTIBOOT_BOOTLOADER_MAGIC,
     mb_hdr_magic = const MULTIBOOT_HEADER_MAGIC,
     mb_hdr_flags = const MULTIBOOT_HEADER_FLAGS,
+    mb2_hdr_magic = const MULTIBOOT2_HEADER_MAGIC,
     entry = sym super::rust_entry,
     entry_secondary = sym super::rust_entry_secondary,
 
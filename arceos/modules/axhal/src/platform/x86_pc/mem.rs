@@ -1,15 +1,41 @@
-// TODO: get memory regions from multiboot info.
+use memory_addr::{align_down_4k, align_up_4k};
 
-use crate::mem::{MemRegion, MemRegionFlags};
+use crate::mem::{kernel_end_paddr, MemRegion, MemRegionFlags};
 
 /// Returns platform-specific memory regions.
+///
+/// The free memory regions are the available RAM in the bootloader's memory
+/// map above the kernel image, or the ones configured in the platform config
+/// file if no memory map is provided.
 pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
+    let ram_ranges = super::multiboot::ram_ranges();
+    let mmap_free_regions = ram_ranges
+        .into_iter()
+        .flatten()
+        .filter_map(|&(start, end)| {
+            // Only the physical memory in the linear mapping can be used.
+            let start = align_up_4k(start).max(kernel_end_paddr().as_usize());
+            let end = align_down_4k(end.min(axconfig::KERNEL_ASPACE_SIZE));
+            (start < end).then(|| MemRegion {
+                paddr: pa!(start),
+                size: end - start,
+                flags: MemRegionFlags::FREE | MemRegionFlags::READ | MemRegionFlags::WRITE,
+                name: "free memory",
+            })
+        });
+    let default_free_regions = ram_ranges
+        .is_none()
+        .then(crate::mem::default_free_regions)
+        .into_iter()
+        .flatten();
+
     core::iter::once(MemRegion {
         paddr: pa!(0x1000),
         size: 0x9e000,
         flags: MemRegionFlags::RESERVED | MemRegionFlags::READ | MemRegionFlags::WRITE,
         name: "low memory",
     })
-    .chain(crate::mem::default_free_regions())
+    .chain(mmap_free_regions)
+    .chain(default_free_regions)
     .chain(crate::mem::default_mmio_regions())
 }
@@ -1,5 +1,7 @@
 use x86_64::instructions::port::PortWriteOnly;
 
+pub use super::multiboot::cmdline;
+
 /// Shutdown the whole system (in QEMU), including all CPUs.
 ///
 /// See <https://wiki.osdev.org/Shutdown> for more information.
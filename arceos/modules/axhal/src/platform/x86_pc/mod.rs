@@ -1,6 +1,7 @@
 mod apic;
 mod boot;
 mod dtables;
+mod multiboot;
 mod uart16550;
 
 pub mod mem;
@@ -32,10 +33,12 @@ fn current_cpu_id() -> usize {
     }
 }
 
-unsafe extern "C" fn rust_entry(magic: usize, _mbi: usize) {
-    // TODO: handle multiboot info
-    if magic == self::boot::MULTIBOOT_BOOTLOADER_MAGIC {
+unsafe extern "C" fn rust_entry(magic: usize, mbi: usize) {
+    if magic == self::multiboot::MULTIBOOT_BOOTLOADER_MAGIC
+        || magic == self::multiboot::MULTIBOOT2_BOOTLOADER_MAGIC
+    {
         crate::mem::clear_bss();
+        self::multiboot::init(magic, mbi);
         crate::cpu::init_primary(current_cpu_id());
         self::uart16550::init();
         self::dtables::init_primary();
@@ -47,7 +50,7 @@ unsafe extern "C" fn rust_entry(magic: usize, _mbi: usize) {
 #[allow(unused_variables)]
 unsafe extern "C" fn rust_entry_secondary(magic: usize) {
     #[cfg(feature = "smp")]
-    if magic == self::boot::MULTIBOOT_BOOTLOADER_MAGIC {
+    if magic == self::multiboot::MULTIBOOT_BOOTLOADER_MAGIC {
         crate::cpu::init_secondary(current_cpu_id());
         self::dtables::init_secondary();
         rust_main_secondary(current_cpu_id());
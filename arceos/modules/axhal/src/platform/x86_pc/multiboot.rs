@@ -0,0 +1,403 @@
+//! Parsing of the boot information passed by Multiboot and Multiboot2
+//! compliant bootloaders.
+//!
+//! Only the memory map and the kernel command line are used. They are copied
+//! out at boot, as the information structure may be placed by the bootloader
+//! in memory that is later handed to the allocator.
+//!
+//! See <https://www.gnu.org/software/grub/manual/multiboot/multiboot.html> and
+//! <https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html>.
+
+use lazyinit::LazyInit;
+
+use crate::mem::phys_to_virt;
+
+/// This should be in EAX.
+pub(super) const MULTIBOOT_BOOTLOADER_MAGIC: usize = 0x2BADB002;
+
+/// This should be in EAX if booted by a Multiboot2 bootloader.
+pub(super) const MULTIBOOT2_BOOTLOADER_MAGIC: usize = 0x36D76289;
+
+/// Maximum number of available RAM ranges kept from the memory map.
+const MAX_RAM_RANGES: usize = 32;
+
+/// Maximum length of the command line, longer ones are truncated.
+const MAX_CMDLINE_LEN: usize = 256;
+
+/// `cmdline` is valid in the Multiboot information structure.
+const MULTIBOOT_INFO_CMDLINE: u32 = 1 << 2;
+/// `mmap_*` are valid in the Multiboot information structure.
+const MULTIBOOT_INFO_MEM_MAP: u32 = 1 << 6;
+
+/// Type of available RAM in memory map entries (same for Multiboot2).
+const MEMORY_AVAILABLE: u32 = 1;
+
+const MULTIBOOT2_TAG_END: u32 = 0;
+const MULTIBOOT2_TAG_CMDLINE: u32 = 1;
+const MULTIBOOT2_TAG_MMAP: u32 = 6;
+
+struct BootInfo {
+    /// Available RAM ranges, in `(start, end)` physical addresses.
+    ram: [(usize, usize); MAX_RAM_RANGES],
+    ram_count: usize,
+    has_mmap: bool,
+    cmdline: [u8; MAX_CMDLINE_LEN],
+    cmdline_len: usize,
+}
+
+static BOOT_INFO: LazyInit<BootInfo> = LazyInit::new();
+
+/// The physical memory holding the boot information, mapped linearly.
+#[derive(Clone, Copy)]
+struct BootMem {
+    /// The virtual address of physical address zero.
+    offset: usize,
+}
+
+impl BootMem {
+    /// Reads a value at the given physical address, which must be covered by
+    /// the boot page table (i.e., below 4G).
+    unsafe fn read<T: Copy>(self, paddr: usize) -> T {
+        (self.offset.wrapping_add(paddr) as *const T).read_unaligned()
+    }
+}
+
+impl BootInfo {
+    const fn new() -> Self {
+        Self {
+            ram: [(0, 0); MAX_RAM_RANGES],
+            ram_count: 0,
+            has_mmap: false,
+            cmdline: [0; MAX_CMDLINE_LEN],
+            cmdline_len: 0,
+        }
+    }
+
+    fn add_mmap_entry(&mut self, base: u64, len: u64, ty: u32) {
+        self.has_mmap = true;
+        if ty != MEMORY_AVAILABLE || len == 0 {
+            return;
+        }
+        if self.ram_count == MAX_RAM_RANGES {
+            warn!(
+                "Too many RAM ranges, ignored [{:#x}, {:#x})",
+                base,
+                base.saturating_add(len)
+            );
+            return;
+        }
+        self.ram[self.ram_count] = (base as usize, base.saturating_add(len) as usize);
+        self.ram_count += 1;
+    }
+
+    unsafe fn set_cmdline(&mut self, mem: BootMem, paddr: usize) {
+        let mut len = 0;
+        while len < MAX_CMDLINE_LEN {
+            let c = mem.read::<u8>(paddr + len);
+            if c == 0 {
+                break;
+            }
+            self.cmdline[len] = c;
+            len += 1;
+        }
+        self.cmdline_len = len;
+    }
+
+    unsafe fn parse(&mut self, mem: BootMem, magic: usize, mbi: usize) {
+        match magic {
+            MULTIBOOT_BOOTLOADER_MAGIC => self.parse_multiboot(mem, mbi),
+            MULTIBOOT2_BOOTLOADER_MAGIC => self.parse_multiboot2(mem, mbi),
+            _ => {}
+        }
+    }
+
+    unsafe fn parse_multiboot(&mut self, mem: BootMem, mbi: usize) {
+        let flags = mem.read::<u32>(mbi);
+        if flags & MULTIBOOT_INFO_CMDLINE != 0 {
+            self.set_cmdline(mem, mem.read::<u32>(mbi + 16) as usize);
+        }
+        if flags & MULTIBOOT_INFO_MEM_MAP != 0 {
+            let mmap_len = mem.read::<u32>(mbi + 44) as usize;
+            let mmap_addr = mem.read::<u32>(mbi + 48) as usize;
+            let mut entry = mmap_addr;
+            while entry + 24 <= mmap_addr + mmap_len {
+                // The `size` field does not count itself.
+                let size = mem.read::<u32>(entry) as usize;
+                let base = mem.read::<u64>(entry + 4);
+                let len = mem.read::<u64>(entry + 12);
+                let ty = mem.read::<u32>(entry + 20);
+                self.add_mmap_entry(base, len, ty);
+                entry += size + 4;
+            }
+        }
+    }
+
+    unsafe fn parse_multiboot2(&mut self, mem: BootMem, mbi: usize) {
+        let total_size = mem.read::<u32>(mbi) as usize;
+        let mut tag = mbi + 8;
+        while tag < mbi + total_size {
+            let ty = mem.read::<u32>(tag);
+            let size = mem.read::<u32>(tag + 4) as usize;
+            if size < 8 {
+                warn!("Invalid Multiboot2 tag size {} at {:#x}", size, tag);
+                break;
+            }
+            match ty {
+                MULTIBOOT2_TAG_END => break,
+                MULTIBOOT2_TAG_CMDLINE => self.set_cmdline(mem, tag + 8),
+                MULTIBOOT2_TAG_MMAP => {
+                    let entry_size = mem.read::<u32>(tag + 8) as usize;
+                    // An entry has at least the base, length and type.
+                    if entry_size < 20 {
+                        warn!("Invalid Multiboot2 memory map entry size {}", entry_size);
+                    } else {
+                        let mut entry = tag + 16;
+                        while entry + entry_size <= tag + size {
+                            let base = mem.read::<u64>(entry);
+                            let len = mem.read::<u64>(entry + 8);
+                            let ty = mem.read::<u32>(entry + 16);
+                            self.add_mmap_entry(base, len, ty);
+                            entry += entry_size;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            // Tags are 8-byte aligned.
+            tag += (size + 7) & !7;
+        }
+    }
+
+    fn ram_ranges(&self) -> Option<&[(usize, usize)]> {
+        self.has_mmap.then(|| &self.ram[..self.ram_count])
+    }
+
+    fn cmdline(&self) -> Option<&str> {
+        let bytes = &self.cmdline[..self.cmdline_len];
+        let s = match core::str::from_utf8(bytes) {
+            Ok(s) => s,
+            // Drop the invalid part, e.g., a character cut by the truncation.
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+        };
+        (!s.is_empty()).then_some(s)
+    }
+}
+
+/// Parses the boot information at physical address `mbi`, whose format is
+/// given by the bootloader `magic`.
+///
+/// It must be called only once on the primary CPU, after the `.bss` section
+/// is cleared.
+pub(super) unsafe fn init(magic: usize, mbi: usize) {
+    let mem = BootMem {
+        offset: phys_to_virt(pa!(0)).as_usize(),
+    };
+    let mut info = BootInfo::new();
+    info.parse(mem, magic, mbi);
+    BOOT_INFO.init_once(info);
+}
+
+/// Returns the available RAM ranges in `(start, end)` physical addresses from
+/// the bootloader's memory map, or `None` if no memory map is provided.
+pub(super) fn ram_ranges() -> Option<&'static [(usize, usize)]> {
+    BOOT_INFO.get()?.ram_ranges()
+}
+
+/// Returns the kernel command line passed by the bootloader, or `None` if not
+/// provided or empty.
+pub fn cmdline() -> Option<&'static str> {
+    BOOT_INFO.get()?.cmdline()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fake physical memory, whose physical addresses are offsets.
+    struct FakeMem(Vec<u8>);
+
+    impl FakeMem {
+        fn new() -> Self {
+            Self(vec![0; 0x1000])
+        }
+
+        fn write<T: Copy>(&mut self, paddr: usize, val: T) {
+            let size = core::mem::size_of::<T>();
+            assert!(paddr + size <= self.0.len());
+            unsafe { (self.0.as_mut_ptr().add(paddr) as *mut T).write_unaligned(val) }
+        }
+
+        fn write_bytes(&mut self, paddr: usize, bytes: &[u8]) {
+            self.0[paddr..paddr + bytes.len()].copy_from_slice(bytes);
+        }
+
+        fn parse(&self, magic: usize, mbi: usize) -> BootInfo {
+            let mut info = BootInfo::new();
+            let mem = BootMem {
+                offset: self.0.as_ptr() as usize,
+            };
+            unsafe { info.parse(mem, magic, mbi) };
+            info
+        }
+    }
+
+    /// Writes a Multiboot information structure at 0x100, with the command
+    /// line at 0x200 and the memory map at 0x300 if given.
+    fn multiboot_info(cmdline: Option<&[u8]>, mmap: Option<&[(u64, u64, u32)]>) -> FakeMem {
+        let mut mem = FakeMem::new();
+        let mut flags = 0;
+        if let Some(cmdline) = cmdline {
+            flags |= MULTIBOOT_INFO_CMDLINE;
+            mem.write_bytes(0x200, cmdline);
+            mem.write(0x100 + 16, 0x200u32);
+        }
+        if let Some(mmap) = mmap {
+            flags |= MULTIBOOT_INFO_MEM_MAP;
+            for (i, &(base, len, ty)) in mmap.iter().enumerate() {
+                let entry = 0x300 + i * 24;
+                mem.write(entry, 20u32);
+                mem.write(entry + 4, base);
+                mem.write(entry + 12, len);
+                mem.write(entry + 20, ty);
+            }
+            mem.write(0x100 + 44, (mmap.len() * 24) as u32);
+            mem.write(0x100 + 48, 0x300u32);
+        }
+        mem.write(0x100, flags);
+        mem
+    }
+
+    /// Writes Multiboot2 tags at 0x100, followed by the end tag.
+    fn multiboot2_info(tags: &[(u32, &[u8])]) -> FakeMem {
+        let mut mem = FakeMem::new();
+        let mut tag = 0x108;
+        for &(ty, data) in tags {
+            mem.write(tag, ty);
+            mem.write(tag + 4, (8 + data.len()) as u32);
+            mem.write_bytes(tag + 8, data);
+            tag += (8 + data.len() + 7) & !7;
+        }
+        mem.write(tag, MULTIBOOT2_TAG_END);
+        mem.write(tag + 4, 8u32);
+        mem.write(0x100, (tag + 8 - 0x100) as u32);
+        mem
+    }
+
+    /// Returns the data of a Multiboot2 memory map tag.
+    fn multiboot2_mmap(entry_size: usize, entries: &[(u64, u64, u32)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(entry_size as u32).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        for &(base, len, ty) in entries {
+            let mut entry = vec![0; entry_size];
+            entry[..8].copy_from_slice(&base.to_le_bytes());
+            entry[8..16].copy_from_slice(&len.to_le_bytes());
+            entry[16..20].copy_from_slice(&ty.to_le_bytes());
+            data.extend_from_slice(&entry);
+        }
+        data
+    }
+
+    const MMAP: [(u64, u64, u32); 5] = [
+        (0, 0x9fc00, MEMORY_AVAILABLE),
+        (0xf0000, 0x10000, 2),
+        (0x10_0000, 0x7ee_0000, MEMORY_AVAILABLE),
+        (0x7fe_0000, 0, MEMORY_AVAILABLE),
+        (0x1_0000_0000, 0x4000_0000, MEMORY_AVAILABLE),
+    ];
+
+    const RAM: [(usize, usize); 3] = [
+        (0, 0x9fc00),
+        (0x10_0000, 0x7fe_0000),
+        (0x1_0000_0000, 0x1_4000_0000),
+    ];
+
+    #[test]
+    fn test_multiboot() {
+        let mem = multiboot_info(Some(b"console=ttyS0 quiet\0"), Some(&MMAP));
+        let info = mem.parse(MULTIBOOT_BOOTLOADER_MAGIC, 0x100);
+        assert_eq!(info.ram_ranges(), Some(&RAM[..]));
+        assert_eq!(info.cmdline(), Some("console=ttyS0 quiet"));
+    }
+
+    #[test]
+    fn test_multiboot_without_info() {
+        let mem = multiboot_info(None, None);
+        let info = mem.parse(MULTIBOOT_BOOTLOADER_MAGIC, 0x100);
+        assert_eq!(info.ram_ranges(), None);
+        assert_eq!(info.cmdline(), None);
+
+        // An empty memory map is ignored, the configured memory is used.
+        let mem = multiboot_info(Some(b"\0"), Some(&[]));
+        let info = mem.parse(MULTIBOOT_BOOTLOADER_MAGIC, 0x100);
+        assert_eq!(info.ram_ranges(), None);
+        assert_eq!(info.cmdline(), None);
+    }
+
+    #[test]
+    fn test_multiboot2() {
+        let mmap = multiboot2_mmap(24, &MMAP);
+        let mem = multiboot2_info(&[
+            (MULTIBOOT2_TAG_CMDLINE, b"init=/bin/sh\0"),
+            // Unknown tags are skipped.
+            (2, b"GRUB 2.06\0"),
+            (MULTIBOOT2_TAG_MMAP, &mmap),
+        ]);
+        let info = mem.parse(MULTIBOOT2_BOOTLOADER_MAGIC, 0x100);
+        assert_eq!(info.ram_ranges(), Some(&RAM[..]));
+        assert_eq!(info.cmdline(), Some("init=/bin/sh"));
+
+        // Entries may be larger than the ones defined now.
+        let mmap = multiboot2_mmap(32, &MMAP);
+        let mem = multiboot2_info(&[(MULTIBOOT2_TAG_MMAP, &mmap)]);
+        let info = mem.parse(MULTIBOOT2_BOOTLOADER_MAGIC, 0x100);
+        assert_eq!(info.ram_ranges(), Some(&RAM[..]));
+        assert_eq!(info.cmdline(), None);
+    }
+
+    #[test]
+    fn test_multiboot2_malformed() {
+        // An entry size too small to hold an entry.
+        let mmap = multiboot2_mmap(0, &[]);
+        let mem = multiboot2_info(&[(MULTIBOOT2_TAG_MMAP, &mmap)]);
+        let info = mem.parse(MULTIBOOT2_BOOTLOADER_MAGIC, 0x100);
+        assert_eq!(info.ram_ranges(), None);
+
+        // A tag size smaller than the tag header stops the parsing.
+        let mut mem = multiboot2_info(&[(MULTIBOOT2_TAG_CMDLINE, b"a\0")]);
+        mem.write(0x108 + 4, 0u32);
+        let info = mem.parse(MULTIBOOT2_BOOTLOADER_MAGIC, 0x100);
+        assert_eq!(info.cmdline(), None);
+    }
+
+    #[test]
+    fn test_unknown_magic() {
+        let mem = multiboot_info(Some(b"quiet\0"), Some(&MMAP));
+        let info = mem.parse(0, 0x100);
+        assert_eq!(info.ram_ranges(), None);
+        assert_eq!(info.cmdline(), None);
+    }
+
+    #[test]
+    fn test_too_many_ranges() {
+        let mmap: Vec<_> = (0..MAX_RAM_RANGES as u64 + 2)
+            .map(|i| (i * 0x2000, 0x1000, MEMORY_AVAILABLE))
+            .collect();
+        let mmap = multiboot2_mmap(24, &mmap);
+        let mem = multiboot2_info(&[(MULTIBOOT2_TAG_MMAP, &mmap)]);
+        let info = mem.parse(MULTIBOOT2_BOOTLOADER_MAGIC, 0x100);
+        let ram = info.ram_ranges().unwrap();
+        assert_eq!(ram.len(), MAX_RAM_RANGES);
+        assert_eq!(ram[MAX_RAM_RANGES - 1], (0x3e000, 0x3f000));
+    }
+
+    #[test]
+    fn test_long_cmdline() {
+        // Truncated, dropping the character cut in the middle.
+        let mut cmdline = vec![b'a'; MAX_CMDLINE_LEN - 1];
+        cmdline.extend_from_slice("é and more\0".as_bytes());
+        let mem = multiboot_info(Some(&cmdline), None);
+        let info = mem.parse(MULTIBOOT_BOOTLOADER_MAGIC, 0x100);
+        assert_eq!(info.cmdline(), Some(&*"a".repeat(MAX_CMDLINE_LEN - 1)));
+    }
+}
@@ -1,33 +1,44 @@
-use raw_cpuid::CpuId;
+use core::arch::x86_64::_rdtsc;
 
-#[cfg(feature = "irq")]
 use int_ratio::Ratio;
+use raw_cpuid::CpuId;
+use x86_64::instructions::port::Port;
 
-#[cfg(feature = "irq")]
-const LAPIC_TICKS_PER_SEC: u64 = 1_000_000_000; // TODO: need to calibrate
+/// Input clock frequency of the PIT (programmable interval timer) in Hz.
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// Duration of the TSC and LAPIC timer calibration in milliseconds.
+///
+/// The PIT counter is 16-bit, so it must not exceed 54 ms.
+const CALIBRATION_MS: u64 = 50;
+
+/// Gives up the PIT calibration if its output is not raised after so many polls.
+const PIT_MAX_POLLS: u64 = 1 << 24;
+
+static mut TSC_TO_NANOS_RATIO: Ratio = Ratio::zero();
+static mut NANOS_TO_TSC_RATIO: Ratio = Ratio::zero();
 
 #[cfg(feature = "irq")]
 static mut NANOS_TO_LAPIC_TICKS_RATIO: Ratio = Ratio::zero();
 
 static mut INIT_TICK: u64 = 0;
-static mut CPU_FREQ_MHZ: u64 = axconfig::TIMER_FREQUENCY as u64 / 1_000_000;
 
 /// RTC wall time offset in nanoseconds at monotonic time base.
 static mut RTC_EPOCHOFFSET_NANOS: u64 = 0;
 
 /// Returns the current clock time in hardware ticks.
 pub fn current_ticks() -> u64 {
-    unsafe { core::arch::x86_64::_rdtsc() - INIT_TICK }
+    unsafe { _rdtsc() - INIT_TICK }
 }
 
 /// Converts hardware ticks to nanoseconds.
 pub fn ticks_to_nanos(ticks: u64) -> u64 {
-    ticks * 1_000 / unsafe { CPU_FREQ_MHZ }
+    unsafe { TSC_TO_NANOS_RATIO.mul_trunc(ticks) }
 }
 
 /// Converts nanoseconds to hardware ticks.
 pub fn nanos_to_ticks(nanos: u64) -> u64 {
-    nanos * unsafe { CPU_FREQ_MHZ } / 1_000
+    unsafe { NANOS_TO_TSC_RATIO.mul_trunc(nanos) }
 }
 
 /// Return epoch offset in nanoseconds (wall time offset to monotonic clock start).
@@ -53,19 +64,68 @@ pub fn set_oneshot_timer(deadline_ns: u64) {
     }
 }
 
-pub(super) fn init_early() {
+/// Measures the TSC frequency in Hz by counting TSC ticks while the PIT
+/// channel 2 counts down for [`CALIBRATION_MS`] milliseconds.
+///
+/// Returns `None` if the PIT does not seem to work.
+fn calibrate_tsc_with_pit() -> Option<u64> {
+    let mut port_b = Port::<u8>::new(0x61);
+    let mut pit_cmd = Port::<u8>::new(0x43);
+    let mut pit_ch2 = Port::<u8>::new(0x42);
+    let latch = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+
+    let (start, end, polls) = unsafe {
+        // Raise the gate of channel 2, and disable the speaker.
+        let val = port_b.read();
+        port_b.write((val & !0x02) | 0x01);
+
+        // Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count).
+        pit_cmd.write(0xb0);
+        pit_ch2.write(latch as u8);
+        pit_ch2.write((latch >> 8) as u8);
+
+        // The output of channel 2 (bit 5) goes high when the counter reaches 0.
+        let start = _rdtsc();
+        let mut polls = 0;
+        while port_b.read() & 0x20 == 0 && polls < PIT_MAX_POLLS {
+            polls += 1;
+        }
+        (start, _rdtsc(), polls)
+    };
+
+    // Too few polls means the output is stuck high (e.g., no PIT at all).
+    if polls < 1000 || polls >= PIT_MAX_POLLS {
+        return None;
+    }
+    Some((end - start) * PIT_FREQUENCY / latch)
+}
+
+fn tsc_frequency() -> u64 {
     if let Some(freq) = CpuId::new()
-        .get_processor_frequency_info()
-        .map(|info| info.processor_base_frequency())
+        .get_tsc_info()
+        .and_then(|info| info.tsc_frequency())
     {
-        if freq > 0 {
-            axlog::ax_println!("Got TSC frequency by CPUID: {} MHz", freq);
-            unsafe { CPU_FREQ_MHZ = freq as u64 }
-        }
+        axlog::ax_println!("Got TSC frequency by CPUID: {} Hz", freq);
+        return freq;
     }
+    if let Some(freq) = calibrate_tsc_with_pit() {
+        axlog::ax_println!("Calibrated TSC frequency with PIT: {} Hz", freq);
+        return freq;
+    }
+    axlog::ax_println!(
+        "Failed to calibrate TSC, assume {} Hz",
+        axconfig::TIMER_FREQUENCY
+    );
+    axconfig::TIMER_FREQUENCY as u64
+}
 
+pub(super) fn init_early() {
+    // The frequency in kHz, as the one in Hz may not fit in 32 bits.
+    let freq_khz = tsc_frequency() / 1000;
     unsafe {
-        INIT_TICK = core::arch::x86_64::_rdtsc();
+        TSC_TO_NANOS_RATIO = Ratio::new(1_000_000, freq_khz as u32);
+        NANOS_TO_TSC_RATIO = TSC_TO_NANOS_RATIO.inverse();
+        INIT_TICK = _rdtsc();
     }
 
     #[cfg(feature = "rtc")]
@@ -90,11 +150,20 @@ pub(super) fn init_primary() {
         lapic.set_timer_divide(TimerDivide::Div256); // indeed it is Div1, the name is confusing.
         lapic.enable_timer();
 
-        // TODO: calibrate with HPET
-        NANOS_TO_LAPIC_TICKS_RATIO = Ratio::new(
-            LAPIC_TICKS_PER_SEC as u32,
-            crate::time::NANOS_PER_SEC as u32,
-        );
+        // Calibrate the LAPIC timer with the (calibrated) TSC.
+        let wait_ticks = nanos_to_ticks(CALIBRATION_MS * 1_000_000);
+        lapic.set_timer_initial(u32::MAX);
+        let start = current_ticks();
+        while current_ticks() - start < wait_ticks {
+            core::hint::spin_loop();
+        }
+        let lapic_ticks = (u32::MAX - lapic.timer_current()) as u64;
+        lapic.set_timer_initial(0);
+
+        let lapic_freq = lapic_ticks * 1000 / CALIBRATION_MS;
+        info!("Calibrated LAPIC timer frequency: {} Hz", lapic_freq);
+        NANOS_TO_LAPIC_TICKS_RATIO =
+            Ratio::new(lapic_freq as u32, crate::time::NANOS_PER_SEC as u32);
     }
 }
 
@@ -65,7 +65,7 @@ cfg_if::cfg_if! {
     } else if #[cfg(target_arch = "aarch64")] {
         const TCB_SIZE: usize = 0;
         const GAP_ABOVE_TP: usize = 16;
-    } else if #[cfg(target_arch = "riscv64")] {
+    } else if #[cfg(any(target_arch = "riscv64", target_arch = "loongarch64"))] {
         const TCB_SIZE: usize = 0;
         const GAP_ABOVE_TP: usize = 0;
     }
@@ -131,7 +131,11 @@ fn static_tls_size() -> usize {
 fn static_tls_offset() -> usize {
     if cfg!(target_arch = "x86_64") {
         0
-    } else if cfg!(any(target_arch = "aarch64", target_arch = "riscv64")) {
+    } else if cfg!(any(
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    )) {
         TCB_SIZE + GAP_ABOVE_TP
     } else {
         unreachable!()
@@ -141,7 +145,11 @@ fn static_tls_offset() -> usize {
 fn tp_offset() -> usize {
     if cfg!(target_arch = "x86_64") {
         static_tls_size()
-    } else if cfg!(any(target_arch = "aarch64", target_arch = "riscv64")) {
+    } else if cfg!(any(
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    )) {
         TCB_SIZE
     } else {
         unreachable!()
@@ -151,7 +159,11 @@ fn tp_offset() -> usize {
 fn tls_area_size() -> usize {
     if cfg!(target_arch = "x86_64") {
         static_tls_size() + TCB_SIZE
-    } else if cfg!(any(target_arch = "aarch64", target_arch = "riscv64")) {
+    } else if cfg!(any(
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    )) {
         TCB_SIZE + GAP_ABOVE_TP + static_tls_size()
     } else {
         unreachable!()
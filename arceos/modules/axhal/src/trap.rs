@@ -1,45 +1,380 @@
 //! Trap handling.
+//!
+//! A trap may have several handlers, which are called in the order of
+//! priority until one of them returns `true` (i.e., handles the trap). The
+//! handlers are registered statically by [`register_trap_handler`], e.g.,
+//! `#[register_trap_handler(PAGE_FAULT)]`, with the priority
+//! [`DEFAULT_PRIORITY`], or by [`register_trap_handler_with_priority!`] with
+//! any priority. IRQ and page fault handlers can also be registered and
+//! unregistered at runtime by [`register_irq_handler`] and
+//! [`register_page_fault_handler`].
+//!
+//! Among handlers of the same priority, the static ones are called first,
+//! then the runtime ones in the order of registration.
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
 use linkme::distributed_slice as def_trap_handler;
 use memory_addr::VirtAddr;
 use page_table_entry::MappingFlags;
 
-#[cfg(feature = "uspace")]
 use crate::arch::TrapFrame;
 
 pub use linkme::distributed_slice as register_trap_handler;
 
+#[doc(hidden)]
+pub use linkme as __linkme;
+
+/// The priority of the handlers registered statically.
+pub const DEFAULT_PRIORITY: i32 = 0;
+
+/// Maximum number of handlers registered at runtime for a trap.
+const MAX_RUNTIME_HANDLERS: usize = 16;
+
+/// The type of an IRQ handler, which takes the IRQ number.
+pub type IrqTrapHandler = fn(usize) -> bool;
+
+/// The type of a page fault handler, which takes the fault address, the
+/// access flags, and whether the fault is from user space.
+pub type PageFaultHandler = fn(VirtAddr, MappingFlags, bool) -> bool;
+
 /// A slice of IRQ handler functions.
 #[def_trap_handler]
-pub static IRQ: [fn(usize) -> bool];
+pub static IRQ: [IrqTrapHandler];
 
 /// A slice of page fault handler functions.
 #[def_trap_handler]
-pub static PAGE_FAULT: [fn(VirtAddr, MappingFlags, bool) -> bool];
+pub static PAGE_FAULT: [PageFaultHandler];
+
+/// A slice of IRQ handler functions with their priorities, registered by
+/// [`register_trap_handler_with_priority!`].
+#[def_trap_handler]
+pub static IRQ_WITH_PRIORITY: [(i32, IrqTrapHandler)];
+
+/// A slice of page fault handler functions with their priorities, registered
+/// by [`register_trap_handler_with_priority!`].
+#[def_trap_handler]
+pub static PAGE_FAULT_WITH_PRIORITY: [(i32, PageFaultHandler)];
+
+/// Registers a static IRQ or page fault handler with the given priority.
+///
+/// The handler is called before the ones registered by
+/// [`register_trap_handler`] if the priority is higher than
+/// [`DEFAULT_PRIORITY`], or after them if it is lower, e.g.:
+///
+/// ```ignore
+/// fn handle_page_fault(vaddr: VirtAddr, flags: MappingFlags, is_user: bool) -> bool {
+///     // ...
+/// }
+///
+/// axhal::register_trap_handler_with_priority!(PAGE_FAULT, 10, handle_page_fault);
+/// ```
+#[macro_export]
+macro_rules! register_trap_handler_with_priority {
+    (IRQ, $priority:expr, $handler:expr) => {
+        $crate::register_trap_handler_with_priority!(
+            @slice IRQ_WITH_PRIORITY, IrqTrapHandler, $priority, $handler
+        );
+    };
+    (PAGE_FAULT, $priority:expr, $handler:expr) => {
+        $crate::register_trap_handler_with_priority!(
+            @slice PAGE_FAULT_WITH_PRIORITY, PageFaultHandler, $priority, $handler
+        );
+    };
+    (@slice $slice:ident, $ty:ident, $priority:expr, $handler:expr) => {
+        const _: () = {
+            #[$crate::trap::register_trap_handler($crate::trap::$slice)]
+            #[linkme(crate = $crate::trap::__linkme)]
+            static HANDLER: (i32, $crate::trap::$ty) = ($priority, $handler);
+        };
+    };
+}
+
+/// A slice of breakpoint handler functions.
+///
+/// The handler returns `true` if it has handled the breakpoint, and is
+/// responsible for advancing the pc past the breakpoint instruction if needed.
+#[def_trap_handler]
+pub static BREAKPOINT: [fn(&mut TrapFrame) -> bool];
 
 /// A slice of syscall handler functions.
+///
+/// The handlers are called in turn until one of them returns other than
+/// `-ENOSYS`.
 #[cfg(feature = "uspace")]
 #[def_trap_handler]
 pub static SYSCALL: [fn(&TrapFrame, usize) -> isize];
 
+/// Identifies a handler registered at runtime, to unregister it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrapHandlerId(usize);
+
+/// A runtime handler, with its identifier and priority.
+type RuntimeHandler<F> = (TrapHandlerId, i32, F);
+
+/// Handlers of a trap registered at runtime, sorted by priority.
+pub(crate) struct HandlerChain<F: Copy> {
+    handlers: SpinNoIrq<([Option<RuntimeHandler<F>>; MAX_RUNTIME_HANDLERS], usize)>,
+}
+
+impl<F: Copy> HandlerChain<F> {
+    const fn new() -> Self {
+        Self {
+            handlers: SpinNoIrq::new(([None; MAX_RUNTIME_HANDLERS], 0)),
+        }
+    }
+
+    fn register(&self, priority: i32, handler: F) -> Option<TrapHandlerId> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let mut guard = self.handlers.lock();
+        let (handlers, len) = &mut *guard;
+        if *len == MAX_RUNTIME_HANDLERS {
+            return None;
+        }
+        // After the handlers of the same or higher priority.
+        let pos = handlers[..*len]
+            .iter()
+            .position(|h| h.unwrap().1 < priority)
+            .unwrap_or(*len);
+        handlers.copy_within(pos..*len, pos + 1);
+        let id = TrapHandlerId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        handlers[pos] = Some((id, priority, handler));
+        *len += 1;
+        Some(id)
+    }
+
+    fn unregister(&self, id: TrapHandlerId) -> bool {
+        let mut guard = self.handlers.lock();
+        let (handlers, len) = &mut *guard;
+        let Some(pos) = handlers[..*len].iter().position(|h| h.unwrap().0 == id) else {
+            return false;
+        };
+        handlers.copy_within(pos + 1..*len, pos);
+        *len -= 1;
+        handlers[*len] = None;
+        true
+    }
+
+    /// Calls `call` with the runtime handlers, the static ones in `statics`
+    /// and the static ones with priorities in `prioritized` in the order of
+    /// priority, until it returns `true`.
+    ///
+    /// Returns `None` if there are no handlers at all.
+    pub(crate) fn handle(
+        &self,
+        statics: &[F],
+        prioritized: &[(i32, F)],
+        mut call: impl FnMut(F) -> bool,
+    ) -> Option<bool> {
+        // Copy the handlers out, so that they are called without the lock held,
+        // and can register or unregister handlers, or trap again.
+        let (handlers, len) = *self.handlers.lock();
+        if len == 0 && statics.is_empty() && prioritized.is_empty() {
+            return None;
+        }
+        let runtime = handlers[..len].iter().map(|h| h.unwrap());
+        let statics = statics
+            .iter()
+            .map(|&h| (DEFAULT_PRIORITY, h))
+            .chain(prioritized.iter().copied());
+        let priorities = runtime
+            .clone()
+            .map(|h| h.1)
+            .chain(statics.clone().map(|h| h.0));
+
+        // Walk through the priorities from the highest one, there are only a
+        // few handlers.
+        let mut above = None;
+        while let Some(priority) = priorities
+            .clone()
+            .filter(|&p| above.is_none_or(|above| p < above))
+            .max()
+        {
+            if statics
+                .clone()
+                .filter(|h| h.0 == priority)
+                .any(|h| call(h.1))
+                || runtime
+                    .clone()
+                    .filter(|h| h.1 == priority)
+                    .any(|h| call(h.2))
+            {
+                return Some(true);
+            }
+            above = Some(priority);
+        }
+        Some(false)
+    }
+}
+
+pub(crate) static IRQ_CHAIN: HandlerChain<IrqTrapHandler> = HandlerChain::new();
+pub(crate) static PAGE_FAULT_CHAIN: HandlerChain<PageFaultHandler> = HandlerChain::new();
+
+/// Registers an IRQ handler at runtime, which is called after the handlers of
+/// higher or the same priority, and before those of lower priority.
+///
+/// Returns `None` if there are too many handlers.
+pub fn register_irq_handler(priority: i32, handler: IrqTrapHandler) -> Option<TrapHandlerId> {
+    IRQ_CHAIN.register(priority, handler)
+}
+
+/// Unregisters an IRQ handler registered by [`register_irq_handler`], returns
+/// `false` if it is not found.
+pub fn unregister_irq_handler(id: TrapHandlerId) -> bool {
+    IRQ_CHAIN.unregister(id)
+}
+
+/// Registers a page fault handler at runtime, which is called after the
+/// handlers of higher or the same priority, and before those of lower
+/// priority.
+///
+/// Returns `None` if there are too many handlers.
+pub fn register_page_fault_handler(
+    priority: i32,
+    handler: PageFaultHandler,
+) -> Option<TrapHandlerId> {
+    PAGE_FAULT_CHAIN.register(priority, handler)
+}
+
+/// Unregisters a page fault handler registered by
+/// [`register_page_fault_handler`], returns `false` if it is not found.
+pub fn unregister_page_fault_handler(id: TrapHandlerId) -> bool {
+    PAGE_FAULT_CHAIN.unregister(id)
+}
+
 #[allow(unused_macros)]
 macro_rules! handle_trap {
-    ($trap:ident, $($args:tt)*) => {{
-        let mut iter = $crate::trap::$trap.iter();
-        if let Some(func) = iter.next() {
-            if iter.next().is_some() {
-                warn!("Multiple handlers for trap {} are not currently supported", stringify!($trap));
-            }
-            func($($args)*)
-        } else {
+    (IRQ, $($args:tt)*) => {
+        handle_trap!(@chain IRQ, IRQ_WITH_PRIORITY, IRQ_CHAIN, $($args)*)
+    };
+    (PAGE_FAULT, $($args:tt)*) => {
+        handle_trap!(@chain PAGE_FAULT, PAGE_FAULT_WITH_PRIORITY, PAGE_FAULT_CHAIN, $($args)*)
+    };
+    (@chain $trap:ident, $prioritized:ident, $chain:ident, $($args:tt)*) => {{
+        let handled = $crate::trap::$chain.handle(
+            &$crate::trap::$trap,
+            &$crate::trap::$prioritized,
+            |func| func($($args)*),
+        );
+        handled.unwrap_or_else(|| {
             warn!("No registered handler for trap {}", stringify!($trap));
             false
+        })
+    }};
+    ($trap:ident, $($args:tt)*) => {{
+        if $crate::trap::$trap.is_empty() {
+            warn!("No registered handler for trap {}", stringify!($trap));
         }
-    }}
+        $crate::trap::$trap.iter().any(|func| func($($args)*))
+    }};
 }
 
-/// Call the external syscall handler.
+/// Call the external syscall handlers.
 #[cfg(feature = "uspace")]
 pub(crate) fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
-    SYSCALL[0](tf, syscall_num)
+    let enosys = -axerrno::LinuxError::ENOSYS.code() as isize;
+    for handler in SYSCALL.iter() {
+        let ret = handler(tf, syscall_num);
+        if ret != enosys {
+            return ret;
+        }
+    }
+    if SYSCALL.is_empty() {
+        warn!("No registered handler for trap SYSCALL");
+    }
+    enosys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls the handlers of `chain`, which are labels here, and returns the
+    /// result and the labels in the order they are called. The handler
+    /// labelled `target` handles the trap.
+    fn run(
+        chain: &HandlerChain<u32>,
+        statics: &[u32],
+        prioritized: &[(i32, u32)],
+        target: u32,
+    ) -> (Option<bool>, Vec<u32>) {
+        let mut called = Vec::new();
+        let handled = chain.handle(statics, prioritized, |label| {
+            called.push(label);
+            label == target
+        });
+        (handled, called)
+    }
+
+    #[test]
+    fn test_order() {
+        let chain = HandlerChain::new();
+        chain.register(5, 1).unwrap();
+        chain.register(-5, 2).unwrap();
+        chain.register(DEFAULT_PRIORITY, 3).unwrap();
+        chain.register(5, 4).unwrap();
+        let statics = [10, 11];
+        let prioritized = [(5, 20), (-5, 21), (7, 22)];
+        let (handled, called) = run(&chain, &statics, &prioritized, 0);
+        assert_eq!(handled, Some(false));
+        assert_eq!(called, [22, 20, 1, 4, 10, 11, 3, 21, 2]);
+    }
+
+    #[test]
+    fn test_handled() {
+        let chain = HandlerChain::new();
+        chain.register(1, 1).unwrap();
+        chain.register(-1, 2).unwrap();
+        let (handled, called) = run(&chain, &[10, 11], &[], 10);
+        assert_eq!(handled, Some(true));
+        assert_eq!(called, [1, 10]);
+
+        let (handled, called) = run(&chain, &[10, 11], &[], 2);
+        assert_eq!(handled, Some(true));
+        assert_eq!(called, [1, 10, 11, 2]);
+    }
+
+    #[test]
+    fn test_no_handlers() {
+        let chain = HandlerChain::new();
+        assert_eq!(run(&chain, &[], &[], 0), (None, Vec::new()));
+
+        let id = chain.register(0, 1).unwrap();
+        assert!(chain.unregister(id));
+        assert_eq!(run(&chain, &[], &[], 0), (None, Vec::new()));
+        assert_eq!(run(&chain, &[], &[(1, 20)], 0), (Some(false), vec![20]));
+    }
+
+    #[test]
+    fn test_unregister() {
+        let chain = HandlerChain::new();
+        let a = chain.register(0, 1).unwrap();
+        let b = chain.register(0, 2).unwrap();
+        let c = chain.register(0, 3).unwrap();
+        assert!(chain.unregister(b));
+        assert!(!chain.unregister(b));
+        assert_eq!(run(&chain, &[], &[], 0).1, [1, 3]);
+
+        // Registering again goes after the remaining ones of the priority.
+        let d = chain.register(0, 2).unwrap();
+        assert_ne!(d, b);
+        assert_eq!(run(&chain, &[], &[], 0).1, [1, 3, 2]);
+        assert!(chain.unregister(a));
+        assert!(chain.unregister(c));
+        assert!(chain.unregister(d));
+        assert_eq!(run(&chain, &[], &[], 0), (None, Vec::new()));
+    }
+
+    #[test]
+    fn test_full() {
+        let chain = HandlerChain::new();
+        let ids: Vec<_> = (0..MAX_RUNTIME_HANDLERS as u32)
+            .map(|i| chain.register(0, i).unwrap())
+            .collect();
+        assert_eq!(chain.register(0, 100), None);
+        assert!(chain.unregister(ids[3]));
+        assert!(chain.register(0, 100).is_some());
+    }
 }
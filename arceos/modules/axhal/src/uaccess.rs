@@ -0,0 +1,98 @@
+//! Safe access to user memory from the kernel.
+//!
+//! The user memory is accessed by a few assembly routines, whose loads and
+//! stores that may fault are recorded in the [exception table](crate::extable),
+//! with the address to resume at. When such an instruction faults and the
+//! fault is not handled otherwise (e.g., by mapping a lazy page), the trap
+//! handler resumes at the fixup address, and the access fails with
+//! [`AxError::BadAddress`] (`EFAULT`) instead of a kernel panic.
+//!
+//! [`AxError::BadAddress`]: axerrno::AxError::BadAddress
+
+use axerrno::{ax_err, AxResult};
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        core::arch::global_asm!(include_str!("arch/x86_64/uaccess.S"));
+        /// End of the lower canonical half.
+        const USER_SPACE_END: usize = 0x0000_8000_0000_0000;
+    } else if #[cfg(target_arch = "riscv64")] {
+        core::arch::global_asm!(include_str!("arch/riscv/uaccess.S"));
+        /// End of the lower half of Sv39.
+        const USER_SPACE_END: usize = 0x0000_0040_0000_0000;
+    } else if #[cfg(target_arch = "aarch64")] {
+        core::arch::global_asm!(include_str!("arch/aarch64/uaccess.S"));
+        /// End of the range translated by `TTBR0_EL1` (48-bit).
+        const USER_SPACE_END: usize = 0x0001_0000_0000_0000;
+    } else if #[cfg(target_arch = "loongarch64")] {
+        core::arch::global_asm!(include_str!("arch/loongarch64/uaccess.S"));
+        /// End of the range translated by `PGDL` (`VA[47] = 0`).
+        const USER_SPACE_END: usize = 0x0000_8000_0000_0000;
+    }
+}
+
+extern "C" {
+    /// Copies `len` bytes from `src` to `dst`, returns the number of bytes
+    /// not copied.
+    fn __copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    /// Copies a NUL-terminated string of at most `len` bytes from `src` to
+    /// `dst`, returns its length without the NUL, `len` if it is not
+    /// terminated, or `usize::MAX` on a fault.
+    fn __strncpy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+}
+
+/// Checks that `[addr, addr + len)` is in the user address space.
+fn access_ok(addr: usize, len: usize) -> AxResult {
+    match addr.checked_add(len) {
+        Some(end) if end <= USER_SPACE_END => Ok(()),
+        _ => ax_err!(BadAddress, "not a user address"),
+    }
+}
+
+/// Copies `dst.len()` bytes from the user address `src` to `dst`.
+///
+/// Returns [`AxError::BadAddress`] if some of the user memory is not
+/// accessible, `dst` may be partially written in this case.
+///
+/// [`AxError::BadAddress`]: axerrno::AxError::BadAddress
+pub fn copy_from_user(dst: &mut [u8], src: *const u8) -> AxResult {
+    access_ok(src as usize, dst.len())?;
+    match unsafe { __copy_user(dst.as_mut_ptr(), src, dst.len()) } {
+        0 => Ok(()),
+        _ => ax_err!(BadAddress),
+    }
+}
+
+/// Copies `src` to the user address `dst`.
+///
+/// Returns [`AxError::BadAddress`] if some of the user memory is not
+/// accessible, the user memory may be partially written in this case.
+///
+/// [`AxError::BadAddress`]: axerrno::AxError::BadAddress
+pub fn copy_to_user(dst: *mut u8, src: &[u8]) -> AxResult {
+    access_ok(dst as usize, src.len())?;
+    match unsafe { __copy_user(dst, src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => ax_err!(BadAddress),
+    }
+}
+
+/// Copies a NUL-terminated string from the user address `src` to `dst`, at
+/// most `dst.len()` bytes including the NUL.
+///
+/// Returns the length of the string without the NUL, or `dst.len()` if it is
+/// longer than `dst` (then `dst` is not terminated). Returns
+/// [`AxError::BadAddress`] if the string is not accessible.
+///
+/// [`AxError::BadAddress`]: axerrno::AxError::BadAddress
+pub fn strncpy_from_user(dst: &mut [u8], src: *const u8) -> AxResult<usize> {
+    access_ok(src as usize, 0)?;
+    // Only the bytes before the end of user space can be read.
+    let max_len = dst.len().min(USER_SPACE_END - src as usize);
+    match unsafe { __strncpy_user(dst.as_mut_ptr(), src, max_len) } {
+        usize::MAX => ax_err!(BadAddress),
+        // Not terminated before the end of user space.
+        len if len == max_len && max_len < dst.len() => ax_err!(BadAddress),
+        len => Ok(len),
+    }
+}
@@ -179,10 +179,11 @@ impl AddrSpace {
 
     /// To process data in this area with the given function.
     ///
-    /// Now it supports reading and writing data in the given interval.
+    /// Now it supports reading and writing data in the given interval. The
+    /// function gets `None` for pages not allocated yet by lazy mappings.
     fn process_area_data<F>(&self, start: VirtAddr, size: usize, mut f: F) -> AxResult
     where
-        F: FnMut(VirtAddr, usize, usize),
+        F: FnMut(Option<VirtAddr>, usize, usize) -> AxResult,
     {
         if !self.contains_range(start, size) {
             return ax_err!(InvalidInput, "address out of range");
@@ -193,7 +194,7 @@ impl AddrSpace {
         for vaddr in PageIter4K::new(start.align_down_4k(), end_align_up)
             .expect("Failed to create page iterator")
         {
-            let (mut paddr, _, _) = self.pt.query(vaddr).map_err(|_| AxError::BadAddress)?;
+            let (mut paddr, flags, _) = self.pt.query(vaddr).map_err(|_| AxError::BadAddress)?;
 
             let mut copy_size = (size - cnt).min(PAGE_SIZE_4K);
 
@@ -205,7 +206,9 @@ impl AddrSpace {
                 copy_size = copy_size.min(PAGE_SIZE_4K - align_offset);
                 paddr += align_offset;
             }
-            f(phys_to_virt(paddr), cnt, copy_size);
+            // Empty entries are lazy mappings not allocated yet.
+            let data = (!flags.is_empty()).then(|| phys_to_virt(paddr));
+            f(data, cnt, copy_size)?;
             cnt += copy_size;
         }
         Ok(())
@@ -213,28 +216,112 @@ impl AddrSpace {
 
     /// To read data from the address space.
     ///
+    /// Pages not allocated yet by lazy mappings are read as zeros.
+    ///
     /// # Arguments
     ///
     /// * `start` - The start virtual address to read.
     /// * `buf` - The buffer to store the data.
     pub fn read(&self, start: VirtAddr, buf: &mut [u8]) -> AxResult {
-        self.process_area_data(start, buf.len(), |src, offset, read_size| unsafe {
-            core::ptr::copy_nonoverlapping(src.as_ptr(), buf.as_mut_ptr().add(offset), read_size);
+        self.process_area_data(start, buf.len(), |src, offset, read_size| {
+            let dst = &mut buf[offset..offset + read_size];
+            match src {
+                Some(src) => unsafe {
+                    core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), read_size)
+                },
+                None => dst.fill(0),
+            }
+            Ok(())
         })
     }
 
     /// To write data to the address space.
     ///
+    /// Pages of lazy mappings must be allocated before, see
+    /// [`populate_area`](Self::populate_area).
+    ///
     /// # Arguments
     ///
     /// * `start_vaddr` - The start virtual address to write.
     /// * `buf` - The buffer to write to the address space.
     pub fn write(&self, start: VirtAddr, buf: &[u8]) -> AxResult {
-        self.process_area_data(start, buf.len(), |dst, offset, write_size| unsafe {
-            core::ptr::copy_nonoverlapping(buf.as_ptr().add(offset), dst.as_mut_ptr(), write_size);
+        self.process_area_data(start, buf.len(), |dst, offset, write_size| {
+            let Some(dst) = dst else {
+                return ax_err!(BadAddress, "write to a page not allocated");
+            };
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    buf.as_ptr().add(offset),
+                    dst.as_mut_ptr(),
+                    write_size,
+                )
+            };
+            Ok(())
         })
     }
 
+    /// Allocates the pages of lazy mappings within the specified virtual
+    /// address range that are not allocated yet, as if they were accessed.
+    ///
+    /// Returns an error if the address range is out of the address space, or
+    /// not in allocation mappings.
+    pub fn populate_area(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        let end = (start + size).align_up_4k();
+        for vaddr in PageIter4K::new(start.align_down_4k(), end).unwrap() {
+            if matches!(self.pt.query(vaddr), Ok((_, flags, _)) if !flags.is_empty()) {
+                continue;
+            }
+            let Some(area) = self.areas.find(vaddr) else {
+                return ax_err!(BadAddress, "address not mapped");
+            };
+            if !area
+                .backend()
+                .handle_page_fault(vaddr, area.flags(), &mut self.pt)
+            {
+                return ax_err!(NoMemory, "failed to populate the page");
+            }
+        }
+        Ok(())
+    }
+
+    /// Frees the pages of lazy mappings at the given virtual addresses. They
+    /// are allocated again (zeroed) on the next access.
+    ///
+    /// All pages are unmapped first, then `flush` is called to flush the stale
+    /// TLB entries on the other CPUs that may use the address space (e.g., the
+    /// nested TLBs of the vCPUs of a VM), and the frames are freed only after
+    /// that, so that they are never accessed after being reused.
+    ///
+    /// Pages that are not aligned, out of the address space, or not in lazy
+    /// allocation mappings are skipped. Returns the number of pages skipped.
+    pub fn reclaim_pages(
+        &mut self,
+        pages: impl IntoIterator<Item = VirtAddr>,
+        flush: impl FnOnce(),
+    ) -> usize {
+        let mut frames = Vec::new();
+        let mut skipped = 0;
+        for vaddr in pages {
+            let reclaimed = vaddr.is_aligned_4k()
+                && self.contains_range(vaddr, PAGE_SIZE_4K)
+                && self
+                    .areas
+                    .find(vaddr)
+                    .is_some_and(|area| area.backend().reclaim(vaddr, &mut self.pt, &mut frames));
+            if !reclaimed {
+                skipped += 1;
+            }
+        }
+        if !frames.is_empty() {
+            flush();
+            Backend::free_reclaimed(&frames);
+        }
+        skipped
+    }
+
     /// Updates mapping within the specified virtual address range.
     ///
     /// Returns an error if the address range is out of the address space or not
@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 use axalloc::global_allocator;
 use axhal::mem::{phys_to_virt, virt_to_phys};
 use axhal::paging::{MappingFlags, PageSize, PageTable};
@@ -94,6 +96,10 @@ impl Backend {
     ) -> bool {
         if populate {
             false // Populated mappings should not trigger page faults.
+        } else if matches!(pt.query(vaddr), Ok((_, flags, _)) if !flags.is_empty()) {
+            // Already allocated by a concurrent fault on the same page (e.g.,
+            // from another CPU), the fault is spurious.
+            true
         } else if let Some(frame) = alloc_frame(true) {
             // Allocate a physical frame lazily and map it to the fault address.
             // `vaddr` does not need to be aligned. It will be automatically
@@ -105,4 +111,41 @@ impl Backend {
             false
         }
     }
+
+    pub(crate) fn reclaim_alloc(
+        &self,
+        vaddr: VirtAddr,
+        pt: &mut PageTable,
+        populate: bool,
+        frames: &mut Vec<PhysAddr>,
+    ) -> bool {
+        if populate {
+            return false; // Populated mappings cannot be faulted in again.
+        }
+        match pt.query(vaddr) {
+            Ok((_, flags, page_size)) if !flags.is_empty() => {
+                if page_size.is_huge() {
+                    return false;
+                }
+                let Ok((frame, _, tlb)) = pt.unmap(vaddr) else {
+                    return false;
+                };
+                tlb.flush();
+                // Freed by the caller after the remote TLBs are flushed.
+                frames.push(frame);
+                // Map to a empty entry again, for the next access to fault.
+                pt.map(vaddr, 0.into(), PageSize::Size4K, MappingFlags::empty())
+                    .map(|tlb| tlb.ignore())
+                    .is_ok()
+            }
+            _ => true, // Not allocated yet.
+        }
+    }
+
+    /// Frees the frames unmapped by [`reclaim_alloc`](Self::reclaim_alloc).
+    pub(crate) fn free_reclaimed(frames: &[PhysAddr]) {
+        for &frame in frames {
+            dealloc_frame(frame);
+        }
+    }
 }
@@ -1,8 +1,9 @@
 //! Memory mapping backends.
 #![allow(dead_code)]
 
+use ::alloc::vec::Vec;
 use axhal::paging::{MappingFlags, PageTable};
-use memory_addr::VirtAddr;
+use memory_addr::{PhysAddr, VirtAddr};
 use memory_set::MappingBackend;
 
 mod alloc;
@@ -85,4 +86,19 @@ impl Backend {
             }
         }
     }
+
+    /// Unmaps the physical frame mapped at `vaddr` (a 4K page) and pushes it
+    /// to `frames` to be freed, it is allocated again on the next access.
+    /// Only lazy allocation mappings support it.
+    pub(crate) fn reclaim(
+        &self,
+        vaddr: VirtAddr,
+        page_table: &mut PageTable,
+        frames: &mut Vec<PhysAddr>,
+    ) -> bool {
+        match *self {
+            Self::Linear { .. } => false,
+            Self::Alloc { populate } => self.reclaim_alloc(vaddr, page_table, populate, frames),
+        }
+    }
 }
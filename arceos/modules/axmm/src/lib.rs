@@ -11,9 +11,10 @@ mod backend;
 
 pub use self::aspace::AddrSpace;
 
+use axalloc::AllocError;
 use axerrno::{AxError, AxResult};
 use axhal::mem::phys_to_virt;
-use axhal::paging::PagingError;
+use axhal::paging::{MappingFlags, PagingError};
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use memory_addr::{va, PhysAddr, VirtAddr};
@@ -47,7 +48,11 @@ fn paging_err_to_ax_err(err: PagingError) -> AxError {
 /// Creates a new address space for user processes.
 pub fn new_user_aspace() -> AxResult<AddrSpace> {
     let mut aspace = AddrSpace::new_empty(VirtAddr::from(USER_ASPACE_BASE), USER_ASPACE_SIZE)?;
-    aspace.copy_mappings_from(&kernel_aspace().lock())?;
+    // On LoongArch, the kernel is accessed through the direct mapping windows,
+    // and the user page table (`PGDL`) is separate from the kernel one.
+    if !cfg!(target_arch = "loongarch64") {
+        aspace.copy_mappings_from(&kernel_aspace().lock())?;
+    }
     Ok(aspace)
 }
 
@@ -73,6 +78,52 @@ pub fn kernel_page_table_root() -> PhysAddr {
     KERNEL_ASPACE.lock().page_table_root()
 }
 
+/// Adds a physical memory region found at runtime (e.g., from a DTB or a
+/// memory hotplug device) to the global allocator.
+///
+/// The region is linearly mapped into the kernel address space first.
+pub fn add_memory_region(paddr: PhysAddr, size: usize) -> AxResult {
+    let vaddr = phys_to_virt(paddr);
+    let mut aspace = kernel_aspace().lock();
+    aspace.map_linear(vaddr, paddr, size, MappingFlags::READ | MappingFlags::WRITE)?;
+    if let Err(e) = axalloc::global_add_memory(vaddr.as_usize(), size) {
+        warn!("failed to add memory region to the allocator: {:?}", e);
+        aspace.unmap(vaddr, size)?;
+        return Err(match e {
+            AllocError::MemoryOverlap => AxError::AlreadyExists,
+            AllocError::NoMemory => AxError::NoMemory,
+            _ => AxError::InvalidInput,
+        });
+    }
+    Ok(())
+}
+
+/// Removes the physical memory region added by [`add_memory_region`] with
+/// the same bounds.
+///
+/// It fails with [`AxError::ResourceBusy`] if some memory in the region is
+/// still in use. Otherwise, the region is also unmapped from the kernel
+/// address space, and can be unplugged safely.
+///
+/// Only single-CPU kernels are supported, [`AxError::Unsupported`] is returned
+/// otherwise: the unmapping only flushes the local TLB, and there is no TLB
+/// shootdown to flush the stale entries of other CPUs.
+pub fn remove_memory_region(paddr: PhysAddr, size: usize) -> AxResult {
+    if axconfig::SMP > 1 {
+        warn!("memory regions cannot be removed without TLB shootdowns");
+        return Err(AxError::Unsupported);
+    }
+    let vaddr = phys_to_virt(paddr);
+    axalloc::global_remove_memory(vaddr.as_usize(), size).map_err(|e| {
+        warn!("failed to remove memory region from the allocator: {:?}", e);
+        match e {
+            AllocError::MemoryOverlap => AxError::ResourceBusy,
+            _ => AxError::InvalidInput,
+        }
+    })?;
+    kernel_aspace().lock().unmap(vaddr, size)
+}
+
 /// Initializes virtual memory management.
 ///
 /// It mainly sets up the kernel virtual memory address space and recreate a
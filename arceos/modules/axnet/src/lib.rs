@@ -8,12 +8,18 @@
 //!
 //! - [`TcpSocket`]: A TCP socket that provides POSIX-like APIs.
 //! - [`UdpSocket`]: A UDP socket that provides POSIX-like APIs.
+//! - [`IcmpSocket`]: An ICMP socket for echo requests (ping) and other ICMP
+//!   messages.
+//! - [`RawSocket`]: A raw IPv4 socket for a given IP protocol.
 //! - [`dns_query`]: Function for DNS query.
+//! - [`pcap`]: Capture of the frames sent and received by the NIC, in the
+//!   pcap format.
 //!
 //! # Cargo Features
 //!
 //! - `smoltcp`: Use [smoltcp] as the underlying network stack. This is enabled
 //!   by default.
+//! - `pcap`: Enable packet capture in the pcap format.
 //!
 //! [smoltcp]: https://github.com/smoltcp-rs/smoltcp
 
@@ -24,6 +30,9 @@
 extern crate log;
 extern crate alloc;
 
+#[cfg(feature = "pcap")]
+pub mod pcap;
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "smoltcp")] {
         mod smoltcp_impl;
@@ -34,7 +43,8 @@ cfg_if::cfg_if! {
 pub use self::net_impl::TcpSocket;
 pub use self::net_impl::UdpSocket;
 pub use self::net_impl::{bench_receive, bench_transmit};
-pub use self::net_impl::{dns_query, poll_interfaces};
+pub use self::net_impl::{dns_query, poll_interfaces, rx_frames};
+pub use self::net_impl::{IcmpSocket, RawSocket};
 
 use axdriver::{prelude::*, AxDeviceContainer};
 
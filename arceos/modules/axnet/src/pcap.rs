@@ -0,0 +1,153 @@
+//! Packet capture in the [pcap] format.
+//!
+//! Every frame received from or transmitted to the NIC is recorded with a
+//! timestamp, so that the trace can be opened directly in Wireshark or
+//! `tcpdump -r`.
+//!
+//! Frames are queued while the NIC is polled, and written to the sink after
+//! the network stack locks are released, so the sink may use the network
+//! stack itself (e.g., to send the trace over a socket).
+//!
+//! [pcap]: https://wiki.wireshark.org/Development/LibpcapFileFormat
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use axerrno::{ax_err, AxResult};
+use axhal::time::wall_time;
+use axio::Write;
+use axsync::Mutex;
+
+/// The pcap magic number, for microsecond-resolution timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+/// Maximum number of bytes recorded for each frame.
+const PCAP_SNAPLEN: u32 = 65535;
+/// `LINKTYPE_ETHERNET`
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+/// Maximum number of frames queued until they are written, later frames are
+/// dropped.
+const PCAP_QUEUE_LEN: usize = 256;
+
+/// The destination of the captured packets, e.g., a file or a serial port.
+pub type PcapSink = Box<dyn Write + Send>;
+
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+static SINK: Mutex<Option<PcapSink>> = Mutex::new(None);
+/// Captured records (headers and frames) not written yet.
+static QUEUE: Mutex<VecDeque<Vec<u8>>> = Mutex::new(VecDeque::new());
+/// Number of frames dropped as the queue was full.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Starts capturing packets to the given sink.
+///
+/// The pcap file header is written immediately. Returns
+/// [`Err(ResourceBusy)`](axerrno::AxError::ResourceBusy) if a capture is
+/// already in progress.
+pub fn start_capture(mut sink: PcapSink) -> AxResult {
+    let mut guard = SINK.lock();
+    if guard.is_some() {
+        return ax_err!(ResourceBusy, "packet capture already started");
+    }
+
+    let mut header = [0u8; 24];
+    header[0..4].copy_from_slice(&PCAP_MAGIC.to_ne_bytes());
+    header[4..6].copy_from_slice(&PCAP_VERSION_MAJOR.to_ne_bytes());
+    header[6..8].copy_from_slice(&PCAP_VERSION_MINOR.to_ne_bytes());
+    // thiszone and sigfigs are always 0
+    header[16..20].copy_from_slice(&PCAP_SNAPLEN.to_ne_bytes());
+    header[20..24].copy_from_slice(&PCAP_LINKTYPE_ETHERNET.to_ne_bytes());
+    sink.write_all(&header)?;
+
+    QUEUE.lock().clear();
+    DROPPED.store(0, Ordering::Relaxed);
+    *guard = Some(sink);
+    CAPTURING.store(true, Ordering::Release);
+    info!("packet capture started");
+    Ok(())
+}
+
+/// Stops the packet capture, writes the queued frames, flushes and drops the
+/// sink.
+///
+/// Returns [`Err(BadState)`](axerrno::AxError::BadState) if no capture is in
+/// progress.
+pub fn stop_capture() -> AxResult {
+    flush();
+    CAPTURING.store(false, Ordering::Release);
+    QUEUE.lock().clear();
+    let sink = SINK.lock().take();
+    match sink {
+        Some(mut sink) => {
+            info!("packet capture stopped");
+            sink.flush()
+        }
+        None => ax_err!(BadState, "packet capture not started"),
+    }
+}
+
+/// Whether a packet capture is in progress.
+pub fn is_capturing() -> bool {
+    CAPTURING.load(Ordering::Acquire)
+}
+
+/// Queues a frame to be written by [`flush`], if a capture is in progress.
+///
+/// It is called with the network stack locked, so it must not write to the
+/// sink.
+pub(crate) fn capture(frame: &[u8]) {
+    if !is_capturing() {
+        return;
+    }
+    let mut queue = QUEUE.lock();
+    if queue.len() >= PCAP_QUEUE_LEN {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    let now = wall_time();
+    let caplen = frame.len().min(PCAP_SNAPLEN as usize);
+    let mut record = Vec::with_capacity(16 + caplen);
+    record.extend_from_slice(&(now.as_secs() as u32).to_ne_bytes());
+    record.extend_from_slice(&now.subsec_micros().to_ne_bytes());
+    record.extend_from_slice(&(caplen as u32).to_ne_bytes());
+    record.extend_from_slice(&(frame.len() as u32).to_ne_bytes());
+    record.extend_from_slice(&frame[..caplen]);
+    queue.push_back(record);
+}
+
+/// Writes the queued frames to the sink.
+///
+/// It must be called without the network stack locked. The frames captured
+/// meanwhile, e.g., sent by the sink itself, are written by the next call.
+/// On write errors, the capture is stopped.
+pub(crate) fn flush() {
+    if !is_capturing() {
+        return;
+    }
+    // The sink is busy if it polls the network stack, which flushes again.
+    let Some(mut guard) = SINK.try_lock() else {
+        return;
+    };
+    let Some(sink) = guard.as_mut() else {
+        return;
+    };
+
+    let records = core::mem::take(&mut *QUEUE.lock());
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        warn!("packet capture: {} frames dropped", dropped);
+    }
+    for record in records {
+        if let Err(e) = sink.write_all(&record) {
+            warn!("packet capture failed: {:?}, stopped", e);
+            CAPTURING.store(false, Ordering::Release);
+            guard.take();
+            QUEUE.lock().clear();
+            return;
+        }
+    }
+}
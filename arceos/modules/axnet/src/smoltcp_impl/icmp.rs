@@ -0,0 +1,259 @@
+use alloc::vec;
+use core::net::IpAddr;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use core::time::Duration;
+
+use axerrno::{ax_err, ax_err_type, AxError, AxResult};
+use axhal::time::monotonic_time;
+use axio::PollState;
+use spin::RwLock;
+
+use smoltcp::iface::SocketHandle;
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::icmp::{self, BindError, SendError};
+use smoltcp::wire::{Icmpv4Packet, Icmpv4Repr};
+
+use super::addr::{from_core_ipaddr, into_core_ipaddr};
+use super::{SocketSetWrapper, SOCKET_SET};
+
+/// Payload length of the echo requests sent by [`IcmpSocket::ping`], the same
+/// as the default of the `ping` utility.
+const PING_DATA_LEN: usize = 56;
+
+/// An ICMP socket that sends and receives ICMP messages of a given identifier.
+///
+/// The data sent and received is the ICMP message (header and payload), without
+/// the IP header, just like a Linux "ping socket"
+/// (`socket(AF_INET, SOCK_DGRAM, IPPROTO_ICMP)`).
+pub struct IcmpSocket {
+    handle: SocketHandle,
+    ident: RwLock<Option<u16>>,
+    nonblock: AtomicBool,
+}
+
+impl IcmpSocket {
+    /// Creates a new ICMP socket.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let socket = SocketSetWrapper::new_icmp_socket();
+        let handle = SOCKET_SET.add(socket);
+        Self {
+            handle,
+            ident: RwLock::new(None),
+            nonblock: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the ICMP identifier this socket is bound to, or
+    /// [`Err(NotConnected)`](AxError::NotConnected) if not bound.
+    pub fn ident(&self) -> AxResult<u16> {
+        self.ident.read().ok_or(AxError::NotConnected)
+    }
+
+    /// Returns whether this socket is in nonblocking mode.
+    #[inline]
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    /// Moves this ICMP socket into or out of nonblocking mode.
+    ///
+    /// This will result in `recv_from` and `send_to` operations becoming
+    /// nonblocking, i.e., immediately returning from their calls. If the IO
+    /// operation could not be completed and needs to be retried, an error with
+    /// kind [`Err(WouldBlock)`](AxError::WouldBlock) is returned.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    /// Binds an unbound socket to the given ICMP identifier.
+    ///
+    /// Only echo replies carrying this identifier (and ICMP errors caused by
+    /// packets sent with it) are delivered to the socket. If `ident` is 0, it
+    /// generates one automatically.
+    pub fn bind(&self, mut ident: u16) -> AxResult {
+        let mut self_ident = self.ident.write();
+        if self_ident.is_some() {
+            return ax_err!(InvalidInput, "socket bind() failed: already bound");
+        }
+        if ident == 0 {
+            ident = get_ephemeral_ident();
+        }
+
+        SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+            socket
+                .bind(icmp::Endpoint::Ident(ident))
+                .or_else(|e| match e {
+                    BindError::InvalidState => ax_err!(AlreadyExists, "socket bind() failed"),
+                    BindError::Unaddressable => ax_err!(InvalidInput, "socket bind() failed"),
+                })
+        })?;
+
+        *self_ident = Some(ident);
+        debug!("ICMP socket {}: bound on ident {}", self.handle, ident);
+        Ok(())
+    }
+
+    /// Sends an ICMP message to the given address. On success, returns the
+    /// number of bytes written.
+    ///
+    /// The checksum in `buf` is ignored and recomputed by the network stack.
+    pub fn send_to(&self, buf: &[u8], remote_addr: IpAddr) -> AxResult<usize> {
+        if remote_addr.is_unspecified() {
+            return ax_err!(InvalidInput, "socket send_to() failed: invalid address");
+        }
+        if self.ident.read().is_none() {
+            self.bind(0)?;
+        }
+
+        let remote_addr = from_core_ipaddr(remote_addr);
+        self.block_on(|| {
+            SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+                if socket.can_send() {
+                    socket.send_slice(buf, remote_addr).map_err(|e| match e {
+                        SendError::BufferFull => AxError::WouldBlock,
+                        SendError::Unaddressable => {
+                            ax_err_type!(ConnectionRefused, "socket send_to() failed")
+                        }
+                    })?;
+                    Ok(buf.len())
+                } else {
+                    // tx buffer is full
+                    Err(AxError::WouldBlock)
+                }
+            })
+        })
+    }
+
+    /// Receives a single ICMP message on the socket. On success, returns the
+    /// number of bytes read and the origin.
+    pub fn recv_from(&self, buf: &mut [u8]) -> AxResult<(usize, IpAddr)> {
+        if self.ident.read().is_none() {
+            return ax_err!(NotConnected, "socket recv_from() failed");
+        }
+        self.block_on(|| self.try_recv_from(buf))
+    }
+
+    /// Sends an ICMP echo request with the given sequence number to `addr`,
+    /// and waits for the matching echo reply.
+    ///
+    /// On success, returns the round-trip time, or `None` if no reply arrived
+    /// within `timeout`. The socket is bound automatically if necessary.
+    pub fn ping(&self, addr: IpAddr, seq_no: u16, timeout: Duration) -> AxResult<Option<Duration>> {
+        if self.ident.read().is_none() {
+            self.bind(0)?;
+        }
+        let ident = self.ident()?;
+
+        let data = [0xa5u8; PING_DATA_LEN];
+        let request = Icmpv4Repr::EchoRequest {
+            ident,
+            seq_no,
+            data: &data,
+        };
+        let mut tx_buf = vec![0; request.buffer_len()];
+        request.emit(
+            &mut Icmpv4Packet::new_unchecked(&mut tx_buf),
+            &ChecksumCapabilities::default(),
+        );
+
+        let start = monotonic_time();
+        let deadline = start + timeout;
+        self.send_to(&tx_buf, addr)?;
+
+        let mut rx_buf = vec![0; tx_buf.len()];
+        loop {
+            SOCKET_SET.poll_interfaces();
+            match self.try_recv_from(&mut rx_buf) {
+                Ok((len, from)) if from == addr && is_echo_reply(&rx_buf[..len], ident, seq_no) => {
+                    return Ok(Some(monotonic_time() - start));
+                }
+                Ok(_) | Err(AxError::WouldBlock) => {}
+                Err(e) => return Err(e),
+            }
+            if monotonic_time() >= deadline {
+                return Ok(None);
+            }
+            axtask::yield_now();
+        }
+    }
+
+    /// Whether the socket is readable or writable.
+    pub fn poll(&self) -> AxResult<PollState> {
+        if self.ident.read().is_none() {
+            return Ok(PollState {
+                readable: false,
+                writable: true,
+            });
+        }
+        SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+            Ok(PollState {
+                readable: socket.can_recv(),
+                writable: socket.can_send(),
+            })
+        })
+    }
+}
+
+/// Private methods
+impl IcmpSocket {
+    fn try_recv_from(&self, buf: &mut [u8]) -> AxResult<(usize, IpAddr)> {
+        SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+            if socket.can_recv() {
+                // data available
+                let (len, addr) = socket
+                    .recv_slice(buf)
+                    .map_err(|_| ax_err_type!(BadState, "socket recv_from() failed"))?;
+                Ok((len, into_core_ipaddr(addr)))
+            } else {
+                // no more data
+                Err(AxError::WouldBlock)
+            }
+        })
+    }
+
+    fn block_on<F, T>(&self, mut f: F) -> AxResult<T>
+    where
+        F: FnMut() -> AxResult<T>,
+    {
+        if self.is_nonblocking() {
+            f()
+        } else {
+            loop {
+                SOCKET_SET.poll_interfaces();
+                match f() {
+                    Ok(t) => return Ok(t),
+                    Err(AxError::WouldBlock) => axtask::yield_now(),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for IcmpSocket {
+    fn drop(&mut self) {
+        SOCKET_SET.remove(self.handle);
+    }
+}
+
+fn is_echo_reply(buf: &[u8], expect_ident: u16, expect_seq_no: u16) -> bool {
+    let Ok(packet) = Icmpv4Packet::new_checked(buf) else {
+        return false;
+    };
+    matches!(
+        Icmpv4Repr::parse(&packet, &ChecksumCapabilities::default()),
+        Ok(Icmpv4Repr::EchoReply { ident, seq_no, .. })
+            if ident == expect_ident && seq_no == expect_seq_no
+    )
+}
+
+fn get_ephemeral_ident() -> u16 {
+    const IDENT_START: u16 = 0x4158; // "AX"
+    static CURR: AtomicU16 = AtomicU16::new(IDENT_START);
+    match CURR.fetch_add(1, Ordering::Relaxed) {
+        0 => CURR.fetch_add(1, Ordering::Relaxed),
+        ident => ident,
+    }
+}
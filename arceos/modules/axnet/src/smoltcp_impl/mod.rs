@@ -1,13 +1,16 @@
 mod addr;
 mod bench;
 mod dns;
+mod icmp;
 mod listen_table;
+mod raw;
 mod tcp;
 mod udp;
 
 use alloc::vec;
 use core::cell::RefCell;
 use core::ops::DerefMut;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use axdriver::prelude::*;
 use axdriver_net::{DevError, NetBufPtr};
@@ -18,11 +21,13 @@ use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
 use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
 use smoltcp::socket::{self, AnySocket};
 use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr};
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpProtocol, IpVersion};
 
 use self::listen_table::ListenTable;
 
 pub use self::dns::dns_query;
+pub use self::icmp::IcmpSocket;
+pub use self::raw::RawSocket;
 pub use self::tcp::TcpSocket;
 pub use self::udp::UdpSocket;
 
@@ -48,11 +53,17 @@ const TCP_RX_BUF_LEN: usize = 64 * 1024;
 const TCP_TX_BUF_LEN: usize = 64 * 1024;
 const UDP_RX_BUF_LEN: usize = 64 * 1024;
 const UDP_TX_BUF_LEN: usize = 64 * 1024;
+const ICMP_RX_BUF_LEN: usize = 16 * 1024;
+const ICMP_TX_BUF_LEN: usize = 16 * 1024;
+const RAW_RX_BUF_LEN: usize = 64 * 1024;
+const RAW_TX_BUF_LEN: usize = 64 * 1024;
 const LISTEN_QUEUE_SIZE: usize = 512;
 
 static LISTEN_TABLE: LazyInit<ListenTable> = LazyInit::new();
 static SOCKET_SET: LazyInit<SocketSetWrapper> = LazyInit::new();
 static ETH0: LazyInit<InterfaceWrapper> = LazyInit::new();
+/// Number of frames received from the NIC so far.
+static RX_FRAMES: AtomicUsize = AtomicUsize::new(0);
 
 struct SocketSetWrapper<'a>(Mutex<SocketSet<'a>>);
 
@@ -90,6 +101,30 @@ impl<'a> SocketSetWrapper<'a> {
         socket::udp::Socket::new(udp_rx_buffer, udp_tx_buffer)
     }
 
+    pub fn new_icmp_socket() -> socket::icmp::Socket<'a> {
+        let icmp_rx_buffer = socket::icmp::PacketBuffer::new(
+            vec![socket::icmp::PacketMetadata::EMPTY; 8],
+            vec![0; ICMP_RX_BUF_LEN],
+        );
+        let icmp_tx_buffer = socket::icmp::PacketBuffer::new(
+            vec![socket::icmp::PacketMetadata::EMPTY; 8],
+            vec![0; ICMP_TX_BUF_LEN],
+        );
+        socket::icmp::Socket::new(icmp_rx_buffer, icmp_tx_buffer)
+    }
+
+    pub fn new_raw_socket(protocol: IpProtocol) -> socket::raw::Socket<'a> {
+        let raw_rx_buffer = socket::raw::PacketBuffer::new(
+            vec![socket::raw::PacketMetadata::EMPTY; 8],
+            vec![0; RAW_RX_BUF_LEN],
+        );
+        let raw_tx_buffer = socket::raw::PacketBuffer::new(
+            vec![socket::raw::PacketMetadata::EMPTY; 8],
+            vec![0; RAW_TX_BUF_LEN],
+        );
+        socket::raw::Socket::new(IpVersion::Ipv4, protocol, raw_rx_buffer, raw_tx_buffer)
+    }
+
     pub fn new_dns_socket() -> socket::dns::Socket<'a> {
         let server_addr = DNS_SEVER.parse().expect("invalid DNS server address");
         socket::dns::Socket::new(&[server_addr], vec![])
@@ -119,8 +154,11 @@ impl<'a> SocketSetWrapper<'a> {
         f(socket)
     }
 
-    pub fn poll_interfaces(&self) {
-        ETH0.poll(&self.0);
+    pub fn poll_interfaces(&self) -> bool {
+        let changed = ETH0.poll(&self.0);
+        #[cfg(feature = "pcap")]
+        crate::pcap::flush();
+        changed
     }
 
     pub fn remove(&self, handle: SocketHandle) {
@@ -170,12 +208,12 @@ impl InterfaceWrapper {
         };
     }
 
-    pub fn poll(&self, sockets: &Mutex<SocketSet>) {
+    pub fn poll(&self, sockets: &Mutex<SocketSet>) -> bool {
         let mut dev = self.dev.lock();
         let mut iface = self.iface.lock();
         let mut sockets = sockets.lock();
         let timestamp = Self::current_time();
-        iface.poll(timestamp, dev.deref_mut(), &mut sockets);
+        iface.poll(timestamp, dev.deref_mut(), &mut sockets)
     }
 }
 
@@ -253,6 +291,9 @@ impl<'a> RxToken for AxNetRxToken<'a> {
             rx_buf.packet_len(),
             rx_buf.packet()
         );
+        #[cfg(feature = "pcap")]
+        crate::pcap::capture(rx_buf.packet());
+        RX_FRAMES.fetch_add(1, Ordering::Release);
         let result = f(rx_buf.packet_mut());
         self.0.borrow_mut().recycle_rx_buffer(rx_buf).unwrap();
         result
@@ -268,6 +309,8 @@ impl<'a> TxToken for AxNetTxToken<'a> {
         let mut tx_buf = dev.alloc_tx_buffer(len).unwrap();
         let ret = f(tx_buf.packet_mut());
         trace!("SEND {} bytes: {:02X?}", len, tx_buf.packet());
+        #[cfg(feature = "pcap")]
+        crate::pcap::capture(tx_buf.packet());
         dev.transmit(tx_buf).unwrap();
         ret
     }
@@ -295,9 +338,17 @@ fn snoop_tcp_packet(buf: &[u8], sockets: &mut SocketSet<'_>) -> Result<(), smolt
 /// Poll the network stack.
 ///
 /// It may receive packets from the NIC and process them, and transmit queued
-/// packets to the NIC.
-pub fn poll_interfaces() {
-    SOCKET_SET.poll_interfaces();
+/// packets to the NIC. Returns whether the readiness of some sockets may have
+/// changed.
+pub fn poll_interfaces() -> bool {
+    SOCKET_SET.poll_interfaces()
+}
+
+/// Returns the number of frames received from the NIC so far.
+///
+/// A change means that new data may have arrived on any socket.
+pub fn rx_frames() -> usize {
+    RX_FRAMES.load(Ordering::Acquire)
 }
 
 /// Benchmark raw socket transmit bandwidth.
@@ -0,0 +1,178 @@
+use alloc::vec;
+use core::net::IpAddr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axerrno::{ax_err, ax_err_type, AxError, AxResult};
+use axio::PollState;
+
+use smoltcp::iface::SocketHandle;
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::raw::{self, SendError};
+use smoltcp::wire::{IpAddress, IpCidr, IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr};
+
+use super::addr::{from_core_ipaddr, into_core_ipaddr};
+use super::{SocketSetWrapper, ETH0, SOCKET_SET};
+
+/// Default TTL of the IPv4 headers built by [`RawSocket::send_to`].
+const DEFAULT_HOP_LIMIT: u8 = 64;
+
+/// A raw IPv4 socket that sends and receives packets of a given IP protocol.
+///
+/// Like a Linux `SOCK_RAW` socket without `IP_HDRINCL`, received packets
+/// include the IPv4 header, while [`send_to`](Self::send_to) takes only the
+/// payload and builds the header itself.
+pub struct RawSocket {
+    handle: SocketHandle,
+    protocol: IpProtocol,
+    nonblock: AtomicBool,
+}
+
+impl RawSocket {
+    /// Creates a new raw socket for the given IP protocol number.
+    pub fn new(protocol: u8) -> Self {
+        let protocol = IpProtocol::from(protocol);
+        let socket = SocketSetWrapper::new_raw_socket(protocol);
+        let handle = SOCKET_SET.add(socket);
+        Self {
+            handle,
+            protocol,
+            nonblock: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the IP protocol number of this socket.
+    pub fn protocol(&self) -> u8 {
+        self.protocol.into()
+    }
+
+    /// Returns whether this socket is in nonblocking mode.
+    #[inline]
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    /// Moves this raw socket into or out of nonblocking mode.
+    ///
+    /// This will result in `recv_from` and `send_to` operations becoming
+    /// nonblocking, i.e., immediately returning from their calls. If the IO
+    /// operation could not be completed and needs to be retried, an error with
+    /// kind [`Err(WouldBlock)`](AxError::WouldBlock) is returned.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    /// Sends `payload` to the given address in a single IPv4 packet. On success,
+    /// returns the number of payload bytes written.
+    ///
+    /// The IPv4 header is filled in with the address of the interface as the
+    /// source.
+    pub fn send_to(&self, payload: &[u8], remote_addr: IpAddr) -> AxResult<usize> {
+        if remote_addr.is_unspecified() {
+            return ax_err!(InvalidInput, "socket send_to() failed: invalid address");
+        }
+        let IpAddress::Ipv4(dst_addr) = from_core_ipaddr(remote_addr);
+        let repr = Ipv4Repr {
+            src_addr: interface_ipv4_addr()?,
+            dst_addr,
+            next_header: self.protocol,
+            payload_len: payload.len(),
+            hop_limit: DEFAULT_HOP_LIMIT,
+        };
+        let mut packet = vec![0; repr.buffer_len() + payload.len()];
+        let mut ipv4_packet = Ipv4Packet::new_unchecked(&mut packet);
+        repr.emit(&mut ipv4_packet, &ChecksumCapabilities::default());
+        ipv4_packet.payload_mut().copy_from_slice(payload);
+
+        self.send(&packet)?;
+        Ok(payload.len())
+    }
+
+    /// Sends a complete IPv4 packet, header included. On success, returns the
+    /// number of bytes written.
+    pub fn send(&self, packet: &[u8]) -> AxResult<usize> {
+        self.block_on(|| {
+            SOCKET_SET.with_socket_mut::<raw::Socket, _, _>(self.handle, |socket| {
+                if socket.can_send() {
+                    socket.send_slice(packet).map_err(|e| match e {
+                        SendError::BufferFull => AxError::WouldBlock,
+                    })?;
+                    Ok(packet.len())
+                } else {
+                    // tx buffer is full
+                    Err(AxError::WouldBlock)
+                }
+            })
+        })
+    }
+
+    /// Receives a single IPv4 packet, header included. On success, returns the
+    /// number of bytes read and the source address of the packet.
+    pub fn recv_from(&self, buf: &mut [u8]) -> AxResult<(usize, IpAddr)> {
+        self.block_on(|| {
+            SOCKET_SET.with_socket_mut::<raw::Socket, _, _>(self.handle, |socket| {
+                if socket.can_recv() {
+                    // data available
+                    let len = socket
+                        .recv_slice(buf)
+                        .map_err(|_| ax_err_type!(BadState, "socket recv_from() failed"))?;
+                    let src_addr = Ipv4Packet::new_checked(&buf[..len])
+                        .map_err(|_| ax_err_type!(InvalidData, "socket recv_from() failed"))?
+                        .src_addr();
+                    Ok((len, into_core_ipaddr(IpAddress::Ipv4(src_addr))))
+                } else {
+                    // no more data
+                    Err(AxError::WouldBlock)
+                }
+            })
+        })
+    }
+
+    /// Whether the socket is readable or writable.
+    pub fn poll(&self) -> AxResult<PollState> {
+        SOCKET_SET.with_socket_mut::<raw::Socket, _, _>(self.handle, |socket| {
+            Ok(PollState {
+                readable: socket.can_recv(),
+                writable: socket.can_send(),
+            })
+        })
+    }
+}
+
+/// Private methods
+impl RawSocket {
+    fn block_on<F, T>(&self, mut f: F) -> AxResult<T>
+    where
+        F: FnMut() -> AxResult<T>,
+    {
+        if self.is_nonblocking() {
+            f()
+        } else {
+            loop {
+                SOCKET_SET.poll_interfaces();
+                match f() {
+                    Ok(t) => return Ok(t),
+                    Err(AxError::WouldBlock) => axtask::yield_now(),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        SOCKET_SET.remove(self.handle);
+    }
+}
+
+fn interface_ipv4_addr() -> AxResult<Ipv4Address> {
+    ETH0.iface
+        .lock()
+        .ip_addrs()
+        .iter()
+        .find_map(|cidr| match cidr {
+            IpCidr::Ipv4(cidr) => Some(cidr.address()),
+        })
+        .ok_or_else(|| ax_err_type!(NotConnected, "no IPv4 address on the interface"))
+}
@@ -271,6 +271,26 @@ impl TcpSocket {
 
     /// Receives data from the socket, stores it in the given buffer.
     pub fn recv(&self, buf: &mut [u8]) -> AxResult<usize> {
+        let mut read = 0;
+        self.recv_with(|data| {
+            let len = data.len().min(buf.len() - read);
+            buf[read..read + len].copy_from_slice(&data[..len]);
+            read += len;
+            len
+        })
+    }
+
+    /// Receives data from the socket without copying it into an intermediate
+    /// buffer.
+    ///
+    /// `f` is called with the data in the receive buffer, and returns how many
+    /// bytes it consumed. As the receive buffer is a ring, `f` may be called
+    /// twice if the data wraps around its end. Returns the total number of bytes
+    /// consumed, or 0 if the connection is closed by the peer.
+    pub fn recv_with<F>(&self, mut f: F) -> AxResult<usize>
+    where
+        F: FnMut(&[u8]) -> usize,
+    {
         if self.is_connecting() {
             return Err(AxError::WouldBlock);
         } else if !self.is_connected() {
@@ -289,11 +309,20 @@ impl TcpSocket {
                     Ok(0)
                 } else if socket.recv_queue() > 0 {
                     // data available
-                    // TODO: use socket.recv(|buf| {...})
-                    let len = socket
-                        .recv_slice(buf)
-                        .map_err(|_| ax_err_type!(BadState, "socket recv() failed"))?;
-                    Ok(len)
+                    let mut total = 0;
+                    while socket.recv_queue() > 0 {
+                        let (len, partial) = socket
+                            .recv(|data| {
+                                let len = f(data).min(data.len());
+                                (len, (len, len < data.len()))
+                            })
+                            .map_err(|_| ax_err_type!(BadState, "socket recv() failed"))?;
+                        total += len;
+                        if partial || len == 0 {
+                            break;
+                        }
+                    }
+                    Ok(total)
                 } else {
                     // no more data
                     Err(AxError::WouldBlock)
@@ -304,6 +333,26 @@ impl TcpSocket {
 
     /// Transmits data in the given buffer.
     pub fn send(&self, buf: &[u8]) -> AxResult<usize> {
+        let mut written = 0;
+        self.send_with(|free| {
+            let len = free.len().min(buf.len() - written);
+            free[..len].copy_from_slice(&buf[written..written + len]);
+            written += len;
+            len
+        })
+    }
+
+    /// Transmits data by filling the transmit buffer in place, without an
+    /// intermediate buffer.
+    ///
+    /// `f` is called with the free space in the transmit buffer, writes the data
+    /// to send into it, and returns how many bytes it wrote. As the transmit
+    /// buffer is a ring, `f` may be called twice if the free space wraps around
+    /// its end. Returns the total number of bytes written.
+    pub fn send_with<F>(&self, mut f: F) -> AxResult<usize>
+    where
+        F: FnMut(&mut [u8]) -> usize,
+    {
         if self.is_connecting() {
             return Err(AxError::WouldBlock);
         } else if !self.is_connected() {
@@ -319,11 +368,20 @@ impl TcpSocket {
                     ax_err!(ConnectionReset, "socket send() failed")
                 } else if socket.can_send() {
                     // connected, and the tx buffer is not full
-                    // TODO: use socket.send(|buf| {...})
-                    let len = socket
-                        .send_slice(buf)
-                        .map_err(|_| ax_err_type!(BadState, "socket send() failed"))?;
-                    Ok(len)
+                    let mut total = 0;
+                    while socket.can_send() {
+                        let (len, partial) = socket
+                            .send(|free| {
+                                let len = f(free).min(free.len());
+                                (len, (len, len < free.len()))
+                            })
+                            .map_err(|_| ax_err_type!(BadState, "socket send() failed"))?;
+                        total += len;
+                        if partial || len == 0 {
+                            break;
+                        }
+                    }
+                    Ok(total)
                 } else {
                     // tx buffer is full
                     Err(AxError::WouldBlock)
@@ -344,6 +402,20 @@ impl TcpSocket {
             }),
         }
     }
+
+    /// Returns whether the peer has shut down its sending half, and whether
+    /// the connection is closed in both directions.
+    pub fn poll_hup(&self) -> AxResult<(bool, bool)> {
+        if !self.is_connected() {
+            return Ok((false, false));
+        }
+        // SAFETY: `self.handle` should be initialized in a connected socket.
+        let handle = unsafe { self.handle.get().read().unwrap() };
+        SOCKET_SET.with_socket::<tcp::Socket, _, _>(handle, |socket| {
+            let read_hup = !socket.may_recv();
+            Ok((read_hup, read_hup && !socket.may_send()))
+        })
+    }
 }
 
 /// Private methods
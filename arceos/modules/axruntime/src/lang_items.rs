@@ -3,5 +3,6 @@ use core::panic::PanicInfo;
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     error!("{}", info);
+    error!("{}", axhal::backtrace::Backtrace::for_panic());
     axhal::misc::terminate()
 }
@@ -83,6 +83,49 @@ impl axlog::LogIf for LogIfImpl {
     }
 }
 
+#[cfg(any(feature = "alloc-tracking", feature = "alloc-debug"))]
+struct TrackIfImpl;
+
+#[cfg(any(feature = "alloc-tracking", feature = "alloc-debug"))]
+#[crate_interface::impl_interface]
+impl axalloc::TrackIf for TrackIfImpl {
+    fn alloc_site() -> usize {
+        // Skip the frames in the allocator, up to the first caller outside.
+        const INTERNAL: &[&str] = &[
+            "axalloc::",
+            "axruntime::",
+            "axhal::backtrace::",
+            "alloc::alloc::",
+            "alloc::raw_vec::",
+            "core::",
+            "__rust_",
+            "__rg_",
+        ];
+        let mut site = 0;
+        for frame in axhal::backtrace::Backtrace::capture().frames() {
+            if site == 0 {
+                site = frame.pc;
+            }
+            match frame.symbolize() {
+                Some((name, _)) => {
+                    let name = name.trim_start_matches('<');
+                    if !INTERNAL.iter().any(|p| name.starts_with(p)) {
+                        return frame.pc;
+                    }
+                }
+                // Without the symbol table, use the innermost frame, which is
+                // in the allocator. `init_allocator` warns about it.
+                None => break,
+            }
+        }
+        site
+    }
+
+    fn symbolize(addr: usize) -> Option<(&'static str, usize)> {
+        axhal::backtrace::symbolize(addr)
+    }
+}
+
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 static INITED_CPUS: AtomicUsize = AtomicUsize::new(0);
@@ -192,6 +235,9 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
 
     unsafe { main() };
 
+    #[cfg(feature = "alloc-tracking")]
+    ax_println!("{}", axalloc::tracking::report());
+
     #[cfg(feature = "multitask")]
     axtask::exit(0);
     #[cfg(not(feature = "multitask"))]
@@ -207,6 +253,12 @@ fn init_allocator() {
 
     info!("Initialize global memory allocator...");
     info!("  use {} allocator.", axalloc::global_allocator().name());
+    #[cfg(feature = "alloc-tracking")]
+    if axhal::backtrace::symbolize(init_allocator as usize).is_none() {
+        warn!(
+            "No symbol table is embedded, allocation sites are not found, build with BACKTRACE=y"
+        );
+    }
 
     let mut max_region_size = 0;
     let mut max_region_paddr = 0.into();
@@ -224,8 +276,10 @@ fn init_allocator() {
     }
     for r in memory_regions() {
         if r.flags.contains(MemRegionFlags::FREE) && r.paddr != max_region_paddr {
-            axalloc::global_add_memory(phys_to_virt(r.paddr).as_usize(), r.size)
-                .expect("add heap memory region failed");
+            // Regions too small to track their own pages are rejected.
+            if let Err(e) = axalloc::global_add_memory(phys_to_virt(r.paddr).as_usize(), r.size) {
+                warn!("failed to add memory region {:#x?}: {:?}", r, e);
+            }
         }
     }
 }
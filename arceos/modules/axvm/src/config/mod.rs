@@ -0,0 +1,452 @@
+//! VM configuration, parsed at runtime from TOML files such as:
+//!
+//! ```toml
+//! name = "linux"
+//! vcpus = 2
+//!
+//! [image]
+//! kind = "linux"                  # "linux" (`Image` header) or "raw"
+//! kernel = "/vm/linux/Image"
+//! initrd = "/vm/linux/initrd.img" # optional
+//! cmdline = "console=hvc0 earlycon=sbi"
+//! # dtb = "/vm/linux/guest.dtb"   # optional, generated if absent
+//! # dtb_load_addr = 0x8fe0_0000   # optional, the last 2 MiB of RAM by default
+//!
+//! [[memory]]
+//! base = 0x8000_0000
+//! size = 0x1000_0000
+//! flags = "rwx"                   # optional, "rwx" by default
+//!
+//! [[devices]]
+//! type = "plic"
+//! base = 0x0c00_0000
+//! num_sources = 96
+//!
+//! [[devices]]
+//! type = "virtio-blk"
+//! base = 0x1000_1000
+//! irq = 1
+//! path = "/vm/linux/rootfs.img"
+//! writable = true
+//!
+//! [[devices]]
+//! type = "virtio-console"
+//! base = 0x1000_2000
+//! irq = 2
+//!
+//! [[devices]]
+//! type = "virtio-balloon"
+//! base = 0x1000_3000
+//! irq = 3
+//!
+//! [[passthrough]]
+//! base = 0x1010_0000
+//! size = 0x1000
+//! # host_base = 0x1010_0000     # optional, same as `base` by default
+//! ```
+//!
+//! Exactly one `[[memory]]` region and at most one PLIC are allowed, the
+//! `irq` of devices must be less than `num_sources` of the PLIC, and the
+//! memory, device and passthrough regions must not overlap.
+//!
+//! A `raw` image is loaded at `load_addr` and entered at `entry` (defaults to
+//! `load_addr`) with `a0` = hart ID and `a1` = address of the DTB, if any.
+
+mod toml;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use axhal::paging::MappingFlags;
+
+use self::toml::{Table, Value};
+
+/// Size of the MMIO region of a virtio-mmio device, if not specified.
+const VIRTIO_MMIO_SIZE: usize = 0x1000;
+
+/// How the guest image is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    /// A RISC-V Linux `Image`, loaded as specified in its header.
+    Linux,
+    /// A raw binary, loaded at `load_addr`.
+    Raw,
+}
+
+/// Configuration of the guest images.
+#[derive(Debug, Clone)]
+pub struct ImageConfig {
+    pub kind: ImageKind,
+    /// Path of the kernel image in the host file system.
+    pub kernel: String,
+    /// Load address of a raw image.
+    pub load_addr: Option<usize>,
+    /// Entry of a raw image, defaults to `load_addr`.
+    pub entry: Option<usize>,
+    /// Path of a prebuilt device tree, or `None` to generate one.
+    pub dtb: Option<String>,
+    pub dtb_load_addr: Option<usize>,
+    pub initrd: Option<String>,
+    pub cmdline: String,
+}
+
+/// A region of guest RAM.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub base: usize,
+    pub size: usize,
+    pub flags: MappingFlags,
+}
+
+/// Type of an emulated device.
+#[derive(Debug, Clone)]
+pub enum EmulatedDeviceKind {
+    Plic { num_sources: usize },
+    VirtioBlk { path: String, writable: bool },
+    VirtioConsole,
+    VirtioBalloon,
+}
+
+/// An emulated device in the guest physical address space.
+#[derive(Debug, Clone)]
+pub struct EmulatedDevice {
+    pub kind: EmulatedDeviceKind,
+    pub base: usize,
+    pub size: usize,
+    /// The PLIC source of the interrupt, 0 for none.
+    pub irq: u32,
+}
+
+/// A host device region mapped into the guest directly.
+#[derive(Debug, Clone, Copy)]
+pub struct PassthroughDevice {
+    pub base: usize,
+    pub host_base: usize,
+    pub size: usize,
+}
+
+/// Configuration of a VM.
+#[derive(Debug, Clone)]
+pub struct VmConfig {
+    pub name: String,
+    pub vcpus: usize,
+    pub image: ImageConfig,
+    pub memory: Vec<MemoryRegion>,
+    pub devices: Vec<EmulatedDevice>,
+    pub passthrough: Vec<PassthroughDevice>,
+}
+
+fn invalid<T>(key: &str) -> AxResult<T> {
+    warn!("VM config: missing or invalid `{}`", key);
+    ax_err!(InvalidInput, "invalid VM config")
+}
+
+fn opt_usize(table: &Table, key: &str) -> AxResult<Option<usize>> {
+    match toml::get(table, key) {
+        None => Ok(None),
+        Some(v) => match v.as_integer() {
+            Some(i) if i >= 0 => Ok(Some(i as usize)),
+            _ => invalid(key),
+        },
+    }
+}
+
+fn req_usize(table: &Table, key: &str) -> AxResult<usize> {
+    opt_usize(table, key)?.map_or_else(|| invalid(key), Ok)
+}
+
+fn opt_str(table: &Table, key: &str) -> AxResult<Option<String>> {
+    match toml::get(table, key) {
+        None => Ok(None),
+        Some(v) => v
+            .as_str()
+            .map_or_else(|| invalid(key), |s| Ok(Some(s.to_string()))),
+    }
+}
+
+fn req_str(table: &Table, key: &str) -> AxResult<String> {
+    opt_str(table, key)?.map_or_else(|| invalid(key), Ok)
+}
+
+/// Returns the tables of an array of tables, or an empty list if absent.
+fn tables<'a>(table: &'a Table, key: &str) -> AxResult<Vec<&'a Table>> {
+    match toml::get(table, key) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|v| v.as_table().map_or_else(|| invalid(key), Ok))
+            .collect(),
+        Some(_) => invalid(key),
+    }
+}
+
+fn parse_flags(flags: &str) -> AxResult<MappingFlags> {
+    let mut ret = MappingFlags::USER;
+    for c in flags.chars() {
+        ret |= match c {
+            'r' => MappingFlags::READ,
+            'w' => MappingFlags::WRITE,
+            'x' => MappingFlags::EXECUTE,
+            _ => return invalid("flags"),
+        };
+    }
+    Ok(ret)
+}
+
+impl ImageConfig {
+    fn from_table(table: &Table) -> AxResult<Self> {
+        let kind = match opt_str(table, "kind")?.as_deref() {
+            None | Some("raw") => ImageKind::Raw,
+            Some("linux") => ImageKind::Linux,
+            Some(_) => return invalid("image.kind"),
+        };
+        let load_addr = opt_usize(table, "load_addr")?;
+        if kind == ImageKind::Raw && load_addr.is_none() {
+            return invalid("image.load_addr");
+        }
+        Ok(Self {
+            kind,
+            kernel: req_str(table, "kernel")?,
+            load_addr,
+            entry: opt_usize(table, "entry")?,
+            dtb: opt_str(table, "dtb")?,
+            dtb_load_addr: opt_usize(table, "dtb_load_addr")?,
+            initrd: opt_str(table, "initrd")?,
+            cmdline: opt_str(table, "cmdline")?.unwrap_or_default(),
+        })
+    }
+}
+
+impl EmulatedDevice {
+    fn from_table(table: &Table) -> AxResult<Self> {
+        let (kind, default_size) = match req_str(table, "type")?.as_str() {
+            "plic" => (
+                EmulatedDeviceKind::Plic {
+                    num_sources: opt_usize(table, "num_sources")?.unwrap_or(96),
+                },
+                axdevice::PLIC_MMIO_SIZE,
+            ),
+            "virtio-blk" => (
+                EmulatedDeviceKind::VirtioBlk {
+                    path: req_str(table, "path")?,
+                    writable: match toml::get(table, "writable") {
+                        None => false,
+                        Some(v) => v.as_bool().map_or_else(|| invalid("writable"), Ok)?,
+                    },
+                },
+                VIRTIO_MMIO_SIZE,
+            ),
+            "virtio-console" => (EmulatedDeviceKind::VirtioConsole, VIRTIO_MMIO_SIZE),
+            "virtio-balloon" => (EmulatedDeviceKind::VirtioBalloon, VIRTIO_MMIO_SIZE),
+            _ => return invalid("devices.type"),
+        };
+        Ok(Self {
+            kind,
+            base: req_usize(table, "base")?,
+            size: opt_usize(table, "size")?.unwrap_or(default_size),
+            irq: match opt_usize(table, "irq")?.map(u32::try_from) {
+                None => 0,
+                Some(Ok(irq)) => irq,
+                Some(Err(_)) => return invalid("irq"),
+            },
+        })
+    }
+}
+
+impl VmConfig {
+    /// Parses the configuration from a TOML string.
+    pub fn from_toml(src: &str) -> AxResult<Self> {
+        let root = toml::parse(src)?;
+
+        let image = match toml::get(&root, "image").and_then(Value::as_table) {
+            Some(table) => ImageConfig::from_table(table)?,
+            None => return invalid("image"),
+        };
+        let memory = tables(&root, "memory")?
+            .into_iter()
+            .map(|t| {
+                Ok(MemoryRegion {
+                    base: req_usize(t, "base")?,
+                    size: req_usize(t, "size")?,
+                    flags: parse_flags(opt_str(t, "flags")?.as_deref().unwrap_or("rwx"))?,
+                })
+            })
+            .collect::<AxResult<Vec<_>>>()?;
+        if memory.is_empty() {
+            return invalid("memory");
+        }
+        let devices = tables(&root, "devices")?
+            .into_iter()
+            .map(EmulatedDevice::from_table)
+            .collect::<AxResult<Vec<_>>>()?;
+        let passthrough = tables(&root, "passthrough")?
+            .into_iter()
+            .map(|t| {
+                let base = req_usize(t, "base")?;
+                Ok(PassthroughDevice {
+                    base,
+                    host_base: opt_usize(t, "host_base")?.unwrap_or(base),
+                    size: req_usize(t, "size")?,
+                })
+            })
+            .collect::<AxResult<Vec<_>>>()?;
+
+        let vcpus = opt_usize(&root, "vcpus")?.unwrap_or(1);
+        if vcpus == 0 {
+            return invalid("vcpus");
+        }
+        let config = Self {
+            name: opt_str(&root, "name")?.unwrap_or_else(|| "vm".to_string()),
+            vcpus,
+            image,
+            memory,
+            devices,
+            passthrough,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the devices and the regions of the guest physical address
+    /// space, so that building the VM neither panics nor fails halfway.
+    fn validate(&self) -> AxResult {
+        // The loader places the images and describes the RAM by one region.
+        if self.memory.len() != 1 {
+            warn!("VM config: exactly one `[[memory]]` region is supported");
+            return ax_err!(InvalidInput, "invalid VM config");
+        }
+
+        let mut num_sources = None;
+        for dev in &self.devices {
+            if let EmulatedDeviceKind::Plic { num_sources: n } = dev.kind {
+                if num_sources.replace(n).is_some() {
+                    warn!("VM config: more than one PLIC");
+                    return ax_err!(InvalidInput, "invalid VM config");
+                }
+                if !(2..=axdevice::PLIC_MAX_SOURCES).contains(&n) {
+                    return invalid("devices.num_sources");
+                }
+            }
+        }
+        if let Some(num_sources) = num_sources {
+            if self
+                .devices
+                .iter()
+                .any(|dev| dev.irq as usize >= num_sources)
+            {
+                warn!("VM config: `irq` must be less than `num_sources` of the PLIC");
+                return ax_err!(InvalidInput, "invalid VM config");
+            }
+        }
+
+        let regions: Vec<(usize, usize, &str)> = self
+            .memory
+            .iter()
+            .map(|r| (r.base, r.size, "memory"))
+            .chain(self.devices.iter().map(|d| (d.base, d.size, "devices")))
+            .chain(
+                self.passthrough
+                    .iter()
+                    .map(|d| (d.base, d.size, "passthrough")),
+            )
+            .collect();
+        for (i, &(base, size, key)) in regions.iter().enumerate() {
+            let end = match base.checked_add(size) {
+                Some(end) if size > 0 => end,
+                _ => return invalid(key),
+            };
+            let overlapped = regions[..i].iter().find(|&&(other_base, other_size, _)| {
+                base < other_base + other_size && other_base < end
+            });
+            if let Some(&(other_base, _, other_key)) = overlapped {
+                warn!(
+                    "VM config: `{}` region at {:#x} overlaps `{}` region at {:#x}",
+                    key, base, other_key, other_base
+                );
+                return ax_err!(InvalidInput, "invalid VM config");
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and parses the configuration file at `path` in the host file
+    /// system.
+    pub fn from_file(path: &str) -> AxResult<Self> {
+        Self::from_toml(&axfs::api::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"
+[image]
+kernel = "/vm/guest.bin"
+load_addr = 0x8020_0000
+
+[[memory]]
+base = 0x8000_0000
+size = 0x100_0000
+
+[[devices]]
+type = "plic"
+base = 0x0c00_0000
+num_sources = 32
+
+[[devices]]
+type = "virtio-console"
+base = 0x1000_1000
+irq = 31
+"#;
+
+    fn with(extra: &str) -> AxResult<VmConfig> {
+        VmConfig::from_toml(&alloc::format!("{}{}", BASE, extra))
+    }
+
+    #[test]
+    fn test_valid() {
+        let config = with("[[passthrough]]\nbase = 0x2200_0000\nsize = 0x200_0000\n").unwrap();
+        assert_eq!(config.name, "vm");
+        assert_eq!(config.vcpus, 1);
+        assert_eq!(config.image.kind, ImageKind::Raw);
+        assert_eq!(config.memory.len(), 1);
+        assert_eq!(config.devices.len(), 2);
+        assert_eq!(config.devices[0].size, axdevice::PLIC_MMIO_SIZE);
+        assert_eq!(config.devices[1].size, VIRTIO_MMIO_SIZE);
+        assert_eq!(config.devices[1].irq, 31);
+        assert_eq!(config.passthrough[0].host_base, 0x2200_0000);
+    }
+
+    #[test]
+    fn test_invalid_irq() {
+        assert!(
+            with("[[devices]]\ntype = \"virtio-balloon\"\nbase = 0x1000_2000\nirq = 32\n").is_err()
+        );
+        assert!(with(
+            "[[devices]]\ntype = \"virtio-balloon\"\nbase = 0x1000_2000\nirq = 0x1_0000_0001\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_multiple_plics() {
+        assert!(with("[[devices]]\ntype = \"plic\"\nbase = 0x2000_0000\n").is_err());
+    }
+
+    #[test]
+    fn test_multiple_memory_regions() {
+        assert!(with("[[memory]]\nbase = 0x9000_0000\nsize = 0x1000\n").is_err());
+    }
+
+    #[test]
+    fn test_overlapping_regions() {
+        // Device over device.
+        assert!(with("[[devices]]\ntype = \"virtio-balloon\"\nbase = 0x1000_1800\n").is_err());
+        // Passthrough over memory.
+        assert!(with("[[passthrough]]\nbase = 0x80ff_f000\nsize = 0x2000\n").is_err());
+        // Empty region.
+        assert!(with("[[passthrough]]\nbase = 0x2200_0000\nsize = 0\n").is_err());
+    }
+}
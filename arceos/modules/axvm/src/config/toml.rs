@@ -0,0 +1,513 @@
+//! A parser of the subset of [TOML](https://toml.io) used by VM configuration
+//! files.
+//!
+//! Supported: comments, bare and quoted keys, basic and literal strings,
+//! integers (decimal, `0x`, `0o`, `0b`, with `_` separators), booleans,
+//! arrays, `[table]` headers and `[[array-of-tables]]` headers. Dotted keys,
+//! inline tables, floats and date-times are not supported.
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+
+/// A TOML value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+/// A TOML table, keys are kept in the order of definition.
+pub type Table = Vec<(String, Value)>;
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+/// Looks up `key` in the table.
+pub fn get<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    table.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+fn get_mut<'a>(table: &'a mut Table, key: &str) -> Option<&'a mut Value> {
+    table.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// Parses a TOML document into its root table.
+pub fn parse(src: &str) -> AxResult<Table> {
+    Parser {
+        src: src.as_bytes(),
+        pos: 0,
+        line: 1,
+    }
+    .parse_document()
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, msg: &str) -> AxResult<T> {
+        warn!("toml: line {}: {}", self.line, msg);
+        ax_err!(InvalidData, "invalid TOML")
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == b'\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> AxResult {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.error("unexpected character")
+        }
+    }
+
+    /// Skips spaces and tabs.
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.bump();
+        }
+    }
+
+    /// Skips whitespace, newlines and comments.
+    fn skip_ws_lines(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b' ' | b'\t' | b'\r' | b'\n') => {
+                    self.bump();
+                }
+                Some(b'#') => self.skip_comment(),
+                _ => break,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while !matches!(self.peek(), None | Some(b'\n')) {
+            self.bump();
+        }
+    }
+
+    /// Expects the end of a line, possibly with a comment.
+    fn end_of_line(&mut self) -> AxResult {
+        self.skip_ws();
+        if self.peek() == Some(b'#') {
+            self.skip_comment();
+        }
+        self.eat(b'\r');
+        match self.peek() {
+            None => Ok(()),
+            Some(b'\n') => {
+                self.bump();
+                Ok(())
+            }
+            _ => self.error("expected a newline"),
+        }
+    }
+
+    fn parse_document(&mut self) -> AxResult<Table> {
+        let mut root = Table::new();
+        // Path of the current table: (key, is an array of tables).
+        let mut current: Option<(String, bool)> = None;
+        loop {
+            self.skip_ws_lines();
+            match self.peek() {
+                None => break,
+                Some(b'[') => {
+                    self.bump();
+                    let is_array = self.eat(b'[');
+                    self.skip_ws();
+                    let key = self.parse_key()?;
+                    self.skip_ws();
+                    self.expect(b']')?;
+                    if is_array {
+                        self.expect(b']')?;
+                    }
+                    self.end_of_line()?;
+                    self.open_table(&mut root, &key, is_array)?;
+                    current = Some((key, is_array));
+                }
+                Some(_) => {
+                    let key = self.parse_key()?;
+                    self.skip_ws();
+                    self.expect(b'=')?;
+                    self.skip_ws();
+                    let value = self.parse_value()?;
+                    self.end_of_line()?;
+                    let table = match &current {
+                        None => &mut root,
+                        Some((name, is_array)) => Self::current_table(&mut root, name, *is_array),
+                    };
+                    if get(table, &key).is_some() {
+                        return self.error("duplicate key");
+                    }
+                    table.push((key, value));
+                }
+            }
+        }
+        Ok(root)
+    }
+
+    fn open_table(&self, root: &mut Table, key: &str, is_array: bool) -> AxResult {
+        match (get_mut(root, key), is_array) {
+            (None, false) => root.push((key.to_owned(), Value::Table(Table::new()))),
+            (None, true) => root.push((
+                key.to_owned(),
+                Value::Array(alloc::vec![Value::Table(Table::new())]),
+            )),
+            (Some(Value::Array(tables)), true)
+                if tables.iter().all(|t| matches!(t, Value::Table(_))) =>
+            {
+                tables.push(Value::Table(Table::new()))
+            }
+            _ => return self.error("table redefined"),
+        }
+        Ok(())
+    }
+
+    fn current_table<'t>(root: &'t mut Table, key: &str, is_array: bool) -> &'t mut Table {
+        let value = get_mut(root, key).unwrap();
+        let value = if is_array {
+            match value {
+                Value::Array(tables) => tables.last_mut().unwrap(),
+                _ => unreachable!(),
+            }
+        } else {
+            value
+        };
+        match value {
+            Value::Table(table) => table,
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_key(&mut self) -> AxResult<String> {
+        match self.peek() {
+            Some(b'"') => self.parse_basic_string(),
+            Some(b'\'') => self.parse_literal_string(),
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == b'_' || c == b'-')
+                {
+                    self.bump();
+                }
+                if self.pos == start {
+                    return self.error("expected a key");
+                }
+                Ok(String::from_utf8_lossy(&self.src[start..self.pos]).into_owned())
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> AxResult<Value> {
+        match self.peek() {
+            Some(b'"') => Ok(Value::String(self.parse_basic_string()?)),
+            Some(b'\'') => Ok(Value::String(self.parse_literal_string()?)),
+            Some(b'[') => self.parse_array(),
+            Some(b't' | b'f') => {
+                if self.src[self.pos..].starts_with(b"true") {
+                    self.pos += 4;
+                    Ok(Value::Boolean(true))
+                } else if self.src[self.pos..].starts_with(b"false") {
+                    self.pos += 5;
+                    Ok(Value::Boolean(false))
+                } else {
+                    self.error("invalid value")
+                }
+            }
+            Some(b'{') => self.error("inline tables are not supported"),
+            Some(_) => self.parse_integer(),
+            None => self.error("expected a value"),
+        }
+    }
+
+    fn parse_basic_string(&mut self) -> AxResult<String> {
+        self.expect(b'"')?;
+        let mut buf = Vec::new();
+        loop {
+            match self.bump() {
+                None | Some(b'\n') => return self.error("unterminated string"),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let c = match self.bump() {
+                        Some(b'n') => b'\n',
+                        Some(b't') => b'\t',
+                        Some(b'r') => b'\r',
+                        Some(b'"') => b'"',
+                        Some(b'\\') => b'\\',
+                        _ => return self.error("unsupported escape sequence"),
+                    };
+                    buf.push(c);
+                }
+                Some(c) => buf.push(c),
+            }
+        }
+        String::from_utf8(buf).or_else(|_| self.error("invalid UTF-8"))
+    }
+
+    fn parse_literal_string(&mut self) -> AxResult<String> {
+        self.expect(b'\'')?;
+        let start = self.pos;
+        loop {
+            match self.bump() {
+                None | Some(b'\n') => return self.error("unterminated string"),
+                Some(b'\'') => break,
+                Some(_) => {}
+            }
+        }
+        let s = &self.src[start..self.pos - 1];
+        String::from_utf8(s.to_vec()).or_else(|_| self.error("invalid UTF-8"))
+    }
+
+    fn parse_array(&mut self) -> AxResult<Value> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws_lines();
+            if self.eat(b']') {
+                break;
+            }
+            items.push(self.parse_value()?);
+            self.skip_ws_lines();
+            if !self.eat(b',') {
+                self.skip_ws_lines();
+                self.expect(b']')?;
+                break;
+            }
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn parse_integer(&mut self) -> AxResult<Value> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || matches!(c, b'_' | b'+' | b'-'))
+        {
+            self.bump();
+        }
+        let text: String = self.src[start..self.pos]
+            .iter()
+            .filter(|&&c| c != b'_')
+            .map(|&c| c as char)
+            .collect();
+        let (neg, digits) = match text.as_bytes().first() {
+            Some(b'-') => (true, &text[1..]),
+            Some(b'+') => (false, &text[1..]),
+            _ => (false, &text[..]),
+        };
+        let (radix, digits) = match digits.get(..2) {
+            Some("0x") => (16, &digits[2..]),
+            Some("0o") => (8, &digits[2..]),
+            Some("0b") => (2, &digits[2..]),
+            _ => (10, digits),
+        };
+        match i64::from_str_radix(digits, radix) {
+            Ok(val) if !digits.is_empty() && !digits.starts_with(['+', '-']) => {
+                Ok(Value::Integer(if neg { -val } else { val }))
+            }
+            _ => self.error("invalid value"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_owned())
+    }
+
+    #[test]
+    fn test_tables() {
+        let root = parse(
+            "# comment\n\
+             name = \"vm\" # trailing comment\n\
+             \n\
+             [image]\n\
+             kind = 'linux'\n\
+             \"quoted key\" = true\n\
+             \n\
+             [empty]\r\n",
+        )
+        .unwrap();
+        assert_eq!(get(&root, "name"), Some(&string("vm")));
+        let image = get(&root, "image").and_then(Value::as_table).unwrap();
+        assert_eq!(get(image, "kind").and_then(Value::as_str), Some("linux"));
+        assert_eq!(
+            get(image, "quoted key").and_then(Value::as_bool),
+            Some(true)
+        );
+        assert_eq!(get(&root, "empty"), Some(&Value::Table(Table::new())));
+        assert_eq!(get(&root, "kind"), None);
+    }
+
+    #[test]
+    fn test_array_of_tables() {
+        let root = parse(
+            "[[devices]]\n\
+             type = \"plic\"\n\
+             [[devices]]\n\
+             type = \"virtio-blk\"\n\
+             irq = 1\n\
+             [other]\n\
+             a = 1\n\
+             [[devices]]\n",
+        )
+        .unwrap();
+        let devices = match get(&root, "devices") {
+            Some(Value::Array(devices)) => devices,
+            _ => panic!("not an array"),
+        };
+        assert_eq!(devices.len(), 3);
+        let dev = devices[1].as_table().unwrap();
+        assert_eq!(get(dev, "type").and_then(Value::as_str), Some("virtio-blk"));
+        assert_eq!(get(dev, "irq").and_then(Value::as_integer), Some(1));
+        assert_eq!(devices[2], Value::Table(Table::new()));
+        let other = get(&root, "other").and_then(Value::as_table).unwrap();
+        assert_eq!(get(other, "a").and_then(Value::as_integer), Some(1));
+    }
+
+    #[test]
+    fn test_integers() {
+        let root = parse(
+            "a = 42\n\
+             b = -17\n\
+             c = +3\n\
+             d = 0x8000_0000\n\
+             e = 0o755\n\
+             f = 0b1010\n\
+             g = 1_000_000\n\
+             h = 0xdead_BEEF\n",
+        )
+        .unwrap();
+        let ints: Vec<i64> = root.iter().map(|(_, v)| v.as_integer().unwrap()).collect();
+        assert_eq!(
+            ints,
+            [
+                42,
+                -17,
+                3,
+                0x8000_0000,
+                0o755,
+                0b1010,
+                1_000_000,
+                0xdead_beef
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let root = parse(
+            r#"a = "tab\there \"quoted\" back\\slash\r\n"
+b = 'C:\path\no "escapes"'
+c = ""
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            get(&root, "a"),
+            Some(&string("tab\there \"quoted\" back\\slash\r\n"))
+        );
+        assert_eq!(get(&root, "b"), Some(&string(r#"C:\path\no "escapes""#)));
+        assert_eq!(get(&root, "c"), Some(&string("")));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let root = parse("a = [1, \"two\", [true],\n  # comment\n  false,\n]\nb = []\n").unwrap();
+        assert_eq!(
+            get(&root, "a"),
+            Some(&Value::Array(alloc::vec![
+                Value::Integer(1),
+                string("two"),
+                Value::Array(alloc::vec![Value::Boolean(true)]),
+                Value::Boolean(false),
+            ]))
+        );
+        assert_eq!(get(&root, "b"), Some(&Value::Array(Vec::new())));
+    }
+
+    #[test]
+    fn test_errors() {
+        for src in [
+            "a = 1\na = 2\n",
+            "[t]\n[t]\n",
+            "t = 1\n[[t]]\n",
+            "[[t]]\n[t]\n",
+            "a = \"unterminated\n",
+            "a = 'unterminated\n",
+            "a = \"\\x41\"\n",
+            "a = 1 b = 2\n",
+            "a = 0x\n",
+            "a = 12ab\n",
+            "a = --1\n",
+            "a = 99999999999999999999\n",
+            "a = 1.5\n",
+            "a = {b = 1}\n",
+            "a = truth\n",
+            "a = [1, 2\n",
+            "a =\n",
+            "= 1\n",
+            "a.b = 1\n",
+            "[t\n",
+            "[[t]\n",
+        ] {
+            assert!(parse(src).is_err(), "{:?} should be rejected", src);
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! A minimal writer of flattened device trees, see the [Devicetree
+//! Specification](https://www.devicetree.org/specifications/), chapter 5.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+
+/// Size of the header.
+const HEADER_SIZE: usize = 40;
+/// Size of the memory reservation block, which has only the terminator.
+const RSVMAP_SIZE: usize = 16;
+
+/// Builds a flattened device tree blob.
+///
+/// Nodes are written in order with [`begin_node`] and [`end_node`], the
+/// properties of a node must be written before its subnodes.
+///
+/// [`begin_node`]: FdtBuilder::begin_node
+/// [`end_node`]: FdtBuilder::end_node
+#[derive(Default)]
+pub struct FdtBuilder {
+    structs: Vec<u8>,
+    strings: Vec<u8>,
+    /// Offsets of the property names in `strings`.
+    string_offsets: Vec<(String, u32)>,
+    depth: usize,
+    next_phandle: u32,
+}
+
+impl FdtBuilder {
+    /// Creates an empty device tree.
+    pub fn new() -> Self {
+        Self {
+            next_phandle: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Allocates a new phandle for a node, which should be written to the
+    /// node with [`property_u32`](Self::property_u32)`("phandle", ..)`.
+    pub fn alloc_phandle(&mut self) -> u32 {
+        let phandle = self.next_phandle;
+        self.next_phandle += 1;
+        phandle
+    }
+
+    fn push_u32(&mut self, val: u32) {
+        self.structs.extend_from_slice(&val.to_be_bytes());
+    }
+
+    fn align(&mut self) {
+        while self.structs.len() % 4 != 0 {
+            self.structs.push(0);
+        }
+    }
+
+    fn string_offset(&mut self, name: &str) -> u32 {
+        if let Some((_, off)) = self.string_offsets.iter().find(|(n, _)| n == name) {
+            return *off;
+        }
+        let off = self.strings.len() as u32;
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        self.string_offsets.push((String::from(name), off));
+        off
+    }
+
+    /// Begins a node, the root node has an empty name.
+    pub fn begin_node(&mut self, name: &str) {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structs.extend_from_slice(name.as_bytes());
+        self.structs.push(0);
+        self.align();
+        self.depth += 1;
+    }
+
+    /// Ends the last begun node.
+    pub fn end_node(&mut self) {
+        assert!(self.depth > 0, "fdt: unbalanced end_node");
+        self.push_u32(FDT_END_NODE);
+        self.depth -= 1;
+    }
+
+    /// Writes a property with the raw value.
+    pub fn property(&mut self, name: &str, val: &[u8]) {
+        let nameoff = self.string_offset(name);
+        self.push_u32(FDT_PROP);
+        self.push_u32(val.len() as u32);
+        self.push_u32(nameoff);
+        self.structs.extend_from_slice(val);
+        self.align();
+    }
+
+    /// Writes a property with an empty value, e.g., `interrupt-controller`.
+    pub fn property_null(&mut self, name: &str) {
+        self.property(name, &[]);
+    }
+
+    /// Writes a property with a NUL-terminated string.
+    pub fn property_string(&mut self, name: &str, val: &str) {
+        self.property_string_list(name, &[val]);
+    }
+
+    /// Writes a property with a list of NUL-terminated strings.
+    pub fn property_string_list(&mut self, name: &str, vals: &[&str]) {
+        let mut buf = Vec::new();
+        for val in vals {
+            buf.extend_from_slice(val.as_bytes());
+            buf.push(0);
+        }
+        self.property(name, &buf);
+    }
+
+    /// Writes a property with a single cell.
+    pub fn property_u32(&mut self, name: &str, val: u32) {
+        self.property(name, &val.to_be_bytes());
+    }
+
+    /// Writes a property with a 64-bit value, i.e., two cells.
+    pub fn property_u64(&mut self, name: &str, val: u64) {
+        self.property(name, &val.to_be_bytes());
+    }
+
+    /// Writes a property with a list of cells.
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) {
+        let buf: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.property(name, &buf);
+    }
+
+    /// Writes a `reg` property of (address, size) pairs, with 2 address cells
+    /// and 2 size cells.
+    pub fn property_reg(&mut self, regs: &[(u64, u64)]) {
+        let buf: Vec<u8> = regs
+            .iter()
+            .flat_map(|&(addr, size)| [addr.to_be_bytes(), size.to_be_bytes()])
+            .flatten()
+            .collect();
+        self.property("reg", &buf);
+    }
+
+    /// Finishes the device tree, returns the blob.
+    pub fn finish(mut self, boot_cpuid: u32) -> Vec<u8> {
+        assert!(self.depth == 0, "fdt: unclosed nodes");
+        self.push_u32(FDT_END);
+
+        let off_mem_rsvmap = HEADER_SIZE;
+        let off_dt_struct = off_mem_rsvmap + RSVMAP_SIZE;
+        let off_dt_strings = off_dt_struct + self.structs.len();
+        let totalsize = off_dt_strings + self.strings.len();
+
+        let mut blob = Vec::with_capacity(totalsize);
+        for val in [
+            FDT_MAGIC,
+            totalsize as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            boot_cpuid,
+            self.strings.len() as u32,
+            self.structs.len() as u32,
+        ] {
+            blob.extend_from_slice(&val.to_be_bytes());
+        }
+        blob.extend_from_slice(&[0; RSVMAP_SIZE]);
+        blob.extend_from_slice(&self.structs);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be32(blob: &[u8], off: usize) -> u32 {
+        u32::from_be_bytes(blob[off..off + 4].try_into().unwrap())
+    }
+
+    fn string_at(strings: &[u8], off: usize) -> &str {
+        let len = strings[off..].iter().position(|&b| b == 0).unwrap();
+        core::str::from_utf8(&strings[off..off + len]).unwrap()
+    }
+
+    fn build() -> Vec<u8> {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("");
+        fdt.property_u32("#address-cells", 2);
+        fdt.begin_node("chosen");
+        fdt.property_string("bootargs", "console=ttyS0");
+        fdt.property_u32("#address-cells", 1);
+        fdt.end_node();
+        fdt.end_node();
+        fdt.finish(3)
+    }
+
+    #[test]
+    fn test_header() {
+        let blob = build();
+        let off_dt_struct = be32(&blob, 8) as usize;
+        let off_dt_strings = be32(&blob, 12) as usize;
+        let size_dt_strings = be32(&blob, 32) as usize;
+        let size_dt_struct = be32(&blob, 36) as usize;
+
+        assert_eq!(be32(&blob, 0), FDT_MAGIC);
+        assert_eq!(be32(&blob, 4) as usize, blob.len());
+        assert_eq!(be32(&blob, 16) as usize, HEADER_SIZE);
+        assert_eq!(be32(&blob, 20), FDT_VERSION);
+        assert_eq!(be32(&blob, 24), FDT_LAST_COMP_VERSION);
+        assert_eq!(be32(&blob, 28), 3);
+        assert_eq!(off_dt_struct, HEADER_SIZE + RSVMAP_SIZE);
+        assert_eq!(off_dt_strings, off_dt_struct + size_dt_struct);
+        assert_eq!(off_dt_strings + size_dt_strings, blob.len());
+        assert_eq!(off_dt_struct % 4, 0);
+        assert!(blob[HEADER_SIZE..off_dt_struct].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_strings() {
+        let blob = build();
+        let off_dt_strings = be32(&blob, 12) as usize;
+        // `#address-cells` is written twice, but stored once.
+        assert_eq!(&blob[off_dt_strings..], b"#address-cells\0bootargs\0");
+    }
+
+    #[test]
+    fn test_struct_block() {
+        let blob = build();
+        let off_dt_struct = be32(&blob, 8) as usize;
+        let off_dt_strings = be32(&blob, 12) as usize;
+        let strings = &blob[off_dt_strings..];
+
+        let mut off = off_dt_struct;
+        let mut tokens = Vec::new();
+        loop {
+            let token = be32(&blob, off);
+            off += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = string_at(&blob, off);
+                    tokens.push(alloc::format!("begin {:?}", name));
+                    off += (name.len() + 1).next_multiple_of(4);
+                }
+                FDT_PROP => {
+                    let len = be32(&blob, off) as usize;
+                    let name = string_at(strings, be32(&blob, off + 4) as usize);
+                    let val = blob[off + 8..off + 8 + len].escape_ascii();
+                    tokens.push(alloc::format!("prop {} {}", name, val));
+                    off += 8 + len.next_multiple_of(4);
+                }
+                FDT_END_NODE => tokens.push(String::from("end")),
+                FDT_END => break,
+                _ => panic!("invalid token {:#x} at {:#x}", token, off - 4),
+            }
+            assert_eq!(off % 4, 0);
+        }
+        assert_eq!(off, off_dt_strings);
+        assert_eq!(
+            tokens,
+            [
+                "begin \"\"",
+                r"prop #address-cells \x00\x00\x00\x02",
+                "begin \"chosen\"",
+                r"prop bootargs console=ttyS0\x00",
+                r"prop #address-cells \x00\x00\x00\x01",
+                "end",
+                "end",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_phandle() {
+        let mut fdt = FdtBuilder::new();
+        assert_eq!(fdt.alloc_phandle(), 1);
+        assert_eq!(fdt.alloc_phandle(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unclosed_node() {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("");
+        fdt.finish(0);
+    }
+}
@@ -0,0 +1,192 @@
+//! Debugging of guests with GDB, the threads seen by GDB are the vCPUs.
+//!
+//! Guest memory is accessed at guest virtual addresses, translated by the
+//! guest page table of the selected vCPU (`vsatp`) and then by the nested page
+//! table. Breakpoints in the guest exit to the hypervisor while the VM is
+//! debugged.
+
+use core::sync::atomic::Ordering;
+
+use axerrno::{ax_err, AxError, AxResult};
+use axgdb::{Action, Connection, GdbStub, Registers, Target, PC_REG, SIGINT, SIGTRAP};
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+use riscv_vcpu::GprIndex;
+
+use crate::loader::write_guest;
+use crate::Vm;
+
+const SATP_MODE_BARE: usize = 0;
+const SATP_MODE_SV39: usize = 8;
+const SATP_MODE_SV48: usize = 9;
+
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_X: u64 = 1 << 3;
+const PTE_PPN_MASK: u64 = (1 << 44) - 1;
+
+/// A paused VM as a GDB target.
+struct GuestTarget<'a> {
+    vm: &'a Vm,
+}
+
+impl GuestTarget<'_> {
+    /// Translates the guest virtual address `gva` with the guest page table of
+    /// the vCPU `tid`, returns the guest physical address.
+    fn translate(&self, tid: usize, gva: usize) -> AxResult<usize> {
+        let satp = self.vm.vcpus[tid].lock().guest_satp();
+        let levels = match satp >> 60 {
+            SATP_MODE_BARE => return Ok(gva),
+            SATP_MODE_SV39 => 3,
+            SATP_MODE_SV48 => 4,
+            _ => return ax_err!(Unsupported, "unsupported guest paging mode"),
+        };
+        let aspace = self.vm.aspace.lock();
+        let mut table = (satp as u64 & PTE_PPN_MASK) << 12;
+        for level in (0..levels).rev() {
+            let shift = 12 + 9 * level;
+            let vpn = (gva >> shift) & 0x1ff;
+            let mut pte = [0; 8];
+            aspace.read(VirtAddr::from(table as usize + vpn * 8), &mut pte)?;
+            let pte = u64::from_le_bytes(pte);
+            if pte & PTE_V == 0 {
+                return Err(AxError::BadAddress);
+            }
+            let paddr = ((pte >> 10) & PTE_PPN_MASK) << 12;
+            if pte & (PTE_R | PTE_X) != 0 {
+                let offset_mask = (1 << shift) - 1;
+                return Ok(paddr as usize & !offset_mask | gva & offset_mask);
+            }
+            table = paddr;
+        }
+        Err(AxError::BadAddress)
+    }
+
+    /// Calls `f` for each part of `[addr, addr + len)` within a guest page,
+    /// with the guest physical address and the offset of the part.
+    fn for_each_page(
+        &self,
+        tid: usize,
+        addr: usize,
+        len: usize,
+        mut f: impl FnMut(VirtAddr, core::ops::Range<usize>) -> AxResult,
+    ) -> AxResult {
+        let mut offset = 0;
+        while offset < len {
+            let gva = addr + offset;
+            let n = (PAGE_SIZE_4K - gva % PAGE_SIZE_4K).min(len - offset);
+            let gpa = self.translate(tid, gva)?;
+            f(VirtAddr::from(gpa), offset..offset + n)?;
+            offset += n;
+        }
+        Ok(())
+    }
+}
+
+impl Target for GuestTarget<'_> {
+    fn num_threads(&self) -> usize {
+        self.vm.vcpus.len()
+    }
+
+    fn read_registers(&mut self, tid: usize, regs: &mut Registers) -> AxResult {
+        let vcpu = self.vm.vcpus[tid].lock();
+        for (i, reg) in regs[..PC_REG].iter_mut().enumerate() {
+            *reg = vcpu.get_gpr(GprIndex::from_raw(i as u32).unwrap());
+        }
+        regs[PC_REG] = vcpu.pc();
+        Ok(())
+    }
+
+    fn write_registers(&mut self, tid: usize, regs: &Registers) -> AxResult {
+        let mut vcpu = self.vm.vcpus[tid].lock();
+        for (i, &reg) in regs[..PC_REG].iter().enumerate().skip(1) {
+            vcpu.set_gpr_from_gpr_index(GprIndex::from_raw(i as u32).unwrap(), reg);
+        }
+        vcpu.set_pc(regs[PC_REG]);
+        Ok(())
+    }
+
+    fn read_memory(&mut self, tid: usize, addr: usize, buf: &mut [u8]) -> AxResult {
+        self.for_each_page(tid, addr, buf.len(), |gpa, range| {
+            self.vm.aspace.lock().read(gpa, &mut buf[range])
+        })
+    }
+
+    fn write_memory(&mut self, tid: usize, addr: usize, buf: &[u8]) -> AxResult {
+        self.for_each_page(tid, addr, buf.len(), |gpa, range| {
+            write_guest(&mut self.vm.aspace.lock(), gpa.as_usize(), &buf[range])
+        })?;
+        self.vm.harts.fence_i_all();
+        Ok(())
+    }
+}
+
+impl Vm {
+    fn set_debug(&self, enable: bool) {
+        for vcpu in &self.vcpus {
+            vcpu.lock().set_debug(enable);
+        }
+    }
+
+    /// Debugs the VM with GDB connected by `conn`, returns when GDB detaches
+    /// or kills the VM, or when the VM is shut down.
+    ///
+    /// The VM is paused while GDB inspects it. It can be debugged before it is
+    /// booted, it starts running when GDB resumes it after the boot.
+    pub fn debug<C: Connection>(&self, conn: C) -> AxResult {
+        let mut stub = GdbStub::new(conn);
+        self.pause();
+        self.set_debug(true);
+        info!("VM[{}] debugged by GDB", self.id());
+
+        let (mut tid, mut signal) = (0, SIGTRAP);
+        let ret = loop {
+            match stub.handle_stop(&mut GuestTarget { vm: self }, tid, signal) {
+                Ok(Action::Resume) => {}
+                Ok(Action::Detach) => break Ok(()),
+                Ok(Action::Kill) => {
+                    self.shutdown();
+                    break Ok(());
+                }
+                Err(e) => {
+                    let _ = stub.clear_breakpoints(&mut GuestTarget { vm: self });
+                    break Err(e);
+                }
+            }
+            self.debug_stop.store(0, Ordering::Release);
+            self.resume();
+
+            // Wait for a breakpoint, or an interrupt from GDB.
+            loop {
+                if self.stopping.load(Ordering::Acquire) {
+                    info!("VM[{}] is shut down while debugged", self.id());
+                    return Ok(());
+                }
+                let stop = self.debug_stop.load(Ordering::Acquire);
+                if stop != 0 {
+                    self.pause();
+                    (tid, signal) = (stop - 1, SIGTRAP);
+                    break;
+                }
+                match stub.poll_interrupt() {
+                    Ok(false) => axtask::yield_now(),
+                    Ok(true) => {
+                        self.pause();
+                        signal = SIGINT;
+                        break;
+                    }
+                    Err(e) => {
+                        self.pause();
+                        let _ = stub.clear_breakpoints(&mut GuestTarget { vm: self });
+                        self.set_debug(false);
+                        self.resume();
+                        return Err(e);
+                    }
+                }
+            }
+        };
+        self.set_debug(false);
+        self.resume();
+        info!("VM[{}] no longer debugged", self.id());
+        ret
+    }
+}
@@ -0,0 +1,33 @@
+//! [ArceOS](https://github.com/arceos-org/arceos) guest VM loading and
+//! management.
+//!
+//! - [`load_linux`]: loads a Linux kernel `Image`, an optional initrd and a
+//!   generated device tree into guest memory, following the RISC-V Linux boot
+//!   protocol.
+//! - [`FdtBuilder`]: a minimal flattened device tree (DTB) writer.
+//! - [`VmConfig`]: VM configuration parsed from TOML files at runtime, see the
+//!   [`config`] module for the format.
+//! - [`VmManager`]: creates [`Vm`]s from the configurations and runs them.
+//!   A VM can be paused, saved to a snapshot with [`Vm::snapshot`] and
+//!   restored by [`VmManager::restore_vm`].
+//!   Guest RAM is allocated on demand, and given back by the guest through a
+//!   virtio balloon, see [`Vm::set_balloon_target`].
+//! - [`Vm::debug`]: debugs a VM with GDB, see [`axgdb`].
+
+#![cfg_attr(not(test), no_std)]
+
+#[macro_use]
+extern crate log;
+extern crate alloc;
+
+pub mod config;
+mod fdt;
+mod gdb;
+mod loader;
+mod manager;
+mod snapshot;
+
+pub use self::config::VmConfig;
+pub use self::fdt::FdtBuilder;
+pub use self::loader::{load_linux, BootInfo, GuestDevice, LinuxBootConfig};
+pub use self::manager::{Vm, VmManager};
@@ -0,0 +1,371 @@
+//! Boots a Linux guest following the RISC-V Linux boot protocol: the kernel
+//! `Image` is loaded at the start of guest RAM plus its `text_offset`, and the
+//! boot hart enters it in S-mode with `a0` = hart ID, `a1` = physical address
+//! of the device tree and the MMU off.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use axmm::AddrSpace;
+use memory_addr::{align_down, align_up_4k, VirtAddr};
+use riscv_vcpu::{GprIndex, RISCVVCpu};
+
+use crate::FdtBuilder;
+
+/// Magic number at offset 56 of the RISC-V Linux `Image` header ("RSC\x05").
+const IMAGE_MAGIC2: u32 = 0x0543_5352;
+const IMAGE_HEADER_SIZE: usize = 64;
+
+/// The device tree is placed in the last 2 MiB of guest RAM, so it does not
+/// depend on the sizes of the other images.
+const DTB_REGION_SIZE: usize = 0x20_0000;
+/// Alignment of the device tree required by the boot protocol.
+const DTB_ALIGN: usize = 8;
+
+/// The interrupt IDs of the supervisor and machine external interrupts, used
+/// in the `interrupts-extended` of the PLIC.
+const IRQ_S_EXT: u32 = 9;
+const IRQ_M_EXT: u32 = 11;
+
+/// An emulated device to be described in the device tree.
+#[derive(Debug, Clone)]
+pub enum GuestDevice {
+    /// A PLIC, whose context `2 * hart + 1` is the S-mode context of the hart
+    /// (see `axdevice::VPlic`).
+    Plic {
+        base: usize,
+        size: usize,
+        num_sources: usize,
+    },
+    /// A virtio-mmio device, whose interrupt is connected to the PLIC source
+    /// `irq`.
+    VirtioMmio { base: usize, size: usize, irq: u32 },
+}
+
+/// Configuration to boot a Linux guest.
+#[derive(Debug, Clone)]
+pub struct LinuxBootConfig {
+    /// Path of the kernel `Image` in the host file system.
+    pub kernel_path: String,
+    /// Path of the initial ramdisk in the host file system.
+    pub initrd_path: Option<String>,
+    /// Kernel command line, passed in `/chosen/bootargs`.
+    pub cmdline: String,
+    /// Start of guest RAM, which must be mapped in the address space.
+    pub mem_base: VirtAddr,
+    /// Size of guest RAM.
+    pub mem_size: usize,
+    /// Number of harts of the VM.
+    pub num_harts: usize,
+    /// Emulated devices of the VM.
+    pub devices: Vec<GuestDevice>,
+    /// Path of a prebuilt device tree, which is loaded instead of the
+    /// generated one. It must describe the initrd by itself, if any.
+    pub dtb_path: Option<String>,
+    /// Where the device tree is loaded, the last 2 MiB of guest RAM by
+    /// default.
+    pub dtb_addr: Option<VirtAddr>,
+}
+
+/// Where the guest is loaded, returned by [`load_linux`].
+#[derive(Debug, Clone, Copy)]
+pub struct BootInfo {
+    /// Entry of the kernel.
+    pub entry: VirtAddr,
+    /// Guest physical address of the device tree.
+    pub dtb_addr: VirtAddr,
+}
+
+impl BootInfo {
+    /// Sets up the boot registers of the boot hart.
+    pub fn setup_vcpu(&self, vcpu: &mut RISCVVCpu) -> AxResult {
+        vcpu.set_entry(self.entry)?;
+        vcpu.set_gpr_from_gpr_index(GprIndex::A0, vcpu.hart_id());
+        vcpu.set_gpr_from_gpr_index(GprIndex::A1, self.dtb_addr.as_usize());
+        Ok(())
+    }
+}
+
+/// Writes `data` to guest RAM at `addr`, allocating the pages that are not
+/// populated yet.
+pub(crate) fn write_guest(aspace: &mut AddrSpace, addr: usize, data: &[u8]) -> AxResult {
+    aspace.populate_area(addr.into(), data.len())?;
+    aspace.write(addr.into(), data)
+}
+
+/// Loads the kernel, the initrd and the generated device tree into guest RAM.
+pub fn load_linux(config: &LinuxBootConfig, aspace: &mut AddrSpace) -> AxResult<BootInfo> {
+    let mem_base = config.mem_base.as_usize();
+    let Some(mem_end) = mem_base.checked_add(config.mem_size) else {
+        return ax_err!(InvalidInput, "guest memory is out of the address space");
+    };
+    if config.mem_size <= DTB_REGION_SIZE || config.num_harts == 0 {
+        return ax_err!(InvalidInput, "invalid guest memory size or number of harts");
+    }
+    let dtb_addr = match config.dtb_addr {
+        Some(addr) if addr.as_usize() < mem_base || addr.as_usize() >= mem_end => {
+            return ax_err!(InvalidInput, "device tree is out of guest memory");
+        }
+        Some(addr) if addr.as_usize() % DTB_ALIGN != 0 => {
+            return ax_err!(InvalidInput, "device tree is not 8-byte aligned");
+        }
+        Some(addr) => addr.as_usize(),
+        None => mem_end - DTB_REGION_SIZE,
+    };
+
+    // Kernel image.
+    let kernel = axfs::api::read(&config.kernel_path)?;
+    let (text_offset, image_size) = parse_image_header(&kernel)?;
+    // The device tree is moved elsewhere if it is placed by the user.
+    let kernel_limit = match config.dtb_addr {
+        Some(_) => mem_end,
+        None => dtb_addr,
+    };
+    let Some((kernel_addr, kernel_end)) = kernel_range(
+        mem_base,
+        kernel_limit,
+        text_offset,
+        image_size.max(kernel.len()),
+    ) else {
+        return ax_err!(NoMemory, "kernel image does not fit in guest memory");
+    };
+    write_guest(aspace, kernel_addr, &kernel)?;
+    info!(
+        "Linux image loaded at {:#x}, size {:#x}",
+        kernel_addr,
+        kernel.len()
+    );
+
+    // Initrd, placed right below the device tree, or at the end of guest RAM
+    // if the device tree is below the kernel.
+    let initrd_top = if dtb_addr >= kernel_end {
+        dtb_addr
+    } else {
+        mem_end
+    };
+    let initrd = match &config.initrd_path {
+        Some(path) => {
+            let initrd = axfs::api::read(path)?;
+            let start = align_down(initrd_top.saturating_sub(initrd.len()), 0x1000);
+            if start < align_up_4k(kernel_end) {
+                return ax_err!(NoMemory, "initrd does not fit in guest memory");
+            }
+            write_guest(aspace, start, &initrd)?;
+            info!("initrd loaded at {:#x}, size {:#x}", start, initrd.len());
+            Some((start, start + initrd.len()))
+        }
+        None => None,
+    };
+
+    let dtb = match &config.dtb_path {
+        Some(path) => axfs::api::read(path)?,
+        None => build_fdt(config, initrd),
+    };
+    if dtb.len() > DTB_REGION_SIZE.min(mem_end - dtb_addr) {
+        return ax_err!(NoMemory, "device tree is too large");
+    }
+    let dtb_range = (dtb_addr, dtb_addr + dtb.len());
+    if overlaps(dtb_range, (kernel_addr, kernel_end))
+        || initrd.is_some_and(|range| overlaps(dtb_range, range))
+    {
+        return ax_err!(InvalidInput, "device tree overlaps the kernel or initrd");
+    }
+    write_guest(aspace, dtb_addr, &dtb)?;
+    info!(
+        "device tree loaded at {:#x}, size {:#x}",
+        dtb_addr,
+        dtb.len()
+    );
+
+    Ok(BootInfo {
+        entry: kernel_addr.into(),
+        dtb_addr: dtb_addr.into(),
+    })
+}
+
+/// Returns the range of a kernel of `size` bytes loaded at `text_offset` from
+/// `mem_base`, if it ends below `limit`.
+///
+/// `text_offset` and `size` are taken from the image header, so they are not
+/// trusted.
+fn kernel_range(
+    mem_base: usize,
+    limit: usize,
+    text_offset: usize,
+    size: usize,
+) -> Option<(usize, usize)> {
+    let start = mem_base.checked_add(text_offset)?;
+    let end = start.checked_add(size)?;
+    (end <= limit).then_some((start, end))
+}
+
+/// Returns whether the ranges `[start, end)` overlap.
+fn overlaps(a: (usize, usize), b: (usize, usize)) -> bool {
+    a.0 < b.1 && b.0 < a.1
+}
+
+/// Parses the header of a RISC-V Linux `Image`, returns `text_offset` and
+/// `image_size`.
+fn parse_image_header(image: &[u8]) -> AxResult<(usize, usize)> {
+    if image.len() < IMAGE_HEADER_SIZE {
+        return ax_err!(InvalidData, "kernel image is too small");
+    }
+    let u64_at = |off: usize| u64::from_le_bytes(image[off..off + 8].try_into().unwrap());
+    let magic2 = u32::from_le_bytes(image[56..60].try_into().unwrap());
+    if magic2 != IMAGE_MAGIC2 {
+        return ax_err!(InvalidData, "not a RISC-V Linux Image");
+    }
+    Ok((u64_at(8) as usize, u64_at(16) as usize))
+}
+
+/// Generates the device tree describing the memory, the harts and the devices
+/// of the guest.
+fn build_fdt(config: &LinuxBootConfig, initrd: Option<(usize, usize)>) -> Vec<u8> {
+    let mut fdt = FdtBuilder::new();
+    let intc_phandles: Vec<u32> = (0..config.num_harts).map(|_| fdt.alloc_phandle()).collect();
+    let plic_phandle = fdt.alloc_phandle();
+    let has_plic = config
+        .devices
+        .iter()
+        .any(|d| matches!(d, GuestDevice::Plic { .. }));
+
+    fdt.begin_node("");
+    fdt.property_u32("#address-cells", 2);
+    fdt.property_u32("#size-cells", 2);
+    fdt.property_string("compatible", "riscv-virtio");
+    fdt.property_string("model", "arceos,riscv-virtio");
+
+    fdt.begin_node("chosen");
+    fdt.property_string("bootargs", &config.cmdline);
+    if let Some((start, end)) = initrd {
+        fdt.property_u64("linux,initrd-start", start as u64);
+        fdt.property_u64("linux,initrd-end", end as u64);
+    }
+    fdt.end_node();
+
+    fdt.begin_node(&format!("memory@{:x}", config.mem_base.as_usize()));
+    fdt.property_string("device_type", "memory");
+    fdt.property_reg(&[(config.mem_base.as_usize() as u64, config.mem_size as u64)]);
+    fdt.end_node();
+
+    fdt.begin_node("cpus");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 0);
+    fdt.property_u32("timebase-frequency", axconfig::TIMER_FREQUENCY as u32);
+    for (hart, &phandle) in intc_phandles.iter().enumerate() {
+        fdt.begin_node(&format!("cpu@{:x}", hart));
+        fdt.property_string("device_type", "cpu");
+        fdt.property_u32("reg", hart as u32);
+        fdt.property_string("status", "okay");
+        fdt.property_string("compatible", "riscv");
+        fdt.property_string("riscv,isa", "rv64imafdc");
+        fdt.property_string("mmu-type", "riscv,sv39");
+
+        fdt.begin_node("interrupt-controller");
+        fdt.property_u32("#interrupt-cells", 1);
+        fdt.property_null("interrupt-controller");
+        fdt.property_string("compatible", "riscv,cpu-intc");
+        fdt.property_u32("phandle", phandle);
+        fdt.end_node();
+
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    fdt.begin_node("soc");
+    fdt.property_u32("#address-cells", 2);
+    fdt.property_u32("#size-cells", 2);
+    fdt.property_string("compatible", "simple-bus");
+    fdt.property_null("ranges");
+    for dev in &config.devices {
+        match *dev {
+            GuestDevice::Plic {
+                base,
+                size,
+                num_sources,
+            } => {
+                fdt.begin_node(&format!("plic@{:x}", base));
+                fdt.property_string_list("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+                fdt.property_reg(&[(base as u64, size as u64)]);
+                fdt.property_u32("#address-cells", 0);
+                fdt.property_u32("#interrupt-cells", 1);
+                fdt.property_null("interrupt-controller");
+                fdt.property_u32("riscv,ndev", num_sources.saturating_sub(1) as u32);
+                let contexts: Vec<u32> = intc_phandles
+                    .iter()
+                    .flat_map(|&p| [p, IRQ_M_EXT, p, IRQ_S_EXT])
+                    .collect();
+                fdt.property_cells("interrupts-extended", &contexts);
+                fdt.property_u32("phandle", plic_phandle);
+                fdt.end_node();
+            }
+            GuestDevice::VirtioMmio { base, size, irq } => {
+                fdt.begin_node(&format!("virtio_mmio@{:x}", base));
+                fdt.property_string("compatible", "virtio,mmio");
+                fdt.property_reg(&[(base as u64, size as u64)]);
+                if has_plic {
+                    fdt.property_u32("interrupts", irq);
+                    fdt.property_u32("interrupt-parent", plic_phandle);
+                }
+                fdt.end_node();
+            }
+        }
+    }
+    fdt.end_node();
+
+    fdt.end_node();
+    fdt.finish(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(text_offset: u64, image_size: u64, magic2: u32) -> Vec<u8> {
+        let mut image = alloc::vec![0; IMAGE_HEADER_SIZE + 16];
+        image[8..16].copy_from_slice(&text_offset.to_le_bytes());
+        image[16..24].copy_from_slice(&image_size.to_le_bytes());
+        image[56..60].copy_from_slice(&magic2.to_le_bytes());
+        image
+    }
+
+    #[test]
+    fn test_parse_image_header() {
+        let img = image(0x20_0000, 0x123_4000, IMAGE_MAGIC2);
+        assert_eq!(parse_image_header(&img).unwrap(), (0x20_0000, 0x123_4000));
+        assert_eq!(&img[56..60], b"RSC\x05");
+    }
+
+    #[test]
+    fn test_parse_image_header_invalid() {
+        let img = image(0x20_0000, 0x1000, 0x464c_457f);
+        assert!(parse_image_header(&img).is_err());
+        let img = image(0x20_0000, 0x1000, IMAGE_MAGIC2);
+        assert!(parse_image_header(&img[..IMAGE_HEADER_SIZE - 1]).is_err());
+        assert!(parse_image_header(&[]).is_err());
+    }
+
+    #[test]
+    fn test_kernel_range() {
+        const BASE: usize = 0x9000_0000;
+        const END: usize = 0xa000_0000;
+        assert_eq!(
+            kernel_range(BASE, END, 0x20_0000, 0x100_0000),
+            Some((0x9020_0000, 0x9120_0000))
+        );
+        assert_eq!(kernel_range(BASE, END, 0, END - BASE), Some((BASE, END)));
+        assert_eq!(kernel_range(BASE, END, 0x20_0000, END - BASE), None);
+        assert_eq!(kernel_range(BASE, END, usize::MAX - 0x1000, 0x1000), None);
+        assert_eq!(kernel_range(BASE, END, 0x20_0000, usize::MAX), None);
+    }
+
+    #[test]
+    fn test_overlaps() {
+        assert!(overlaps((0x1000, 0x2000), (0x1fff, 0x3000)));
+        assert!(overlaps((0x1000, 0x3000), (0x1800, 0x2000)));
+        assert!(!overlaps((0x1000, 0x2000), (0x2000, 0x3000)));
+        assert!(!overlaps((0x2000, 0x3000), (0x1000, 0x2000)));
+    }
+}
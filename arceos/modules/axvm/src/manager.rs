@@ -0,0 +1,549 @@
+//! Builds VMs from [`VmConfig`]s and runs their vCPUs, each on its own task.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use axdevice::{
+    GuestMemory, IrqLine, MmioDevice, VPlic, VirtioBalloon, VirtioBlk, VirtioConsole,
+    VirtioMmioDevice,
+};
+use axerrno::{ax_err, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::VirtAddr;
+use riscv_vcpu::{AccessWidth, AxVCpuExitReason, RISCVVCpu, VmHarts};
+
+use crate::config::{EmulatedDeviceKind, ImageKind, VmConfig};
+use crate::loader::write_guest;
+use crate::{load_linux, BootInfo, GuestDevice, LinuxBootConfig};
+
+const VM_ASPACE_BASE: usize = 0x0;
+const VM_ASPACE_SIZE: usize = 0x7fff_ffff_f000;
+
+pub(crate) struct MmioRegion {
+    pub base: VirtAddr,
+    size: usize,
+    pub dev: Arc<dyn MmioDevice>,
+}
+
+/// A guest VM.
+pub struct Vm {
+    id: usize,
+    pub(crate) config: VmConfig,
+    pub(crate) aspace: GuestMemory,
+    pub(crate) harts: Arc<VmHarts>,
+    pub(crate) mmio_regions: Vec<MmioRegion>,
+    /// Consoles whose input is polled from the host console.
+    consoles: Vec<Arc<VirtioMmioDevice<VirtioConsole>>>,
+    balloon: Option<Arc<VirtioMmioDevice<VirtioBalloon>>>,
+    /// The vCPUs, each locked by its task while running the guest.
+    pub(crate) vcpus: Vec<Mutex<RISCVVCpu>>,
+    booted: AtomicBool,
+    paused: AtomicBool,
+    running_vcpus: AtomicUsize,
+    /// Number of vCPU tasks waiting for [`Vm::resume`].
+    parked_vcpus: AtomicUsize,
+    pub(crate) stopping: AtomicBool,
+    /// The hart that has hit a breakpoint plus one, or 0, while debugged.
+    pub(crate) debug_stop: AtomicUsize,
+}
+
+impl Vm {
+    /// Creates a VM: maps the guest memory, creates the devices and the
+    /// vCPUs, and loads the images.
+    pub fn new(id: usize, config: VmConfig) -> AxResult<Arc<Self>> {
+        let vm = Self::build(id, config)?;
+        let boot_info = Self::load_images(&vm.config, &mut vm.aspace.lock())?;
+        boot_info.setup_vcpu(&mut vm.vcpus[0].lock())?;
+        info!(
+            "VM[{}] \"{}\" created: {} vCPUs, entry {:#x}",
+            id, vm.config.name, vm.config.vcpus, boot_info.entry
+        );
+        Ok(Arc::new(vm))
+    }
+
+    /// Creates the memory, the devices and the vCPUs of a VM, without loading
+    /// the images.
+    pub(crate) fn build(id: usize, config: VmConfig) -> AxResult<Self> {
+        let mut aspace = AddrSpace::new_empty(VirtAddr::from(VM_ASPACE_BASE), VM_ASPACE_SIZE)?;
+        // Guest RAM is allocated on demand, on nested page faults.
+        for region in &config.memory {
+            aspace.map_alloc(region.base.into(), region.size, region.flags, false)?;
+        }
+        for dev in &config.passthrough {
+            aspace.map_linear(
+                dev.base.into(),
+                dev.host_base.into(),
+                dev.size,
+                MappingFlags::READ
+                    | MappingFlags::WRITE
+                    | MappingFlags::DEVICE
+                    | MappingFlags::USER,
+            )?;
+        }
+        let ept_root = aspace.page_table_root();
+        let aspace: GuestMemory = Arc::new(Mutex::new(aspace));
+
+        // Devices, the PLIC is created first to connect the others to it.
+        let harts = Arc::new(VmHarts::new(config.vcpus));
+        let plic = config.devices.iter().find_map(|dev| match dev.kind {
+            EmulatedDeviceKind::Plic { num_sources } => {
+                Some(Arc::new(VPlic::new(num_sources, harts.clone())))
+            }
+            _ => None,
+        });
+        let irq_line = |irq: u32| -> Option<Arc<dyn IrqLine>> {
+            match &plic {
+                Some(plic) if irq != 0 => Some(plic.irq_line(irq as usize)),
+                _ => None,
+            }
+        };
+        let mut mmio_regions = Vec::new();
+        let mut consoles = Vec::new();
+        let mut balloon = None;
+        for dev in &config.devices {
+            let mmio: Arc<dyn MmioDevice> = match &dev.kind {
+                EmulatedDeviceKind::Plic { .. } => plic.clone().unwrap(),
+                EmulatedDeviceKind::VirtioBlk { path, writable } => {
+                    let blk = VirtioBlk::new(path, *writable)?;
+                    Arc::new(VirtioMmioDevice::new(
+                        blk,
+                        aspace.clone(),
+                        irq_line(dev.irq),
+                    ))
+                }
+                EmulatedDeviceKind::VirtioConsole => {
+                    let console = Arc::new(VirtioMmioDevice::new(
+                        VirtioConsole::new(),
+                        aspace.clone(),
+                        irq_line(dev.irq),
+                    ));
+                    consoles.push(console.clone());
+                    console
+                }
+                EmulatedDeviceKind::VirtioBalloon => {
+                    let dev = Arc::new(VirtioMmioDevice::new(
+                        VirtioBalloon::new(harts.clone()),
+                        aspace.clone(),
+                        irq_line(dev.irq),
+                    ));
+                    balloon = Some(dev.clone());
+                    dev
+                }
+            };
+            mmio_regions.push(MmioRegion {
+                base: dev.base.into(),
+                size: dev.size,
+                dev: mmio,
+            });
+        }
+
+        let mut vcpus = Vec::with_capacity(config.vcpus);
+        for hart_id in 0..config.vcpus {
+            let mut vcpu = RISCVVCpu::init_smp(harts.clone(), hart_id);
+            vcpu.set_ept_root(ept_root)?;
+            for region in &mmio_regions {
+                vcpu.add_mmio_region(region.base, region.size);
+            }
+            vcpus.push(Mutex::new(vcpu));
+        }
+
+        Ok(Self {
+            id,
+            config,
+            aspace,
+            harts,
+            mmio_regions,
+            consoles,
+            balloon,
+            vcpus,
+            booted: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            running_vcpus: AtomicUsize::new(0),
+            parked_vcpus: AtomicUsize::new(0),
+            stopping: AtomicBool::new(false),
+            debug_stop: AtomicUsize::new(0),
+        })
+    }
+
+    fn load_images(config: &VmConfig, aspace: &mut AddrSpace) -> AxResult<BootInfo> {
+        let image = &config.image;
+        match image.kind {
+            ImageKind::Linux => {
+                let devices = config
+                    .devices
+                    .iter()
+                    .map(|dev| match dev.kind {
+                        EmulatedDeviceKind::Plic { num_sources } => GuestDevice::Plic {
+                            base: dev.base,
+                            size: dev.size,
+                            num_sources,
+                        },
+                        _ => GuestDevice::VirtioMmio {
+                            base: dev.base,
+                            size: dev.size,
+                            irq: dev.irq,
+                        },
+                    })
+                    .collect();
+                let linux = LinuxBootConfig {
+                    kernel_path: image.kernel.clone(),
+                    initrd_path: image.initrd.clone(),
+                    cmdline: image.cmdline.clone(),
+                    mem_base: config.memory[0].base.into(),
+                    mem_size: config.memory[0].size,
+                    num_harts: config.vcpus,
+                    devices,
+                    dtb_path: image.dtb.clone(),
+                    dtb_addr: image.dtb_load_addr.map(VirtAddr::from),
+                };
+                load_linux(&linux, aspace)
+            }
+            ImageKind::Raw => {
+                let load_addr = image.load_addr.unwrap();
+                write_guest(aspace, load_addr, &axfs::api::read(&image.kernel)?)?;
+                let dtb_addr = match (&image.dtb, image.dtb_load_addr) {
+                    (Some(path), Some(addr)) => {
+                        write_guest(aspace, addr, &axfs::api::read(path)?)?;
+                        addr
+                    }
+                    (Some(_), None) => {
+                        return ax_err!(InvalidInput, "`dtb_load_addr` is required for raw images")
+                    }
+                    (None, _) => 0,
+                };
+                Ok(BootInfo {
+                    entry: image.entry.unwrap_or(load_addr).into(),
+                    dtb_addr: dtb_addr.into(),
+                })
+            }
+        }
+    }
+
+    /// Returns the ID of the VM.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the configuration of the VM.
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
+    /// Returns the guest physical memory of the VM.
+    pub fn memory(&self) -> &GuestMemory {
+        &self.aspace
+    }
+
+    /// Asks the guest to give back `num_pages` 4 KiB pages of its RAM through
+    /// the virtio balloon, or fewer to take them again.
+    ///
+    /// The guest inflates or deflates the balloon asynchronously, see
+    /// [`balloon_pages`](Self::balloon_pages).
+    pub fn set_balloon_target(&self, num_pages: u32) -> AxResult {
+        match &self.balloon {
+            Some(balloon) => {
+                if balloon.with_backend(|b| b.set_target(num_pages)) {
+                    balloon.notify_config_change();
+                }
+                Ok(())
+            }
+            None => ax_err!(Unsupported, "VM has no virtio balloon"),
+        }
+    }
+
+    /// Returns the number of 4 KiB pages given back by the guest through the
+    /// virtio balloon, if any.
+    pub fn balloon_pages(&self) -> Option<u32> {
+        let balloon = self.balloon.as_ref()?;
+        Some(balloon.with_backend(|b| b.actual()))
+    }
+
+    /// Whether any vCPU of the VM is still running.
+    pub fn is_running(&self) -> bool {
+        self.running_vcpus.load(Ordering::Acquire) != 0
+    }
+
+    /// Stops all vCPUs of the VM. They stop on their next VM exits.
+    pub fn shutdown(&self) {
+        self.stopping.store(true, Ordering::Release);
+    }
+
+    /// Whether the VM has been booted.
+    pub fn is_booted(&self) -> bool {
+        self.booted.load(Ordering::Acquire)
+    }
+
+    /// Starts running the VM, each vCPU runs on its own task.
+    pub fn boot(self: &Arc<Self>) -> AxResult {
+        if self.booted.swap(true, Ordering::AcqRel) {
+            return ax_err!(BadState, "VM is already booted");
+        }
+        self.running_vcpus
+            .fetch_add(self.vcpus.len(), Ordering::AcqRel);
+        for hart_id in 0..self.vcpus.len() {
+            let vm = self.clone();
+            axtask::spawn(move || {
+                vm.vcpu_loop(hart_id);
+                vm.running_vcpus.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the VM is paused, or not booted yet. The state of a paused VM
+    /// does not change, so it can be saved by [`Vm::snapshot`].
+    pub fn is_paused(&self) -> bool {
+        !self.is_booted()
+            || self.paused.load(Ordering::Acquire)
+                && self.parked_vcpus.load(Ordering::Acquire)
+                    >= self.running_vcpus.load(Ordering::Acquire)
+    }
+
+    /// Pauses the VM, returns when all vCPUs have stopped running the guest.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Release);
+        for hart_id in 0..self.vcpus.len() {
+            self.harts.kick(hart_id);
+        }
+        while !self.is_paused() {
+            axtask::yield_now();
+        }
+        info!("VM[{}] paused", self.id);
+    }
+
+    /// Resumes the VM paused by [`Vm::pause`].
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::AcqRel) {
+            info!("VM[{}] resumed", self.id);
+        }
+    }
+
+    fn find_mmio(&self, addr: VirtAddr) -> Option<&MmioRegion> {
+        self.mmio_regions
+            .iter()
+            .find(|r| addr >= r.base && addr < r.base + r.size)
+    }
+
+    fn handle_mmio_read(
+        &self,
+        vcpu: &mut RISCVVCpu,
+        addr: VirtAddr,
+        width: AccessWidth,
+        reg: usize,
+        signed_ext: bool,
+    ) -> AxResult {
+        let Some(region) = self.find_mmio(addr) else {
+            return ax_err!(NotFound, "no device at the MMIO address");
+        };
+        let val = region
+            .dev
+            .handle_read(addr.as_usize() - region.base.as_usize(), width)?;
+        vcpu.complete_mmio_read(reg, width, signed_ext, val);
+        Ok(())
+    }
+
+    fn handle_mmio_write(
+        &self,
+        vcpu: &mut RISCVVCpu,
+        addr: VirtAddr,
+        width: AccessWidth,
+        data: u64,
+    ) -> AxResult {
+        let Some(region) = self.find_mmio(addr) else {
+            return ax_err!(NotFound, "no device at the MMIO address");
+        };
+        region
+            .dev
+            .handle_write(addr.as_usize() - region.base.as_usize(), width, data)?;
+        vcpu.complete_mmio_write();
+        Ok(())
+    }
+
+    /// Handles a VM exit, returns `false` if the vCPU should stop.
+    fn handle_exit(&self, vcpu: &mut RISCVVCpu, exit_reason: AxVCpuExitReason) -> AxResult<bool> {
+        match exit_reason {
+            AxVCpuExitReason::Nothing | AxVCpuExitReason::ExternalInterrupt { .. } => {
+                // The boot hart polls the input of the consoles.
+                if vcpu.hart_id() == 0 {
+                    for console in &self.consoles {
+                        console.poll()?;
+                    }
+                }
+            }
+            AxVCpuExitReason::CpuDown => {
+                // Not started yet or stopped, wait for `hart_start`.
+                axtask::yield_now();
+            }
+            AxVCpuExitReason::MmioRead {
+                addr,
+                width,
+                reg,
+                signed_ext,
+                ..
+            } => self.handle_mmio_read(vcpu, addr, width, reg, signed_ext)?,
+            AxVCpuExitReason::MmioWrite { addr, width, data } => {
+                self.handle_mmio_write(vcpu, addr, width, data)?
+            }
+            AxVCpuExitReason::NestedPageFault { addr, access_flags } => {
+                if !self.aspace.lock().handle_page_fault(addr, access_flags) {
+                    warn!(
+                        "VM[{}] vCPU {} unhandled nested page fault @ {:#x} {:?}",
+                        self.id,
+                        vcpu.hart_id(),
+                        addr,
+                        access_flags
+                    );
+                    return ax_err!(BadAddress);
+                }
+            }
+            AxVCpuExitReason::Breakpoint { pc } => {
+                debug!(
+                    "VM[{}] vCPU {} breakpoint @ {:#x}",
+                    self.id,
+                    vcpu.hart_id(),
+                    pc
+                );
+                // The first hart stops the VM for the debugger, the others hit
+                // their breakpoints again after resuming.
+                let stop = vcpu.hart_id() + 1;
+                if self
+                    .debug_stop
+                    .compare_exchange(0, stop, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    self.paused.store(true, Ordering::Release);
+                    for hart_id in 0..self.vcpus.len() {
+                        self.harts.kick(hart_id);
+                    }
+                }
+            }
+            AxVCpuExitReason::SystemDown => {
+                info!("VM[{}] is shut down by the guest", self.id);
+                self.shutdown();
+                return Ok(false);
+            }
+            _ => {
+                warn!("VM[{}] unhandled VM exit: {:?}", self.id, exit_reason);
+                return ax_err!(Unsupported);
+            }
+        }
+        Ok(true)
+    }
+
+    fn vcpu_loop(&self, hart_id: usize) {
+        unsafe {
+            riscv_vcpu::setup_csrs();
+        }
+        while !self.stopping.load(Ordering::Acquire) {
+            if self.paused.load(Ordering::Acquire) {
+                self.parked_vcpus.fetch_add(1, Ordering::AcqRel);
+                while self.paused.load(Ordering::Acquire) && !self.stopping.load(Ordering::Acquire)
+                {
+                    axtask::yield_now();
+                }
+                self.parked_vcpus.fetch_sub(1, Ordering::AcqRel);
+                continue;
+            }
+
+            let mut vcpu = self.vcpus[hart_id].lock();
+            let flags = axhal::arch::local_irq_save_and_disable();
+            let ret = vcpu.run();
+            axhal::arch::local_irq_restore(flags);
+
+            match ret.and_then(|exit_reason| self.handle_exit(&mut vcpu, exit_reason)) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    error!(
+                        "VM[{}] vCPU {} stopped on error: {:?}",
+                        self.id,
+                        vcpu.hart_id(),
+                        e
+                    );
+                    self.shutdown();
+                    break;
+                }
+            }
+        }
+        debug!("VM[{}] vCPU {} exited", self.id, hart_id);
+    }
+}
+
+/// Manages the VMs created from configuration files.
+pub struct VmManager {
+    vms: Mutex<Vec<Arc<Vm>>>,
+    /// The ID of the next VM, IDs of VMs failed to create are not reused.
+    next_id: AtomicUsize,
+}
+
+impl VmManager {
+    /// Creates an empty manager.
+    pub const fn new() -> Self {
+        Self {
+            vms: Mutex::new(Vec::new()),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// Creates a VM from the configuration, returns it.
+    pub fn create_vm(&self, config: VmConfig) -> AxResult<Arc<Vm>> {
+        // Load the images without the lock, which may take a while.
+        let vm = Vm::new(self.next_id.fetch_add(1, Ordering::Relaxed), config)?;
+        self.vms.lock().push(vm.clone());
+        Ok(vm)
+    }
+
+    /// Creates a VM from the configuration file at `path` in the host file
+    /// system, returns it.
+    pub fn create_vm_from_file(&self, path: &str) -> AxResult<Arc<Vm>> {
+        self.create_vm(VmConfig::from_file(path)?)
+    }
+
+    /// Recreates a VM from the configuration and the snapshot at `path` in
+    /// the host file system, returns it. It is not booted.
+    ///
+    /// The configuration must be the one with which the snapshot was taken.
+    pub fn restore_vm(&self, config: VmConfig, path: &str) -> AxResult<Arc<Vm>> {
+        let vm = Vm::build(self.next_id.fetch_add(1, Ordering::Relaxed), config)?;
+        vm.restore(path)?;
+        let vm = Arc::new(vm);
+        self.vms.lock().push(vm.clone());
+        Ok(vm)
+    }
+
+    /// Returns the VM with the given ID.
+    pub fn vm(&self, id: usize) -> Option<Arc<Vm>> {
+        self.vms.lock().iter().find(|vm| vm.id == id).cloned()
+    }
+
+    /// Returns all VMs.
+    pub fn vms(&self) -> Vec<Arc<Vm>> {
+        self.vms.lock().clone()
+    }
+
+    /// Boots all VMs that are not booted yet.
+    pub fn boot_all(&self) -> AxResult {
+        for vm in self.vms() {
+            if !vm.is_booted() {
+                vm.boot()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until all vCPUs of all VMs have stopped.
+    pub fn wait_all(&self) {
+        while self.vms().iter().any(|vm| vm.is_running()) {
+            axtask::yield_now();
+        }
+    }
+}
+
+impl Default for VmManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,331 @@
+//! VM snapshots, saved to and restored from files in the host file system.
+//!
+//! A snapshot consists of (all integers are little-endian):
+//!
+//! - Header: magic `AXVMSNAP`, version (u32).
+//! - vCPUs: count (u32), then each [`VCpuState`] as u64 words, including the
+//!   FP registers and the guest timer.
+//! - Memory: count of regions (u32), then each region as base (u64), size
+//!   (u64) and the contents.
+//! - Devices: count (u32), then each device as base (u64) and its state as a
+//!   byte string (see [`StateWriter`]).
+//!
+//! The configuration is not saved, the VM is restored with the same
+//! configuration with which the snapshot was taken.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use axdevice::{StateReader, StateWriter};
+use axerrno::{ax_err, AxResult};
+use axfs::fops::{File, OpenOptions};
+use memory_addr::PAGE_SIZE_4K;
+use riscv_vcpu::VCpuState;
+
+use crate::loader::write_guest;
+use crate::Vm;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"AXVMSNAP";
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// Memory is copied in chunks of this size.
+const CHUNK_SIZE: usize = 0x10_0000;
+
+/// Number of u64 words of a [`VCpuState`]: GPRs, CSRs and the timer, FP
+/// registers and `fcsr`.
+const VCPU_STATE_WORDS: usize = 32 + 19 + 33;
+
+fn vcpu_state_words(state: &VCpuState) -> Vec<u64> {
+    let mut words: Vec<u64> = state.gprs.iter().map(|&r| r as u64).collect();
+    words.extend(
+        [
+            state.sstatus,
+            state.hstatus,
+            state.scounteren,
+            state.sepc,
+            state.guest_time,
+            state.vsstatus,
+            state.vsie,
+            state.vstvec,
+            state.vsscratch,
+            state.vsepc,
+            state.vscause,
+            state.vstval,
+            state.vsatp,
+            state.hvip,
+            state.hart_status,
+            state.start_addr,
+            state.opaque,
+            state.requests,
+        ]
+        .iter()
+        .map(|&w| w as u64),
+    );
+    words.push(state.timer_deadline);
+    words.extend_from_slice(&state.fp);
+    words.push(state.fcsr as u64);
+    words
+}
+
+fn vcpu_state_from_words(words: &[u64]) -> VCpuState {
+    let w = |i: usize| words[32 + i] as usize;
+    let mut state = VCpuState::default();
+    for (gpr, &word) in state.gprs.iter_mut().zip(words) {
+        *gpr = word as usize;
+    }
+    state.sstatus = w(0);
+    state.hstatus = w(1);
+    state.scounteren = w(2);
+    state.sepc = w(3);
+    state.guest_time = w(4);
+    state.vsstatus = w(5);
+    state.vsie = w(6);
+    state.vstvec = w(7);
+    state.vsscratch = w(8);
+    state.vsepc = w(9);
+    state.vscause = w(10);
+    state.vstval = w(11);
+    state.vsatp = w(12);
+    state.hvip = w(13);
+    state.hart_status = w(14);
+    state.start_addr = w(15);
+    state.opaque = w(16);
+    state.requests = w(17);
+    state.timer_deadline = words[32 + 18];
+    state.fp.copy_from_slice(&words[32 + 19..32 + 19 + 32]);
+    state.fcsr = w(19 + 32);
+    state
+}
+
+/// Sequential access to a snapshot file.
+struct SnapshotFile {
+    file: File,
+    /// The number of bytes read so far.
+    pos: u64,
+}
+
+impl SnapshotFile {
+    fn write_all(&mut self, mut buf: &[u8]) -> AxResult {
+        while !buf.is_empty() {
+            let n = self.file.write(buf)?;
+            if n == 0 {
+                return ax_err!(StorageFull, "failed to write the snapshot");
+            }
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> AxResult {
+        while !buf.is_empty() {
+            let n = self.file.read(buf)?;
+            if n == 0 {
+                return ax_err!(InvalidData, "snapshot is truncated");
+            }
+            self.pos += n as u64;
+            buf = &mut buf[n..];
+        }
+        Ok(())
+    }
+
+    /// Reads a length-prefixed block, refusing lengths that run past the end
+    /// of the file before allocating for them.
+    fn get_block(&mut self) -> AxResult<Vec<u8>> {
+        let len = self.get_u32()? as u64;
+        let size = self.file.get_attr()?.size();
+        if len > size.saturating_sub(self.pos) {
+            return ax_err!(InvalidData, "snapshot is truncated");
+        }
+        let mut block = vec![0; len as usize];
+        self.read_exact(&mut block)?;
+        Ok(block)
+    }
+
+    fn put_u32(&mut self, val: u32) -> AxResult {
+        self.write_all(&val.to_le_bytes())
+    }
+
+    fn put_u64(&mut self, val: u64) -> AxResult {
+        self.write_all(&val.to_le_bytes())
+    }
+
+    fn get_u32(&mut self) -> AxResult<u32> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn get_u64(&mut self) -> AxResult<u64> {
+        let mut buf = [0; 8];
+        self.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Reads a count and checks that it equals `expected`.
+    fn expect_count(&mut self, expected: usize, what: &str) -> AxResult {
+        let count = self.get_u32()? as usize;
+        if count != expected {
+            warn!(
+                "snapshot: {} {}, but {} in the VM config",
+                count, what, expected
+            );
+            return ax_err!(InvalidData, "snapshot does not match the VM config");
+        }
+        Ok(())
+    }
+}
+
+impl Vm {
+    /// Saves the state of the VM to a snapshot file at `path` in the host
+    /// file system. The VM must be paused (see [`Vm::pause`]).
+    pub fn snapshot(&self, path: &str) -> AxResult {
+        if !self.is_paused() {
+            return ax_err!(BadState, "VM must be paused to take a snapshot");
+        }
+        let mut opts = OpenOptions::new();
+        opts.write(true);
+        opts.create(true);
+        opts.truncate(true);
+        let mut f = SnapshotFile {
+            file: File::open(path, &opts)?,
+            pos: 0,
+        };
+
+        f.write_all(SNAPSHOT_MAGIC)?;
+        f.put_u32(SNAPSHOT_VERSION)?;
+
+        f.put_u32(self.vcpus.len() as u32)?;
+        for vcpu in &self.vcpus {
+            for word in vcpu_state_words(&vcpu.lock().save_state()) {
+                f.put_u64(word)?;
+            }
+        }
+
+        let aspace = self.aspace.lock();
+        let mut buf = vec![0; CHUNK_SIZE];
+        f.put_u32(self.config.memory.len() as u32)?;
+        for region in &self.config.memory {
+            f.put_u64(region.base as u64)?;
+            f.put_u64(region.size as u64)?;
+            let mut offset = 0;
+            while offset < region.size {
+                let len = CHUNK_SIZE.min(region.size - offset);
+                aspace.read((region.base + offset).into(), &mut buf[..len])?;
+                f.write_all(&buf[..len])?;
+                offset += len;
+            }
+        }
+        drop(aspace);
+
+        f.put_u32(self.mmio_regions.len() as u32)?;
+        for region in &self.mmio_regions {
+            let mut w = StateWriter::new();
+            region.dev.save_state(&mut w);
+            let state = w.into_bytes();
+            f.put_u64(region.base.as_usize() as u64)?;
+            f.put_u32(state.len() as u32)?;
+            f.write_all(&state)?;
+        }
+        f.file.flush()?;
+        info!("VM[{}] snapshot saved to {}", self.id(), path);
+        Ok(())
+    }
+
+    /// Restores the state of a VM not booted yet from the snapshot file at
+    /// `path`.
+    pub(crate) fn restore(&self, path: &str) -> AxResult {
+        let mut opts = OpenOptions::new();
+        opts.read(true);
+        let mut f = SnapshotFile {
+            file: File::open(path, &opts)?,
+            pos: 0,
+        };
+
+        let mut magic = [0; 8];
+        f.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC || f.get_u32()? != SNAPSHOT_VERSION {
+            return ax_err!(InvalidData, "not a VM snapshot, or unsupported version");
+        }
+
+        f.expect_count(self.vcpus.len(), "vCPUs")?;
+        for vcpu in &self.vcpus {
+            let mut words = [0; VCPU_STATE_WORDS];
+            for word in &mut words {
+                *word = f.get_u64()?;
+            }
+            vcpu.lock().restore_state(&vcpu_state_from_words(&words));
+        }
+
+        let mut aspace = self.aspace.lock();
+        let mut buf = vec![0; CHUNK_SIZE];
+        f.expect_count(self.config.memory.len(), "memory regions")?;
+        for region in &self.config.memory {
+            if f.get_u64()? != region.base as u64 || f.get_u64()? != region.size as u64 {
+                return ax_err!(InvalidData, "snapshot memory layout mismatch");
+            }
+            let mut offset = 0;
+            while offset < region.size {
+                let len = CHUNK_SIZE.min(region.size - offset);
+                f.read_exact(&mut buf[..len])?;
+                // The memory of the new VM is not allocated yet, zero pages
+                // are left so.
+                for (i, page) in buf[..len].chunks(PAGE_SIZE_4K).enumerate() {
+                    if page.iter().any(|&b| b != 0) {
+                        let addr = region.base + offset + i * PAGE_SIZE_4K;
+                        write_guest(&mut aspace, addr, page)?;
+                    }
+                }
+                offset += len;
+            }
+        }
+        drop(aspace);
+
+        f.expect_count(self.mmio_regions.len(), "devices")?;
+        for region in &self.mmio_regions {
+            if f.get_u64()? != region.base.as_usize() as u64 {
+                return ax_err!(InvalidData, "snapshot device layout mismatch");
+            }
+            let state = f.get_block()?;
+            region.dev.restore_state(&mut StateReader::new(&state))?;
+        }
+        info!("VM[{}] restored from {}", self.id(), path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vcpu_state_words() {
+        let mut state = VCpuState::default();
+        for (i, gpr) in state.gprs.iter_mut().enumerate() {
+            *gpr = i * 0x101;
+        }
+        state.sstatus = 0x2_0000_6120;
+        state.sepc = 0x8020_0000;
+        state.guest_time = 0x1234_5678;
+        state.vsatp = 0x8000_0000_0008_0400;
+        state.requests = 0b1010;
+        for (i, fp) in state.fp.iter_mut().enumerate() {
+            *fp = (i as u64) << 52 | 0xf00d;
+        }
+        state.fcsr = 0x85;
+        state.timer_deadline = 0xdead_beef;
+
+        let words = vcpu_state_words(&state);
+        assert_eq!(words.len(), VCPU_STATE_WORDS);
+        let restored = vcpu_state_from_words(&words);
+        assert_eq!(restored.gprs, state.gprs);
+        assert_eq!(restored.sstatus, state.sstatus);
+        assert_eq!(restored.sepc, state.sepc);
+        assert_eq!(restored.guest_time, state.guest_time);
+        assert_eq!(restored.vsatp, state.vsatp);
+        assert_eq!(restored.requests, state.requests);
+        assert_eq!(restored.fp, state.fp);
+        assert_eq!(restored.fcsr, state.fcsr);
+        assert_eq!(restored.timer_deadline, state.timer_deadline);
+        assert_eq!(vcpu_state_words(&restored), words);
+    }
+}
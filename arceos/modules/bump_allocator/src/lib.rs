@@ -29,8 +29,9 @@ impl<const SIZE: usize> BaseAllocator for EarlyAllocator<SIZE> {
         todo!()
     }
 
-    fn add_memory(&mut self, start: usize, size: usize) -> allocator::AllocResult {
-        todo!()
+    /// The early allocator only manages the region given to `init`.
+    fn add_memory(&mut self, _start: usize, _size: usize) -> allocator::AllocResult {
+        Err(allocator::AllocError::NoMemory)
     }
 }
 
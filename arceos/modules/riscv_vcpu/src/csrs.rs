@@ -54,6 +54,7 @@ impl<R: RegisterLongName, const V: u16> ReadWriteCsr<R, V> {
     }
 }
 
+#[cfg(target_arch = "riscv64")]
 impl<R: RegisterLongName, const V: u16> RiscvCsrTrait for ReadWriteCsr<R, V> {
     type R = R;
 
@@ -96,6 +97,33 @@ impl<R: RegisterLongName, const V: u16> RiscvCsrTrait for ReadWriteCsr<R, V> {
     }
 }
 
+/// CSRs are not accessible on other targets, the crate is only built there
+/// for the unit tests, which do not access them.
+#[cfg(not(target_arch = "riscv64"))]
+impl<R: RegisterLongName, const V: u16> RiscvCsrTrait for ReadWriteCsr<R, V> {
+    type R = R;
+
+    fn get_value(&self) -> usize {
+        unimplemented!("CSR {:#x} is only accessible on RISC-V", V)
+    }
+
+    fn write_value(&self, _value: usize) {
+        unimplemented!("CSR {:#x} is only accessible on RISC-V", V)
+    }
+
+    fn atomic_replace(&self, _value: usize) -> usize {
+        unimplemented!("CSR {:#x} is only accessible on RISC-V", V)
+    }
+
+    fn read_and_set_bits(&self, _bitmask: usize) -> usize {
+        unimplemented!("CSR {:#x} is only accessible on RISC-V", V)
+    }
+
+    fn read_and_clear_bits(&self, _bitmask: usize) -> usize {
+        unimplemented!("CSR {:#x} is only accessible on RISC-V", V)
+    }
+}
+
 // The Readable and Writeable traits aren't object-safe so unfortunately we can't implement them
 // for RiscvCsrInterface.
 impl<R: RegisterLongName, const V: u16> Readable for ReadWriteCsr<R, V> {
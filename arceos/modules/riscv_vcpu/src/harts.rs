@@ -0,0 +1,265 @@
+//! Harts (vCPUs) of a VM, shared between the vCPUs to emulate the SBI HSM,
+//! IPI and remote fence extensions.
+//!
+//! A request to another hart (e.g., an IPI) is recorded in the target hart,
+//! which handles it before entering the guest next time. If the target is
+//! running the guest on another host hart, a host IPI is sent to kick it out.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[cfg(target_arch = "riscv64")]
+use axhal::irq::kick_cpu;
+
+#[cfg(not(target_arch = "riscv64"))]
+use crate::host::kick_cpu;
+use crate::sbi::{SBI_ERR_ALREADY_AVAILABLE, SBI_ERR_INAVLID_PARAM, SBI_SUCCESS};
+
+/// The hart is running.
+pub const HART_STATE_STARTED: usize = 0;
+/// The hart is not running, and can be started by `hart_start`.
+pub const HART_STATE_STOPPED: usize = 1;
+/// `hart_start` is called, but the hart has not started yet.
+pub const HART_STATE_START_PENDING: usize = 2;
+
+/// Requests to a hart.
+pub(crate) const REQ_START: usize = 1 << 0;
+pub(crate) const REQ_IPI: usize = 1 << 1;
+pub(crate) const REQ_FENCE_I: usize = 1 << 2;
+pub(crate) const REQ_HFENCE_VVMA: usize = 1 << 3;
+pub(crate) const REQ_HFENCE_GVMA: usize = 1 << 4;
+
+/// `host_hart` of a vCPU not in the guest.
+const NOT_RUNNING: usize = usize::MAX;
+
+struct HartState {
+    status: AtomicUsize,
+    start_addr: AtomicUsize,
+    opaque: AtomicUsize,
+    requests: AtomicUsize,
+    /// The host hart on which the vCPU is running the guest.
+    host_hart: AtomicUsize,
+    /// Level of the external interrupt, i.e., `VSEIP`.
+    ext_irq: AtomicBool,
+}
+
+impl HartState {
+    /// Kicks the vCPU out of the guest if it is running on another host hart,
+    /// so that the changes take effect soon.
+    fn kick(&self) {
+        let host_hart = self.host_hart.load(Ordering::Acquire);
+        if host_hart != NOT_RUNNING && host_hart != axhal::cpu::this_cpu_id() {
+            kick_cpu(host_hart);
+        }
+    }
+}
+
+/// The harts of a VM.
+pub struct VmHarts {
+    harts: Vec<HartState>,
+}
+
+impl VmHarts {
+    /// Creates `num` harts, only the boot hart (hart 0) is started.
+    pub fn new(num: usize) -> Self {
+        let harts = (0..num)
+            .map(|i| HartState {
+                status: AtomicUsize::new(if i == 0 {
+                    HART_STATE_STARTED
+                } else {
+                    HART_STATE_STOPPED
+                }),
+                start_addr: AtomicUsize::new(0),
+                opaque: AtomicUsize::new(0),
+                requests: AtomicUsize::new(0),
+                host_hart: AtomicUsize::new(NOT_RUNNING),
+                ext_irq: AtomicBool::new(false),
+            })
+            .collect();
+        Self { harts }
+    }
+
+    /// Returns the number of harts.
+    pub fn num_harts(&self) -> usize {
+        self.harts.len()
+    }
+
+    /// Returns the HSM state of the given hart.
+    pub fn status(&self, hartid: usize) -> Option<usize> {
+        self.harts
+            .get(hartid)
+            .map(|h| h.status.load(Ordering::Acquire))
+    }
+
+    pub(crate) fn hart_start(&self, hartid: usize, start_addr: usize, opaque: usize) -> isize {
+        let Some(hart) = self.harts.get(hartid) else {
+            return SBI_ERR_INAVLID_PARAM;
+        };
+        if hart
+            .status
+            .compare_exchange(
+                HART_STATE_STOPPED,
+                HART_STATE_START_PENDING,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_err()
+        {
+            return SBI_ERR_ALREADY_AVAILABLE;
+        }
+        hart.start_addr.store(start_addr, Ordering::Relaxed);
+        hart.opaque.store(opaque, Ordering::Relaxed);
+        hart.requests.fetch_or(REQ_START, Ordering::Release);
+        SBI_SUCCESS as isize
+    }
+
+    /// Takes the start address and opaque argument given by `hart_start`, if
+    /// the hart is requested to start.
+    pub(crate) fn take_start(&self, hartid: usize) -> Option<(usize, usize)> {
+        let hart = &self.harts[hartid];
+        if hart.requests.fetch_and(!REQ_START, Ordering::Acquire) & REQ_START == 0 {
+            return None;
+        }
+        let start = (
+            hart.start_addr.load(Ordering::Relaxed),
+            hart.opaque.load(Ordering::Relaxed),
+        );
+        hart.status.store(HART_STATE_STARTED, Ordering::Release);
+        Some(start)
+    }
+
+    pub(crate) fn hart_stop(&self, hartid: usize) {
+        self.harts[hartid]
+            .status
+            .store(HART_STATE_STOPPED, Ordering::Release);
+    }
+
+    /// Posts `req` to the harts in the mask, returns an SBI error code.
+    ///
+    /// If `wait`, it waits until the harts running the guest on other host
+    /// harts have handled the request.
+    pub(crate) fn send_request(
+        &self,
+        hart_mask: usize,
+        hart_mask_base: usize,
+        req: usize,
+        wait: bool,
+    ) -> isize {
+        let targets = if hart_mask_base == usize::MAX {
+            // All harts.
+            (1usize << self.harts.len().min(usize::BITS as usize - 1)) - 1
+        } else {
+            if hart_mask_base >= self.harts.len() {
+                return SBI_ERR_INAVLID_PARAM;
+            }
+            let valid = self.harts.len() - hart_mask_base;
+            if valid < usize::BITS as usize && hart_mask >> valid != 0 {
+                return SBI_ERR_INAVLID_PARAM;
+            }
+            hart_mask
+        };
+        let base = if hart_mask_base == usize::MAX {
+            0
+        } else {
+            hart_mask_base
+        };
+        let harts = (0..usize::BITS as usize)
+            .filter(|i| targets & (1 << i) != 0)
+            .map(|i| &self.harts[base + i]);
+        for hart in harts.clone() {
+            hart.requests.fetch_or(req, Ordering::Release);
+            hart.kick();
+        }
+        if wait {
+            for hart in harts {
+                while hart.requests.load(Ordering::Acquire) & req != 0
+                    && hart.host_hart.load(Ordering::Acquire) != NOT_RUNNING
+                {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+        SBI_SUCCESS as isize
+    }
+
+    /// Sets the level of the external interrupt of the hart, which is injected
+    /// as the virtual supervisor external interrupt.
+    pub fn set_external_irq(&self, hartid: usize, level: bool) {
+        let Some(hart) = self.harts.get(hartid) else {
+            return;
+        };
+        if hart.ext_irq.swap(level, Ordering::AcqRel) != level {
+            hart.kick();
+        }
+    }
+
+    /// Kicks the hart out of the guest, if it is running the guest on another
+    /// host hart.
+    pub fn kick(&self, hartid: usize) {
+        if let Some(hart) = self.harts.get(hartid) {
+            hart.kick();
+        }
+    }
+
+    /// Requests all harts to synchronize their instruction caches before
+    /// entering the guest again, after the guest code is modified by the
+    /// hypervisor.
+    pub fn fence_i_all(&self) {
+        self.send_request(0, usize::MAX, REQ_FENCE_I, false);
+    }
+
+    /// Flushes the nested TLB of all harts after mappings of the guest memory
+    /// are removed, and waits until the harts running the guest have done it.
+    pub fn hfence_gvma_all(&self) {
+        self.send_request(0, usize::MAX, REQ_HFENCE_GVMA, true);
+    }
+
+    /// Returns the HSM state, the `hart_start` arguments and the pending
+    /// requests of the hart, to be saved in a snapshot.
+    pub(crate) fn save_hart(&self, hartid: usize) -> (usize, usize, usize, usize) {
+        let hart = &self.harts[hartid];
+        (
+            hart.status.load(Ordering::Acquire),
+            hart.start_addr.load(Ordering::Relaxed),
+            hart.opaque.load(Ordering::Relaxed),
+            hart.requests.load(Ordering::Acquire),
+        )
+    }
+
+    /// Restores the state saved by `save_hart`.
+    pub(crate) fn restore_hart(
+        &self,
+        hartid: usize,
+        status: usize,
+        start_addr: usize,
+        opaque: usize,
+        requests: usize,
+    ) {
+        let hart = &self.harts[hartid];
+        hart.start_addr.store(start_addr, Ordering::Relaxed);
+        hart.opaque.store(opaque, Ordering::Relaxed);
+        hart.requests.store(requests, Ordering::Release);
+        hart.status.store(status, Ordering::Release);
+    }
+
+    /// Returns the level of the external interrupt of the hart.
+    pub(crate) fn external_irq(&self, hartid: usize) -> bool {
+        self.harts[hartid].ext_irq.load(Ordering::Acquire)
+    }
+
+    /// Marks the hart as running the guest on the current host hart, and takes
+    /// the pending requests except `REQ_START`.
+    pub(crate) fn enter_guest(&self, hartid: usize) -> usize {
+        let hart = &self.harts[hartid];
+        hart.host_hart
+            .store(axhal::cpu::this_cpu_id(), Ordering::Release);
+        hart.requests.fetch_and(REQ_START, Ordering::AcqRel) & !REQ_START
+    }
+
+    /// Marks the hart as not running the guest.
+    pub(crate) fn exit_guest(&self, hartid: usize) {
+        self.harts[hartid]
+            .host_hart
+            .store(NOT_RUNNING, Ordering::Release);
+    }
+}
@@ -0,0 +1,34 @@
+//! Stand-ins of the RISC-V specific parts of the crate on other targets.
+//!
+//! They let the crate and its dependents build for the unit tests on the
+//! host (`make unittest`), which never run a guest.
+
+/// The FP registers, as `axhal::arch::FpState` on RISC-V.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FpState {
+    /// The F/D registers (f0-f31).
+    pub fp: [u64; 32],
+    /// Floating-point Control and Status Register.
+    pub fcsr: usize,
+}
+
+impl FpState {
+    pub fn save(&mut self) {
+        unimplemented!("FP registers are only accessible on RISC-V")
+    }
+
+    pub fn restore(&self) {
+        unimplemented!("FP registers are only accessible on RISC-V")
+    }
+}
+
+/// No guest runs on the host, so there is no vCPU to kick out of it.
+pub fn kick_cpu(_cpu_id: usize) {}
+
+pub unsafe fn _run_guest<T>(_state: *mut T) {
+    unimplemented!("guests only run on RISC-V")
+}
+
+pub unsafe fn _fetch_guest_instruction(_gva: usize, _insn: *mut u32) -> isize {
+    unimplemented!("guests only run on RISC-V")
+}
@@ -1,24 +1,39 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
 #![feature(naked_functions)]
 #![feature(riscv_ext_intrinsics)]
 #![feature(asm_const)]
 #![doc = include_str!("../README.md")]
 
+extern crate alloc;
 #[macro_use]
 extern crate log;
 
 pub mod csrs;
+#[cfg(target_arch = "riscv64")]
 mod detect;
+mod harts;
+#[cfg(not(target_arch = "riscv64"))]
+mod host;
 mod regs;
 pub mod sbi;
 mod vcpu;
+mod vmexit;
 
-pub use self::vcpu::RISCVVCpu;
+pub use self::harts::{VmHarts, HART_STATE_STARTED, HART_STATE_START_PENDING, HART_STATE_STOPPED};
+pub use self::regs::GprIndex;
+pub use self::vcpu::{AccessWidth, RISCVVCpu, VCpuState};
+#[cfg(target_arch = "riscv64")]
 pub use detect::detect_h_extension as has_hardware_support;
 pub use vcpu::AxVCpuExitReason;
 use csrs::{traps, CSR, RiscvCsrTrait};
 
+/// The hypervisor extension is never available on other targets.
+#[cfg(not(target_arch = "riscv64"))]
+pub fn has_hardware_support() -> bool {
+    false
+}
+
 pub struct RISCVPerCpu {}
 
 /// Initialize (H)S-level CSRs to a reasonable state.
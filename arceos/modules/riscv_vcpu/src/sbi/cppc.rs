@@ -0,0 +1,53 @@
+use axerrno::{AxError, AxResult};
+
+/// Extension ID of the CPPC extension.
+pub const EID_CPPC: usize = 0x4350_5043;
+
+const CPPC_PROBE: usize = 0;
+const CPPC_READ: usize = 1;
+const CPPC_READ_HI: usize = 2;
+const CPPC_WRITE: usize = 3;
+
+/// Functions for the Collaborative Processor Performance Control extension
+#[derive(Copy, Clone, Debug)]
+pub enum CppcFunction {
+    /// Returns the width of the register, or 0 if it is not implemented.
+    Probe {
+        /// The CPPC register ID.
+        reg_id: u32,
+    },
+    /// Reads the register, or its lower 32 bits for RV32.
+    Read {
+        /// The CPPC register ID.
+        reg_id: u32,
+    },
+    /// Reads the upper 32 bits of the register for RV32.
+    ReadHi {
+        /// The CPPC register ID.
+        reg_id: u32,
+    },
+    /// Writes the register.
+    Write {
+        /// The CPPC register ID.
+        reg_id: u32,
+        /// The value to write.
+        val: u64,
+    },
+}
+
+impl CppcFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[usize]) -> AxResult<Self> {
+        let reg_id = args[0] as u32;
+        match args[6] {
+            CPPC_PROBE => Ok(Self::Probe { reg_id }),
+            CPPC_READ => Ok(Self::Read { reg_id }),
+            CPPC_READ_HI => Ok(Self::ReadHi { reg_id }),
+            CPPC_WRITE => Ok(Self::Write {
+                reg_id,
+                val: args[1] as u64,
+            }),
+            _ => Err(AxError::NotFound),
+        }
+    }
+}
@@ -1,11 +1,53 @@
+use axerrno::{AxError, AxResult};
+
+/// Extension ID of the Debug Console extension.
+pub const EID_DBCN: usize = 0x4442_434e;
+
+const CONSOLE_WRITE: usize = 0;
+const CONSOLE_READ: usize = 1;
+const CONSOLE_WRITE_BYTE: usize = 2;
+
 /// Functions for the Debug Console extension
 #[derive(Copy, Clone, Debug)]
 pub enum DebugConsoleFunction {
-    /// Prints the given string to the system console.
-    PutString {
-        /// The length of the string to print.
-        len: u64,
-        /// The address of the string.
-        addr: u64,
+    /// Writes the bytes at the given guest physical address to the console.
+    Write {
+        /// The number of bytes to write.
+        num_bytes: u64,
+        /// The guest physical address of the bytes.
+        base_addr: u64,
     },
+    /// Reads the bytes from the console into the given guest physical
+    /// address, without blocking.
+    Read {
+        /// The size of the buffer.
+        num_bytes: u64,
+        /// The guest physical address of the buffer.
+        base_addr: u64,
+    },
+    /// Writes a single byte to the console.
+    WriteByte(u8),
+}
+
+impl DebugConsoleFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[usize]) -> AxResult<Self> {
+        // `a2` holds the address bits beyond XLEN, which must be 0 on RV64.
+        let base_addr = args[1] as u64;
+        if matches!(args[6], CONSOLE_WRITE | CONSOLE_READ) && args[2] != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        match args[6] {
+            CONSOLE_WRITE => Ok(Self::Write {
+                num_bytes: args[0] as u64,
+                base_addr,
+            }),
+            CONSOLE_READ => Ok(Self::Read {
+                num_bytes: args[0] as u64,
+                base_addr,
+            }),
+            CONSOLE_WRITE_BYTE => Ok(Self::WriteByte(args[0] as u8)),
+            _ => Err(AxError::NotFound),
+        }
+    }
 }
@@ -0,0 +1,57 @@
+use sbi_spec::hsm::{HART_GET_STATUS, HART_START, HART_STOP, HART_SUSPEND};
+
+use axerrno::{AxError, AxResult};
+
+/// Functions for the Hart State Management extension
+#[derive(Copy, Clone, Debug)]
+pub enum HsmFunction {
+    /// Starts the given hart at `start_addr` in supervisor mode, with `a0` set
+    /// to the hart ID and `a1` set to `opaque`.
+    HartStart {
+        /// The hart to start.
+        hartid: u64,
+        /// The guest physical address to start at.
+        start_addr: u64,
+        /// The value passed in `a1`.
+        opaque: u64,
+    },
+    /// Stops the calling hart.
+    HartStop,
+    /// Returns the HSM state of the given hart.
+    HartGetStatus {
+        /// The hart to query.
+        hartid: u64,
+    },
+    /// Suspends the calling hart.
+    HartSuspend {
+        /// The type of the suspend.
+        suspend_type: u32,
+        /// The address to resume at for non-retentive suspends.
+        resume_addr: u64,
+        /// The value passed in `a1` on resume.
+        opaque: u64,
+    },
+}
+
+impl HsmFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[usize]) -> AxResult<Self> {
+        match args[6] {
+            HART_START => Ok(Self::HartStart {
+                hartid: args[0] as u64,
+                start_addr: args[1] as u64,
+                opaque: args[2] as u64,
+            }),
+            HART_STOP => Ok(Self::HartStop),
+            HART_GET_STATUS => Ok(Self::HartGetStatus {
+                hartid: args[0] as u64,
+            }),
+            HART_SUSPEND => Ok(Self::HartSuspend {
+                suspend_type: args[0] as u32,
+                resume_addr: args[1] as u64,
+                opaque: args[2] as u64,
+            }),
+            _ => Err(AxError::NotFound),
+        }
+    }
+}
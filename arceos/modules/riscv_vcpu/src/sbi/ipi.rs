@@ -0,0 +1,28 @@
+use sbi_spec::spi::SEND_IPI;
+
+use axerrno::{AxError, AxResult};
+
+/// Functions for the IPI extension
+#[derive(Copy, Clone, Debug)]
+pub enum IpiFunction {
+    /// Sends a supervisor software interrupt to the harts in the mask.
+    SendIpi {
+        /// The mask of the harts, relative to `hart_mask_base`.
+        hart_mask: u64,
+        /// The starting hart ID of the mask, or `-1` for all harts.
+        hart_mask_base: u64,
+    },
+}
+
+impl IpiFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[usize]) -> AxResult<Self> {
+        match args[6] {
+            SEND_IPI => Ok(Self::SendIpi {
+                hart_mask: args[0] as u64,
+                hart_mask_base: args[1] as u64,
+            }),
+            _ => Err(AxError::NotFound),
+        }
+    }
+}
@@ -1,16 +1,24 @@
 mod base;
+mod cppc;
 mod dbcn;
+mod hsm;
+mod ipi;
 mod pmu;
 mod rfnc;
 mod srst;
+mod susp;
 
 use axerrno::{AxError, AxResult};
 pub use base::BaseFunction;
-use dbcn::DebugConsoleFunction;
+pub use cppc::{CppcFunction, EID_CPPC};
+pub use dbcn::{DebugConsoleFunction, EID_DBCN};
+pub use hsm::HsmFunction;
+pub use ipi::IpiFunction;
 pub use pmu::PmuFunction;
 pub use rfnc::RemoteFenceFunction;
 use sbi_spec;
 pub use srst::ResetFunction;
+pub use susp::{SuspendFunction, EID_SUSP, SUSPEND_TO_RAM};
 
 pub const SBI_SUCCESS: usize = 0;
 pub const SBI_ERR_FAILUER: isize = -1;
@@ -58,12 +66,23 @@ pub enum SbiMessage {
     RemoteFence(RemoteFenceFunction),
     /// The PMU Extension
     PMU(PmuFunction),
+    /// The Hart State Management Extension
+    Hsm(HsmFunction),
+    /// The IPI Extension
+    Ipi(IpiFunction),
+    /// The System Suspend Extension
+    Suspend(SuspendFunction),
+    /// The CPPC Extension
+    Cppc(CppcFunction),
 }
 
 impl SbiMessage {
     /// Creates an SbiMessage struct from the given GPRs. Intended for use from the ECALL handler
     /// and passed the saved register state from the calling OS. A7 must contain a valid SBI
     /// extension and the other A* registers will be interpreted based on the extension A7 selects.
+    ///
+    /// Returns `NotFound` for unknown extensions and functions, and `InvalidInput` for invalid
+    /// arguments.
     pub fn from_regs(args: &[usize]) -> AxResult<Self> {
         match args[7] {
             sbi_spec::base::EID_BASE => BaseFunction::from_regs(args).map(SbiMessage::Base),
@@ -77,10 +96,13 @@ impl SbiMessage {
                 RemoteFenceFunction::from_args(args).map(SbiMessage::RemoteFence)
             }
             sbi_spec::pmu::EID_PMU => PmuFunction::from_regs(args).map(SbiMessage::PMU),
+            sbi_spec::hsm::EID_HSM => HsmFunction::from_regs(args).map(SbiMessage::Hsm),
+            sbi_spec::spi::EID_SPI => IpiFunction::from_regs(args).map(SbiMessage::Ipi),
+            EID_DBCN => DebugConsoleFunction::from_regs(args).map(SbiMessage::DebugConsole),
+            EID_SUSP => SuspendFunction::from_regs(args).map(SbiMessage::Suspend),
+            EID_CPPC => CppcFunction::from_regs(args).map(SbiMessage::Cppc),
             _ => {
-                error!("args: {:?}", args);
-                error!("args[7]: {:#x}", args[7]);
-                error!("EID_RFENCE: {:#x}", sbi_spec::rfnc::EID_RFNC);
+                debug!("unknown SBI extension {:#x}", args[7]);
                 Err(AxError::NotFound)
             }
         }
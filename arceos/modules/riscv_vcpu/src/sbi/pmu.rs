@@ -1,4 +1,4 @@
-use axerrno::AxResult;
+use axerrno::{AxError, AxResult};
 
 #[derive(Clone, Copy, Debug)]
 pub enum PmuFunction {
@@ -28,7 +28,7 @@ impl PmuFunction {
                 counter_mask: args[1] as u64,
                 stop_flags: args[2] as u64,
             }),
-            _ => panic!("Unsupported yet!"),
+            _ => Err(AxError::NotFound),
         }
     }
 }
@@ -70,7 +70,7 @@ impl ResetFunction {
                 reset_type: ResetType::from_reg(args[0])?,
                 reason: ResetReason::from_reg(args[1])?,
             },
-            _ => return Err(AxError::InvalidInput),
+            _ => return Err(AxError::NotFound),
         })
     }
 
@@ -0,0 +1,38 @@
+use axerrno::{AxError, AxResult};
+
+/// Extension ID of the System Suspend extension.
+pub const EID_SUSP: usize = 0x5355_5350;
+
+const SYSTEM_SUSPEND: usize = 0;
+
+/// The only sleep type defined by the specification.
+pub const SUSPEND_TO_RAM: u32 = 0;
+
+/// Functions for the System Suspend extension
+#[derive(Copy, Clone, Debug)]
+pub enum SuspendFunction {
+    /// Suspends the system, which resumes at `resume_addr` in supervisor mode
+    /// with `a0` set to the hart ID and `a1` set to `opaque`.
+    SystemSuspend {
+        /// The type of the sleep.
+        sleep_type: u32,
+        /// The guest physical address to resume at.
+        resume_addr: u64,
+        /// The value passed in `a1` on resume.
+        opaque: u64,
+    },
+}
+
+impl SuspendFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[usize]) -> AxResult<Self> {
+        match args[6] {
+            SYSTEM_SUSPEND => Ok(Self::SystemSuspend {
+                sleep_type: args[0] as u32,
+                resume_addr: args[1] as u64,
+                opaque: args[2] as u64,
+            }),
+            _ => Err(AxError::NotFound),
+        }
+    }
+}
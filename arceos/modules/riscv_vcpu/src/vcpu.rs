@@ -1,4 +1,7 @@
-use core::arch::global_asm;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+#[cfg(target_arch = "riscv64")]
+use core::arch::{asm, global_asm};
 use core::mem::size_of;
 
 use memoffset::offset_of;
@@ -6,18 +9,38 @@ use riscv::register::{htinst, htval, scause, sstatus, stval};
 use sbi_rt::{pmu_counter_get_info, pmu_counter_stop};
 use tock_registers::LocalRegisterCopy;
 
-use axerrno::AxResult;
+use axerrno::{AxError, AxResult};
 
 use super::csrs::defs::hstatus;
 use super::csrs::{traps, RiscvCsrTrait, CSR};
-use super::sbi::{BaseFunction, PmuFunction, RemoteFenceFunction, SbiMessage};
+use super::harts::{self, VmHarts, HART_STATE_STOPPED, REQ_IPI};
+#[cfg(not(target_arch = "riscv64"))]
+use super::host::{FpState, _fetch_guest_instruction, _run_guest};
+use super::sbi::{
+    BaseFunction, CppcFunction, DebugConsoleFunction, HsmFunction, IpiFunction, PmuFunction,
+    RemoteFenceFunction, SbiMessage, SuspendFunction, EID_CPPC, EID_DBCN, EID_SUSP, SBI_ERR_DENIED,
+    SBI_ERR_INAVLID_PARAM, SBI_ERR_NOT_SUPPORTED, SBI_SUCCESS, SUSPEND_TO_RAM,
+};
 
 use super::regs::{GeneralPurposeRegisters, GprIndex};
+use super::vmexit::MmioInstruction;
 use memory_addr::{VirtAddr, PhysAddr};
+#[cfg(target_arch = "riscv64")]
+use axhal::arch::FpState;
+use axhal::mem::{memory_regions, phys_to_virt, MemRegionFlags};
 use axhal::paging::MappingFlags;
 
 /// Guest physical address.
 pub type GuestPhysAddr = VirtAddr;
+
+/// The `FS` field of `sstatus`, and its values.
+const SSTATUS_FS: usize = 0b11 << 13;
+const FS_INITIAL: usize = 0b01 << 13;
+const FS_CLEAN: usize = 0b10 << 13;
+const FS_DIRTY: usize = 0b11 << 13;
+
+/// `timer_deadline` of a vCPU that has no timer set.
+const NO_TIMER: u64 = u64::MAX;
 /// Host physical address.
 pub type HostPhysAddr = PhysAddr;
 
@@ -69,6 +92,7 @@ pub struct GuestVirtualHsCsrs {
     hie: usize,
     hgeie: usize,
     hgatp: usize,
+    hvip: usize,
 }
 
 /// CSRs written on an exit from virtualization that are used by the hypervisor to determine the cause
@@ -103,6 +127,41 @@ pub struct VmCpuRegisters {
     pub trap_csrs: VmCpuTrapState,
 }
 
+/// Architectural state of a vCPU, saved by [`RISCVVCpu::save_state`] when the
+/// vCPU is not running, e.g., for snapshots.
+#[derive(Debug, Clone, Default)]
+pub struct VCpuState {
+    pub gprs: [usize; 32],
+    pub sstatus: usize,
+    pub hstatus: usize,
+    pub scounteren: usize,
+    pub sepc: usize,
+    /// The guest time (`time` + `htimedelta`), instead of `htimedelta` itself,
+    /// so the guest time is continuous after restoring on another host.
+    pub guest_time: usize,
+    pub vsstatus: usize,
+    pub vsie: usize,
+    pub vstvec: usize,
+    pub vsscratch: usize,
+    pub vsepc: usize,
+    pub vscause: usize,
+    pub vstval: usize,
+    pub vsatp: usize,
+    pub hvip: usize,
+    /// HSM state of the hart, and the arguments of a pending `hart_start`.
+    pub hart_status: usize,
+    pub start_addr: usize,
+    pub opaque: usize,
+    /// Requests from the other harts not handled yet.
+    pub requests: usize,
+    /// The F/D registers (f0-f31) and `fcsr`.
+    pub fp: [u64; 32],
+    pub fcsr: usize,
+    /// The guest time of the timer set by SBI `set_timer`, or `u64::MAX` if
+    /// none is set.
+    pub timer_deadline: u64,
+}
+
 #[allow(dead_code)]
 const fn hyp_gpr_offset(index: GprIndex) -> usize {
     offset_of!(VmCpuRegisters, hyp_regs)
@@ -131,6 +190,7 @@ macro_rules! guest_csr_offset {
     };
 }
 
+#[cfg(target_arch = "riscv64")]
 global_asm!(
     include_str!("guest.S"),
     hyp_ra = const hyp_gpr_offset(GprIndex::RA),
@@ -199,18 +259,43 @@ global_asm!(
 
 );
 
+#[cfg(target_arch = "riscv64")]
+global_asm!(include_str!("mem_extable.S"));
+
+#[cfg(target_arch = "riscv64")]
 extern "C" {
     fn _run_guest(state: *mut VmCpuRegisters);
+    /// Fetches the instruction at the guest virtual address `gva` to `insn`,
+    /// returns -1 if it faults.
+    fn _fetch_guest_instruction(gva: usize, insn: *mut u32) -> isize;
 }
 
 /// The architecture dependent configuration of a `AxArchVCpu`.
 #[derive(Clone, Copy, Debug, Default)]
 pub struct VCpuConfig {}
 
-#[derive(Default)]
 /// A virtual CPU within a guest
 pub struct RISCVVCpu {
     regs: VmCpuRegisters,
+    /// All harts of the VM, shared with the other vCPUs.
+    harts: Arc<VmHarts>,
+    /// The hart ID of this vCPU in the VM.
+    hart_id: usize,
+    /// Guest physical regions whose accesses are reported as MMIO exits.
+    mmio_regions: Vec<(GuestPhysAddr, usize)>,
+    /// Length of the instruction of the pending MMIO access.
+    mmio_insn_len: usize,
+    /// Whether guest breakpoints are reported to the hypervisor.
+    debug: bool,
+    /// The FP registers of the guest while it is not running, the guest
+    /// `sstatus.FS` tells whether the registers are changed since loaded.
+    fp: FpState,
+    /// The guest time of the pending timer set by SBI `set_timer`, or
+    /// [`NO_TIMER`] once it has fired.
+    timer_deadline: u64,
+    /// Whether the host timer must be programmed for `timer_deadline` before
+    /// entering the guest, e.g., after restoring the state.
+    timer_reload: bool,
 }
 
 impl RISCVVCpu {
@@ -222,6 +307,7 @@ impl RISCVVCpu {
 
     pub fn set_ept_root(&mut self, ept_root: HostPhysAddr) -> AxResult {
         self.regs.virtual_hs_csrs.hgatp = 8usize << 60 | usize::from(ept_root) >> 12;
+        #[cfg(target_arch = "riscv64")]
         unsafe {
             core::arch::asm!(
                 "csrw hgatp, {hgatp}",
@@ -232,19 +318,50 @@ impl RISCVVCpu {
         Ok(())
     }
 
+    /// Runs the guest until a VM exit that needs the hypervisor's attention.
+    ///
+    /// If the hart is stopped (see [`VmHarts`]), it returns
+    /// [`AxVCpuExitReason::CpuDown`] immediately, the caller should yield and
+    /// try again later.
     pub fn run(&mut self) -> AxResult<AxVCpuExitReason> {
-        let regs = &mut self.regs;
+        if !self.check_started() {
+            return Ok(AxVCpuExitReason::CpuDown);
+        }
+        if self.timer_reload {
+            self.timer_reload = false;
+            if self.timer_deadline != NO_TIMER {
+                self.set_timer(self.timer_deadline);
+            }
+        }
+        let requests = self.harts.enter_guest(self.hart_id);
+        self.load_guest_csrs(requests);
+        let host_fp = self.load_guest_fp();
         unsafe {
             // Safe to run the guest as it only touches memory assigned to it by being owned
             // by its page table
-            _run_guest(regs);
+            _run_guest(&mut self.regs);
         }
-        self.vmexit_handler()
+        self.save_guest_fp(host_fp);
+        self.harts.exit_guest(self.hart_id);
+        let exit_reason = self.vmexit_handler();
+        self.save_guest_csrs();
+        exit_reason
     }
 }
 
 impl RISCVVCpu {
+    /// Creates the vCPU of a uniprocessor VM.
     pub fn init() -> Self {
+        Self::init_smp(Arc::new(VmHarts::new(1)), 0)
+    }
+
+    /// Creates the vCPU of hart `hart_id` in a multiprocessor VM.
+    ///
+    /// Only the boot hart (hart 0) is started, the others are started by the
+    /// guest with SBI `hart_start`. Each vCPU should run on its own host task
+    /// or CPU, and [`setup_csrs`](crate::setup_csrs) must be called on every
+    /// host CPU that runs a vCPU.
+    pub fn init_smp(harts: Arc<VmHarts>, hart_id: usize) -> Self {
         let mut regs = VmCpuRegisters::default();
         // Set hstatus
         let mut hstatus = LocalRegisterCopy::<usize, hstatus::Register>::new(
@@ -256,14 +373,29 @@ impl RISCVVCpu {
         CSR.hstatus.write_value(hstatus.get());
         regs.guest_regs.hstatus = hstatus.get();
 
-        // Set sstatus
+        // Set sstatus, the guest FP registers start zeroed.
         let mut sstatus = sstatus::read();
         sstatus.set_spp(sstatus::SPP::Supervisor);
-        regs.guest_regs.sstatus = sstatus.bits();
+        regs.guest_regs.sstatus = (sstatus.bits() & !SSTATUS_FS) | FS_INITIAL;
 
         CSR.sie
             .read_and_clear_bits(traps::interrupt::SUPERVISOR_TIMER);
-        Self { regs }
+        Self {
+            regs,
+            harts,
+            hart_id,
+            mmio_regions: Vec::new(),
+            mmio_insn_len: 0,
+            debug: false,
+            fp: FpState::default(),
+            timer_deadline: NO_TIMER,
+            timer_reload: false,
+        }
+    }
+
+    /// Returns the hart ID of this vCPU in the VM.
+    pub fn hart_id(&self) -> usize {
+        self.hart_id
     }
 
     /// Gets one of the vCPU's general purpose registers.
@@ -276,6 +408,30 @@ impl RISCVVCpu {
         self.regs.guest_regs.gprs.set_reg(index, val);
     }
 
+    /// Gets the guest pc.
+    pub fn pc(&self) -> usize {
+        self.regs.guest_regs.sepc
+    }
+
+    /// Sets the guest pc.
+    pub fn set_pc(&mut self, pc: usize) {
+        self.regs.guest_regs.sepc = pc;
+    }
+
+    /// Gets the guest `satp` (`vsatp`), to translate guest virtual addresses.
+    pub fn guest_satp(&self) -> usize {
+        self.regs.vs_csrs.vsatp
+    }
+
+    /// Enables or disables guest debugging.
+    ///
+    /// When enabled, breakpoint exceptions (`ebreak`) in the guest are not
+    /// delegated to the guest, but reported as
+    /// [`AxVCpuExitReason::Breakpoint`].
+    pub fn set_debug(&mut self, enable: bool) {
+        self.debug = enable;
+    }
+
     /// Advance guest pc by `instr_len` bytes
     pub fn advance_pc(&mut self, instr_len: usize) {
         self.regs.guest_regs.sepc += instr_len
@@ -285,6 +441,121 @@ impl RISCVVCpu {
     pub fn regs(&mut self) -> &mut VmCpuRegisters {
         &mut self.regs
     }
+
+    /// Registers an emulated MMIO region.
+    ///
+    /// Loads and stores that fault in the region are decoded and reported as
+    /// [`AxVCpuExitReason::MmioRead`] and [`AxVCpuExitReason::MmioWrite`],
+    /// other faults are still reported as [`AxVCpuExitReason::NestedPageFault`].
+    pub fn add_mmio_region(&mut self, addr: GuestPhysAddr, size: usize) {
+        self.mmio_regions.push((addr, size));
+    }
+
+    /// Completes an [`AxVCpuExitReason::MmioRead`] exit by writing the value
+    /// read from the device into the destination register, and advances the
+    /// guest pc past the load instruction.
+    pub fn complete_mmio_read(
+        &mut self,
+        reg: usize,
+        width: AccessWidth,
+        signed_ext: bool,
+        val: u64,
+    ) {
+        let shift = 64 - width.size() * 8;
+        let val = if signed_ext {
+            ((val << shift) as i64 >> shift) as u64
+        } else {
+            (val << shift) >> shift
+        };
+        if let Some(reg) = GprIndex::from_raw(reg as u32) {
+            self.set_gpr_from_gpr_index(reg, val as usize);
+        }
+        self.advance_pc(self.mmio_insn_len);
+    }
+
+    /// Completes an [`AxVCpuExitReason::MmioWrite`] exit by advancing the guest
+    /// pc past the store instruction.
+    pub fn complete_mmio_write(&mut self) {
+        self.advance_pc(self.mmio_insn_len);
+    }
+
+    /// Saves the state of the vCPU, which must not be running.
+    pub fn save_state(&self) -> VCpuState {
+        let guest = &self.regs.guest_regs;
+        let vs = &self.regs.vs_csrs;
+        let mut gprs = [0; 32];
+        for (i, gpr) in gprs.iter_mut().enumerate() {
+            *gpr = guest.gprs.reg(GprIndex::from_raw(i as u32).unwrap());
+        }
+        let (hart_status, start_addr, opaque, requests) = self.harts.save_hart(self.hart_id);
+        VCpuState {
+            gprs,
+            sstatus: guest.sstatus,
+            hstatus: guest.hstatus,
+            scounteren: guest.scounteren,
+            sepc: guest.sepc,
+            guest_time: riscv::register::time::read().wrapping_add(vs.htimedelta),
+            vsstatus: vs.vsstatus,
+            vsie: vs.vsie,
+            vstvec: vs.vstvec,
+            vsscratch: vs.vsscratch,
+            vsepc: vs.vsepc,
+            vscause: vs.vscause,
+            vstval: vs.vstval,
+            vsatp: vs.vsatp,
+            hvip: self.regs.virtual_hs_csrs.hvip,
+            hart_status,
+            start_addr,
+            opaque,
+            requests,
+            fp: self.fp.fp,
+            fcsr: self.fp.fcsr,
+            timer_deadline: self.timer_deadline,
+        }
+    }
+
+    /// Restores the state saved by [`save_state`](Self::save_state). The
+    /// vCPU keeps its own stage-2 page table (`hgatp`).
+    ///
+    /// The timer of the guest is programmed again when the vCPU runs next.
+    pub fn restore_state(&mut self, state: &VCpuState) {
+        let guest = &mut self.regs.guest_regs;
+        for (i, &gpr) in state.gprs.iter().enumerate() {
+            guest
+                .gprs
+                .set_reg(GprIndex::from_raw(i as u32).unwrap(), gpr);
+        }
+        guest.sstatus = state.sstatus;
+        guest.hstatus = state.hstatus;
+        guest.scounteren = state.scounteren;
+        guest.sepc = state.sepc;
+        self.regs.vs_csrs = GuestVsCsrs {
+            htimedelta: state.guest_time.wrapping_sub(riscv::register::time::read()),
+            vsstatus: state.vsstatus,
+            vsie: state.vsie,
+            vstvec: state.vstvec,
+            vsscratch: state.vsscratch,
+            vsepc: state.vsepc,
+            vscause: state.vscause,
+            vstval: state.vstval,
+            vsatp: state.vsatp,
+            vstimecmp: 0,
+        };
+        self.regs.virtual_hs_csrs.hvip = state.hvip;
+        self.fp = FpState {
+            fp: state.fp,
+            fcsr: state.fcsr,
+        };
+        self.timer_deadline = state.timer_deadline;
+        self.timer_reload = true;
+        self.harts.restore_hart(
+            self.hart_id,
+            state.hart_status,
+            state.start_addr,
+            state.opaque,
+            state.requests,
+        );
+    }
 }
 
 impl RISCVVCpu {
@@ -298,51 +569,81 @@ impl RISCVVCpu {
         use scause::{Exception, Interrupt, Trap};
         match scause.cause() {
             Trap::Exception(Exception::VirtualSupervisorEnvCall) => {
-                let sbi_msg = SbiMessage::from_regs(self.regs.guest_regs.gprs.a_regs()).ok();
+                let sbi_msg = SbiMessage::from_regs(self.regs.guest_regs.gprs.a_regs());
                 debug!("VSuperEcall: {:?}", sbi_msg);
-                if let Some(sbi_msg) = sbi_msg {
-                    match sbi_msg {
-                        SbiMessage::Base(base) => {
-                            self.handle_base_function(base).unwrap();
-                        }
-                        SbiMessage::GetChar => {
-                            #[allow(deprecated)]
-                            let c = sbi_rt::legacy::console_getchar();
-                            self.set_gpr_from_gpr_index(GprIndex::A0, c);
-                        }
-                        SbiMessage::PutChar(c) => {
-                            #[allow(deprecated)]
-                            sbi_rt::legacy::console_putchar(c);
-                        }
-                        SbiMessage::SetTimer(timer) => {
-                            info!("Set timer... ");
-                            sbi_rt::set_timer(timer as u64);
-                            // Clear guest timer interrupt
-                            CSR.hvip
-                                .read_and_clear_bits(traps::interrupt::VIRTUAL_SUPERVISOR_TIMER);
-                            //  Enable host timer interrupt
-                            CSR.sie
-                                .read_and_set_bits(traps::interrupt::SUPERVISOR_TIMER);
-                        }
-                        SbiMessage::Reset(_) => {
-                            sbi_rt::system_reset(sbi_rt::Shutdown, sbi_rt::SystemFailure);
-                        }
-                        SbiMessage::RemoteFence(rfnc) => {
-                            self.handle_rfnc_function(rfnc).unwrap();
-                        }
-                        SbiMessage::PMU(pmu) => {
-                            self.handle_pmu_function(pmu).unwrap();
+                match sbi_msg {
+                    Ok(SbiMessage::Base(base)) => {
+                        self.handle_base_function(base).unwrap();
+                    }
+                    Ok(SbiMessage::GetChar) => {
+                        #[allow(deprecated)]
+                        let c = sbi_rt::legacy::console_getchar();
+                        self.set_gpr_from_gpr_index(GprIndex::A0, c);
+                    }
+                    Ok(SbiMessage::PutChar(c)) => {
+                        #[allow(deprecated)]
+                        sbi_rt::legacy::console_putchar(c);
+                    }
+                    Ok(SbiMessage::SetTimer(timer)) => {
+                        info!("Set timer... ");
+                        self.timer_deadline = timer as u64;
+                        self.set_timer(self.timer_deadline);
+                    }
+                    Ok(SbiMessage::Reset(_)) => {
+                        sbi_rt::system_reset(sbi_rt::Shutdown, sbi_rt::SystemFailure);
+                    }
+                    Ok(SbiMessage::RemoteFence(rfnc)) => {
+                        self.handle_rfnc_function(rfnc).unwrap();
+                    }
+                    Ok(SbiMessage::PMU(pmu)) => {
+                        self.handle_pmu_function(pmu).unwrap();
+                    }
+                    Ok(SbiMessage::Hsm(HsmFunction::HartStop)) => {
+                        self.harts.hart_stop(self.hart_id);
+                        return Ok(AxVCpuExitReason::CpuDown);
+                    }
+                    Ok(SbiMessage::Hsm(hsm)) => {
+                        self.handle_hsm_function(hsm).unwrap();
+                    }
+                    Ok(SbiMessage::Ipi(IpiFunction::SendIpi {
+                        hart_mask,
+                        hart_mask_base,
+                    })) => {
+                        let error = self.harts.send_request(
+                            hart_mask as usize,
+                            hart_mask_base as usize,
+                            REQ_IPI,
+                            false,
+                        );
+                        self.set_gpr_from_gpr_index(GprIndex::A0, error as usize);
+                    }
+                    Ok(SbiMessage::DebugConsole(dbcn)) => {
+                        self.handle_dbcn_function(dbcn).unwrap();
+                    }
+                    Ok(SbiMessage::Suspend(susp)) => {
+                        if self.handle_susp_function(susp).unwrap() {
+                            // Resumed at the given address.
+                            return Ok(AxVCpuExitReason::Nothing);
                         }
-                        _ => todo!(),
                     }
-                    self.advance_pc(4);
-                    Ok(AxVCpuExitReason::Nothing)
-                } else {
-                    panic!()
+                    Ok(SbiMessage::Cppc(cppc)) => {
+                        self.handle_cppc_function(cppc).unwrap();
+                    }
+                    Err(err) => {
+                        let error = match err {
+                            AxError::InvalidInput => SBI_ERR_INAVLID_PARAM,
+                            _ => SBI_ERR_NOT_SUPPORTED,
+                        };
+                        self.set_gpr_from_gpr_index(GprIndex::A0, error as usize);
+                    }
                 }
+                self.advance_pc(4);
+                Ok(AxVCpuExitReason::Nothing)
             }
             Trap::Interrupt(Interrupt::SupervisorTimer) => {
                 info!("timer irq emulation");
+                // The timer has fired, so a restored vCPU must not set it again
+                self.timer_deadline = NO_TIMER;
                 // Enable guest timer interrupt
                 CSR.hvip
                     .read_and_set_bits(traps::interrupt::VIRTUAL_SUPERVISOR_TIMER);
@@ -351,17 +652,44 @@ impl RISCVVCpu {
                     .read_and_clear_bits(traps::interrupt::SUPERVISOR_TIMER);
                 Ok(AxVCpuExitReason::Nothing)
             }
+            Trap::Interrupt(Interrupt::SupervisorSoft) => {
+                // Kicked by another vCPU, its requests are handled before entering the guest.
+                // The interrupt is left pending, and acknowledged by the host IRQ handler once
+                // IRQs are enabled again.
+                Ok(AxVCpuExitReason::Nothing)
+            }
             Trap::Interrupt(Interrupt::SupervisorExternal) => {
                 Ok(AxVCpuExitReason::ExternalInterrupt { vector: 0 })
             }
-            Trap::Exception(Exception::LoadGuestPageFault)
-            | Trap::Exception(Exception::StoreGuestPageFault) => {
+            Trap::Exception(Exception::Breakpoint) => Ok(AxVCpuExitReason::Breakpoint {
+                pc: self.regs.guest_regs.sepc,
+            }),
+            Trap::Exception(Exception::InstructionGuestPageFault) => {
                 let fault_addr = self.regs.trap_csrs.htval << 2 | self.regs.trap_csrs.stval & 0x3;
                 Ok(AxVCpuExitReason::NestedPageFault {
                     addr: GuestPhysAddr::from(fault_addr),
-                    access_flags: MappingFlags::empty(),
+                    access_flags: MappingFlags::EXECUTE,
                 })
             }
+            Trap::Exception(Exception::LoadGuestPageFault)
+            | Trap::Exception(Exception::StoreGuestPageFault) => {
+                let fault_addr = self.regs.trap_csrs.htval << 2 | self.regs.trap_csrs.stval & 0x3;
+                let addr = GuestPhysAddr::from(fault_addr);
+                if self.is_mmio_addr(addr) {
+                    if let Some(exit) = self.decode_mmio_access(addr) {
+                        return Ok(exit);
+                    }
+                    warn!(
+                        "Failed to decode MMIO access at {:#x}, sepc: {:#x}",
+                        fault_addr, self.regs.guest_regs.sepc
+                    );
+                }
+                let access_flags = match scause.cause() {
+                    Trap::Exception(Exception::StoreGuestPageFault) => MappingFlags::WRITE,
+                    _ => MappingFlags::READ,
+                };
+                Ok(AxVCpuExitReason::NestedPageFault { addr, access_flags })
+            }
             _ => {
                 panic!(
                     "Unhandled trap: {:?}, sepc: {:#x}, stval: {:#x}",
@@ -373,6 +701,211 @@ impl RISCVVCpu {
         }
     }
 
+    /// Checks whether the hart is started, and resets it to the start address
+    /// if it is just started by `hart_start`.
+    fn check_started(&mut self) -> bool {
+        match self.harts.status(self.hart_id) {
+            Some(harts::HART_STATE_STARTED) => true,
+            Some(harts::HART_STATE_START_PENDING) => {
+                let Some((start_addr, opaque)) = self.harts.take_start(self.hart_id) else {
+                    return false;
+                };
+                debug!(
+                    "hart {} started at {:#x}, opaque: {:#x}",
+                    self.hart_id, start_addr, opaque
+                );
+                // The hart starts in S-mode with the MMU off and interrupts disabled.
+                self.regs.vs_csrs = GuestVsCsrs::default();
+                self.regs.virtual_hs_csrs.hvip = 0;
+                self.regs.guest_regs.sepc = start_addr;
+                self.set_gpr_from_gpr_index(GprIndex::A0, self.hart_id);
+                self.set_gpr_from_gpr_index(GprIndex::A1, opaque);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Loads the per-hart CSRs of this vCPU, which may be changed by another
+    /// vCPU running on the same host hart, and handles the `requests` from the
+    /// other vCPUs.
+    fn load_guest_csrs(&mut self, requests: usize) {
+        let hvip = &mut self.regs.virtual_hs_csrs.hvip;
+        if requests & REQ_IPI != 0 {
+            *hvip |= traps::interrupt::VIRTUAL_SUPERVISOR_SOFT;
+        }
+        if self.harts.external_irq(self.hart_id) {
+            *hvip |= traps::interrupt::VIRTUAL_SUPERVISOR_EXTERNAL;
+        } else {
+            *hvip &= !traps::interrupt::VIRTUAL_SUPERVISOR_EXTERNAL;
+        }
+        #[cfg(target_arch = "riscv64")]
+        unsafe {
+            let vs = &self.regs.vs_csrs;
+            let hgatp = self.regs.virtual_hs_csrs.hgatp;
+            asm!(
+                "csrw 0x605, {htimedelta}",
+                "csrw 0x200, {vsstatus}",
+                "csrw 0x204, {vsie}",
+                "csrw 0x205, {vstvec}",
+                "csrw 0x240, {vsscratch}",
+                "csrw 0x241, {vsepc}",
+                "csrw 0x242, {vscause}",
+                "csrw 0x243, {vstval}",
+                "csrw 0x280, {vsatp}",
+                htimedelta = in(reg) vs.htimedelta,
+                vsstatus = in(reg) vs.vsstatus,
+                vsie = in(reg) vs.vsie,
+                vstvec = in(reg) vs.vstvec,
+                vsscratch = in(reg) vs.vsscratch,
+                vsepc = in(reg) vs.vsepc,
+                vscause = in(reg) vs.vscause,
+                vstval = in(reg) vs.vstval,
+                vsatp = in(reg) vs.vsatp,
+            );
+            let cur_hgatp: usize;
+            asm!("csrr {}, hgatp", out(reg) cur_hgatp);
+            if cur_hgatp != hgatp {
+                asm!("csrw hgatp, {}", in(reg) hgatp);
+                core::arch::riscv64::hfence_gvma_all();
+            } else if requests & harts::REQ_HFENCE_GVMA != 0 {
+                core::arch::riscv64::hfence_gvma_all();
+            }
+            if requests & harts::REQ_FENCE_I != 0 {
+                asm!("fence.i");
+            }
+            if requests & harts::REQ_HFENCE_VVMA != 0 {
+                core::arch::riscv64::hfence_vvma_all();
+            }
+        }
+        CSR.hvip.write_value(self.regs.virtual_hs_csrs.hvip);
+        if self.debug {
+            CSR.hedeleg
+                .read_and_clear_bits(traps::exception::BREAKPOINT);
+        } else {
+            CSR.hedeleg.read_and_set_bits(traps::exception::BREAKPOINT);
+        }
+    }
+
+    /// Saves the per-hart CSRs of this vCPU after a VM exit.
+    fn save_guest_csrs(&mut self) {
+        #[cfg(target_arch = "riscv64")]
+        unsafe {
+            let vs = &mut self.regs.vs_csrs;
+            asm!(
+                "csrr {htimedelta}, 0x605",
+                "csrr {vsstatus}, 0x200",
+                "csrr {vsie}, 0x204",
+                "csrr {vstvec}, 0x205",
+                "csrr {vsscratch}, 0x240",
+                "csrr {vsepc}, 0x241",
+                "csrr {vscause}, 0x242",
+                "csrr {vstval}, 0x243",
+                "csrr {vsatp}, 0x280",
+                htimedelta = out(reg) vs.htimedelta,
+                vsstatus = out(reg) vs.vsstatus,
+                vsie = out(reg) vs.vsie,
+                vstvec = out(reg) vs.vstvec,
+                vsscratch = out(reg) vs.vsscratch,
+                vsepc = out(reg) vs.vsepc,
+                vscause = out(reg) vs.vscause,
+                vstval = out(reg) vs.vstval,
+                vsatp = out(reg) vs.vsatp,
+            );
+        }
+        self.regs.virtual_hs_csrs.hvip = CSR.hvip.get_value();
+    }
+
+    /// Loads the FP registers of the guest, unless they are disabled by the
+    /// guest `sstatus.FS`. Returns the FP registers and `sstatus.FS` of the
+    /// host to be given back by [`save_guest_fp`](Self::save_guest_fp).
+    fn load_guest_fp(&mut self) -> Option<(FpState, sstatus::FS)> {
+        if self.regs.guest_regs.sstatus & SSTATUS_FS == 0 {
+            return None;
+        }
+        let host_fs = sstatus::read().fs();
+        let mut host_fp = FpState::default();
+        unsafe {
+            if host_fs == sstatus::FS::Off {
+                sstatus::set_fs(sstatus::FS::Initial);
+            } else {
+                host_fp.save();
+            }
+        }
+        self.fp.restore();
+        let guest_sstatus = &mut self.regs.guest_regs.sstatus;
+        *guest_sstatus = (*guest_sstatus & !SSTATUS_FS) | FS_CLEAN;
+        Some((host_fp, host_fs))
+    }
+
+    /// Saves the FP registers of the guest if it has changed them, and gives
+    /// back those of the host.
+    fn save_guest_fp(&mut self, host_fp: Option<(FpState, sstatus::FS)>) {
+        let Some((host_fp, host_fs)) = host_fp else {
+            return;
+        };
+        let guest_sstatus = &mut self.regs.guest_regs.sstatus;
+        if *guest_sstatus & SSTATUS_FS == FS_DIRTY {
+            self.fp.save();
+            *guest_sstatus = (*guest_sstatus & !SSTATUS_FS) | FS_CLEAN;
+        }
+        if host_fs != sstatus::FS::Off {
+            host_fp.restore();
+        }
+        unsafe { sstatus::set_fs(host_fs) };
+    }
+
+    /// Programs the host timer for the guest timer at `deadline` in the guest
+    /// time.
+    fn set_timer(&self, deadline: u64) {
+        sbi_rt::set_timer(deadline.wrapping_sub(self.regs.vs_csrs.htimedelta as u64));
+        // Clear guest timer interrupt
+        CSR.hvip
+            .read_and_clear_bits(traps::interrupt::VIRTUAL_SUPERVISOR_TIMER);
+        //  Enable host timer interrupt
+        CSR.sie
+            .read_and_set_bits(traps::interrupt::SUPERVISOR_TIMER);
+    }
+
+    fn is_mmio_addr(&self, addr: GuestPhysAddr) -> bool {
+        self.mmio_regions
+            .iter()
+            .any(|&(start, size)| addr >= start && addr < start + size)
+    }
+
+    /// Decodes the trapped load or store instruction into an MMIO exit.
+    fn decode_mmio_access(&mut self, addr: GuestPhysAddr) -> Option<AxVCpuExitReason> {
+        let insn = MmioInstruction::from_htinst(self.regs.trap_csrs.htinst)
+            .or_else(|| MmioInstruction::decode(self.fetch_guest_instruction()?))?;
+        self.mmio_insn_len = insn.len;
+        if insn.is_write {
+            Some(AxVCpuExitReason::MmioWrite {
+                addr,
+                width: insn.width,
+                data: self.get_gpr(insn.reg) as u64,
+            })
+        } else {
+            Some(AxVCpuExitReason::MmioRead {
+                addr,
+                width: insn.width,
+                reg: insn.reg as usize,
+                reg_width: AccessWidth::Qword,
+                signed_ext: insn.signed_ext,
+            })
+        }
+    }
+
+    /// Fetches the instruction at the guest pc with `HLVX`, using the guest
+    /// translation that is still in effect after the VM exit.
+    ///
+    /// Returns `None` if the pc is not mapped in the guest, the fault is
+    /// caught by the exception table.
+    fn fetch_guest_instruction(&self) -> Option<u32> {
+        let mut insn = 0;
+        let ret = unsafe { _fetch_guest_instruction(self.regs.guest_regs.sepc, &mut insn) };
+        (ret == 0).then_some(insn)
+    }
+
     fn handle_base_function(&mut self, base: BaseFunction) -> AxResult<()> {
         match base {
             BaseFunction::GetSepcificationVersion => {
@@ -392,8 +925,27 @@ impl RISCVVCpu {
                 self.set_gpr_from_gpr_index(GprIndex::A1, impl_version);
             }
             BaseFunction::ProbeSbiExtension(extension) => {
-                let extension = sbi_rt::probe_extension(extension as usize).raw;
-                self.set_gpr_from_gpr_index(GprIndex::A1, extension);
+                use sbi_spec::legacy::*;
+                let available = match extension as usize {
+                    // Emulated by the hypervisor.
+                    sbi_spec::base::EID_BASE
+                    | sbi_spec::time::EID_TIME
+                    | sbi_spec::spi::EID_SPI
+                    | sbi_spec::rfnc::EID_RFNC
+                    | sbi_spec::hsm::EID_HSM
+                    | sbi_spec::srst::EID_SRST
+                    | EID_DBCN
+                    | EID_SUSP
+                    | EID_CPPC
+                    | LEGACY_SET_TIMER
+                    | LEGACY_CONSOLE_PUTCHAR
+                    | LEGACY_CONSOLE_GETCHAR
+                    | LEGACY_SHUTDOWN => 1,
+                    // Forwarded to the host.
+                    sbi_spec::pmu::EID_PMU => sbi_rt::probe_extension(extension as usize).raw,
+                    _ => 0,
+                };
+                self.set_gpr_from_gpr_index(GprIndex::A1, available);
             }
             BaseFunction::GetMachineVendorID => {
                 let mvendorid = sbi_rt::get_mvendorid();
@@ -413,32 +965,201 @@ impl RISCVVCpu {
     }
 
     fn handle_rfnc_function(&mut self, rfnc: RemoteFenceFunction) -> AxResult<()> {
-        self.set_gpr_from_gpr_index(GprIndex::A0, 0);
-        match rfnc {
+        let (hart_mask, hart_mask_base, req) = match rfnc {
             RemoteFenceFunction::FenceI {
                 hart_mask,
                 hart_mask_base,
-            } => {
-                let sbi_ret = sbi_rt::remote_fence_i(hart_mask as usize, hart_mask_base as usize);
-                self.set_gpr_from_gpr_index(GprIndex::A0, sbi_ret.error);
-                self.set_gpr_from_gpr_index(GprIndex::A1, sbi_ret.value);
-            }
+            } => (hart_mask, hart_mask_base, REQ_FENCE_I),
+            // The address range is ignored, the whole guest TLB is flushed.
             RemoteFenceFunction::RemoteSFenceVMA {
                 hart_mask,
                 hart_mask_base,
+                ..
+            } => (hart_mask, hart_mask_base, REQ_HFENCE_VVMA),
+        };
+        let error = self
+            .harts
+            .send_request(hart_mask as usize, hart_mask_base as usize, req, true);
+        self.set_gpr_from_gpr_index(GprIndex::A0, error as usize);
+        self.set_gpr_from_gpr_index(GprIndex::A1, 0);
+        Ok(())
+    }
+
+    fn handle_hsm_function(&mut self, hsm: HsmFunction) -> AxResult<()> {
+        let (error, value) = match hsm {
+            HsmFunction::HartStart {
+                hartid,
                 start_addr,
-                size,
+                opaque,
             } => {
-                let sbi_ret = sbi_rt::remote_sfence_vma(
-                    hart_mask as usize,
-                    hart_mask_base as usize,
-                    start_addr as usize,
-                    size as usize,
-                );
-                self.set_gpr_from_gpr_index(GprIndex::A0, sbi_ret.error);
-                self.set_gpr_from_gpr_index(GprIndex::A1, sbi_ret.value);
+                let error =
+                    self.harts
+                        .hart_start(hartid as usize, start_addr as usize, opaque as usize);
+                (error, 0)
             }
+            HsmFunction::HartGetStatus { hartid } => match self.harts.status(hartid as usize) {
+                Some(status) => (0, status),
+                None => (SBI_ERR_INAVLID_PARAM, 0),
+            },
+            HsmFunction::HartStop | HsmFunction::HartSuspend { .. } => (SBI_ERR_NOT_SUPPORTED, 0),
+        };
+        self.set_gpr_from_gpr_index(GprIndex::A0, error as usize);
+        self.set_gpr_from_gpr_index(GprIndex::A1, value);
+        Ok(())
+    }
+
+    /// Returns the host mapping of the guest RAM at `gpa`, at most `len` bytes
+    /// within a page, by walking the nested page table.
+    ///
+    /// Returns `None` if `gpa` is not in guest RAM, not allocated yet, or not
+    /// writable by the guest for `write`.
+    fn guest_ram_slice(&self, gpa: usize, len: usize, write: bool) -> Option<&mut [u8]> {
+        const PTE_V: usize = 1 << 0;
+        const PTE_R: usize = 1 << 1;
+        const PTE_W: usize = 1 << 2;
+        const PTE_X: usize = 1 << 3;
+        const PTE_U: usize = 1 << 4;
+        const PPN_MASK: usize = (1 << 44) - 1;
+
+        // Sv39x4: 41-bit guest physical addresses.
+        if gpa >> 41 != 0 {
+            return None;
+        }
+        let mut table = (self.regs.virtual_hs_csrs.hgatp & PPN_MASK) << 12;
+        for level in (0..3).rev() {
+            let shift = 12 + 9 * level;
+            // The root table is indexed with 2 extra bits.
+            let index_mask = if level == 2 { 0x7ff } else { 0x1ff };
+            let pte_addr = phys_to_virt(PhysAddr::from(table + (gpa >> shift & index_mask) * 8));
+            let pte = unsafe { (pte_addr.as_ptr() as *const usize).read_volatile() };
+            if pte & PTE_V == 0 {
+                return None;
+            }
+            let paddr = (pte >> 10 & PPN_MASK) << 12;
+            if pte & (PTE_R | PTE_X) == 0 {
+                table = paddr;
+                continue;
+            }
+            if pte & (PTE_R | PTE_U) != PTE_R | PTE_U || (write && pte & PTE_W == 0) {
+                return None;
+            }
+            let offset = gpa & ((1 << shift) - 1);
+            let hpa = paddr + offset;
+            let len = len.min((1 << shift) - offset);
+            // Passthrough device memory is not accessed.
+            let in_ram = memory_regions().any(|r| {
+                !r.flags.contains(MemRegionFlags::DEVICE)
+                    && hpa >= r.paddr.as_usize()
+                    && hpa + len <= r.paddr.as_usize() + r.size
+            });
+            if !in_ram {
+                return None;
+            }
+            let ptr = phys_to_virt(PhysAddr::from(hpa)).as_mut_ptr();
+            return Some(unsafe { core::slice::from_raw_parts_mut(ptr, len) });
         }
+        None
+    }
+
+    fn handle_dbcn_function(&mut self, dbcn: DebugConsoleFunction) -> AxResult<()> {
+        let (num_bytes, base_addr, to_console) = match dbcn {
+            DebugConsoleFunction::WriteByte(byte) => {
+                axhal::console::write_bytes(&[byte]);
+                self.set_gpr_from_gpr_index(GprIndex::A0, SBI_SUCCESS);
+                self.set_gpr_from_gpr_index(GprIndex::A1, 0);
+                return Ok(());
+            }
+            DebugConsoleFunction::Write {
+                num_bytes,
+                base_addr,
+            } => (num_bytes as usize, base_addr as usize, true),
+            DebugConsoleFunction::Read {
+                num_bytes,
+                base_addr,
+            } => (num_bytes as usize, base_addr as usize, false),
+        };
+        // Fewer bytes than requested may be written or read, the guest calls
+        // again for the rest.
+        let mut done = 0;
+        let mut error = SBI_SUCCESS as isize;
+        while done < num_bytes {
+            let addr = base_addr + done;
+            let Some(buf) = self.guest_ram_slice(addr, num_bytes - done, !to_console) else {
+                if done == 0 {
+                    error = SBI_ERR_INAVLID_PARAM;
+                }
+                break;
+            };
+            let n = if to_console {
+                axhal::console::write_bytes(buf);
+                buf.len()
+            } else {
+                buf.iter_mut()
+                    .map_while(|b| axhal::console::getchar().map(|c| *b = c))
+                    .count()
+            };
+            done += n;
+            if n < buf.len() {
+                break;
+            }
+        }
+        self.set_gpr_from_gpr_index(GprIndex::A0, error as usize);
+        self.set_gpr_from_gpr_index(GprIndex::A1, done);
+        Ok(())
+    }
+
+    /// Handles the system suspend, returns whether the hart is resumed at the
+    /// address given by the guest.
+    fn handle_susp_function(&mut self, susp: SuspendFunction) -> AxResult<bool> {
+        const SSTATUS_SIE: usize = 1 << 1;
+        let SuspendFunction::SystemSuspend {
+            sleep_type,
+            resume_addr,
+            opaque,
+        } = susp;
+        let others_stopped = (0..self.harts.num_harts())
+            .filter(|&i| i != self.hart_id)
+            .all(|i| self.harts.status(i) == Some(HART_STATE_STOPPED));
+        let error = if sleep_type == SUSPEND_TO_RAM && others_stopped {
+            // Nothing in a VM waits for a wakeup event, so the system wakes
+            // up at once. The hart resumes as from a non-retentive suspend,
+            // with the MMU and interrupts disabled.
+            self.regs.vs_csrs.vsatp = 0;
+            self.regs.vs_csrs.vsstatus &= !SSTATUS_SIE;
+            self.regs.guest_regs.sepc = resume_addr as usize;
+            self.set_gpr_from_gpr_index(GprIndex::A0, self.hart_id);
+            self.set_gpr_from_gpr_index(GprIndex::A1, opaque as usize);
+            return Ok(true);
+        } else if sleep_type == SUSPEND_TO_RAM {
+            SBI_ERR_DENIED
+        } else if sleep_type >= 0x8000_0000 {
+            // Platform specific sleep types.
+            SBI_ERR_NOT_SUPPORTED
+        } else {
+            SBI_ERR_INAVLID_PARAM
+        };
+        self.set_gpr_from_gpr_index(GprIndex::A0, error as usize);
+        Ok(false)
+    }
+
+    fn handle_cppc_function(&mut self, cppc: CppcFunction) -> AxResult<()> {
+        // The host CPU performance is not controlled by guests, so no CPPC
+        // register is implemented.
+        let reg_id = match cppc {
+            CppcFunction::Probe { reg_id }
+            | CppcFunction::Read { reg_id }
+            | CppcFunction::ReadHi { reg_id }
+            | CppcFunction::Write { reg_id, .. } => reg_id,
+        };
+        let defined = reg_id <= 0x16 || reg_id == 0x8000_0000;
+        let (error, value) = match cppc {
+            _ if !defined => (SBI_ERR_INAVLID_PARAM, 0),
+            // A width of 0 means not implemented.
+            CppcFunction::Probe { .. } => (SBI_SUCCESS as isize, 0),
+            _ => (SBI_ERR_NOT_SUPPORTED, 0),
+        };
+        self.set_gpr_from_gpr_index(GprIndex::A0, error as usize);
+        self.set_gpr_from_gpr_index(GprIndex::A1, value);
         Ok(())
     }
 
@@ -486,6 +1207,32 @@ pub enum AccessWidth {
     Qword,
 }
 
+impl AccessWidth {
+    /// Returns the size of the access in bytes.
+    pub const fn size(&self) -> usize {
+        match self {
+            Self::Byte => 1,
+            Self::Word => 2,
+            Self::Dword => 4,
+            Self::Qword => 8,
+        }
+    }
+}
+
+impl TryFrom<usize> for AccessWidth {
+    type Error = ();
+
+    fn try_from(size: usize) -> Result<Self, Self::Error> {
+        match size {
+            1 => Ok(Self::Byte),
+            2 => Ok(Self::Word),
+            4 => Ok(Self::Dword),
+            8 => Ok(Self::Qword),
+            _ => Err(()),
+        }
+    }
+}
+
 /// The port number of an I/O operation.
 type Port = u16;
 
@@ -511,6 +1258,8 @@ pub enum AxVCpuExitReason {
         reg: usize,
         /// The width of the reg to be read
         reg_width: AccessWidth,
+        /// Whether the value read should be sign-extended to the reg width
+        signed_ext: bool,
     },
     /// The instruction executed by the vcpu performs a MMIO write operation.
     MmioWrite {
@@ -557,6 +1306,14 @@ pub enum AxVCpuExitReason {
         /// The access flags of the fault.
         access_flags: MappingFlags,
     },
+    /// The vcpu hit a breakpoint while guest debugging is enabled (see
+    /// [`RISCVVCpu::set_debug`]).
+    ///
+    /// The guest pc is not advanced.
+    Breakpoint {
+        /// The guest virtual address of the breakpoint.
+        pc: usize,
+    },
     /// The vcpu is halted.
     Halt,
     /// The vcpu is powered off.
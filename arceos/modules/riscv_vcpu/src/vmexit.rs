@@ -0,0 +1,194 @@
+//! Decoding of the load and store instructions trapped on guest page faults.
+//!
+//! The faulting instruction is taken from `htinst` if the hardware provides
+//! the transformed instruction, otherwise it is fetched from the guest memory.
+
+use crate::regs::GprIndex;
+use crate::vcpu::AccessWidth;
+
+const OPCODE_LOAD: u32 = 0b000_0011;
+const OPCODE_STORE: u32 = 0b010_0011;
+
+/// A load or store instruction that accesses an emulated MMIO region.
+#[derive(Debug, Clone, Copy)]
+pub struct MmioInstruction {
+    /// The width of the access.
+    pub width: AccessWidth,
+    /// The destination register of a load, or the source register of a store.
+    pub reg: GprIndex,
+    /// Whether it is a store instruction.
+    pub is_write: bool,
+    /// Whether the loaded value is sign-extended.
+    pub signed_ext: bool,
+    /// The length of the instruction in bytes, 2 for compressed instructions.
+    pub len: usize,
+}
+
+impl MmioInstruction {
+    /// Decodes the transformed instruction written to `htinst`.
+    ///
+    /// Returns `None` if `htinst` is zero (not provided by the hardware), a
+    /// pseudoinstruction for the implicit accesses of VS-stage address
+    /// translation, or not a load or store instruction.
+    pub fn from_htinst(htinst: usize) -> Option<Self> {
+        let insn = htinst as u32;
+        // Bit 0 is always 1 for a transformed instruction. Bit 1 is cleared if
+        // the trapped instruction is a compressed one.
+        if insn & 0b01 == 0 {
+            return None;
+        }
+        let len = if insn & 0b10 != 0 { 4 } else { 2 };
+        Self::decode_standard(insn | 0b11, len)
+    }
+
+    /// Decodes an instruction fetched from the guest memory, which can be
+    /// either a standard or a compressed one.
+    pub fn decode(insn: u32) -> Option<Self> {
+        if insn & 0b11 == 0b11 {
+            Self::decode_standard(insn, 4)
+        } else {
+            Self::decode_compressed(insn as u16)
+        }
+    }
+
+    fn decode_standard(insn: u32, len: usize) -> Option<Self> {
+        let funct3 = (insn >> 12) & 0b111;
+        let (is_write, reg) = match insn & 0x7f {
+            OPCODE_LOAD => (false, (insn >> 7) & 0x1f),
+            OPCODE_STORE => (true, (insn >> 20) & 0x1f),
+            _ => return None,
+        };
+        let (width, signed_ext) = match (is_write, funct3) {
+            (_, 0b000) => (AccessWidth::Byte, !is_write),
+            (_, 0b001) => (AccessWidth::Word, !is_write),
+            (_, 0b010) => (AccessWidth::Dword, !is_write),
+            (_, 0b011) => (AccessWidth::Qword, false),
+            (false, 0b100) => (AccessWidth::Byte, false),
+            (false, 0b101) => (AccessWidth::Word, false),
+            (false, 0b110) => (AccessWidth::Dword, false),
+            _ => return None,
+        };
+        Some(Self {
+            width,
+            reg: GprIndex::from_raw(reg)?,
+            is_write,
+            signed_ext,
+            len,
+        })
+    }
+
+    fn decode_compressed(insn: u16) -> Option<Self> {
+        let insn = insn as u32;
+        let funct3 = (insn >> 13) & 0b111;
+        // rd' or rs2' in bits [4:2] for c.lw/c.ld/c.sw/c.sd
+        let reg_prime = ((insn >> 2) & 0b111) + 8;
+        let (width, reg, is_write) = match (insn & 0b11, funct3) {
+            (0b00, 0b010) => (AccessWidth::Dword, reg_prime, false), // c.lw
+            (0b00, 0b011) => (AccessWidth::Qword, reg_prime, false), // c.ld
+            (0b00, 0b110) => (AccessWidth::Dword, reg_prime, true),  // c.sw
+            (0b00, 0b111) => (AccessWidth::Qword, reg_prime, true),  // c.sd
+            (0b10, 0b010) => (AccessWidth::Dword, (insn >> 7) & 0x1f, false), // c.lwsp
+            (0b10, 0b011) => (AccessWidth::Qword, (insn >> 7) & 0x1f, false), // c.ldsp
+            (0b10, 0b110) => (AccessWidth::Dword, (insn >> 2) & 0x1f, true), // c.swsp
+            (0b10, 0b111) => (AccessWidth::Qword, (insn >> 2) & 0x1f, true), // c.sdsp
+            _ => return None,
+        };
+        Some(Self {
+            width,
+            reg: GprIndex::from_raw(reg)?,
+            is_write,
+            signed_ext: !is_write && width == AccessWidth::Dword,
+            len: 2,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AccessWidth::*;
+    use GprIndex::*;
+
+    /// Encoding, width, register, is_write, signed_ext and length.
+    type Case = (u32, AccessWidth, GprIndex, bool, bool, usize);
+
+    const STANDARD: &[Case] = &[
+        (0x0005_8503, Byte, A0, false, true, 4),    // lb a0, 0(a1)
+        (0x0081_1303, Word, T1, false, true, 4),    // lh t1, 8(sp)
+        (0xffc5_2903, Dword, S2, false, true, 4),   // lw s2, -4(a0)
+        (0x0104_3083, Qword, RA, false, false, 4),  // ld ra, 16(s0)
+        (0x0015_c783, Byte, A5, false, false, 4),   // lbu a5, 1(a1)
+        (0x0026_5f83, Word, T6, false, false, 4),   // lhu t6, 2(a2)
+        (0x0002_ed83, Dword, S11, false, false, 4), // lwu s11, 0(t0)
+        (0x00a5_8023, Byte, A0, true, false, 4),    // sb a0, 0(a1)
+        (0x0061_1423, Word, T1, true, false, 4),    // sh t1, 8(sp)
+        (0xff25_2e23, Dword, S2, true, false, 4),   // sw s2, -4(a0)
+        (0x0014_3823, Qword, RA, true, false, 4),   // sd ra, 16(s0)
+    ];
+
+    const COMPRESSED: &[Case] = &[
+        (0x41c8, Dword, A0, false, true, 2),  // c.lw a0, 4(a1)
+        (0x6784, Qword, S1, false, false, 2), // c.ld s1, 8(a5)
+        (0xc010, Dword, A2, true, false, 2),  // c.sw a2, 0(s0)
+        (0xfef8, Qword, A4, true, false, 2),  // c.sd a4, 248(a3)
+        (0x42b2, Dword, T0, false, true, 2),  // c.lwsp t0, 12(sp)
+        (0x60a2, Qword, RA, false, false, 2), // c.ldsp ra, 8(sp)
+        (0xc24e, Dword, S3, true, false, 2),  // c.swsp s3, 4(sp)
+        (0xfffe, Qword, T6, true, false, 2),  // c.sdsp t6, 504(sp)
+    ];
+
+    const NOT_LOAD_STORE: &[u32] = &[
+        0x0015_0513, // addi a0, a0, 1
+        0x0505,      // c.addi a0, 1
+        0x0005_2007, // flw ft0, 0(a0)
+        0x2100,      // c.fld fs0, 0(a0)
+    ];
+
+    fn check(insn: Option<MmioInstruction>, case: &Case) {
+        let &(raw, width, reg, is_write, signed_ext, len) = case;
+        let insn = insn.unwrap_or_else(|| panic!("{:#x} not decoded", raw));
+        assert_eq!(insn.width, width, "{:#x}", raw);
+        assert_eq!(insn.reg, reg, "{:#x}", raw);
+        assert_eq!(insn.is_write, is_write, "{:#x}", raw);
+        assert_eq!(insn.signed_ext, signed_ext, "{:#x}", raw);
+        assert_eq!(insn.len, len, "{:#x}", raw);
+    }
+
+    #[test]
+    fn decode_standard() {
+        for case in STANDARD {
+            check(MmioInstruction::decode(case.0), case);
+        }
+    }
+
+    #[test]
+    fn decode_compressed() {
+        for case in COMPRESSED {
+            check(MmioInstruction::decode(case.0), case);
+        }
+    }
+
+    #[test]
+    fn decode_not_load_store() {
+        for &raw in NOT_LOAD_STORE {
+            assert!(MmioInstruction::decode(raw).is_none(), "{:#x}", raw);
+        }
+    }
+
+    #[test]
+    fn decode_htinst() {
+        // not provided, or a pseudoinstruction of VS-stage translation
+        assert!(MmioInstruction::from_htinst(0).is_none());
+        assert!(MmioInstruction::from_htinst(0x0000_3000).is_none());
+        // the offset is zeroed in a transformed instruction
+        check(
+            MmioInstruction::from_htinst(0x0000_3083),
+            &(0, Qword, RA, false, false, 4), // ld ra, 0(zero)
+        );
+        // bit 1 is cleared for a compressed one
+        check(
+            MmioInstruction::from_htinst(0x00a0_2021),
+            &(0, Dword, A0, true, false, 2), // c.sw a0, 0(zero)
+        );
+    }
+}
@@ -9,6 +9,26 @@ else
   rust_elf := $(TARGET_DIR)/$(TARGET)/$(MODE)/$(rust_package)
 endif
 
+ifeq ($(BACKTRACE), y)
+  # Symbol table of the kernel, embedded by the build script of axhal
+  KSYMS := $(abspath $(OUT_DIR)/$(APP_NAME)_$(PLATFORM_NAME).ksyms)
+  export AX_KSYMS=$(KSYMS)
+endif
+
+ksym_tool := cargo run --manifest-path tools/ksym/Cargo.toml --release --quiet --
+
+# Generates the symbol table from the built kernel, and rebuilds it if the
+# embedded table is outdated. Embedding the table does not move any function,
+# as it is placed after the code, so a single rebuild is enough.
+define embed_ksyms
+  $(call run_cmd,RUSTFLAGS= $(ksym_tool),$(1) $(KSYMS).new)
+  @cmp -s $(KSYMS).new $(KSYMS) || cp $(KSYMS).new $(KSYMS)
+  $(call cargo_build,$(2),$(3))
+  @RUSTFLAGS= $(ksym_tool) $(1) $(KSYMS).new
+  @cmp -s $(KSYMS).new $(KSYMS) || printf "$(YELLOW_C)warning$(END_C): the embedded symbol table is outdated\n"
+  @rm -f $(KSYMS).new
+endef
+
 ifneq ($(filter $(MAKECMDGOALS),doc doc_check_missing),)  # run `cargo doc`
   $(if $(V), $(info RUSTDOCFLAGS: "$(RUSTDOCFLAGS)"))
   export RUSTDOCFLAGS
@@ -33,6 +53,9 @@ _cargo_build:
 	@printf "    $(GREEN_C)Building$(END_C) App: $(APP_NAME), Arch: $(ARCH), Platform: $(PLATFORM_NAME), App type: $(APP_TYPE)\n"
 ifeq ($(APP_TYPE), rust)
 	$(call cargo_build,$(APP),$(AX_FEAT) $(LIB_FEAT) $(APP_FEAT))
+  ifeq ($(BACKTRACE), y)
+	$(call embed_ksyms,$(rust_elf),$(APP),$(AX_FEAT) $(LIB_FEAT) $(APP_FEAT))
+  endif
 	@cp $(rust_elf) $(OUT_ELF)
 else ifeq ($(APP_TYPE), c)
 	$(call cargo_build,ulib/axlibc,$(AX_FEAT) $(LIB_FEAT))
@@ -17,7 +17,16 @@ build_args := \
   $(build_args-$(MODE)) \
   $(verbose)
 
+# The toolchain has no prebuilt `rust-std` for LoongArch64, build it from
+# `rust-src` instead.
+ifeq ($(ARCH), loongarch64)
+  build_args += -Z build-std=core,alloc -Z build-std-features=compiler-builtins-mem
+endif
+
 RUSTFLAGS := -C link-arg=-T$(LD_SCRIPT) -C link-arg=-no-pie -C link-arg=-znostart-stop-gc
+ifeq ($(BACKTRACE), y)
+  RUSTFLAGS += -C force-frame-pointers=yes
+endif
 RUSTDOCFLAGS := -Z unstable-options --enable-index-page -D rustdoc::broken_intra_doc_links
 
 ifeq ($(MAKECMDGOALS), doc_check_missing)
@@ -58,3 +58,11 @@ lib_feat += $(filter $(lib_features),$(FEATURES))
 AX_FEAT := $(strip $(addprefix $(ax_feat_prefix),$(ax_feat)))
 LIB_FEAT := $(strip $(addprefix $(lib_feat_prefix),$(lib_feat)))
 APP_FEAT := $(strip $(shell echo $(APP_FEATURES) | tr ',' ' '))
+
+# Allocation sites are found by the symbol table
+alloc_site_feat := $(filter alloc-tracking %/alloc-tracking alloc-debug %/alloc-debug,$(FEATURES) $(APP_FEAT))
+ifneq ($(alloc_site_feat),)
+  ifneq ($(BACKTRACE), y)
+    $(error "$(firstword $(notdir $(alloc_site_feat)))" needs the symbol table to find allocation sites, build with "BACKTRACE=y")
+  endif
+endif
@@ -24,7 +24,18 @@ qemu_args-aarch64 := \
   -machine virt \
   -kernel $(OUT_BIN)
 
-qemu_args-y := -m 128M -smp $(SMP) $(qemu_args-$(ARCH))
+qemu_args-loongarch64 := \
+  -machine virt \
+  -kernel $(OUT_ELF)
+
+# The LoongArch virt machine requires at least 1G memory.
+ifeq ($(ARCH), loongarch64)
+  qemu_mem := 1G
+else
+  qemu_mem := 128M
+endif
+
+qemu_args-y := -m $(qemu_mem) -smp $(SMP) $(qemu_args-$(ARCH))
 
 qemu_args-$(PFLASH) += \
   -drive if=pflash,file=$(CURDIR)/$(PFLASH_IMG),format=raw,unit=1
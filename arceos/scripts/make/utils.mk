@@ -13,8 +13,10 @@ else ifeq ($(ARCH), riscv64)
   COUNT ?= 32
 else ifeq ($(ARCH), aarch64)
   COUNT ?= 64
+else ifeq ($(ARCH), loongarch64)
+  COUNT ?= 64
 else
-  $(error "ARCH" must be one of "x86_64", "riscv64", or "aarch64")
+  $(error "ARCH" must be one of "x86_64", "riscv64", "aarch64", or "loongarch64")
 endif
 
 define run_cmd
@@ -0,0 +1,89 @@
+//! Generates the symbol table embedded into the kernel image, which is used
+//! by `axhal::backtrace` to symbolize the addresses of backtraces.
+//!
+//! The table is in little endian, and consists of:
+//!
+//! - The magic `b"KSYM"`, and the number of symbols as a `u32`.
+//! - An entry per function symbol, sorted by address: the address as a `u64`,
+//!   the size as a `u32`, and the offset of the name in the string table as a
+//!   `u32`.
+//! - The string table, with the demangled names terminated by NUL.
+
+use std::{env, fs, process};
+
+use elf::{abi::STT_FUNC, endian::AnyEndian, ElfBytes};
+
+const MAGIC: &[u8; 4] = b"KSYM";
+
+struct Symbol {
+    addr: u64,
+    size: u64,
+    name: String,
+}
+
+fn read_symbols(data: &[u8]) -> Result<Vec<Symbol>, String> {
+    let file = ElfBytes::<AnyEndian>::minimal_parse(data).map_err(|e| e.to_string())?;
+    let (symtab, strtab) = file
+        .symbol_table()
+        .map_err(|e| e.to_string())?
+        .ok_or("no symbol table, is the kernel stripped?")?;
+
+    let mut syms = Vec::new();
+    for sym in symtab.iter() {
+        if sym.st_symtype() != STT_FUNC || sym.st_value == 0 {
+            continue;
+        }
+        let name = strtab
+            .get(sym.st_name as usize)
+            .map_err(|e| e.to_string())?;
+        syms.push(Symbol {
+            addr: sym.st_value,
+            size: sym.st_size,
+            name: format!("{:#}", rustc_demangle::demangle(name)),
+        });
+    }
+    syms.sort_by_key(|s| s.addr);
+    // Keep one name for the aliases of a function.
+    syms.dedup_by_key(|s| s.addr);
+    Ok(syms)
+}
+
+fn encode(syms: &[Symbol]) -> Vec<u8> {
+    let mut entries = Vec::with_capacity(syms.len() * 16);
+    let mut names = Vec::new();
+    for sym in syms {
+        entries.extend_from_slice(&sym.addr.to_le_bytes());
+        entries.extend_from_slice(&(sym.size.min(u32::MAX as u64) as u32).to_le_bytes());
+        entries.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        names.extend_from_slice(sym.name.as_bytes());
+        names.push(0);
+    }
+
+    let mut out = Vec::with_capacity(8 + entries.len() + names.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&(syms.len() as u32).to_le_bytes());
+    out.extend_from_slice(&entries);
+    out.extend_from_slice(&names);
+    out
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <kernel-elf> <output>", args[0]);
+        process::exit(1);
+    }
+
+    let data = fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", args[1], e);
+        process::exit(1);
+    });
+    let syms = read_symbols(&data).unwrap_or_else(|e| {
+        eprintln!("Failed to parse {}: {}", args[1], e);
+        process::exit(1);
+    });
+    if let Err(e) = fs::write(&args[2], encode(&syms)) {
+        eprintln!("Failed to write {}: {}", args[2], e);
+        process::exit(1);
+    }
+}
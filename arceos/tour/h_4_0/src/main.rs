@@ -13,19 +13,26 @@ use riscv_vcpu::AxVCpuExitReason;
 use axerrno::{ax_err_type, AxResult};
 use memory_addr::VirtAddr;
 use alloc::string::String;
+use alloc::sync::Arc;
 use std::fs::File;
-use riscv_vcpu::RISCVVCpu;
+use std::sync::Mutex;
+use std::thread;
+use riscv_vcpu::{RISCVVCpu, VmHarts};
 use riscv_vcpu::AxVCpuExitReason::NestedPageFault;
 
 use axmm::AddrSpace;
 use axhal::paging::MappingFlags;
-use vmdev::VmDevGroup;
+use axdevice::{VPlic, PLIC_MMIO_SIZE};
+use vmdev::{PFlash, VmDevGroup};
 
 const VM_ASPACE_BASE: usize = 0x0;
 const VM_ASPACE_SIZE: usize = 0x7fff_ffff_f000;
 const PHY_MEM_START: usize = 0x8000_0000;
 const PHY_MEM_SIZE: usize = 0x100_0000;
 const KERNEL_BASE: usize = 0x8020_0000;
+const VCPU_NUM: usize = 1;
+const PLIC_BASE: usize = 0x0c00_0000;
+const PLIC_NUM_SOURCES: usize = 96;
 
 #[no_mangle]
 fn main() {
@@ -37,40 +44,73 @@ fn main() {
     // Setup AddressSpace and regions.
     let mut aspace = AddrSpace::new_empty(VirtAddr::from(VM_ASPACE_BASE), VM_ASPACE_SIZE).unwrap();
 
-    // Physical memory region. Full access flags.
+    // Physical memory region. Full access flags, allocated on demand.
     let mapping_flags = MappingFlags::from_bits(0xf).unwrap();
-    aspace.map_alloc(PHY_MEM_START.into(), PHY_MEM_SIZE, mapping_flags, true).unwrap();
+    aspace.map_alloc(PHY_MEM_START.into(), PHY_MEM_SIZE, mapping_flags, false).unwrap();
 
     // Load corresponding images for VM.
     info!("VM created success, loading images...");
     let image_fname = "/sbin/m_1_1_riscv64-qemu-virt.bin";
-    load_vm_image(image_fname.to_string(), KERNEL_BASE.into(), &aspace).expect("Failed to load VM images");
+    load_vm_image(image_fname.to_string(), KERNEL_BASE.into(), &mut aspace).expect("Failed to load VM images");
 
-    // Register pflash device into vm.
+    // Register emulated pflash device into vm.
     let mut vmdevs = VmDevGroup::new();
-    vmdevs.add_dev(0x2200_0000.into(), 0x200_0000);
-
-    // Create VCpus.
-    let mut arch_vcpu = RISCVVCpu::init();
-
-    // Setup VCpus.
-    info!("bsp_entry: {:#x}; ept: {:#x}", KERNEL_BASE, aspace.page_table_root());
-    arch_vcpu.set_entry(KERNEL_BASE.into()).unwrap();
-    arch_vcpu.set_ept_root(aspace.page_table_root()).unwrap();
+    let pflash = Arc::new(PFlash::new(0x2200_0000.into()));
+    vmdevs.add_dev(0x2200_0000.into(), 0x200_0000, pflash);
+
+    // Register emulated PLIC, devices raise interrupts through its lines.
+    let harts = Arc::new(VmHarts::new(VCPU_NUM));
+    let vplic = Arc::new(VPlic::new(PLIC_NUM_SOURCES, harts.clone()));
+    vmdevs.add_dev(PLIC_BASE.into(), PLIC_MMIO_SIZE, vplic);
+    let vmdevs = Arc::new(vmdevs);
+
+    // Create VCpus, each runs on its own task. Only the boot hart starts
+    // running, the others wait for SBI `hart_start` from the guest.
+    let ept_root = aspace.page_table_root();
+    info!("bsp_entry: {:#x}; ept: {:#x}", KERNEL_BASE, ept_root);
+    let aspace = Arc::new(Mutex::new(aspace));
+    for hart_id in (0..VCPU_NUM).rev() {
+        let mut arch_vcpu = RISCVVCpu::init_smp(harts.clone(), hart_id);
+        arch_vcpu.set_ept_root(ept_root).unwrap();
+        vmdevs.register_mmio_regions(&mut arch_vcpu);
+        if hart_id == 0 {
+            arch_vcpu.set_entry(KERNEL_BASE.into()).unwrap();
+            vcpu_loop(arch_vcpu, &vmdevs, &aspace);
+        } else {
+            let vmdevs = vmdevs.clone();
+            let aspace = aspace.clone();
+            thread::spawn(move || vcpu_loop(arch_vcpu, &vmdevs, &aspace));
+        }
+    }
+}
 
+fn vcpu_loop(mut arch_vcpu: RISCVVCpu, vmdevs: &VmDevGroup, aspace: &Mutex<AddrSpace>) {
     loop {
         match vcpu_run(&mut arch_vcpu) {
             Ok(exit_reason) => match exit_reason {
                 AxVCpuExitReason::Nothing => {},
+                AxVCpuExitReason::CpuDown => {
+                    // Not started yet or stopped, wait for `hart_start`.
+                    thread::yield_now();
+                },
+                AxVCpuExitReason::MmioRead { addr, width, reg, signed_ext, .. } => {
+                    debug!("mmio read addr {:#x} width {:?}", addr, width);
+                    // Find dev and emulate the access.
+                    let dev = vmdevs.find_dev(addr).expect("No dev.");
+                    dev.handle_mmio_read(&mut arch_vcpu, addr, width, reg, signed_ext).unwrap();
+                },
+                AxVCpuExitReason::MmioWrite { addr, width, data } => {
+                    debug!("mmio write addr {:#x} width {:?} data {:#x}", addr, width, data);
+                    let dev = vmdevs.find_dev(addr).expect("No dev.");
+                    dev.handle_mmio_write(&mut arch_vcpu, addr, width, data).unwrap();
+                },
                 NestedPageFault{addr, access_flags} => {
                     debug!("addr {:#x} access {:#x}", addr, access_flags);
-                    if addr < PHY_MEM_START.into() {
-                        // Find dev and handle mmio region.
-                        let dev = vmdevs.find_dev(addr).expect("No dev.");
-                        dev.handle_mmio(addr, &mut aspace).unwrap();
-                    } else {
-                        unimplemented!("Handle #PF for memory region.");
-                    }
+                    // Allocate the guest RAM on first access.
+                    assert!(
+                        aspace.lock().handle_page_fault(addr, access_flags),
+                        "Unhandled #PF @ {:#x}", addr
+                    );
                 },
                 _ => {
                     panic!("Unhandled VM-Exit: {:?}", exit_reason);
@@ -83,10 +123,12 @@ fn main() {
     }
 }
 
-fn load_vm_image(image_path: String, image_load_gpa: VirtAddr, aspace: &AddrSpace) -> AxResult {
+fn load_vm_image(image_path: String, image_load_gpa: VirtAddr, aspace: &mut AddrSpace) -> AxResult {
     use std::io::{BufReader, Read};
     let (image_file, image_size) = open_image_file(image_path.as_str())?;
 
+    // Guest RAM is allocated on demand, populate the image region first.
+    aspace.populate_area(image_load_gpa, image_size)?;
     let image_load_regions = aspace
         .translated_byte_buffer(image_load_gpa, image_size)
         .expect("Failed to translate kernel image load address");
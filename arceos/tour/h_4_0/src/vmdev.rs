@@ -1,24 +1,79 @@
 use alloc::vec::Vec;
 use alloc::sync::Arc;
+use axdevice::MmioDevice;
 use axerrno::AxResult;
-use memory_addr::VirtAddr;
-use axhal::paging::MappingFlags;
-use axmm::AddrSpace;
+use memory_addr::{PhysAddr, VirtAddr};
+use riscv_vcpu::{AccessWidth, RISCVVCpu};
+
+/// Emulated pflash, reads are served from the host pflash.
+pub struct PFlash {
+    host_base: PhysAddr,
+}
+
+impl PFlash {
+    pub fn new(host_base: PhysAddr) -> Self {
+        Self { host_base }
+    }
+}
+
+impl MmioDevice for PFlash {
+    fn handle_read(&self, offset: usize, width: AccessWidth) -> AxResult<u64> {
+        let ptr = axhal::mem::phys_to_virt(self.host_base + offset).as_usize();
+        let val = unsafe {
+            match width {
+                AccessWidth::Byte => (ptr as *const u8).read_volatile() as u64,
+                AccessWidth::Word => (ptr as *const u16).read_volatile() as u64,
+                AccessWidth::Dword => (ptr as *const u32).read_volatile() as u64,
+                AccessWidth::Qword => (ptr as *const u64).read_volatile(),
+            }
+        };
+        Ok(val)
+    }
+
+    fn handle_write(&self, offset: usize, _width: AccessWidth, val: u64) -> AxResult {
+        // Read-only, programming the flash is not supported.
+        warn!("pflash: ignore write {:#x} at offset {:#x}", val, offset);
+        Ok(())
+    }
+}
 
 pub struct VmDev {
     start: VirtAddr,
     size: usize,
+    dev: Arc<dyn MmioDevice>,
 }
 
 impl VmDev {
-    pub fn new(start: VirtAddr, size: usize) -> Self {
-        Self { start, size }
+    pub fn new(start: VirtAddr, size: usize, dev: Arc<dyn MmioDevice>) -> Self {
+        Self { start, size, dev }
     }
 
-    pub fn handle_mmio(&self, addr: VirtAddr , aspace: &mut AddrSpace) -> AxResult {
-        let mapping_flags = MappingFlags::from_bits(0xf).unwrap();
-        // Passthrough-Mode
-        aspace.map_linear(addr, addr.as_usize().into(), 4096, mapping_flags)
+    /// Emulates an MMIO read, then completes it in the vcpu.
+    pub fn handle_mmio_read(
+        &self,
+        vcpu: &mut RISCVVCpu,
+        addr: VirtAddr,
+        width: AccessWidth,
+        reg: usize,
+        signed_ext: bool,
+    ) -> AxResult {
+        let val = self.dev.handle_read(addr.as_usize() - self.start.as_usize(), width)?;
+        vcpu.complete_mmio_read(reg, width, signed_ext, val);
+        Ok(())
+    }
+
+    /// Emulates an MMIO write, then completes it in the vcpu.
+    pub fn handle_mmio_write(
+        &self,
+        vcpu: &mut RISCVVCpu,
+        addr: VirtAddr,
+        width: AccessWidth,
+        data: u64,
+    ) -> AxResult {
+        self.dev
+            .handle_write(addr.as_usize() - self.start.as_usize(), width, data)?;
+        vcpu.complete_mmio_write();
+        Ok(())
     }
 
     pub fn check_addr(&self, addr: VirtAddr) -> bool {
@@ -35,9 +90,16 @@ impl VmDevGroup {
         Self { devices: Vec::new() }
     }
 
-    pub fn add_dev(&mut self, addr: VirtAddr, size: usize) {
-        let dev = VmDev::new(addr, size);
-        self.devices.push(Arc::new(dev));
+    pub fn add_dev(&mut self, addr: VirtAddr, size: usize, dev: Arc<dyn MmioDevice>) {
+        self.devices.push(Arc::new(VmDev::new(addr, size, dev)));
+    }
+
+    /// Registers the regions of all devices in the vcpu, so that the accesses
+    /// to them are reported as MMIO exits.
+    pub fn register_mmio_regions(&self, vcpu: &mut RISCVVCpu) {
+        for dev in &self.devices {
+            vcpu.add_mmio_region(dev.start, dev.size);
+        }
     }
 
     pub fn find_dev(&self, addr: VirtAddr) -> Option<Arc<VmDev>> {
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate log;
+extern crate axstd as std;
+
+use axvm::VmManager;
+use std::fs;
+
+/// Each `*.toml` file in this directory describes a VM, see `axvm::config`.
+const VM_CONFIG_DIR: &str = "/sbin";
+
+static VM_MANAGER: VmManager = VmManager::new();
+
+#[no_mangle]
+fn main() {
+    info!("Starting virtualization...");
+
+    // Create VMs from the configuration files, so that guests can be changed
+    // by editing the files on the disk instead of recompiling.
+    let entries = fs::read_dir(VM_CONFIG_DIR).expect("Failed to read the VM config directory");
+    for entry in entries {
+        let path = entry
+            .expect("Failed to read the VM config directory")
+            .path();
+        if !path.ends_with(".toml") {
+            continue;
+        }
+        match VM_MANAGER.create_vm_from_file(&path) {
+            Ok(vm) => info!("VM[{}] created from {}", vm.id(), path),
+            Err(err) => error!("Failed to create a VM from {}: {:?}", path, err),
+        }
+    }
+
+    VM_MANAGER.boot_all().expect("Failed to boot VMs");
+    VM_MANAGER.wait_all();
+    info!("All VMs have stopped.");
+}
@@ -1,8 +1,11 @@
 #![allow(dead_code)]
 
+use alloc::vec;
 use core::ffi::{c_void, c_char, c_int};
+use core::mem::size_of;
 use axhal::arch::TrapFrame;
 use axhal::trap::{register_trap_handler, SYSCALL};
+use axhal::uaccess::{copy_from_user, copy_to_user, strncpy_from_user};
 use axerrno::LinuxError;
 use axtask::current;
 use axtask::TaskExtRef;
@@ -19,6 +22,11 @@ const SYS_EXIT_GROUP: usize = 94;
 const SYS_SET_TID_ADDRESS: usize = 96;
 
 const AT_FDCWD: i32 = -100;
+const PATH_MAX: usize = 4096;
+const IOV_MAX: i32 = 1024;
+
+/// Size of the kernel buffer through which `read` and `write` copy user data.
+const UACCESS_BUF_SIZE: usize = 4096;
 
 #[register_trap_handler(SYSCALL)]
 fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
@@ -49,7 +57,12 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
 
 fn sys_openat(dfd: c_int, fname: *const c_char, flags: c_int, mode: api::ctypes::mode_t) -> isize {
     assert_eq!(dfd, AT_FDCWD);
-    api::sys_open(fname, flags, mode) as isize
+    let mut path = vec![0u8; PATH_MAX];
+    match strncpy_from_user(&mut path, fname as _) {
+        Ok(len) if len < PATH_MAX => api::sys_open(path.as_ptr() as _, flags, mode) as isize,
+        Ok(_) => -LinuxError::ENAMETOOLONG.code() as _,
+        Err(_) => -LinuxError::EFAULT.code() as _,
+    }
 }
 
 fn sys_close(fd: i32) -> isize {
@@ -57,15 +70,63 @@ fn sys_close(fd: i32) -> isize {
 }
 
 fn sys_read(fd: i32, buf: *mut c_void, count: usize) -> isize {
-    api::sys_read(fd, buf, count)
+    // Only one chunk is read, a short read is allowed and does not block for
+    // more data.
+    let mut kbuf = [0u8; UACCESS_BUF_SIZE];
+    let len = count.min(UACCESS_BUF_SIZE);
+    let ret = api::sys_read(fd, kbuf.as_mut_ptr() as _, len);
+    if ret > 0 && copy_to_user(buf as _, &kbuf[..ret as usize]).is_err() {
+        return -LinuxError::EFAULT.code() as _;
+    }
+    ret
 }
 
 fn sys_write(fd: i32, buf: *const c_void, count: usize) -> isize {
-    api::sys_write(fd, buf, count)
+    let mut kbuf = [0u8; UACCESS_BUF_SIZE];
+    let mut written = 0;
+    while written < count {
+        let len = (count - written).min(UACCESS_BUF_SIZE);
+        let src = (buf as *const u8).wrapping_add(written);
+        if copy_from_user(&mut kbuf[..len], src).is_err() {
+            if written > 0 {
+                break;
+            }
+            return -LinuxError::EFAULT.code() as _;
+        }
+        let ret = api::sys_write(fd, kbuf.as_ptr() as _, len);
+        if ret < 0 {
+            return if written > 0 { written as _ } else { ret };
+        }
+        written += ret as usize;
+        if (ret as usize) < len {
+            break;
+        }
+    }
+    written as _
 }
 
 fn sys_writev(fd: i32, iov: *const api::ctypes::iovec, iocnt: i32) -> isize {
-    unsafe { api::sys_writev(fd, iov, iocnt) }
+    if !(0..=IOV_MAX).contains(&iocnt) {
+        return -LinuxError::EINVAL.code() as _;
+    }
+    let iov_size = size_of::<api::ctypes::iovec>();
+    let mut iovs = vec![0u8; iocnt as usize * iov_size];
+    if copy_from_user(&mut iovs, iov as _).is_err() {
+        return -LinuxError::EFAULT.code() as _;
+    }
+    let mut written = 0;
+    for iov in iovs.chunks_exact(iov_size) {
+        let iov = unsafe { (iov.as_ptr() as *const api::ctypes::iovec).read_unaligned() };
+        let ret = sys_write(fd, iov.iov_base, iov.iov_len);
+        if ret < 0 {
+            return if written > 0 { written } else { ret };
+        }
+        written += ret;
+        if (ret as usize) < iov.iov_len {
+            break;
+        }
+    }
+    written
 }
 
 fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
@@ -105,9 +105,12 @@ pub use self::fs::{ax_open, fstat, getcwd, lseek, lstat, rename, stat};
 #[cfg(feature = "net")]
 pub use self::net::{
     accept, bind, connect, freeaddrinfo, getaddrinfo, getpeername, getsockname, listen, recv,
-    recvfrom, send, sendto, shutdown, socket,
+    recvfrom, recvmsg, send, sendmsg, sendto, shutdown, socket, socketpair,
 };
 
+#[cfg(all(feature = "net", feature = "fs"))]
+pub use self::net::sendfile;
+
 #[cfg(feature = "multitask")]
 pub use self::pthread::{pthread_create, pthread_exit, pthread_join, pthread_self};
 #[cfg(feature = "multitask")]
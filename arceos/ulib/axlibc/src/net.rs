@@ -1,7 +1,7 @@
 use arceos_posix_api::{
     sys_accept, sys_bind, sys_connect, sys_freeaddrinfo, sys_getaddrinfo, sys_getpeername,
-    sys_getsockname, sys_listen, sys_recv, sys_recvfrom, sys_send, sys_sendto, sys_shutdown,
-    sys_socket,
+    sys_getsockname, sys_listen, sys_recv, sys_recvfrom, sys_recvmsg, sys_send, sys_sendmsg,
+    sys_sendto, sys_shutdown, sys_socket, sys_socketpair,
 };
 use core::ffi::{c_char, c_int, c_void};
 
@@ -101,6 +101,58 @@ pub unsafe extern "C" fn recv(
     e(sys_recv(socket_fd, buf_ptr, len, flag) as _) as _
 }
 
+/// Send a message on a socket, gathered from an I/O vector.
+///
+/// Return the number of bytes sent if success.
+#[no_mangle]
+pub unsafe extern "C" fn sendmsg(
+    socket_fd: c_int,
+    msg: *const ctypes::msghdr,
+    flags: c_int,
+) -> ctypes::ssize_t {
+    e(sys_sendmsg(socket_fd, msg, flags) as _) as _
+}
+
+/// Receive a message on a socket, scattered into an I/O vector.
+///
+/// Return the number of bytes received if success.
+#[no_mangle]
+pub unsafe extern "C" fn recvmsg(
+    socket_fd: c_int,
+    msg: *mut ctypes::msghdr,
+    flags: c_int,
+) -> ctypes::ssize_t {
+    e(sys_recvmsg(socket_fd, msg, flags) as _) as _
+}
+
+/// Transfer data from a file to a socket.
+///
+/// Return the number of bytes sent if success.
+#[cfg(feature = "fs")]
+#[no_mangle]
+pub unsafe extern "C" fn sendfile(
+    out_fd: c_int,
+    in_fd: c_int,
+    offset: *mut ctypes::off_t,
+    count: ctypes::size_t,
+) -> ctypes::ssize_t {
+    e(arceos_posix_api::sys_sendfile(out_fd, in_fd, offset, count) as _) as _
+}
+
+/// Create a pair of connected sockets.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn socketpair(
+    domain: c_int,
+    socktype: c_int,
+    protocol: c_int,
+    sv: *mut c_int,
+) -> c_int {
+    let sv = unsafe { core::slice::from_raw_parts_mut(sv, 2) };
+    e(sys_socketpair(domain, socktype, protocol, sv))
+}
+
 /// Listen for connections on a socket
 ///
 /// Return 0 if success.
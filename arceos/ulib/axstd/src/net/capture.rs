@@ -0,0 +1,27 @@
+extern crate alloc;
+
+use crate::io::{self, Write};
+use alloc::boxed::Box;
+
+use arceos_api::net as api;
+
+/// Starts capturing the frames sent and received by the NIC, and writes them
+/// to `writer` in the pcap format.
+///
+/// The writer can be a `File` in the file system, or anything else that
+/// implements [`Write`], e.g., a serial port. The trace can be opened
+/// directly in Wireshark.
+pub fn start_capture<W: Write + Send + 'static>(writer: W) -> io::Result<()> {
+    api::ax_net_capture_start(Box::new(writer))
+}
+
+/// Stops the packet capture started by [`start_capture`], and flushes the
+/// writer.
+pub fn stop_capture() -> io::Result<()> {
+    api::ax_net_capture_stop()
+}
+
+/// Returns whether a packet capture is in progress.
+pub fn is_capturing() -> bool {
+    api::ax_net_capture_enabled()
+}
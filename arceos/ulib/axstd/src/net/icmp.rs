@@ -0,0 +1,22 @@
+extern crate alloc;
+
+use super::IpAddr;
+use crate::io;
+use crate::time::Duration;
+use alloc::vec::Vec;
+
+use arceos_api::net as api;
+
+/// Sends `count` ICMP echo requests to `addr`, one after another, like the
+/// `ping` utility.
+///
+/// Each request waits up to `timeout` for its reply. Returns the round-trip
+/// time of every request in order, with `None` for the requests that got no
+/// reply in time.
+pub fn ping(addr: IpAddr, count: u16, timeout: Duration) -> io::Result<Vec<Option<Duration>>> {
+    let socket = api::ax_icmp_socket();
+    api::ax_icmp_bind(&socket, 0)?;
+    (0..count)
+        .map(|seq_no| api::ax_icmp_ping(&socket, addr, seq_no, timeout))
+        .collect()
+}
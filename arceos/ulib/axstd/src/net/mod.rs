@@ -7,6 +7,9 @@
 //!
 //! * [`TcpListener`] and [`TcpStream`] provide functionality for communication over TCP
 //! * [`UdpSocket`] provides functionality for communication over UDP
+//! * [`ping`] sends ICMP echo requests and reports the round-trip times
+//! * [`start_capture`] and [`stop_capture`] record the frames sent and received by
+//!   the NIC in the pcap format (requires the `net-pcap` feature)
 //! * [`IpAddr`] represents IP addresses of either IPv4 or IPv6; [`Ipv4Addr`] and
 //!   [`Ipv6Addr`] are respectively IPv4 and IPv6 addresses
 //! * [`SocketAddr`] represents socket addresses of either IPv4 or IPv6; [`SocketAddrV4`]
@@ -14,10 +17,16 @@
 //! * [`ToSocketAddrs`] is a trait that is used for generic address resolution when interacting
 //!   with networking objects like [`TcpListener`], [`TcpStream`] or [`UdpSocket`]
 
+#[cfg(feature = "net-pcap")]
+mod capture;
+mod icmp;
 mod socket_addr;
 mod tcp;
 mod udp;
 
+#[cfg(feature = "net-pcap")]
+pub use self::capture::{is_capturing, start_capture, stop_capture};
+pub use self::icmp::ping;
 pub use self::socket_addr::{IpAddr, Ipv4Addr, Ipv6Addr};
 pub use self::socket_addr::{SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
 pub use self::tcp::{TcpListener, TcpStream};
@@ -101,6 +101,12 @@ run_test "aarch64" "tour/m_1_1" "y" "y" "payload/origin/origin" "" "monolithic k
 run_test "aarch64" "tour/m_2_0" "y" "y" "payload/origin/origin" "" "monolithic kernel exit [Some(0)] normally!"
 run_test "aarch64" "tour/m_3_0" "y" "y" "payload/hello_c/hello" "" "Hello, UserApp!" "monolithic kernel exit [Some(0)] normally!"
 run_test "aarch64" "tour/m_3_1" "y" "y" "payload/fileops_c/fileops" "" "FileOps ok!" "monolithic kernel exit [Some(0)] normally!"
+make clean >/dev/null 2>&1
+run_test "loongarch64" "tour/u_1_0" "n" "" "" "" "Hello, Arceos!"
+run_test "loongarch64" "tour/u_2_0" "n" "" "" "" "Alloc Vec" "Alloc String:"
+run_test "loongarch64" "tour/u_5_0" "n" "" "" "" "worker2 ok!" "worker1 ok!" "Multi-task OK!"
+run_test "loongarch64" "tour/u_6_0" "n" "" "" "" "Multi-task(Preemptible) ok!"
+run_test "loongarch64" "tour/u_6_1" "n" "" "" "" "worker2 ok!" "worker1 ok!" "WaitQ ok!"
 
 if [[ -s Error.log ]]; then
     cat Error.log # Print the content of Error.log